@@ -2,9 +2,92 @@ use std::path::PathBuf;
 
 use clap::Parser;
 
-#[derive(Parser, Clone)]
+use crate::loader::folder::SortMode;
+
+#[derive(Parser, Clone, Debug, Default)]
 #[command(version, about)]
-pub(crate) struct Args {
-    /// File to open
+pub struct Args {
+    /// File or folder to open
     pub file: Option<PathBuf>,
+    /// Order of images in the folder [default: from config]
+    #[arg(short, long, value_enum)]
+    pub sort: Option<SortMode>,
+    /// Reverse the folder order, or with `=false` keep it [default: from
+    /// config]
+    #[arg(
+        short,
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub reverse: Option<bool>,
+    /// Show images as stored, ignoring the EXIF orientation
+    #[arg(long)]
+    pub no_auto_orient: bool,
+    /// Browse without the catalog, keeping edits in memory until exit
+    #[arg(long)]
+    pub no_catalog: bool,
+    /// Print metadata of the file and exit
+    #[arg(short, long)]
+    pub info: bool,
+    /// Open the library grid of a catalog collection or smart collection,
+    /// or without one of the folder of FILE
+    #[arg(long, value_name = "COLLECTION", num_args = 0..=1)]
+    pub library: Option<Option<String>>,
+    /// Import a folder into the catalog and exit
+    #[arg(long, value_name = "FOLDER")]
+    pub import: Option<PathBuf>,
+    /// Only import files matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "import")]
+    pub include: Vec<String>,
+    /// Skip files and folders matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "import")]
+    pub exclude: Vec<String>,
+    /// List catalog collections and smart collections, or the images of
+    /// COLLECTION, and exit
+    #[arg(long, value_name = "COLLECTION", num_args = 0..=1)]
+    pub list: Option<Option<String>>,
+    /// Add the keywords of a Lightroom keyword list to the catalog and exit
+    #[arg(long, value_name = "FILE")]
+    pub import_keywords: Option<PathBuf>,
+    /// Write the catalog keywords as a Lightroom keyword list and exit
+    #[arg(long, value_name = "FILE")]
+    pub export_keywords: Option<PathBuf>,
+    /// Rename, reparent or merge a keyword, retagging its images, and exit
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    pub move_keyword: Option<Vec<String>>,
+    /// Remove a keyword and those under it from the catalog and exit
+    #[arg(long, value_name = "KEYWORD")]
+    pub delete_keyword: Option<String>,
+    /// Print the catalog images matching a search query and exit
+    #[arg(long, value_name = "QUERY")]
+    pub find: Option<String>,
+    /// Save a search query as a smart collection and exit
+    #[arg(long, num_args = 2, value_names = ["NAME", "QUERY"])]
+    pub save_query: Option<Vec<String>>,
+    /// Remove a smart collection and exit
+    #[arg(long, value_name = "NAME")]
+    pub delete_query: Option<String>,
+    /// Add marks, tags and captions from the XMP sidecars under a folder, or
+    /// from a darktable, digiKam or Lightroom catalog, and exit
+    #[arg(long, value_name = "SOURCE")]
+    pub import_marks: Option<PathBuf>,
+    /// Only list what --import-marks would change, and the conflicts
+    #[arg(long, requires = "import_marks")]
+    pub dry_run: bool,
+    /// On conflicts, take the values of the other program over the catalog's
+    #[arg(long, requires = "import_marks")]
+    pub overwrite: bool,
+    /// Set where catalog edits of a collection's images are written: off,
+    /// embedded or sidecar
+    #[arg(long, num_args = 2, value_names = ["COLLECTION", "POLICY"])]
+    pub sync_policy: Option<Vec<String>>,
+    /// Write catalog edits not yet in the image files or sidecars, and exit
+    #[arg(long)]
+    pub write_pending: bool,
+    /// Write over files changed by another program since last read
+    #[arg(long, requires = "write_pending")]
+    pub force: bool,
 }
@@ -0,0 +1,232 @@
+//! Filmstrip under the viewer: thumbnails of the images around the current
+//! one in the folder or collection being viewed. Like the grid, only the
+//! thumbnails in view are built.
+
+use std::ops::Range;
+
+use iced::theme;
+use iced::widget::image::Handle;
+use iced::widget::scrollable::{AbsoluteOffset, Direction, Properties, Viewport};
+use iced::widget::{button, container, image, scrollable, Row, Space};
+use iced::{Element, Length};
+
+use crate::components::image::ViewerEvent;
+use crate::components::thumbnails::Thumbnails;
+use crate::loader::thumbnail::Size as ThumbnailSize;
+
+/// Side of the thumbnails, in pixels.
+const THUMBNAIL: f32 = 64.;
+/// Space around each thumbnail.
+const PADDING: f32 = 3.;
+const CELL: f32 = THUMBNAIL + 2. * PADDING;
+/// Room left under the thumbnails for the scrollbar.
+const SCROLLBAR: f32 = 8.;
+/// Thumbnails built beyond those in view, so that scrolling shows no gaps.
+const OVERSCAN: usize = 4;
+/// Thumbnails kept on either side of those in view, in screens.
+const KEEP: usize = 4;
+
+/// Id of the scrollable, to scroll the current image into view.
+pub fn scrollable_id() -> scrollable::Id {
+    scrollable::Id::new("filmstrip")
+}
+
+/// The part of the strip in view, as the viewer shows it.
+#[derive(Clone, Debug, Default)]
+pub struct Filmstrip {
+    /// Paths of the images in view, from `first`, with their thumbnails
+    /// once loaded.
+    pub items: Vec<(String, Option<Handle>)>,
+    pub first: usize,
+    /// Images in the strip.
+    pub len: usize,
+    /// Index of the image viewed.
+    pub current: usize,
+}
+
+/// Scroll offset and thumbnails of the strip.
+pub struct FilmstripState {
+    offset: f32,
+    width: f32,
+    thumbnails: Thumbnails,
+}
+
+impl Default for FilmstripState {
+    fn default() -> Self {
+        Self {
+            offset: 0.,
+            width: 1024.,
+            thumbnails: Thumbnails::default(),
+        }
+    }
+}
+
+impl FilmstripState {
+    /// Indices of the images in view, out of `len`.
+    pub fn visible(&self, len: usize) -> Range<usize> {
+        let first = (self.offset / CELL) as usize;
+        let last = ((self.offset + self.width) / CELL).ceil() as usize;
+        let end = (last + OVERSCAN).min(len);
+        first.saturating_sub(OVERSCAN).min(end)..end
+    }
+
+    pub fn scrolled(&mut self, viewport: Viewport) {
+        self.offset = viewport.absolute_offset().x;
+        self.width = viewport.bounds().width;
+    }
+
+    /// Follow a window resize until the scrollable reports its bounds.
+    pub fn resized(&mut self, width: f32) {
+        self.width = width;
+    }
+
+    /// Center image `current` if it is out of view, and return the offset
+    /// to scroll to.
+    pub fn reveal(&mut self, current: usize) -> AbsoluteOffset {
+        let left = current as f32 * CELL;
+        if left < self.offset || left + CELL > self.offset + self.width {
+            self.offset = (left - (self.width - CELL) / 2.).max(0.);
+        }
+        AbsoluteOffset {
+            x: self.offset,
+            y: 0.,
+        }
+    }
+
+    /// Of `paths`, those to ask a thumbnail for, see [`Thumbnails::missing`].
+    pub fn missing_thumbnails(&mut self, paths: &[String]) -> Vec<String> {
+        self.thumbnails
+            .missing(paths.iter().map(String::as_str), ThumbnailSize::Normal)
+    }
+
+    /// Keep a thumbnail received, see [`Thumbnails::set`].
+    pub fn set_thumbnail(&mut self, path: String, handle: Handle) {
+        self.thumbnails.set(path, ThumbnailSize::Normal, handle);
+    }
+
+    /// Drop the thumbnails of images far out of view, so that a long
+    /// strip does not keep them all. `near` tells whether a path is close
+    /// enough to keep.
+    pub fn forget(&mut self, near: impl Fn(&str) -> bool) {
+        self.thumbnails.retain(near);
+    }
+
+    /// Indices of the images whose thumbnails are worth keeping, out of
+    /// `len`.
+    pub fn near(&self, len: usize) -> Range<usize> {
+        let visible = self.visible(len);
+        let margin = KEEP * visible.len().max(1);
+        visible.start.saturating_sub(margin)..(visible.end + margin).min(len)
+    }
+
+    /// The strip as shown, given the paths of the images in view.
+    pub fn strip(&self, paths: Vec<String>, first: usize, len: usize, current: usize) -> Filmstrip {
+        Filmstrip {
+            items: paths
+                .into_iter()
+                .map(|x| {
+                    let handle = self.thumbnails.get(&x).cloned();
+                    (x, handle)
+                })
+                .collect(),
+            first,
+            len,
+            current,
+        }
+    }
+}
+
+/// Build the strip, with the images out of view replaced by space.
+pub fn filmstrip<'a>(strip: &Filmstrip) -> Element<'a, ViewerEvent> {
+    let mut cells = Row::new().push(Space::new(
+        Length::Fixed(strip.first as f32 * CELL),
+        Length::Fixed(CELL),
+    ));
+    for (i, (_, handle)) in (strip.first..).zip(&strip.items) {
+        let thumbnail: Element<ViewerEvent> = match handle {
+            Some(handle) => image(handle.clone())
+                .width(Length::Fixed(THUMBNAIL))
+                .height(Length::Fixed(THUMBNAIL))
+                .into(),
+            None => Space::new(Length::Fixed(THUMBNAIL), Length::Fixed(THUMBNAIL)).into(),
+        };
+        let style = if i == strip.current {
+            theme::Button::Primary
+        } else {
+            theme::Button::Text
+        };
+        cells = cells.push(
+            button(thumbnail)
+                .padding(PADDING)
+                .style(style)
+                .on_press(ViewerEvent::Jump(i)),
+        );
+    }
+    let rest = strip.len.saturating_sub(strip.first + strip.items.len());
+    cells = cells.push(Space::new(
+        Length::Fixed(rest as f32 * CELL),
+        Length::Fixed(CELL),
+    ));
+    let strip = scrollable(cells)
+        .id(scrollable_id())
+        .direction(Direction::Horizontal(
+            Properties::new().width(4).scroller_width(4),
+        ))
+        .on_scroll(ViewerEvent::FilmstripScrolled)
+        .width(Length::Fill);
+    container(strip)
+        .height(Length::Fixed(CELL + SCROLLBAR))
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(offset: f32, width: f32) -> FilmstripState {
+        FilmstripState {
+            offset,
+            width,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn visible() {
+        // Ten cells in view, with the overscan after them.
+        let strip = state(0., 10. * CELL);
+        assert_eq!(strip.visible(100), 0..10 + OVERSCAN);
+        assert_eq!(strip.visible(3), 0..3);
+        assert_eq!(strip.visible(0), 0..0);
+        // Scrolled to the end, and past it once the strip got shorter.
+        let strip = state(90. * CELL, 10. * CELL);
+        assert_eq!(strip.visible(100), 90 - OVERSCAN..100);
+        assert_eq!(strip.visible(50), 50..50);
+        assert_eq!(strip.visible(0), 0..0);
+    }
+
+    #[test]
+    fn near() {
+        let strip = state(0., 10. * CELL);
+        let visible = strip.visible(1000);
+        assert_eq!(strip.near(1000), 0..visible.end + KEEP * visible.len());
+        assert_eq!(strip.near(5), 0..5);
+        assert_eq!(strip.near(0), 0..0);
+        let strip = state(990. * CELL, 10. * CELL);
+        let visible = strip.visible(1000);
+        assert_eq!(strip.near(1000), visible.start - KEEP * visible.len()..1000);
+        // Past the end, with nothing in view, a margin is still kept.
+        assert_eq!(strip.near(500), 500 - KEEP..500);
+    }
+
+    #[test]
+    fn reveal() {
+        let mut strip = state(0., 10. * CELL);
+        // In view, first and last: no scrolling.
+        assert_eq!(strip.reveal(0).x, 0.);
+        assert_eq!(strip.reveal(9).x, 0.);
+        // Out of view: centered, but never before the start.
+        assert_eq!(strip.reveal(50).x, 50. * CELL - 4.5 * CELL);
+        assert_eq!(strip.reveal(2).x, 0.);
+    }
+}
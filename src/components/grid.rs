@@ -0,0 +1,735 @@
+//! Library grid: thumbnails of a collection or folder, with sorting,
+//! filtering and multiple selection. Only the rows in view are built, so
+//! large collections scroll as fast as small ones.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use iced::keyboard::Modifiers;
+use iced::theme;
+use iced::widget::image::Handle;
+use iced::widget::scrollable::{AbsoluteOffset, Viewport};
+use iced::widget::text::Shaping;
+use iced::widget::{
+    button, checkbox, column, container, image, pick_list, row, scrollable, slider, text,
+    text_input, Column, Row, Space,
+};
+use iced::{Alignment, Color, Element, Length};
+use iced_aw::{Bootstrap, BOOTSTRAP_FONT};
+use serde::{Deserialize, Serialize};
+
+use crate::components::image::label_color;
+use crate::components::thumbnails::Thumbnails;
+use crate::db::datastore::{Flag, Image, Marks};
+use crate::db::query::Query;
+use crate::loader::folder::natural_cmp;
+use crate::loader::thumbnail::Size as ThumbnailSize;
+
+/// Range of thumbnail sizes, in pixels.
+pub const MIN_THUMBNAIL: u32 = 64;
+pub const MAX_THUMBNAIL: u32 = 512;
+/// Space around each thumbnail.
+const PADDING: f32 = 6.;
+/// Height of the marks and file name under each thumbnail.
+const CAPTION: f32 = 20.;
+/// Height of the bar above the grid.
+pub const TOOLBAR: f32 = 44.;
+/// Rows built beyond those in view, so that scrolling shows no gaps.
+const OVERSCAN: usize = 1;
+/// Thumbnails kept above and below those in view, in screens.
+const KEEP: usize = 4;
+/// Longest time between the clicks of a double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// What the grid shows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// A collection or smart collection of the catalog, by name.
+    Collection(String),
+    Folder(PathBuf),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Collection(name) => write!(f, "{}", name),
+            Source::Folder(dir) => write!(f, "{}", dir.to_string_lossy()),
+        }
+    }
+}
+
+/// Order of the images in the grid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GridSort {
+    /// File name, with digit runs compared as numbers.
+    #[default]
+    Name,
+    /// Date taken; images without one go last.
+    DateTaken,
+    /// Highest rating first, rejects last.
+    Rating,
+}
+
+impl GridSort {
+    pub const ALL: [GridSort; 3] = [GridSort::Name, GridSort::DateTaken, GridSort::Rating];
+}
+
+impl fmt::Display for GridSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GridSort::Name => "Name",
+            GridSort::DateTaken => "Date taken",
+            GridSort::Rating => "Rating",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum GridEvent {
+    Scrolled(Viewport),
+    Clicked(usize),
+    Filter(String),
+    Sort(GridSort),
+    Reverse(bool),
+    ThumbnailSize(u32),
+}
+
+/// Id of the scrollable, to restore its offset.
+pub fn scrollable_id() -> scrollable::Id {
+    scrollable::Id::new("library-grid")
+}
+
+/// Record of a file the catalog does not know, for showing it in a grid.
+pub fn loose_image(path: &Path) -> Image {
+    Image {
+        path: path.to_string_lossy().into_owned(),
+        collection: String::new(),
+        size: (0, 0),
+        tags: vec![],
+        title: String::new(),
+        description: String::new(),
+        marks: Marks::default(),
+        shot: Default::default(),
+        gps: None,
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or(path)
+}
+
+/// Images of a source in display order, with the selection, the scroll
+/// offset and the thumbnails loaded so far.
+pub struct GridState {
+    pub source: Source,
+    images: Vec<Image>,
+    /// Indices into `images` of those passing the filter, in order.
+    shown: Vec<usize>,
+    pub sort: GridSort,
+    pub reverse: bool,
+    /// Filter as typed, a search query.
+    pub filter: String,
+    query: Option<Query>,
+    /// Why `filter` does not parse; the last valid one stays applied.
+    filter_error: Option<String>,
+    /// Paths of the selected images.
+    selected: HashSet<String>,
+    /// Image keys act on, by path.
+    cursor: Option<String>,
+    /// Image a Shift-click range starts from, by path.
+    anchor: Option<String>,
+    /// Side of the thumbnails, in pixels.
+    pub thumbnail_size: u32,
+    offset: f32,
+    viewport: iced::Size,
+    thumbnails: Thumbnails,
+    last_click: Option<(usize, Instant)>,
+    /// Images are still being listed.
+    pub loading: bool,
+}
+
+impl GridState {
+    /// An empty grid of `source`, filled by [`GridState::extend`].
+    pub fn new(source: Source, sort: GridSort, reverse: bool, thumbnail_size: u32) -> GridState {
+        GridState {
+            source,
+            images: vec![],
+            shown: vec![],
+            sort,
+            reverse,
+            filter: String::new(),
+            query: None,
+            filter_error: None,
+            selected: HashSet::new(),
+            cursor: None,
+            anchor: None,
+            thumbnail_size: thumbnail_size.clamp(MIN_THUMBNAIL, MAX_THUMBNAIL),
+            offset: 0.,
+            viewport: iced::Size::new(1024., 768.),
+            thumbnails: Thumbnails::default(),
+            last_click: None,
+            loading: true,
+        }
+    }
+
+    /// Add listed images, merging those passing the filter into the order
+    /// shown.
+    pub fn extend(&mut self, images: Vec<Image>) {
+        let start = self.images.len();
+        self.images.extend(images);
+        let mut added = self.filtered(start..self.images.len());
+        added.sort_by(|&a, &b| self.compare(a, b));
+        let mut shown = Vec::with_capacity(self.shown.len() + added.len());
+        let (mut old, mut added) = (self.shown.iter().peekable(), added.into_iter().peekable());
+        while let (Some(&&a), Some(&b)) = (old.peek(), added.peek()) {
+            if self.compare(b, a).is_lt() {
+                shown.push(b);
+                added.next();
+            } else {
+                shown.push(a);
+                old.next();
+            }
+        }
+        shown.extend(old);
+        shown.extend(added);
+        self.shown = shown;
+        // Indices moved.
+        self.last_click = None;
+    }
+
+    /// Images shown.
+    pub fn len(&self) -> usize {
+        self.shown.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shown.is_empty()
+    }
+
+    /// The `i`th image shown.
+    pub fn image(&self, i: usize) -> &Image {
+        &self.images[self.shown[i]]
+    }
+
+    fn index_of(&self, path: &str) -> Option<usize> {
+        (0..self.len()).find(|&i| self.image(i).path == path)
+    }
+
+    /// Indices among `range` of the images passing the filter.
+    fn filtered(&self, range: Range<usize>) -> Vec<usize> {
+        range
+            .filter(|&i| {
+                self.query
+                    .as_ref()
+                    .is_none_or(|q| q.matches(&self.images[i]))
+            })
+            .collect()
+    }
+
+    /// Order of the images at indices `a` and `b` as shown.
+    fn compare(&self, a: usize, b: usize) -> Ordering {
+        let (a, b) = (&self.images[a], &self.images[b]);
+        let by_name = || natural_cmp(file_name(&a.path), file_name(&b.path));
+        let order = match self.sort {
+            GridSort::Name => by_name(),
+            GridSort::DateTaken => match (&a.shot.taken, &b.shot.taken) {
+                (Some(x), Some(y)) => x.cmp(y),
+                (x, y) => x.is_none().cmp(&y.is_none()),
+            }
+            .then_with(by_name),
+            GridSort::Rating => b
+                .marks
+                .xmp_rating()
+                .cmp(&a.marks.xmp_rating())
+                .then_with(by_name),
+        };
+        if self.reverse {
+            order.reverse()
+        } else {
+            order
+        }
+    }
+
+    /// Filter and sort the images again, keeping the cursor and selection.
+    fn refresh(&mut self) {
+        let mut shown = self.filtered(0..self.images.len());
+        shown.sort_by(|&a, &b| self.compare(a, b));
+        self.shown = shown;
+        let shown: HashSet<&str> = self
+            .shown
+            .iter()
+            .map(|&i| self.images[i].path.as_str())
+            .collect();
+        self.selected.retain(|x| shown.contains(&**x));
+        if self.cursor.as_deref().is_some_and(|x| !shown.contains(x)) {
+            self.cursor = None;
+        }
+        self.last_click = None;
+    }
+
+    /// Apply a filter query; an empty one shows every image.
+    pub fn set_filter(&mut self, filter: String) {
+        match Query::parse(&filter) {
+            _ if filter.trim().is_empty() => {
+                self.query = None;
+                self.filter_error = None;
+            }
+            Ok(query) => {
+                self.query = Some(query);
+                self.filter_error = None;
+            }
+            Err(e) => self.filter_error = Some(e.to_string()),
+        }
+        self.filter = filter;
+        self.refresh();
+    }
+
+    pub fn set_sort(&mut self, sort: GridSort, reverse: bool) {
+        self.sort = sort;
+        self.reverse = reverse;
+        self.refresh();
+    }
+
+    /// Set the marks of the image at `path`. The order is kept until the
+    /// grid is sorted again, so that images do not jump away while rated.
+    pub fn set_marks(&mut self, path: &str, marks: Marks) {
+        if let Some(x) = self.images.iter_mut().find(|x| x.path == path) {
+            x.marks = marks;
+        }
+    }
+
+    /// Change the marks of the selected images, or of the image under the
+    /// cursor if none is, with `f`. Returns their paths and new marks.
+    pub fn mark_selection(&mut self, f: impl Fn(Marks) -> Marks) -> Vec<(String, Marks)> {
+        let mut changed = vec![];
+        for image in &mut self.images {
+            let selected = if self.selected.is_empty() {
+                self.cursor.as_ref() == Some(&image.path)
+            } else {
+                self.selected.contains(&image.path)
+            };
+            if selected {
+                image.marks = f(image.marks);
+                changed.push((image.path.clone(), image.marks));
+            }
+        }
+        changed
+    }
+
+    /// Index of the image keys act on.
+    pub fn cursor(&self) -> Option<usize> {
+        self.cursor.as_deref().and_then(|x| self.index_of(x))
+    }
+
+    /// Put the cursor on the image at `path`, if shown, selecting only it.
+    pub fn select_path(&mut self, path: &str) -> bool {
+        match self.index_of(path) {
+            Some(i) => {
+                self.select_only(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn select_only(&mut self, i: usize) {
+        let path = self.image(i).path.clone();
+        self.selected = HashSet::from([path.clone()]);
+        self.cursor = Some(path.clone());
+        self.anchor = Some(path);
+    }
+
+    /// Select from the anchor to `i`, replacing the selection unless
+    /// `add`.
+    fn select_range(&mut self, i: usize, add: bool) {
+        let from = self
+            .anchor
+            .as_deref()
+            .and_then(|x| self.index_of(x))
+            .unwrap_or(i);
+        let range = from.min(i)..=from.max(i);
+        if !add {
+            self.selected.clear();
+        }
+        for j in range {
+            self.selected.insert(self.image(j).path.clone());
+        }
+        self.cursor = Some(self.image(i).path.clone());
+    }
+
+    pub fn select_all(&mut self) {
+        self.selected = (0..self.len())
+            .map(|i| self.image(i).path.clone())
+            .collect();
+    }
+
+    pub fn is_selected(&self, i: usize) -> bool {
+        self.selected.contains(&self.image(i).path)
+    }
+
+    /// Handle a click on image `i`: Ctrl toggles it, Shift selects a
+    /// range, else it alone is selected. Returns whether it was the second
+    /// click of a double click.
+    pub fn click(&mut self, i: usize, modifiers: Modifiers) -> bool {
+        let double = self
+            .last_click
+            .is_some_and(|(j, at)| j == i && at.elapsed() < DOUBLE_CLICK);
+        self.last_click = (!double).then(Instant::now).map(|at| (i, at));
+        if modifiers.shift() {
+            self.select_range(i, modifiers.command());
+        } else if modifiers.command() {
+            let path = self.image(i).path.clone();
+            if !self.selected.remove(&path) {
+                self.selected.insert(path.clone());
+            }
+            self.cursor = Some(path.clone());
+            self.anchor = Some(path);
+        } else {
+            self.select_only(i);
+        }
+        double && !modifiers.shift() && !modifiers.command()
+    }
+
+    /// Move the cursor by `delta` images, extending the selection if
+    /// `extend`. Returns the path under the new cursor.
+    pub fn move_cursor(&mut self, delta: isize, extend: bool) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let i = match self.cursor() {
+            Some(i) => i.saturating_add_signed(delta).min(self.len() - 1),
+            None => 0,
+        };
+        if extend {
+            self.select_range(i, false);
+        } else {
+            self.select_only(i);
+        }
+        self.cursor.clone()
+    }
+
+    /// Move the cursor to image `i`, or the last one.
+    pub fn jump(&mut self, i: usize) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        self.select_only(i.min(self.len() - 1));
+        self.cursor.clone()
+    }
+
+    /// Images per row.
+    pub fn columns(&self) -> usize {
+        ((self.viewport.width / self.cell_width()) as usize).max(1)
+    }
+
+    /// Images per screen, for paging.
+    pub fn page(&self) -> usize {
+        self.columns() * ((self.viewport.height / self.cell_height()) as usize).max(1)
+    }
+
+    fn cell_width(&self) -> f32 {
+        self.thumbnail_size as f32 + 2. * PADDING
+    }
+
+    fn cell_height(&self) -> f32 {
+        self.thumbnail_size as f32 + CAPTION + 2. * PADDING
+    }
+
+    fn rows(&self) -> usize {
+        self.len().div_ceil(self.columns())
+    }
+
+    /// Rows in view, with some to spare.
+    fn visible_rows(&self) -> Range<usize> {
+        let first = (self.offset / self.cell_height()) as usize;
+        let last = ((self.offset + self.viewport.height) / self.cell_height()).ceil() as usize;
+        let end = (last + OVERSCAN).min(self.rows());
+        first.saturating_sub(OVERSCAN).min(end)..end
+    }
+
+    /// Indices of the images in view.
+    pub fn visible(&self) -> Range<usize> {
+        let rows = self.visible_rows();
+        let columns = self.columns();
+        (rows.start * columns).min(self.len())..(rows.end * columns).min(self.len())
+    }
+
+    pub fn scrolled(&mut self, viewport: Viewport) {
+        self.offset = viewport.absolute_offset().y;
+        self.viewport = viewport.bounds().size();
+    }
+
+    /// Follow a window resize until the scrollable reports its bounds.
+    pub fn resized(&mut self, width: f32, height: f32) {
+        self.viewport = iced::Size::new(width, (height - TOOLBAR).max(1.));
+    }
+
+    pub fn offset(&self) -> AbsoluteOffset {
+        AbsoluteOffset {
+            x: 0.,
+            y: self.offset,
+        }
+    }
+
+    /// Scroll so that the cursor is in view; returns whether the offset
+    /// changed.
+    pub fn reveal_cursor(&mut self) -> bool {
+        let Some(i) = self.cursor() else {
+            return false;
+        };
+        let top = (i / self.columns()) as f32 * self.cell_height();
+        let bottom = top + self.cell_height();
+        let offset = if top < self.offset {
+            top
+        } else if bottom > self.offset + self.viewport.height {
+            bottom - self.viewport.height
+        } else {
+            return false;
+        };
+        self.offset = offset.max(0.);
+        true
+    }
+
+    /// Thumbnail size the grid shows.
+    pub fn thumbnail_tier(&self) -> ThumbnailSize {
+        ThumbnailSize::fitting(self.thumbnail_size)
+    }
+
+    /// Paths of the images in view lacking a thumbnail of the size shown
+    /// and not yet asked for, which are then noted as asked for.
+    pub fn missing_thumbnails(&mut self) -> Vec<String> {
+        let size = self.thumbnail_tier();
+        let (images, shown) = (&self.images, &self.shown);
+        let paths = self.visible().map(|i| images[shown[i]].path.as_str());
+        self.thumbnails.missing(paths, size)
+    }
+
+    /// Indices of the images whose thumbnails are worth keeping.
+    fn near(&self) -> Range<usize> {
+        let visible = self.visible();
+        let margin = KEEP * visible.len().max(self.page());
+        visible.start.saturating_sub(margin)..(visible.end + margin).min(self.len())
+    }
+
+    /// Drop the thumbnails of images far out of view, so that scrolling
+    /// through a large collection does not keep them all.
+    pub fn forget(&mut self) {
+        let (images, shown) = (&self.images, &self.shown);
+        let near: HashSet<&str> = self
+            .near()
+            .map(|i| images[shown[i]].path.as_str())
+            .collect();
+        self.thumbnails.retain(|x| near.contains(x));
+    }
+
+    /// Keep a thumbnail received, see [`Thumbnails::set`].
+    pub fn set_thumbnail(&mut self, path: String, size: ThumbnailSize, handle: Handle) {
+        self.thumbnails.set(path, size, handle);
+    }
+}
+
+/// Rating, flag and label of an image, in little.
+fn badges<'a>(marks: Marks) -> Row<'a, GridEvent> {
+    let icon = |icon: Bootstrap, color: Color| {
+        text(icon.to_string())
+            .font(BOOTSTRAP_FONT)
+            .size(11)
+            .style(color)
+    };
+    let mut items = row![].spacing(1).align_items(Alignment::Center);
+    let flag = match marks.flag {
+        Flag::Pick => Some((Bootstrap::FlagFill, Color::from_rgb8(0x40, 0xb0, 0x50))),
+        Flag::Reject => Some((Bootstrap::XCircleFill, Color::from_rgb8(0xe0, 0x40, 0x40))),
+        Flag::Unflagged => None,
+    };
+    if let Some((flag, color)) = flag {
+        items = items.push(icon(flag, color));
+    }
+    if let Some(label) = marks.label {
+        items = items.push(icon(Bootstrap::CircleFill, label_color(label)));
+    }
+    let gold = Color::from_rgb8(0xf0, 0xc0, 0x20);
+    for _ in 0..marks.rating {
+        items = items.push(icon(Bootstrap::StarFill, gold));
+    }
+    items
+}
+
+/// Image `i` of the grid, `cursor` the index of the image under the
+/// cursor.
+fn cell(state: &GridState, i: usize, cursor: Option<usize>) -> Element<'_, GridEvent> {
+    let image_record = state.image(i);
+    let side = state.thumbnail_size as f32;
+    let thumbnail: Element<GridEvent> = match state.thumbnails.get(&image_record.path) {
+        Some(handle) => image(handle.clone())
+            .width(Length::Fixed(side))
+            .height(Length::Fixed(side))
+            .into(),
+        None => Space::new(Length::Fixed(side), Length::Fixed(side)).into(),
+    };
+    let caption = row![
+        badges(image_record.marks),
+        text(file_name(&image_record.path))
+            .size(12)
+            .shaping(Shaping::Advanced),
+    ]
+    .spacing(4)
+    .align_items(Alignment::Center);
+    let style = if state.is_selected(i) {
+        theme::Button::Primary
+    } else if cursor == Some(i) {
+        theme::Button::Secondary
+    } else {
+        theme::Button::Text
+    };
+    button(column![
+        thumbnail,
+        container(caption)
+            .width(Length::Fixed(side))
+            .height(Length::Fixed(CAPTION))
+            .center_y()
+            .clip(true),
+    ])
+    .padding(PADDING)
+    .style(style)
+    .on_press(GridEvent::Clicked(i))
+    .into()
+}
+
+/// Bar of sorting, filtering and size controls.
+fn toolbar(state: &GridState) -> Element<'_, GridEvent> {
+    let count = match state.selected.len() {
+        0 => format!("{} images", state.len()),
+        n => format!("{} images, {} selected", state.len(), n),
+    };
+    let filter = text_input("Filter, e.g. rating>=3 tag:Places", &state.filter)
+        .on_input(GridEvent::Filter)
+        .size(14)
+        .width(Length::Fixed(280.));
+    let mut bar = row![
+        text(state.source.to_string())
+            .size(14)
+            .shaping(Shaping::Advanced),
+        filter,
+        pick_list(&GridSort::ALL[..], Some(state.sort), GridEvent::Sort).text_size(14),
+        checkbox("Reverse", state.reverse)
+            .on_toggle(GridEvent::Reverse)
+            .text_size(14),
+        slider(
+            MIN_THUMBNAIL..=MAX_THUMBNAIL,
+            state.thumbnail_size,
+            GridEvent::ThumbnailSize
+        )
+        .step(16u32)
+        .width(Length::Fixed(120.)),
+        text(count).size(14),
+    ]
+    .spacing(12)
+    .padding(6)
+    .align_items(Alignment::Center);
+    if state.loading {
+        bar = bar.push(text("Loading...").size(14));
+    }
+    if let Some(ref e) = state.filter_error {
+        bar = bar.push(text(e).size(12).style(Color::from_rgb8(0xe0, 0x40, 0x40)));
+    }
+    container(bar).height(Length::Fixed(TOOLBAR)).into()
+}
+
+/// Build the grid, with the rows out of view replaced by space.
+pub fn grid(state: &GridState) -> Element<'_, GridEvent> {
+    let rows = state.visible_rows();
+    let columns = state.columns();
+    let height = state.cell_height();
+    let cursor = state.cursor();
+    let mut body = Column::new().push(Space::new(
+        Length::Fill,
+        Length::Fixed(rows.start as f32 * height),
+    ));
+    for r in rows.clone() {
+        let mut cells = Row::new();
+        for i in r * columns..((r + 1) * columns).min(state.len()) {
+            cells = cells.push(cell(state, i, cursor));
+        }
+        body = body.push(cells);
+    }
+    body = body.push(Space::new(
+        Length::Fill,
+        Length::Fixed((state.rows() - rows.end) as f32 * height),
+    ));
+    let body = scrollable(body)
+        .id(scrollable_id())
+        .on_scroll(GridEvent::Scrolled)
+        .width(Length::Fill)
+        .height(Length::Fill);
+    column![toolbar(state), body].into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(names: impl IntoIterator<Item = String>) -> GridState {
+        let mut grid = GridState::new(Source::Folder("/p".into()), GridSort::Name, false, 128);
+        grid.extend(
+            names
+                .into_iter()
+                .map(|x| loose_image(&Path::new("/p").join(x)))
+                .collect(),
+        );
+        grid
+    }
+
+    #[test]
+    fn forget_far_thumbnails() {
+        let mut grid = grid((0..10_000).map(|i| format!("{:05}.jpg", i)));
+        grid.resized(1400., 1000.);
+        let handle = Handle::from_pixels(1, 1, vec![0; 4]);
+        for i in 0..grid.len() {
+            let path = grid.image(i).path.clone();
+            grid.set_thumbnail(path, ThumbnailSize::Normal, handle.clone());
+        }
+        grid.forget();
+        let near = grid.near();
+        assert!(near.start == 0 && near.end < grid.len());
+        let kept = (0..grid.len()).filter(|&i| grid.thumbnails.get(&grid.image(i).path).is_some());
+        assert!(kept.eq(near));
+    }
+
+    #[test]
+    fn extend_merges_in_order() {
+        let names = |grid: &GridState| -> Vec<String> {
+            (0..grid.len())
+                .map(|i| file_name(&grid.image(i).path).to_owned())
+                .collect()
+        };
+        let mut grid = grid(["b10.jpg", "a.jpg", "b2.jpg"].map(String::from));
+        grid.set_filter("-c".into());
+        grid.extend(
+            ["c.jpg", "b1.jpg", "z.jpg", "0.jpg"]
+                .map(|x| loose_image(Path::new(x)))
+                .to_vec(),
+        );
+        let merged = names(&grid);
+        assert_eq!(
+            merged,
+            ["0.jpg", "a.jpg", "b1.jpg", "b2.jpg", "b10.jpg", "z.jpg"]
+        );
+        grid.refresh();
+        assert_eq!(names(&grid), merged);
+
+        grid.set_sort(GridSort::Name, true);
+        grid.extend(vec![loose_image(Path::new("b3.jpg"))]);
+        let merged = names(&grid);
+        grid.refresh();
+        assert_eq!(names(&grid), merged);
+        assert_eq!(merged[..3], ["z.jpg", "b10.jpg", "b3.jpg"]);
+    }
+}
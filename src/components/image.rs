@@ -1,7 +1,15 @@
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use crate::components::viewer::Viewer;
+use crate::components::filmstrip::{filmstrip, Filmstrip};
+use crate::components::info::{info_panel, InfoSection, InfoState};
+use crate::components::tags::{tag_editor, TagEditorState};
+use crate::components::viewer::{Scaling, Viewer};
+use crate::db::datastore::{ColorLabel, Flag, Marks};
+use crate::db::keywords::{self, Keyword};
+use crate::keymap::Action;
+use crate::loader::LoadedImage;
 use crate::ui::MainEvent;
 use iced::advanced::widget::Text;
 use iced::advanced::Widget;
@@ -14,11 +22,12 @@ use iced::keyboard::Key;
 use iced::theme;
 use iced::widget::button;
 use iced::widget::image::Handle;
+use iced::widget::scrollable::Viewport;
 use iced::widget::text::Shaping;
 use iced::widget::Image;
 use iced::widget::Space;
 use iced::widget::{
-    checkbox, column, container, horizontal_space, image, radio, row, scrollable, slider, text,
+    checkbox, column, container, horizontal_space, image, pick_list, radio, row, scrollable, slider, text,
     text_input, toggler, vertical_space,
 };
 use iced::widget::{Button, Column, Component, Container, Slider};
@@ -29,30 +38,92 @@ use iced::Padding;
 use iced::Size;
 use iced::Theme;
 use iced::Vector;
-use iced::{Color, Element, Length, Renderer, Sandbox, Settings};
-use iced_aw::{Bootstrap, BOOTSTRAP_FONT, BOOTSTRAP_FONT_BYTES};
+use iced::{Alignment, Color, Element, Length, Renderer, Sandbox, Settings};
+use iced_aw::floating_element::Anchor;
+use iced_aw::{
+    Badge, BadgeStyles, Bootstrap, FloatingElement, BOOTSTRAP_FONT, BOOTSTRAP_FONT_BYTES,
+};
+
+/// Scalings offered in the toolbar.
+const SCALING_MODES: [Scaling; 6] = [
+    Scaling::Fit,
+    Scaling::Fill,
+    Scaling::FitWidth,
+    Scaling::FitHeight,
+    Scaling::Image(1.),
+    Scaling::Image(2.),
+];
 
-#[derive(Default)]
 pub struct ViewerUI {
     viewer: Option<image::Handle>,
-    scale: Option<f32>,
+    min_scale: f32,
+    max_scale: f32,
+    scale_step: f32,
+    /// Scaling of a newly opened image.
+    scaling: Scaling,
+    /// Changes whenever a different image is opened, so that view state
+    /// from the previous one is dropped.
+    generation: u64,
     position: Option<Vector>,
     filename: Option<String>,
+    image: Option<Arc<LoadedImage>>,
+    /// Event sent from outside, e.g. by a key binding, with its sequence number.
+    command: Option<(u64, ViewerEvent)>,
+    /// Marks of the image, shown over it.
+    marks: Option<Marks>,
+    /// Tags of the image, if it can be tagged.
+    tags: Option<Vec<String>>,
+    /// Keyword tree, for completion.
+    keywords: Arc<Vec<Keyword>>,
+    /// Neighboring images, if the filmstrip is shown.
+    filmstrip: Option<Filmstrip>,
     //display_metadata: bool,
 }
 
+impl Default for ViewerUI {
+    fn default() -> Self {
+        Self {
+            viewer: None,
+            min_scale: 0.1,
+            max_scale: 20.,
+            scale_step: 0.1,
+            scaling: Scaling::default(),
+            generation: 0,
+            position: None,
+            filename: None,
+            image: None,
+            command: None,
+            marks: None,
+            tags: None,
+            keywords: Arc::default(),
+            filmstrip: None,
+        }
+    }
+}
+
 pub struct ViewerState {
+    scaling: Scaling,
+    /// Effective scale, as last resolved by the viewer.
     scale: f32,
     position: Vector,
+    generation: u64,
     display_metadata: bool,
+    info: InfoState,
+    tags: TagEditorState,
+    clipboard: Option<arboard::Clipboard>,
 }
 
 impl Default for ViewerState {
     fn default() -> Self {
         Self {
+            scaling: Scaling::default(),
             scale: 1.,
             position: Vector::new(0., 0.),
+            generation: 0,
             display_metadata: false,
+            info: InfoState::default(),
+            tags: TagEditorState::default(),
+            clipboard: None,
         }
     }
 }
@@ -64,9 +135,13 @@ pub enum ViewerEvent {
     ZoomOriginal,
     ZoomChange,
     Scale(f32),
+    Resolved(f32),
+    SetScaling(Scaling),
     Move(Vector),
     RotateCW,
     RotateCCW,
+    FlipHorizontal,
+    FlipVertical,
     Reset,
     Open,
     Save,
@@ -78,6 +153,19 @@ pub enum ViewerEvent {
     Info,
     Exit,
     Ready(Handle),
+    ToggleSection(InfoSection),
+    TagFilter(String),
+    Copy(String),
+    /// Open the info pane to edit tags.
+    EditTags,
+    TagInput(String),
+    SubmitTag,
+    AddTag(String),
+    RemoveTag(String),
+    ToggleFilmstrip,
+    FilmstripScrolled(Viewport),
+    /// Open the image at this position of the filmstrip.
+    Jump(usize),
 }
 
 #[derive(Debug, Clone)]
@@ -89,15 +177,92 @@ impl ViewerUI {
         self
     }
 
+    /// Sets the decoded image, whose metadata fills the info pane.
+    pub fn set_image(mut self, image: Arc<LoadedImage>) -> Self {
+        self.filename = image
+            .path
+            .as_ref()
+            .and_then(|x| x.file_name())
+            .map(|x| x.to_string_lossy().into_owned());
+        self.image = Some(image);
+        self
+    }
+
     pub fn set_position(mut self, position: Vector) -> Self {
         self.position = Some(position);
         self
     }
 
     pub fn set_scale(mut self, scale: f32) -> Self {
-        self.scale = Some(scale);
+        self.scaling = Scaling::Image(scale);
+        self
+    }
+
+    /// Sets the scaling a newly opened image starts with.
+    pub fn set_scaling(mut self, scaling: Scaling) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    /// Sets the zoom range and the ratio one zoom step changes the scale by.
+    pub fn set_scale_limits(mut self, min_scale: f32, max_scale: f32, scale_step: f32) -> Self {
+        self.min_scale = min_scale;
+        self.max_scale = max_scale.max(min_scale);
+        self.scale_step = scale_step;
+        self
+    }
+
+    /// Sets the image generation; a new value resets zoom and position.
+    pub fn set_generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    /// Sends `event` to the viewer once; `sequence` must grow with each
+    /// new command.
+    pub fn set_command(mut self, sequence: u64, event: ViewerEvent) -> Self {
+        self.command = Some((sequence, event));
+        self
+    }
+
+    pub fn set_marks(mut self, marks: Marks) -> Self {
+        self.marks = Some(marks);
         self
     }
+
+    /// Sets the tags of the image, making them editable, and the keywords
+    /// they are completed from.
+    pub fn set_tags(mut self, tags: Vec<String>, keywords: Arc<Vec<Keyword>>) -> Self {
+        self.tags = Some(tags);
+        self.keywords = keywords;
+        self
+    }
+
+    /// Shows the filmstrip with these images.
+    pub fn set_filmstrip(mut self, filmstrip: Filmstrip) -> Self {
+        self.filmstrip = Some(filmstrip);
+        self
+    }
+
+    /// The tags with `tag` added, unless it is there already.
+    fn add_tag(&self, tag: Option<String>) -> Option<MainEvent> {
+        let (Some(tags), Some(tag)) = (self.tags.as_ref(), tag) else {
+            return None;
+        };
+        if tags.contains(&tag) {
+            return None;
+        }
+        Some(MainEvent::SetTags([&tags[..], &[tag]].concat()))
+    }
+
+    /// Scaling and position to show, ignoring state left by a previous image.
+    fn view_state(&self, state: &ViewerState) -> (Scaling, Vector) {
+        if state.generation == self.generation {
+            (state.scaling, state.position)
+        } else {
+            (self.scaling, Vector::default())
+        }
+    }
 }
 
 impl Component<MainEvent> for ViewerUI {
@@ -128,6 +293,11 @@ impl Component<MainEvent> for ViewerUI {
 
 
     fn update(&mut self, state: &mut ViewerState, event: ViewerEvent) -> Option<MainEvent> {
+        if state.generation != self.generation {
+            state.generation = self.generation;
+            state.scaling = self.scaling;
+            state.position = Vector::default();
+        }
         match event {
             ViewerEvent::Ready(v) => {
                 self.viewer = Some(v);
@@ -136,35 +306,110 @@ impl Component<MainEvent> for ViewerUI {
                 state.position = v;
             }
             ViewerEvent::Scale(f) => {
+                state.scaling = Scaling::Image(f);
+                state.scale = f;
+            }
+            ViewerEvent::Resolved(f) => {
                 state.scale = f;
             }
+            ViewerEvent::SetScaling(scaling) => {
+                state.scaling = scaling;
+            }
             ViewerEvent::ZoomIn => {
-                state.scale *= 1.1;
-                state.scale = state.scale.clamp(0.1, 20.);
+                state.scaling = Scaling::Image(
+                    (state.scale * (1. + self.scale_step)).clamp(self.min_scale, self.max_scale),
+                );
             }
             ViewerEvent::ZoomOut => {
-                state.scale /= 1.1;
-                state.scale = state.scale.clamp(0.1, 20.);
+                state.scaling = Scaling::Image(
+                    (state.scale / (1. + self.scale_step)).clamp(self.min_scale, self.max_scale),
+                );
             }
             ViewerEvent::ZoomOriginal => {
-                state.scale = 1.;
+                state.scaling = Scaling::Image(1.);
             }
+            // Fit -> 100% -> 200% -> Fit
             ViewerEvent::ZoomChange => {
-                if state.scale < 1. {
-                    state.scale = 1.;
-                } else if state.scale < 2. {
-                    state.scale = 2.;
-                } else {
-                    state.scale = 0.5;
-                }
+                state.scaling = match state.scaling {
+                    Scaling::Fit => Scaling::Image(1.),
+                    Scaling::Image(1.) => Scaling::Image(2.),
+                    _ => Scaling::Fit,
+                };
+            }
+            // Keep the same image point centered: offsets turn with the image.
+            ViewerEvent::RotateCW => {
+                state.position = Vector::new(-state.position.y, state.position.x);
+                return Some(MainEvent::RotateCW);
+            }
+            ViewerEvent::RotateCCW => {
+                state.position = Vector::new(state.position.y, -state.position.x);
+                return Some(MainEvent::RotateCCW);
+            }
+            ViewerEvent::FlipHorizontal => {
+                state.position.x = -state.position.x;
+                return Some(MainEvent::FlipHorizontal);
+            }
+            ViewerEvent::FlipVertical => {
+                state.position.y = -state.position.y;
+                return Some(MainEvent::FlipVertical);
             }
             ViewerEvent::Reset => {
-                state.scale = 1.;
+                state.scaling = self.scaling;
                 state.position = Vector::default();
             }
+            ViewerEvent::Exit => return Some(MainEvent::Action(Action::Exit)),
+            ViewerEvent::Fullscreen => return Some(MainEvent::Action(Action::Fullscreen)),
+            ViewerEvent::ToggleFilmstrip => return Some(MainEvent::Action(Action::Filmstrip)),
+            ViewerEvent::FilmstripScrolled(viewport) => {
+                return Some(MainEvent::FilmstripScrolled(viewport))
+            }
+            ViewerEvent::Jump(i) => return Some(MainEvent::Jump(i)),
             ViewerEvent::Info => {
                 state.display_metadata = !state.display_metadata;
             }
+            ViewerEvent::ToggleSection(section) => {
+                let collapsed = &mut state.info.collapsed;
+                if collapsed.contains(&section) {
+                    collapsed.remove(&section);
+                } else {
+                    collapsed.insert(section);
+                }
+            }
+            ViewerEvent::TagFilter(filter) => {
+                state.info.tag_filter = filter;
+            }
+            ViewerEvent::EditTags => {
+                state.display_metadata = true;
+            }
+            ViewerEvent::TagInput(input) => {
+                state.tags.input = input;
+            }
+            ViewerEvent::SubmitTag => {
+                let input = std::mem::take(&mut state.tags.input);
+                return self.add_tag(keywords::resolve(&self.keywords, &input));
+            }
+            ViewerEvent::AddTag(tag) => {
+                state.tags.input.clear();
+                return self.add_tag(Some(tag));
+            }
+            ViewerEvent::RemoveTag(tag) => {
+                let tags = self.tags.as_ref()?;
+                return Some(MainEvent::SetTags(
+                    tags.iter().filter(|x| **x != tag).cloned().collect(),
+                ));
+            }
+            ViewerEvent::Copy(value) => {
+                if state.clipboard.is_none() {
+                    state.clipboard = arboard::Clipboard::new()
+                        .map_err(|e| log::warn!("clipboard unavailable: {}", e))
+                        .ok();
+                }
+                if let Some(ref mut clipboard) = state.clipboard {
+                    if let Err(e) = clipboard.set_text(value) {
+                        log::warn!("copy failed: {}", e);
+                    }
+                }
+            }
             _ => {}
         }
         None
@@ -177,19 +422,28 @@ impl Component<MainEvent> for ViewerUI {
         Self::Event
 
     > {
+        let (scaling, position) = self.view_state(state);
         let mut window = column![];
         if let Some(v) = &self.viewer {
-            let viewer = Viewer::new(v.clone());
+            let mut viewer = Viewer::new(v.clone());
+            if let Some((sequence, ref event)) = self.command {
+                viewer = viewer.command(sequence, event.clone());
+            }
             let viewer = viewer
                 .width(Length::Fill)
                 .height(Length::Fill)
-                .min_scale(0.1)
-                .max_scale(20.)
-                .set_offset(state.position)
-                .set_scale(state.scale)
+                .min_scale(self.min_scale)
+                .max_scale(self.max_scale)
+                .scale_step(self.scale_step)
+                .set_offset(position)
+                .set_scaling(scaling)
                 .on_scale(|x| ViewerEvent::Scale(x))
+                .on_resolve(ViewerEvent::Resolved)
                 .on_move(|x| ViewerEvent::Move(x))
                 .on_middle(|| ViewerEvent::ZoomChange);
+            let viewer = FloatingElement::new(viewer, marks_overlay(self.marks))
+                .anchor(Anchor::NorthWest)
+                .hide(self.marks.is_none());
             let col = if state.display_metadata {
                 let info_box = scrollable(
                     column![
@@ -198,10 +452,14 @@ impl Component<MainEvent> for ViewerUI {
                             .horizontal_alignment(alignment::Horizontal::Center)
                             .width(Length::Fill),
                         Space::new(Length::Shrink, Length::Fixed(8.)),
-                        text("1").shaping(Shaping::Advanced),
-                        text("1").shaping(Shaping::Advanced),
-                        text("1").shaping(Shaping::Advanced),
-                        text("1").shaping(Shaping::Advanced),
+                        match self.tags {
+                            Some(ref tags) => tag_editor(tags, &self.keywords, &state.tags),
+                            None => Space::new(Length::Shrink, Length::Shrink).into(),
+                        },
+                        match self.image {
+                            Some(ref image) => info_panel(image, &state.info),
+                            None => text("No metadata").shaping(Shaping::Advanced).into(),
+                        },
                     ]
                     .padding(Padding {
                         top: 4.,
@@ -212,7 +470,7 @@ impl Component<MainEvent> for ViewerUI {
                 );
                 container(row![
                     info_box.width(Length::Fixed(320.)),
-                    viewer.width(Length::FillPortion(5))
+                    viewer
                 ])
             } else {
                 container(column![viewer])
@@ -228,6 +486,21 @@ impl Component<MainEvent> for ViewerUI {
             );
             window = window.push(Space::new(Length::Fill, Length::Fill));
         }
+        let preview_badge: Element<ViewerEvent> = match self.image {
+            Some(ref image) if image.preview => Badge::new(
+                text("Embedded preview")
+                    .size(14)
+                    .vertical_alignment(alignment::Vertical::Center),
+            )
+            .style(BadgeStyles::Info)
+            .into(),
+            _ => Space::new(Length::Shrink, Length::Shrink).into(),
+        };
+        let filmstrip_icon = if self.filmstrip.is_some() {
+            Bootstrap::MenuDown
+        } else {
+            Bootstrap::MenuUp
+        };
         let toolbar = row![
             button(
                 text(Bootstrap::InfoCircle.to_string())
@@ -247,7 +520,8 @@ impl Component<MainEvent> for ViewerUI {
                     .vertical_alignment(alignment::Vertical::Center)
             )
             .padding(6)
-            .style(theme::Button::Text),
+            .style(theme::Button::Text)
+            .on_press(ViewerEvent::RotateCW),
             button(
                 text(Bootstrap::ArrowCounterclockwise.to_string())
                     .size(24)
@@ -256,7 +530,28 @@ impl Component<MainEvent> for ViewerUI {
                     .vertical_alignment(alignment::Vertical::Center)
             )
             .padding(6)
-            .style(theme::Button::Text),
+            .style(theme::Button::Text)
+            .on_press(ViewerEvent::RotateCCW),
+            button(
+                text(Bootstrap::SymmetryVertical.to_string())
+                    .size(24)
+                    .font(BOOTSTRAP_FONT)
+                    .horizontal_alignment(alignment::Horizontal::Center)
+                    .vertical_alignment(alignment::Vertical::Center)
+            )
+            .padding(6)
+            .style(theme::Button::Text)
+            .on_press(ViewerEvent::FlipHorizontal),
+            button(
+                text(Bootstrap::SymmetryHorizontal.to_string())
+                    .size(24)
+                    .font(BOOTSTRAP_FONT)
+                    .horizontal_alignment(alignment::Horizontal::Center)
+                    .vertical_alignment(alignment::Vertical::Center)
+            )
+            .padding(6)
+            .style(theme::Button::Text)
+            .on_press(ViewerEvent::FlipVertical),
             button(
                 text("reset")
                     .size(24)
@@ -268,6 +563,7 @@ impl Component<MainEvent> for ViewerUI {
             .style(theme::Button::Text)
             .on_press(ViewerEvent::Reset),
             Space::new(Length::Fill, Length::Shrink),
+            preview_badge,
             /*
             text(format!(
                 "{},{} {}",
@@ -279,6 +575,13 @@ impl Component<MainEvent> for ViewerUI {
             .horizontal_alignment(alignment::Horizontal::Right)
             .vertical_alignment(alignment::Vertical::Center),
             */
+            pick_list(
+                &SCALING_MODES[..],
+                Some(scaling),
+                ViewerEvent::SetScaling
+            )
+            .text_size(16)
+            .padding(6),
             button(
                 text(format!("{:.0}%", state.scale * 100.))
                     .shaping(Shaping::Advanced)
@@ -291,7 +594,7 @@ impl Component<MainEvent> for ViewerUI {
             .style(theme::Button::Text)
             .on_press(ViewerEvent::ZoomChange),
             button(
-                text(Bootstrap::MenuUp)
+                text(filmstrip_icon)
                     .font(BOOTSTRAP_FONT)
                     .size(24)
                     .height(Length::Shrink)
@@ -299,8 +602,12 @@ impl Component<MainEvent> for ViewerUI {
                     .vertical_alignment(alignment::Vertical::Center),
             )
             .padding(6)
-            .style(theme::Button::Text),
+            .style(theme::Button::Text)
+            .on_press(ViewerEvent::ToggleFilmstrip),
         ];
+        if let Some(ref strip) = self.filmstrip {
+            window = window.push(filmstrip(strip));
+        }
         window = window.push(toolbar.padding(4));
         container(window)
             .width(Length::Fill)
@@ -309,3 +616,50 @@ impl Component<MainEvent> for ViewerUI {
             .into()
     }
 }
+
+/// Color a label is shown in.
+pub fn label_color(label: ColorLabel) -> Color {
+    match label {
+        ColorLabel::Red => Color::from_rgb8(0xe0, 0x40, 0x40),
+        ColorLabel::Yellow => Color::from_rgb8(0xe8, 0xc8, 0x30),
+        ColorLabel::Green => Color::from_rgb8(0x40, 0xb0, 0x50),
+        ColorLabel::Blue => Color::from_rgb8(0x40, 0x80, 0xe0),
+        ColorLabel::Purple => Color::from_rgb8(0xa0, 0x50, 0xd0),
+    }
+}
+
+/// Rating stars, color label and flag of an image.
+fn marks_overlay<'a>(marks: Option<Marks>) -> Element<'a, ViewerEvent> {
+    let Some(marks) = marks else {
+        return Space::new(Length::Shrink, Length::Shrink).into();
+    };
+    let icon = |icon: Bootstrap, color: Color| {
+        text(icon.to_string())
+            .font(BOOTSTRAP_FONT)
+            .size(18)
+            .style(color)
+    };
+    let mut items = row![].spacing(2).align_items(Alignment::Center);
+    for star in 1..=Marks::MAX_RATING {
+        items = items.push(if star <= marks.rating {
+            icon(Bootstrap::StarFill, Color::from_rgb8(0xf0, 0xc0, 0x20))
+        } else {
+            icon(Bootstrap::Star, Color::from_rgb8(0xc0, 0xc0, 0xc0))
+        });
+    }
+    if let Some(label) = marks.label {
+        items = items.push(icon(Bootstrap::CircleFill, label_color(label)));
+    }
+    let flag = match marks.flag {
+        Flag::Pick => Some((Bootstrap::FlagFill, Color::from_rgb8(0x40, 0xb0, 0x50))),
+        Flag::Reject => Some((Bootstrap::XCircleFill, Color::from_rgb8(0xe0, 0x40, 0x40))),
+        Flag::Unflagged => None,
+    };
+    if let Some((flag, color)) = flag {
+        items = items.push(icon(flag, color));
+    }
+    container(items)
+        .padding(6)
+        .style(theme::Container::Box)
+        .into()
+}
@@ -0,0 +1,238 @@
+//! Metadata pane shown beside the viewer.
+
+use std::collections::HashSet;
+
+use iced::alignment;
+use iced::theme;
+use iced::widget::text::Shaping;
+use iced::widget::{button, column, row, text, text_input, Column, Space};
+use iced::{Element, Length, Padding};
+use iced_aw::{Bootstrap, BOOTSTRAP_FONT};
+
+use crate::components::image::ViewerEvent;
+use crate::loader::{BitDepth, LoadedImage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InfoSection {
+    File,
+    Camera,
+    Exposure,
+    Lens,
+    Gps,
+    Keywords,
+    Tags,
+}
+
+impl InfoSection {
+    fn title(self) -> &'static str {
+        match self {
+            InfoSection::File => "File",
+            InfoSection::Camera => "Camera",
+            InfoSection::Exposure => "Exposure",
+            InfoSection::Lens => "Lens",
+            InfoSection::Gps => "GPS",
+            InfoSection::Keywords => "Keywords",
+            InfoSection::Tags => "All tags",
+        }
+    }
+}
+
+/// Local state of the info pane.
+#[derive(Default)]
+pub struct InfoState {
+    pub collapsed: HashSet<InfoSection>,
+    pub tag_filter: String,
+}
+
+/// Grouped `(label, value)` rows of an image.
+fn sections(image: &LoadedImage) -> Vec<(InfoSection, Vec<(String, String)>)> {
+    let meta = &image.metadata;
+    let mut file = vec![];
+    if let Some(ref path) = image.path {
+        if let Some(name) = path.file_name() {
+            file.push(("Name".into(), name.to_string_lossy().into_owned()));
+        }
+        if let Some(dir) = path.parent() {
+            file.push(("Folder".into(), dir.to_string_lossy().into_owned()));
+        }
+    }
+    if let Some(size) = image.file_size {
+        file.push(("Size".into(), human_size(size)));
+    }
+    file.push((
+        "Dimensions".into(),
+        format!("{} x {}", image.width, image.height),
+    ));
+    file.push((
+        "Depth".into(),
+        match image.depth {
+            BitDepth::Eight => "8 bit",
+            BitDepth::Sixteen => "16 bit",
+            BitDepth::Float32 => "32 bit float",
+        }
+        .into(),
+    ));
+    file.push(("Channels".into(), format!("{:?}", image.layout)));
+
+    let mut camera = vec![];
+    if let Some(ref v) = meta.make {
+        camera.push(("Make".into(), v.clone()));
+    }
+    if let Some(ref v) = meta.model {
+        camera.push(("Model".into(), v.clone()));
+    }
+    if let Some(v) = meta.rating {
+        camera.push(("Rating".into(), v.to_string()));
+    }
+    if let Some(ref v) = meta.copyright {
+        camera.push(("Copyright".into(), v.clone()));
+    }
+
+    let mut exposure = vec![];
+    if let Some(v) = meta.exposure_time {
+        exposure.push(("Shutter".into(), format!("{} s", v)));
+    }
+    if let Some(v) = meta.aperture {
+        exposure.push(("Aperture".into(), format!("f/{:.1}", v)));
+    }
+    if let Some(v) = meta.iso {
+        exposure.push(("ISO".into(), v.to_string()));
+    }
+    if let Some(v) = meta.date_taken {
+        exposure.push(("Taken".into(), v.to_string()));
+    }
+
+    let mut lens = vec![];
+    if let Some(ref v) = meta.lens {
+        lens.push(("Lens".into(), v.clone()));
+    }
+    if let Some(v) = meta.focal_length {
+        lens.push(("Focal length".into(), format!("{:.0} mm", v)));
+    }
+
+    let mut gps = vec![];
+    if let Some(g) = meta.gps {
+        gps.push(("Latitude".into(), format!("{:.6}", g.latitude)));
+        gps.push(("Longitude".into(), format!("{:.6}", g.longitude)));
+        gps.push(("Altitude".into(), format!("{:.1} m", g.altitude)));
+    }
+
+    let keywords = meta
+        .keywords
+        .iter()
+        .map(|k| (String::new(), k.clone()))
+        .collect();
+
+    vec![
+        (InfoSection::File, file),
+        (InfoSection::Camera, camera),
+        (InfoSection::Exposure, exposure),
+        (InfoSection::Lens, lens),
+        (InfoSection::Gps, gps),
+        (InfoSection::Keywords, keywords),
+    ]
+}
+
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut v = bytes as f64;
+    let mut unit = 0;
+    while v >= 1024. && unit < units.len() - 1 {
+        v /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", v, units[unit])
+    }
+}
+
+fn header<'a>(section: InfoSection, collapsed: bool) -> Element<'a, ViewerEvent> {
+    let icon = if collapsed {
+        Bootstrap::ChevronRight
+    } else {
+        Bootstrap::ChevronDown
+    };
+    button(row![
+        text(icon.to_string())
+            .font(BOOTSTRAP_FONT)
+            .vertical_alignment(alignment::Vertical::Center),
+        Space::new(Length::Fixed(6.), Length::Shrink),
+        text(section.title()).size(18),
+    ])
+    .width(Length::Fill)
+    .padding(2)
+    .style(theme::Button::Text)
+    .on_press(ViewerEvent::ToggleSection(section))
+    .into()
+}
+
+/// A labelled value; pressing the value copies it.
+fn value_row<'a>(label: &str, value: &str) -> Element<'a, ViewerEvent> {
+    let value_button = button(text(value).shaping(Shaping::Advanced).size(14))
+        .padding(0)
+        .style(theme::Button::Text)
+        .on_press(ViewerEvent::Copy(value.to_owned()));
+    if label.is_empty() {
+        value_button.into()
+    } else {
+        row![
+            text(label)
+                .size(14)
+                .width(Length::Fixed(100.))
+                .shaping(Shaping::Advanced),
+            value_button,
+        ]
+        .into()
+    }
+}
+
+/// Build the info pane for `image`.
+pub fn info_panel<'a>(image: &LoadedImage, state: &InfoState) -> Element<'a, ViewerEvent> {
+    let mut col = Column::new().spacing(2);
+    for (section, rows) in sections(image) {
+        if rows.is_empty() {
+            continue;
+        }
+        let collapsed = state.collapsed.contains(&section);
+        col = col.push(header(section, collapsed));
+        if !collapsed {
+            for (k, v) in rows {
+                col = col.push(value_row(&k, &v));
+            }
+        }
+    }
+
+    let collapsed = state.collapsed.contains(&InfoSection::Tags);
+    col = col.push(header(InfoSection::Tags, collapsed));
+    if !collapsed {
+        col = col.push(
+            text_input("Search tags", &state.tag_filter)
+                .on_input(ViewerEvent::TagFilter)
+                .size(14),
+        );
+        let filter = state.tag_filter.to_lowercase();
+        let mut table = column![].spacing(2);
+        for (k, v) in image.metadata.raw.iter().filter(|(k, v)| {
+            filter.is_empty()
+                || k.to_lowercase().contains(&filter)
+                || v.to_lowercase().contains(&filter)
+        }) {
+            table = table.push(column![
+                text(k).size(12),
+                button(text(v).shaping(Shaping::Advanced).size(14))
+                    .padding(Padding {
+                        top: 0.,
+                        bottom: 0.,
+                        left: 8.,
+                        right: 0.,
+                    })
+                    .style(theme::Button::Text)
+                    .on_press(ViewerEvent::Copy(v.clone())),
+            ]);
+        }
+        col = col.push(table);
+    }
+    col.into()
+}
@@ -1,3 +1,8 @@
+pub mod filmstrip;
+pub mod grid;
 pub mod image;
+pub mod info;
+pub mod tags;
+pub mod thumbnails;
 pub mod viewer;
 use viewer::*;
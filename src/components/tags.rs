@@ -0,0 +1,82 @@
+//! Keyword editor of the current image, completing from the catalog's
+//! keyword tree.
+
+use iced::theme;
+use iced::widget::text::Shaping;
+use iced::widget::{button, column, row, text, text_input, Column, Space};
+use iced::{Alignment, Element, Length};
+use iced_aw::{Bootstrap, BOOTSTRAP_FONT};
+
+use crate::components::image::ViewerEvent;
+use crate::db::keywords::{self, Keyword, SEPARATOR};
+
+/// Suggestions shown under the input.
+const SUGGESTIONS: usize = 8;
+
+/// Local state of the editor.
+#[derive(Default)]
+pub struct TagEditorState {
+    pub input: String,
+}
+
+/// Id of the keyword input, to focus it from a key binding.
+pub fn input_id() -> text_input::Id {
+    text_input::Id::new("tag-input")
+}
+
+/// A keyword path as shown, `Places › Europe › Berlin`.
+fn display(path: &str) -> String {
+    path.split(SEPARATOR).collect::<Vec<_>>().join(" › ")
+}
+
+/// Build the editor for an image carrying `tags`.
+pub fn tag_editor<'a>(
+    tags: &[String],
+    keywords: &[Keyword],
+    state: &TagEditorState,
+) -> Element<'a, ViewerEvent> {
+    let mut col = Column::new().spacing(2);
+    col = col.push(text("Tags").size(18));
+    for tag in tags {
+        col = col.push(
+            row![
+                text(display(tag))
+                    .size(14)
+                    .shaping(Shaping::Advanced)
+                    .width(Length::Fill),
+                button(text(Bootstrap::X.to_string()).font(BOOTSTRAP_FONT).size(14))
+                    .padding(0)
+                    .style(theme::Button::Text)
+                    .on_press(ViewerEvent::RemoveTag(tag.clone())),
+            ]
+            .align_items(Alignment::Center),
+        );
+    }
+    col = col.push(
+        text_input("Add keyword", &state.input)
+            .id(input_id())
+            .on_input(ViewerEvent::TagInput)
+            .on_submit(ViewerEvent::SubmitTag)
+            .size(14),
+    );
+    let mut suggestions = column![].spacing(0);
+    for k in keywords::complete(keywords, &state.input, SUGGESTIONS)
+        .into_iter()
+        .filter(|k| !tags.contains(&k.path))
+    {
+        let mut label = display(&k.path);
+        if !k.synonyms.is_empty() {
+            label = format!("{} ({})", label, k.synonyms.join(", "));
+        }
+        suggestions = suggestions.push(
+            button(text(label).size(14).shaping(Shaping::Advanced))
+                .width(Length::Fill)
+                .padding(2)
+                .style(theme::Button::Text)
+                .on_press(ViewerEvent::AddTag(k.path.clone())),
+        );
+    }
+    col = col.push(suggestions);
+    col = col.push(Space::new(Length::Shrink, Length::Fixed(8.)));
+    col.into()
+}
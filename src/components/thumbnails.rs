@@ -0,0 +1,81 @@
+//! Thumbnails of the images near the view, as the grid and the filmstrip
+//! keep them: what was loaded, at which size, and what was asked for.
+
+use std::collections::{HashMap, HashSet};
+
+use iced::widget::image::Handle;
+
+use crate::loader::thumbnail::Size;
+
+#[derive(Default)]
+pub struct Thumbnails {
+    loaded: HashMap<String, (Size, Handle)>,
+    /// Thumbnails asked for and not received, or failed.
+    requested: HashSet<(String, Size)>,
+}
+
+impl Thumbnails {
+    pub fn get(&self, path: &str) -> Option<&Handle> {
+        self.loaded.get(path).map(|x| &x.1)
+    }
+
+    /// Of `paths`, those lacking a thumbnail of at least `size` and not yet
+    /// asked for, which are then noted as asked for.
+    pub fn missing<'a>(
+        &mut self,
+        paths: impl IntoIterator<Item = &'a str>,
+        size: Size,
+    ) -> Vec<String> {
+        let mut missing = vec![];
+        for path in paths {
+            let loaded = self.loaded.get(path).is_some_and(|x| x.0 >= size);
+            let key = (path.to_owned(), size);
+            if !loaded && !self.requested.contains(&key) {
+                self.requested.insert(key);
+                missing.push(path.to_owned());
+            }
+        }
+        missing
+    }
+
+    /// Keep a thumbnail received, unless a larger one is. Failures stay
+    /// noted as asked for, so that they are not asked for again.
+    pub fn set(&mut self, path: String, size: Size, handle: Handle) {
+        self.requested.remove(&(path.clone(), size));
+        let larger = self.loaded.get(&path).is_some_and(|x| x.0 > size);
+        if !larger {
+            self.loaded.insert(path, (size, handle));
+        }
+    }
+
+    /// Drop the thumbnails of the paths `near` tells are not close enough
+    /// to the view to keep.
+    pub fn retain(&mut self, near: impl Fn(&str) -> bool) {
+        self.loaded.retain(|x, _| near(x));
+        self.requested.retain(|(x, _)| near(x));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asked_once() {
+        let mut thumbnails = Thumbnails::default();
+        let handle = Handle::from_pixels(1, 1, vec![0; 4]);
+        assert_eq!(thumbnails.missing(["a", "b"], Size::Normal), ["a", "b"]);
+        assert!(thumbnails.missing(["a", "b"], Size::Normal).is_empty());
+        thumbnails.set("a".into(), Size::Large, handle.clone());
+        // A larger thumbnail does for a smaller one, not the other way.
+        assert!(thumbnails.missing(["a"], Size::Normal).is_empty());
+        assert_eq!(thumbnails.missing(["a"], Size::XLarge), ["a"]);
+        thumbnails.set("a".into(), Size::Normal, handle);
+        assert_eq!(thumbnails.loaded["a"].0, Size::Large);
+
+        thumbnails.retain(|x| x == "b");
+        assert!(thumbnails.get("a").is_none());
+        assert_eq!(thumbnails.missing(["a", "b"], Size::XLarge), ["a", "b"]);
+        assert!(thumbnails.missing(["b"], Size::Normal).is_empty());
+    }
+}
@@ -10,6 +10,8 @@ use iced::advanced::widget::tree::{self, Tree};
 use iced::advanced::{Clipboard, Layout, Shell, Widget};
 use iced::event::{self, Event};
 use iced::{ContentFit, Element, Length, Pixels, Point, Radians, Rectangle, Size, Vector};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::hash::Hash;
 
 /// A frame that displays an image with the ability to zoom in/out and pan.
@@ -26,8 +28,10 @@ pub struct Viewer<Handle, Message> {
     move_handler: Option<Box<dyn Fn(Vector) -> Message>>,
     scale_handler: Option<Box<dyn Fn(f32) -> Message>>,
     middle_handler: Option<Box<dyn Fn() -> Message>>,
-    scale: Option<f32>,
+    resolve_handler: Option<Box<dyn Fn(f32) -> Message>>,
+    scaling: Option<Scaling>,
     position: Option<Vector>,
+    command: Option<(u64, Message)>,
 }
 
 impl<Handle, Message> Viewer<Handle, Message> {
@@ -44,9 +48,11 @@ impl<Handle, Message> Viewer<Handle, Message> {
             filter_method: image::FilterMethod::default(),
             move_handler: None,
             scale_handler: None,
-            scale: None,
+            scaling: None,
             position: None,
             middle_handler: None,
+            resolve_handler: None,
+            command: None,
         }
     }
 
@@ -117,8 +123,25 @@ impl<Handle, Message> Viewer<Handle, Message> {
         }
     }
 
+    /// Handler for the effective scale, reported when it changes without
+    /// user zoom, e.g. a fit mode after a resize.
+    pub fn on_resolve(mut self, f: impl Fn(f32) -> Message + 'static) -> Self {
+        {
+            self.resolve_handler = Some(Box::new(f));
+            self
+        }
+    }
+
     pub fn set_scale(mut self, scale: f32) -> Self {
-        self.scale = Some(scale.clamp(self.min_scale, self.max_scale));
+        self.scaling = Some(Scaling::Image(scale.clamp(self.min_scale, self.max_scale)));
+        self
+    }
+
+    pub fn set_scaling(mut self, scaling: Scaling) -> Self {
+        self.scaling = Some(match scaling {
+            Scaling::Image(scale) => Scaling::Image(scale.clamp(self.min_scale, self.max_scale)),
+            x => x,
+        });
         self
     }
 
@@ -126,6 +149,14 @@ impl<Handle, Message> Viewer<Handle, Message> {
         self.position = Some(offset);
         self
     }
+
+    /// Publishes `message` once, on the next event, if `sequence` is newer
+    /// than the last command seen. Lets the parent send messages that
+    /// originate outside the widget tree, e.g. key bindings.
+    pub fn command(mut self, sequence: u64, message: Message) -> Self {
+        self.command = Some((sequence, message));
+        self
+    }
 }
 
 impl<Message, Theme, Renderer, Handle> Widget<Message, Theme, Renderer> for Viewer<Handle, Message>
@@ -139,8 +170,10 @@ where
 
     fn state(&self) -> tree::State {
         let state = State {
-            scale: self.scale.unwrap_or(0.),
+            scaling: self.scaling.unwrap_or_default(),
             current_offset: self.position.unwrap_or(Default::default()),
+            // A recreated widget must not replay the command.
+            last_command: self.command.as_ref().map_or(0, |x| x.0),
             ..Default::default()
         };
         tree::State::new(state)
@@ -205,12 +238,25 @@ where
         let bounds = layout.bounds();
         // Take state from original.
         let state = tree.state.downcast_mut::<State>();
-        if let Some(scale) = self.scale.take() {
-            state.scale = scale;
+        if let Some(scaling) = self.scaling.take() {
+            state.scaling = scaling;
         }
         if let Some(pos) = self.position.take() {
             state.current_offset = pos;
         }
+        if let Some((sequence, message)) = self.command.take() {
+            if sequence > state.last_command {
+                state.last_command = sequence;
+                _shell.publish(message);
+            }
+        }
+        let effective = state.scale(renderer.dimensions(&self.handle), bounds.size());
+        if effective != state.reported_scale {
+            state.reported_scale = effective;
+            if let Some(ref handler) = self.resolve_handler {
+                _shell.publish(handler(effective));
+            }
+        }
 
         match event {
             Event::Mouse(mouse::Event::WheelScrolled { delta }) => {
@@ -221,22 +267,25 @@ where
                 match delta {
                     mouse::ScrollDelta::Lines { y, .. } | mouse::ScrollDelta::Pixels { y, .. } => {
                         let state = tree.state.downcast_mut::<State>();
-                        let previous_scale = state.scale;
+                        let previous_scale =
+                            state.scale(renderer.dimensions(&self.handle), bounds.size());
 
                         if y < 0.0 && previous_scale > self.min_scale
                             || y > 0.0 && previous_scale < self.max_scale
                         {
-                            state.scale = (if y > 0.0 {
-                                state.scale * (1.0 + self.scale_step)
+                            let scale = (if y > 0.0 {
+                                previous_scale * (1.0 + self.scale_step)
                             } else {
-                                state.scale / (1.0 + self.scale_step)
+                                previous_scale / (1.0 + self.scale_step)
                             })
                             .clamp(self.min_scale, self.max_scale);
+                            state.scaling = Scaling::Image(scale);
+                            state.reported_scale = scale;
 
                             let image_size =
                                 image_size(renderer, &self.handle, state, bounds.size());
 
-                            let factor = state.scale / previous_scale - 1.0;
+                            let factor = scale / previous_scale - 1.0;
 
                             let cursor_to_center = cursor_position - bounds.center();
 
@@ -256,7 +305,7 @@ where
                                 },
                             );
                             if let Some(ref handler) = self.scale_handler {
-                                _shell.publish(handler(state.scale));
+                                _shell.publish(handler(scale));
                             }
                             if let Some(ref handler) = self.move_handler {
                                 _shell.publish(handler(state.current_offset));
@@ -320,8 +369,8 @@ where
 
                     let y = if bounds.height < image_size.height {
                         (state.starting_offset.y - delta.y).clamp(
-                            -image_size.width / 2. + bounds.width / 2.,
-                            image_size.width / 2. - bounds.width / 2.,
+                            -image_size.height / 2. + bounds.height / 2.,
+                            image_size.height / 2. - bounds.height / 2.,
                         )
                     } else {
                         0.0
@@ -406,9 +455,12 @@ where
 /// The local state of a [`Viewer`].
 #[derive(Debug, Clone, Copy)]
 pub struct State {
-    /// The ratio is now in image size (absolute).
-    /// Use fit_ratio to get ratio to keep in frame.
-    scale: f32,
+    /// How the image is scaled; resolved against the bounds on use.
+    scaling: Scaling,
+    /// Last effective scale sent to the resolve handler.
+    reported_scale: f32,
+    /// Sequence number of the last command published.
+    last_command: u64,
     starting_offset: Vector,
     current_offset: Vector,
     cursor_grabbed_at: Option<Point>,
@@ -417,7 +469,9 @@ pub struct State {
 impl Default for State {
     fn default() -> Self {
         Self {
-            scale: 1.0,
+            scaling: Scaling::default(),
+            reported_scale: 0.0,
+            last_command: 0,
             starting_offset: Vector::default(),
             current_offset: Vector::default(),
             cursor_grabbed_at: None,
@@ -431,6 +485,13 @@ impl State {
         State::default()
     }
 
+    /// Returns the effective scale, in times of image size, for an image of
+    /// `image` pixels shown in `bounds`.
+    pub fn scale(&self, image: Size<u32>, bounds: Size) -> f32 {
+        self.scaling
+            .resolve(Size::new(image.width as f32, image.height as f32), bounds)
+    }
+
     /// Returns the current offset of the [`State`], given the bounds
     /// of the [`Viewer`] and its image.
     fn offset(&self, bounds: Rectangle, image_size: Size) -> Vector {
@@ -496,18 +557,61 @@ where
     };
     */
 
-    let scale = state.scale;
+    let scale = state.scale(Size { width, height }, bounds);
     Size::new(width as f32 * scale, height as f32 * scale)
 }
 
 /// Scaling option
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub enum Scaling {
     /// Fit the view.
     #[default]
     Fit,
+    /// Cover the view, cropping the longer side.
+    Fill,
+    /// Fit the width of the view.
+    FitWidth,
+    /// Fit the height of the view.
+    FitHeight,
     /// Scale in size of image.
     Image(f32),
     /// Scale in size of viewport.
     View(f32),
 }
+
+impl Scaling {
+    /// Scale in times of image size, for `image` shown in `view`.
+    pub fn resolve(self, image: Size, view: Size) -> f32 {
+        if image.width <= 0. || image.height <= 0. {
+            return 1.;
+        }
+        let width = view.width / image.width;
+        let height = view.height / image.height;
+        match self {
+            Scaling::Fit => width.min(height),
+            Scaling::Fill => width.max(height),
+            Scaling::FitWidth => width,
+            Scaling::FitHeight => height,
+            Scaling::Image(scale) => scale,
+            Scaling::View(scale) => scale * width.min(height),
+        }
+    }
+
+    /// Whether the scale follows the view size.
+    pub fn is_relative(self) -> bool {
+        !matches!(self, Scaling::Image(_))
+    }
+}
+
+impl fmt::Display for Scaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scaling::Fit => write!(f, "Fit"),
+            Scaling::Fill => write!(f, "Fill"),
+            Scaling::FitWidth => write!(f, "Fit width"),
+            Scaling::FitHeight => write!(f, "Fit height"),
+            Scaling::Image(scale) => write!(f, "{:.0}%", scale * 100.),
+            Scaling::View(scale) => write!(f, "{:.0}% of view", scale * 100.),
+        }
+    }
+}
@@ -1,20 +1,386 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::components::grid::GridSort;
+use crate::components::viewer::Scaling;
+use crate::keymap::KeyConfig;
+use crate::loader::folder::SortMode;
+
+/// Version of the config schema written by this build.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Number of recent files remembered.
+const RECENT_MAX: usize = 20;
+
 /// Perstistent config information.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Config {}
+#[serde(default)]
+pub struct Config {
+    pub version: u32,
+    pub theme: ThemeChoice,
+    pub viewer: ViewerConfig,
+    pub folder: FolderConfig,
+    pub library: LibraryConfig,
+    pub window: WindowConfig,
+    pub keys: KeyConfig,
+    pub catalog: CatalogConfig,
+    pub recent: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThemeChoice {
+    Light,
+    #[default]
+    Dark,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewerConfig {
+    /// Scaling a newly opened image starts with.
+    pub default_scaling: Scaling,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Ratio one zoom step changes the scale by.
+    pub scale_step: f32,
+    /// Apply the EXIF orientation when an image is opened.
+    pub auto_orient: bool,
+    /// Show the filmstrip under the viewer.
+    pub filmstrip: bool,
+}
+
+impl ViewerConfig {
+    /// Replace scales that are not positive and finite by the defaults.
+    fn validate(&mut self) {
+        let default = ViewerConfig::default();
+        let valid = |x: f32| x.is_finite() && x > 0.;
+        for (name, value, default) in [
+            ("min_scale", &mut self.min_scale, default.min_scale),
+            ("max_scale", &mut self.max_scale, default.max_scale),
+            ("scale_step", &mut self.scale_step, default.scale_step),
+        ] {
+            if !valid(*value) {
+                log::warn!("config: ignoring invalid viewer.{}: {}", name, value);
+                *value = default;
+            }
+        }
+        if self.min_scale > self.max_scale {
+            log::warn!(
+                "config: viewer.min_scale {} is above max_scale {}, using defaults",
+                self.min_scale,
+                self.max_scale
+            );
+            self.min_scale = default.min_scale;
+            self.max_scale = default.max_scale;
+        }
+        if let Scaling::Image(x) | Scaling::View(x) = self.default_scaling {
+            if !valid(x) {
+                log::warn!("config: ignoring invalid viewer.default_scaling: {}", x);
+                self.default_scaling = default.default_scaling;
+            }
+        }
+    }
+}
+
+impl Default for ViewerConfig {
+    fn default() -> Self {
+        Self {
+            default_scaling: Scaling::Fit,
+            min_scale: 0.1,
+            max_scale: 20.,
+            scale_step: 0.1,
+            auto_orient: true,
+            filmstrip: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FolderConfig {
+    pub sort: SortMode,
+    pub reverse: bool,
+}
+
+/// Library grid settings, kept as last used.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Side of the thumbnails, in pixels.
+    pub thumbnail_size: u32,
+    pub sort: GridSort,
+    pub reverse: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            thumbnail_size: 160,
+            sort: GridSort::default(),
+            reverse: false,
+        }
+    }
+}
+
+/// Last window geometry, in logical pixels.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 768,
+            x: None,
+            y: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CatalogConfig {
+    /// Write edits to XMP as they are made, not only when writing the
+    /// pending edits: into the sidecar if the collection's policy says so,
+    /// else into the image file.
+    pub mirror_xmp: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            theme: ThemeChoice::default(),
+            viewer: ViewerConfig::default(),
+            folder: FolderConfig::default(),
+            library: LibraryConfig::default(),
+            window: WindowConfig::default(),
+            keys: KeyConfig::default(),
+            catalog: CatalogConfig::default(),
+            recent: vec![],
+        }
+    }
+}
 
 impl Config {
     pub fn new() -> Config {
-        Config {}
+        Config::default()
+    }
+
+    /// `config.toml` under the platform config dir.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|x| x.join("phany").join("config.toml"))
     }
 
+    /// Load the config, falling back to defaults for anything missing or
+    /// unreadable. Problems are logged, never fatal.
     pub fn load() -> Config {
-        todo!()
+        let Some(path) = Self::path() else {
+            log::warn!("no config dir, using defaults");
+            return Config::default();
+        };
+        Self::load_from(&path)
+    }
+
+    /// Load the config at `path`. A file that is not valid TOML is copied
+    /// to `config.toml.bak` first, since saving would replace it.
+    pub fn load_from(path: &Path) -> Config {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Self::try_parse(&s).unwrap_or_else(|e| {
+                let backup = path.with_extension("toml.bak");
+                log::warn!(
+                    "{}: not valid TOML, using defaults and keeping it as {}: {}",
+                    path.to_string_lossy(),
+                    backup.to_string_lossy(),
+                    e
+                );
+                if let Err(e) = std::fs::copy(path, &backup) {
+                    log::warn!("{}: {}", backup.to_string_lossy(), e);
+                }
+                Config::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                log::warn!("{}: {}, using defaults", path.to_string_lossy(), e);
+                Config::default()
+            }
+        }
+    }
+
+    /// Parse config text, keeping every field that is valid.
+    pub fn parse(s: &str) -> Config {
+        Self::try_parse(s).unwrap_or_else(|e| {
+            log::warn!("config is not valid TOML, using defaults: {}", e);
+            Config::default()
+        })
+    }
+
+    /// Parse config text, keeping every field that is valid. Fails only if
+    /// the text is not TOML.
+    fn try_parse(s: &str) -> Result<Config> {
+        let mut table = s.parse::<toml::Table>()?;
+        let version = table
+            .get("version")
+            .and_then(|x| x.as_integer())
+            .unwrap_or(0) as u32;
+        if version > CONFIG_VERSION {
+            log::warn!(
+                "config version {} is newer than {}, unknown fields are ignored",
+                version,
+                CONFIG_VERSION
+            );
+        }
+        migrate(&mut table, version);
+
+        const KNOWN: &[&str] = &[
+            "version", "theme", "viewer", "folder", "library", "window", "keys", "catalog",
+            "recent",
+        ];
+        for key in table.keys().filter(|x| !KNOWN.contains(&x.as_str())) {
+            log::warn!("config: ignoring unknown {}", key);
+        }
+        let mut viewer: ViewerConfig = section(&table, "viewer");
+        viewer.validate();
+        Ok(Config {
+            version: CONFIG_VERSION,
+            theme: field(&table, "theme"),
+            viewer,
+            folder: section(&table, "folder"),
+            library: section(&table, "library"),
+            window: section(&table, "window"),
+            keys: section(&table, "keys"),
+            catalog: section(&table, "catalog"),
+            recent: field(&table, "recent"),
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow!("no config dir"))?;
+        self.save_to(&path)
+    }
+
+    /// Write the config, replacing the old file atomically.
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, toml::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Put `path` on top of the recent files.
+    pub fn add_recent(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        self.recent.retain(|x| *x != path);
+        self.recent.insert(0, path);
+        self.recent.truncate(RECENT_MAX);
+    }
+}
+
+/// Bring a table written by schema `from` up to [`CONFIG_VERSION`].
+fn migrate(table: &mut toml::Table, from: u32) {
+    // 0 -> 1: the version key was introduced; fields are unchanged.
+    if from < 1 {
+        table.insert("version".into(), toml::Value::Integer(1));
+    }
+}
+
+/// Deserialize the table `key` field by field: unknown keys and values of
+/// the wrong type are dropped with a warning, keeping the default instead.
+fn section<T>(table: &toml::Table, key: &str) -> T
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    let Some(value) = table.get(key) else {
+        return T::default();
+    };
+    let Some(fields) = value.as_table() else {
+        log::warn!("config: {} is not a table, using defaults", key);
+        return T::default();
+    };
+    let mut merged = match toml::Value::try_from(T::default()) {
+        Ok(toml::Value::Table(x)) => x,
+        _ => toml::Table::new(),
+    };
+    for (k, v) in fields {
+        let mut probe = merged.clone();
+        probe.insert(k.clone(), v.clone());
+        match toml::Value::Table(probe.clone()).try_into::<T>() {
+            // Re-serialize to tell known fields from ignored ones.
+            Ok(x) => match toml::Value::try_from(x) {
+                Ok(toml::Value::Table(t)) if t.contains_key(k) => merged = probe,
+                _ => log::warn!("config: ignoring unknown {}.{}", key, k),
+            },
+            Err(e) => log::warn!("config: ignoring invalid {}.{}: {}", key, k, e),
+        }
+    }
+    toml::Value::Table(merged).try_into().unwrap_or_default()
+}
+
+/// Deserialize the value `key`, or the default if it is missing or invalid.
+fn field<T>(table: &toml::Table, key: &str) -> T
+where
+    T: Default + DeserializeOwned,
+{
+    match table.get(key).cloned().map(|x| x.try_into()) {
+        Some(Ok(x)) => x,
+        Some(Err(e)) => {
+            log::warn!("config: ignoring invalid {}: {}", key, e);
+            T::default()
+        }
+        None => T::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_scales() {
+        let config = Config::parse(
+            "[viewer]\nmin_scale = 0.0\nmax_scale = -2.0\nscale_step = nan\n\
+             default_scaling = { Image = inf }\n",
+        );
+        let default = ViewerConfig::default();
+        assert_eq!(config.viewer.min_scale, default.min_scale);
+        assert_eq!(config.viewer.max_scale, default.max_scale);
+        assert_eq!(config.viewer.scale_step, default.scale_step);
+        assert_eq!(config.viewer.default_scaling, default.default_scaling);
+
+        let config = Config::parse("[viewer]\nmin_scale = 5.0\nmax_scale = 2.0\n");
+        assert_eq!(config.viewer.min_scale, default.min_scale);
+        assert_eq!(config.viewer.max_scale, default.max_scale);
+
+        let config = Config::parse("[viewer]\nmin_scale = 0.5\ndefault_scaling = { View = 2.0 }\n");
+        assert_eq!(config.viewer.min_scale, 0.5);
+        assert_eq!(config.viewer.default_scaling, Scaling::View(2.0));
     }
 
-    pub fn save() -> Result<()> {
-        Err(anyhow!("Not implemented"))
+    #[test]
+    fn broken_file_kept() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("phany-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "theme = \"Light\"\n[viewer\n")?;
+        let config = Config::load_from(&path);
+        assert_eq!(config.theme, ThemeChoice::default());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("config.toml.bak"))?,
+            "theme = \"Light\"\n[viewer\n"
+        );
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
     }
 }
@@ -1,32 +1,402 @@
 //! Data backends are to implement `trait Datastore`.
 //! To avoid blocking, backends are to be async.
 
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
 use serde::{Deserialize, Serialize};
 
+use super::keywords::Keyword;
+use super::query::Query;
+use crate::loader::meta::Gps;
+
+/// Page size of the streaming listings.
+pub const PAGE_SIZE: usize = 500;
+
 #[async_trait]
-pub trait Datastore {
-    async fn get(&self, key: &str) -> Option<Vec<u8>>;
-    async fn set(&self, key: &str, value: [u8]);
-    async fn delete(&self, key: &str);
+pub trait Datastore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()>;
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    async fn get_collections(&self) -> Result<Vec<Collection>>;
+    /// Up to `limit` collections in name order, starting after the name
+    /// `after`.
+    async fn get_collections_page(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Collection>>;
+    async fn get_collection(&self, name: &str) -> Result<Option<Collection>>;
+    /// Insert or replace the collection named `collection.name`.
+    async fn put_collection(&self, collection: &Collection) -> Result<()>;
+    /// Remove a collection and its images.
+    async fn delete_collection(&self, name: &str) -> Result<()>;
+
+    async fn get_images_in_collection(&self, collection: &str) -> Result<Vec<Image>>;
+    /// Up to `limit` images of `collection` in path order, starting after
+    /// the path `after`.
+    async fn get_images_page(
+        &self,
+        collection: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Image>>;
+    async fn get_image(&self, path: &str) -> Result<Option<Image>>;
+    /// Insert or replace the image at `image.path`, tags and captions
+    /// included. Its collection must exist.
+    async fn put_image(&self, image: &Image) -> Result<()>;
+    async fn delete_image(&self, path: &str) -> Result<()>;
+    /// Move an image record to a new path and collection, keeping its
+    /// tags and captions. An image already at `to` is replaced.
+    async fn rename_image(&self, from: &str, to: &str, collection: &str) -> Result<()>;
+
+    async fn set_tags(&self, path: &str, tags: &[String]) -> Result<()>;
+    async fn set_description(&self, path: &str, description: &str) -> Result<()>;
+    async fn set_marks(&self, path: &str, marks: Marks) -> Result<()>;
+    /// Images carrying `tag`, in path order.
+    async fn get_images_with_tag(&self, tag: &str) -> Result<Vec<Image>>;
+
+    /// The keyword tree, in path order. Image tags are added to it when
+    /// stored.
+    async fn get_keywords(&self) -> Result<Vec<Keyword>>;
+    /// Insert or replace a keyword, adding the keywords above it.
+    async fn put_keyword(&self, keyword: &Keyword) -> Result<()>;
+    /// Remove a keyword and those under it, from the tree and every image.
+    async fn delete_keyword(&self, path: &str) -> Result<()>;
+    /// Move a keyword and those under it to the path `to`, retagging every
+    /// image. Moving onto an existing keyword merges the two, synonyms
+    /// included. Renaming and reparenting are moves, see
+    /// [`super::keywords::renamed`] and [`super::keywords::reparented`].
+    async fn move_keyword(&self, from: &str, to: &str) -> Result<()>;
+
+    /// Saved searches, in name order.
+    async fn get_smart_collections(&self) -> Result<Vec<SmartCollection>>;
+    async fn get_smart_collection(&self, name: &str) -> Result<Option<SmartCollection>>;
+    /// Insert or replace the smart collection named `collection.name`.
+    async fn put_smart_collection(&self, collection: &SmartCollection) -> Result<()>;
+    async fn delete_smart_collection(&self, name: &str) -> Result<()>;
+    /// Up to `limit` images matching `query` in path order, starting after
+    /// the path `after`.
+    async fn find_images_page(
+        &self,
+        query: &Query,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Image>>;
+
+    /// Note `fields` of an image as edited in the catalog and not yet
+    /// written to its file, on top of those already noted.
+    async fn add_pending(&self, path: &str, fields: Fields) -> Result<()>;
+    /// Note `fields` of an image as written; the others stay pending.
+    async fn clear_pending(&self, path: &str, fields: Fields) -> Result<()>;
+    /// Up to `limit` images with pending fields in path order, starting
+    /// after the path `after`.
+    async fn get_pending_page(&self, after: Option<&str>, limit: usize) -> Result<Page<Pending>>;
+
+    /// All collections, a page at a time.
+    fn get_collections_iter(&self) -> BoxStream<'_, Result<Vec<Collection>>> {
+        pages(
+            move |after| async move { self.get_collections_page(after.as_deref(), PAGE_SIZE).await },
+        )
+    }
+
+    /// Images of `collection`, a page at a time. Images added during the
+    /// walk show up if they sort after the current page.
+    fn get_images_in_collection_iter<'a>(
+        &'a self,
+        collection: &'a str,
+    ) -> BoxStream<'a, Result<Vec<Image>>> {
+        pages(move |after| async move {
+            self.get_images_page(collection, after.as_deref(), PAGE_SIZE)
+                .await
+        })
+    }
+
+    /// Images with pending fields, a page at a time.
+    fn get_pending_iter(&self) -> BoxStream<'_, Result<Vec<Pending>>> {
+        pages(move |after| async move { self.get_pending_page(after.as_deref(), PAGE_SIZE).await })
+    }
+
+    /// Images matching `query`, a page at a time.
+    fn find_images_iter<'a>(&'a self, query: &'a Query) -> BoxStream<'a, Result<Vec<Image>>> {
+        pages(move |after| async move {
+            self.find_images_page(query, after.as_deref(), PAGE_SIZE)
+                .await
+        })
+    }
+}
+
+/// Items in a stable order, and where the next page starts.
+#[derive(Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Key to pass as `after` for the next page; `None` on the last one.
+    pub next: Option<String>,
+}
 
-    async fn get_collections(&self) -> Vec<Collection>;
-    //async fn get_collections_iter(&self) -> Vec<Collection>;
-    async fn get_images_in_collection(&self, collection: String) -> Vec<Image>;
-    //async fn get_images_in_collection_iter(&self) -> Vec<Image>;
+impl<T> Page<T> {
+    /// A page of a query for `limit` items; a full page may have more
+    /// after it.
+    pub fn new(items: Vec<T>, limit: usize, key: impl Fn(&T) -> String) -> Page<T> {
+        let next = if items.len() >= limit {
+            items.last().map(key)
+        } else {
+            None
+        };
+        Page { items, next }
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Stream the pages returned by `fetch`, which gets the cursor of the
+/// previous page. Stops after the last page or the first error.
+fn pages<'a, T, F, Fut>(fetch: F) -> BoxStream<'a, Result<Vec<T>>>
+where
+    T: Send + 'a,
+    F: Fn(Option<String>) -> Fut + Send + 'a,
+    Fut: std::future::Future<Output = Result<Page<T>>> + Send + 'a,
+{
+    // `None` as state means done; `Some(cursor)` means fetch from cursor.
+    stream::unfold(Some(None), move |state| {
+        let page = state.map(&fetch);
+        async move {
+            match page?.await {
+                Ok(page) if page.items.is_empty() => None,
+                Ok(page) => Some((Ok(page.items), page.next.map(Some))),
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Collection {
     pub folder: String,
     pub name: String,
+    /// Where catalog edits of its images are written.
+    #[serde(default)]
+    pub sync: SyncPolicy,
+}
+
+/// Where catalog edits are written back, see [`super::sync`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncPolicy {
+    /// Only into the catalog.
+    #[default]
+    Off,
+    /// Into the XMP inside the image file.
+    Embedded,
+    /// Into a `.xmp` file next to the image.
+    Sidecar,
+}
+
+impl SyncPolicy {
+    pub const ALL: [SyncPolicy; 3] = [SyncPolicy::Off, SyncPolicy::Embedded, SyncPolicy::Sidecar];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SyncPolicy::Off => "off",
+            SyncPolicy::Embedded => "embedded",
+            SyncPolicy::Sidecar => "sidecar",
+        }
+    }
+
+    /// The policy called `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<SyncPolicy> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// A set of the image fields written back to files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Fields(pub u8);
+
+impl Fields {
+    pub const NONE: Fields = Fields(0);
+    /// Rating, label and flag.
+    pub const MARKS: Fields = Fields(1);
+    pub const TAGS: Fields = Fields(2);
+    pub const TITLE: Fields = Fields(4);
+    pub const DESCRIPTION: Fields = Fields(8);
+    pub const GPS: Fields = Fields(16);
+
+    pub fn contains(self, other: Fields) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn without(self, other: Fields) -> Fields {
+        Fields(self.0 & !other.0)
+    }
+
+    /// Fields that differ between two records of an image.
+    pub fn changed(old: &Image, new: &Image) -> Fields {
+        [
+            (old.marks != new.marks, Fields::MARKS),
+            (old.tags != new.tags, Fields::TAGS),
+            (old.title != new.title, Fields::TITLE),
+            (old.description != new.description, Fields::DESCRIPTION),
+            (old.gps != new.gps, Fields::GPS),
+        ]
+        .into_iter()
+        .filter(|x| x.0)
+        .fold(Fields::NONE, |a, x| a | x.1)
+    }
+}
+
+impl std::ops::BitOr for Fields {
+    type Output = Fields;
+
+    fn bitor(self, other: Fields) -> Fields {
+        Fields(self.0 | other.0)
+    }
+}
+
+/// An image and its fields waiting to be written to its file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pending {
+    pub image: Image,
+    pub fields: Fields,
+}
+
+/// A saved search, listed with the folder collections.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SmartCollection {
+    pub name: String,
+    /// Source of the [`Query`].
+    pub query: String,
+}
+
+impl SmartCollection {
+    pub fn parse(&self) -> Result<Query> {
+        Query::parse(&self.query).map_err(|e| anyhow!("{}: {}", self.name, e))
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Image {
     pub path: String,
     pub collection: String,
     pub size: (u32, u32),
+    /// Keyword paths, see [`super::keywords`].
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub title: String,
     pub description: String,
+    #[serde(default)]
+    pub marks: Marks,
+    #[serde(default)]
+    pub shot: Shot,
+    #[serde(default)]
+    pub gps: Option<Gps>,
+}
+
+/// Capture settings read from the file, for searching.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shot {
+    /// Make and model, `FUJIFILM X-T4`.
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub iso: Option<u32>,
+    pub aperture: Option<f64>,
+    /// Exposure time in seconds.
+    pub exposure: Option<f64>,
+    /// Focal length in mm.
+    pub focal_length: Option<f64>,
+    /// Local time taken as `YYYY-MM-DDTHH:MM:SS`, which sorts in time order
+    /// and has months and days as prefixes.
+    pub taken: Option<String>,
+}
+
+/// Color labels, named as in `xmp:Label` by Lightroom and darktable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub const ALL: [ColorLabel; 5] = [
+        ColorLabel::Red,
+        ColorLabel::Yellow,
+        ColorLabel::Green,
+        ColorLabel::Blue,
+        ColorLabel::Purple,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorLabel::Red => "Red",
+            ColorLabel::Yellow => "Yellow",
+            ColorLabel::Green => "Green",
+            ColorLabel::Blue => "Blue",
+            ColorLabel::Purple => "Purple",
+        }
+    }
+
+    /// The label called `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<ColorLabel> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.name().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Flag {
+    #[default]
+    Unflagged,
+    Pick,
+    Reject,
+}
+
+/// Culling marks of an image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Marks {
+    /// Stars, 0 to [`Marks::MAX_RATING`]; 0 is unrated.
+    pub rating: u8,
+    pub label: Option<ColorLabel>,
+    pub flag: Flag,
+}
+
+/// A change to one of the [`Marks`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    Rating(u8),
+    Label(Option<ColorLabel>),
+    Flag(Flag),
+}
+
+impl Marks {
+    pub const MAX_RATING: u8 = 5;
+
+    pub fn with(self, mark: Mark) -> Marks {
+        match mark {
+            Mark::Rating(x) => Marks {
+                rating: x.min(Self::MAX_RATING),
+                ..self
+            },
+            Mark::Label(label) => Marks { label, ..self },
+            Mark::Flag(flag) => Marks { flag, ..self },
+        }
+    }
+
+    /// Value of `xmp:Rating`, where -1 means rejected.
+    pub fn xmp_rating(self) -> i32 {
+        match self.flag {
+            Flag::Reject => -1,
+            _ => self.rating as i32,
+        }
+    }
 }
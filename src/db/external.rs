@@ -0,0 +1,1014 @@
+//! Marks, tags, captions and positions kept by other software: XMP sidecars, and the
+//! catalogs of darktable (`library.db`), digiKam (`digikam4.db`) and
+//! Lightroom Classic (`.lrcat`).
+//!
+//! A source is read into [`Record`]s, which [`plan`] merges with the
+//! catalog. The plan lists the changes and the conflicts, so it can be
+//! shown as a dry run before [`apply`] writes it, noting the changed
+//! fields as pending for [`super::sync`].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use rusqlite::{Connection, OpenFlags};
+
+use super::datastore::{ColorLabel, Datastore, Fields, Flag, Image, Marks, Pending};
+use super::import;
+use super::keywords::{self, Keyword, SEPARATOR};
+use crate::loader::meta::{Gps, Metadata};
+
+/// What another program knows about an image.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    /// The image file, canonical if it exists.
+    pub path: PathBuf,
+    pub marks: Marks,
+    /// Keyword paths.
+    pub tags: Vec<String>,
+    pub title: String,
+    pub description: String,
+    pub gps: Option<Gps>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// `a.xmp` or darktable's `a.jpg.xmp` next to the images of a folder.
+    Sidecars,
+    Darktable,
+    Digikam,
+    Lightroom,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Sidecars => "XMP sidecars",
+            Source::Darktable => "darktable",
+            Source::Digikam => "digiKam",
+            Source::Lightroom => "Lightroom",
+        }
+    }
+
+    /// The source at `path`: a folder of sidecars, or a catalog told apart
+    /// by its tables.
+    pub fn detect(path: &Path) -> Result<Source> {
+        if path.is_dir() {
+            return Ok(Source::Sidecars);
+        }
+        let conn = open(path)?;
+        for (table, source) in [
+            ("film_rolls", Source::Darktable),
+            ("AlbumRoots", Source::Digikam),
+            ("Adobe_images", Source::Lightroom),
+        ] {
+            if !columns(&conn, table)?.is_empty() {
+                return Ok(source);
+            }
+        }
+        Err(anyhow!(
+            "{}: not a folder, or a darktable, digiKam or Lightroom catalog",
+            path.to_string_lossy()
+        ))
+    }
+
+    /// Read the records of the source at `path`, in path order. `keywords`
+    /// tells synonyms in sidecars from tags.
+    pub fn read(self, path: &Path, keywords: &[Keyword]) -> Result<Vec<Record>> {
+        let mut records = match self {
+            Source::Sidecars => read_sidecars(path, keywords)?,
+            Source::Darktable => read_darktable(path)?,
+            Source::Digikam => read_digikam(path)?,
+            Source::Lightroom => read_lightroom(path)?,
+        };
+        for record in &mut records {
+            if let Ok(path) = record.path.canonicalize() {
+                record.path = path;
+            }
+            record.tags.retain(|x| !internal_tag(x));
+        }
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(records)
+    }
+}
+
+/// Root of the tags digiKam keeps for itself, its labels among them.
+const DIGIKAM_INTERNAL: &str = "_Digikam_Internal_Tags_";
+
+/// Tags programs keep for themselves, like darktable's `darktable|format|nef`.
+fn internal_tag(tag: &str) -> bool {
+    ["darktable", DIGIKAM_INTERNAL]
+        .iter()
+        .any(|x| keywords::is_under(tag, x))
+}
+
+fn open(path: &Path) -> Result<Connection> {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| anyhow!("{}: {}", path.to_string_lossy(), e))
+}
+
+/// Columns of `table`, none if there is no such table.
+fn columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    let names = stmt
+        .query_map([table], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(names)
+}
+
+/// Keyword paths of a tree stored as id, parent and name rows. Nodes
+/// without a name, like Lightroom's root, are left out of the paths.
+fn tree_paths(nodes: &HashMap<i64, (Option<i64>, Option<String>)>) -> HashMap<i64, String> {
+    let mut paths = HashMap::new();
+    for &id in nodes.keys() {
+        let mut names = vec![];
+        let mut next = Some(id);
+        // Bounded, in case of a cycle.
+        while let Some((parent, name)) = next.and_then(|x| nodes.get(&x)) {
+            if names.len() > nodes.len() {
+                break;
+            }
+            if let Some(name) = name {
+                names.push(name.replace(SEPARATOR, "/"));
+            }
+            next = *parent;
+        }
+        names.reverse();
+        if let Some(path) = keywords::normalize(&names.join(&SEPARATOR.to_string())) {
+            paths.insert(id, path);
+        }
+    }
+    paths
+}
+
+/// Position from nullable columns; catalogs without one store null.
+fn position(latitude: Option<f64>, longitude: Option<f64>, altitude: Option<f64>) -> Option<Gps> {
+    Some(Gps {
+        latitude: latitude?,
+        longitude: longitude?,
+        altitude: altitude.unwrap_or(0.),
+    })
+}
+
+/// Color labels numbered as by darktable.
+fn darktable_label(x: i64) -> Option<ColorLabel> {
+    ColorLabel::ALL.get(usize::try_from(x).ok()?).copied()
+}
+
+/// Color labels numbered as by digiKam, which also has orange and greys.
+fn digikam_label(x: i64) -> Option<ColorLabel> {
+    match x {
+        1 => Some(ColorLabel::Red),
+        3 => Some(ColorLabel::Yellow),
+        4 => Some(ColorLabel::Green),
+        5 => Some(ColorLabel::Blue),
+        6 => Some(ColorLabel::Purple),
+        _ => None,
+    }
+}
+
+/// digiKam pick labels: rejected, pending and accepted.
+fn digikam_flag(x: i64) -> Flag {
+    match x {
+        1 => Flag::Reject,
+        3 => Flag::Pick,
+        _ => Flag::Unflagged,
+    }
+}
+
+/// Set the label named by a digiKam internal tag, like `Color Label Red`
+/// or `Pick Label Accepted`, which is how its catalog keeps labels.
+fn digikam_tag_label(name: &str, marks: &mut Marks) {
+    if let Some(color) = name.strip_prefix("Color Label ") {
+        let label = match color {
+            "Magenta" => Some(ColorLabel::Purple),
+            x => ColorLabel::from_name(x),
+        };
+        if label.is_some() {
+            marks.label = label;
+        }
+    } else if let Some(pick) = name.strip_prefix("Pick Label ") {
+        match pick {
+            "Rejected" => marks.flag = Flag::Reject,
+            "Accepted" => marks.flag = Flag::Pick,
+            _ => {}
+        }
+    }
+}
+
+fn read_sidecars(folder: &Path, keywords: &[Keyword]) -> Result<Vec<Record>> {
+    let root = folder
+        .canonicalize()
+        .map_err(|e| anyhow!("{}: {}", folder.to_string_lossy(), e))?;
+    let is_xmp = |x: &Path| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("xmp"));
+    let mut sidecars = vec![];
+    let mut files: HashSet<PathBuf> = HashSet::new();
+    // Images by path without the extension.
+    let mut images: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    for entry in walkdir::WalkDir::new(&root).sort_by_file_name() {
+        let entry = match entry {
+            Ok(x) if x.file_type().is_file() => x,
+            Ok(_) => continue,
+            Err(e) => {
+                log::warn!("{}", e);
+                continue;
+            }
+        };
+        let path = entry.into_path();
+        if is_xmp(&path) {
+            sidecars.push(path);
+        } else {
+            images
+                .entry(path.with_extension(""))
+                .or_default()
+                .push(path.clone());
+            files.insert(path);
+        }
+    }
+    let mut records = vec![];
+    for sidecar in sidecars {
+        let base = sidecar.with_extension("");
+        // darktable's `a.jpg.xmp` is of `a.jpg`; `a.xmp` of every `a.*`.
+        let owners = if files.contains(&base) {
+            vec![base.clone()]
+        } else {
+            images.get(&base).cloned().unwrap_or_default()
+        };
+        if owners.is_empty() {
+            log::info!("{}: no image", sidecar.to_string_lossy());
+            continue;
+        }
+        let meta = match Metadata::read(&sidecar) {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!("{}: {}", sidecar.to_string_lossy(), e);
+                continue;
+            }
+        };
+        let record = sidecar_record(&meta, keywords);
+        records.extend(owners.into_iter().map(|path| Record {
+            path,
+            ..record.clone()
+        }));
+    }
+    Ok(records)
+}
+
+/// Record of sidecar metadata, with the labels darktable and digiKam keep
+/// in their own XMP tags.
+fn sidecar_record(meta: &Metadata, keywords: &[Keyword]) -> Record {
+    let number = |tag: &str| {
+        meta.raw.get(tag).and_then(|x| {
+            x.split(|c: char| !c.is_ascii_digit())
+                .find(|x| !x.is_empty())?
+                .parse::<i64>()
+                .ok()
+        })
+    };
+    let mut marks = import::marks(meta);
+    if marks.label.is_none() {
+        marks.label = number("Xmp.darktable.colorlabels")
+            .and_then(darktable_label)
+            .or_else(|| number("Xmp.digiKam.ColorLabel").and_then(digikam_label));
+    }
+    if marks.flag == Flag::Unflagged {
+        marks.flag = number("Xmp.digiKam.PickLabel").map_or(Flag::Unflagged, digikam_flag);
+    }
+    Record {
+        path: PathBuf::new(),
+        marks,
+        tags: keywords::from_xmp(&meta.keywords, &meta.hierarchical_keywords, keywords),
+        title: import::title(meta),
+        description: import::description(meta),
+        gps: meta.gps,
+    }
+}
+
+fn read_darktable(library: &Path) -> Result<Vec<Record>> {
+    let conn = open(library)?;
+    let mut records: BTreeMap<i64, Record> = BTreeMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT images.id, folder, filename, flags FROM images
+         JOIN film_rolls ON film_rolls.id = film_id",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let (folder, file): (String, String) = (row.get(1)?, row.get(2)?);
+        let flags: i64 = row.get(3)?;
+        // The low bits are the stars, 6 being the old way to reject.
+        let rejected = flags & 7 == 6 || flags & 8 != 0;
+        let marks = Marks {
+            rating: if rejected {
+                0
+            } else {
+                (flags & 7).min(5) as u8
+            },
+            flag: if rejected {
+                Flag::Reject
+            } else {
+                Flag::Unflagged
+            },
+            label: None,
+        };
+        let record = Record {
+            path: Path::new(&folder).join(file),
+            marks,
+            ..Default::default()
+        };
+        records.insert(row.get(0)?, record);
+    }
+
+    // Positions, in versions that have them.
+    let images = columns(&conn, "images")?;
+    if images.iter().any(|x| x == "latitude") {
+        let altitude = if images.iter().any(|x| x == "altitude") {
+            "altitude"
+        } else {
+            "NULL"
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, latitude, longitude, {} FROM images",
+            altitude
+        ))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(record) = records.get_mut(&row.get::<_, i64>(0)?) {
+                record.gps = position(row.get(1)?, row.get(2)?, row.get(3)?);
+            }
+        }
+    }
+
+    let mut stmt = conn.prepare("SELECT imgid, color FROM color_labels ORDER BY imgid, color")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let Some(record) = records.get_mut(&row.get::<_, i64>(0)?) {
+            if record.marks.label.is_none() {
+                record.marks.label = darktable_label(row.get(1)?);
+            }
+        }
+    }
+
+    // Titles and descriptions.
+    let mut stmt = conn.prepare("SELECT id, key, value FROM meta_data WHERE key IN (2, 3)")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let Some(record) = records.get_mut(&row.get::<_, i64>(0)?) {
+            let value = row.get::<_, Option<String>>(2)?.unwrap_or_default();
+            match row.get::<_, i64>(1)? {
+                2 => record.title = value,
+                _ => record.description = value,
+            }
+        }
+    }
+
+    // Tag names live in `data.db` next to the library; old versions kept
+    // them in the library.
+    let data = library.with_file_name("data.db");
+    let data = if data.is_file() {
+        open(&data)?
+    } else {
+        open(library)?
+    };
+    let mut names: HashMap<i64, String> = HashMap::new();
+    if columns(&data, "tags")?.is_empty() {
+        log::warn!(
+            "{}: no tag names, tags are not read",
+            library.to_string_lossy()
+        );
+    } else {
+        let mut stmt = data.prepare("SELECT id, name FROM tags")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(name) = keywords::normalize(&row.get::<_, String>(1)?) {
+                names.insert(row.get(0)?, name);
+            }
+        }
+    }
+    let mut stmt = conn.prepare("SELECT imgid, tagid FROM tagged_images ORDER BY imgid")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let (Some(record), Some(name)) = (
+            records.get_mut(&row.get::<_, i64>(0)?),
+            names.get(&row.get::<_, i64>(1)?),
+        ) {
+            record.tags.push(name.clone());
+        }
+    }
+    Ok(records.into_values().collect())
+}
+
+/// Decode `%XX` escapes.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = text
+            .get(i + 1..i + 3)
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(x)) => {
+                out.push(x);
+                i += 3;
+            }
+            (x, _) => {
+                out.push(x);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Where the filesystem with `uuid` is mounted.
+fn mount_point(uuid: &str) -> Option<PathBuf> {
+    let device = Path::new("/dev/disk/by-uuid")
+        .join(uuid)
+        .canonicalize()
+        .ok()?;
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    mounts.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let (dev, dir) = (fields.next()?, fields.next()?);
+        (Path::new(dev).canonicalize().ok()? == device)
+            // Spaces are written as octal escapes.
+            .then(|| PathBuf::from(dir.replace("\\040", " ")))
+    })
+}
+
+/// Folder of a digiKam album root, identified as `volumeid:?path=…`, or as
+/// `volumeid:?uuid=…` with `specific` under the volume's mount point.
+fn digikam_root(identifier: &str, specific: &str) -> PathBuf {
+    let query = identifier.split_once('?').map_or("", |x| x.1);
+    for (key, value) in query.split('&').filter_map(|x| x.split_once('=')) {
+        match key {
+            "path" => return PathBuf::from(percent_decode(value)),
+            "uuid" => match mount_point(&percent_decode(value)) {
+                Some(mount) => return mount.join(specific.trim_start_matches('/')),
+                None => log::warn!("digiKam volume {} is not mounted", value),
+            },
+            _ => {}
+        }
+    }
+    PathBuf::from(specific)
+}
+
+fn read_digikam(db: &Path) -> Result<Vec<Record>> {
+    let conn = open(db)?;
+    let mut records: BTreeMap<i64, Record> = BTreeMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT Images.id, identifier, specificPath, relativePath, Images.name, rating
+         FROM Images
+         JOIN Albums ON Albums.id = album
+         JOIN AlbumRoots ON AlbumRoots.id = albumRoot
+         LEFT JOIN ImageInformation ON imageid = Images.id",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut roots: HashMap<(String, String), PathBuf> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let identifier: String = row.get(1)?;
+        let specific = row.get::<_, Option<String>>(2)?.unwrap_or_default();
+        let root = roots
+            .entry((identifier.clone(), specific.clone()))
+            .or_insert_with(|| digikam_root(&identifier, &specific));
+        let (album, name): (String, String) = (row.get(3)?, row.get(4)?);
+        let rating: Option<i64> = row.get(5)?;
+        let marks = Marks {
+            // -1 is unrated.
+            rating: rating.unwrap_or(0).clamp(0, Marks::MAX_RATING as i64) as u8,
+            ..Default::default()
+        };
+        let record = Record {
+            path: root.join(album.trim_start_matches('/')).join(name),
+            marks,
+            ..Default::default()
+        };
+        records.insert(row.get(0)?, record);
+    }
+
+    let mut stmt = conn.prepare("SELECT id, pid, name FROM Tags")?;
+    let nodes = stmt
+        .query_map([], |r| Ok((r.get(0)?, (r.get(1)?, r.get(2)?))))?
+        .collect::<rusqlite::Result<HashMap<i64, _>>>()?;
+    let paths = tree_paths(&nodes);
+    let mut stmt = conn.prepare("SELECT imageid, tagid FROM ImageTags ORDER BY imageid")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let (Some(record), Some(path)) = (
+            records.get_mut(&row.get::<_, i64>(0)?),
+            paths.get(&row.get::<_, i64>(1)?),
+        ) {
+            if keywords::is_under(path, DIGIKAM_INTERNAL) {
+                digikam_tag_label(keywords::name(path), &mut record.marks);
+            } else {
+                record.tags.push(path.clone());
+            }
+        }
+    }
+
+    // Comments of type 1 are descriptions and 3 titles; the default
+    // language is read last, to win.
+    let mut stmt = conn.prepare(
+        "SELECT imageid, type, comment FROM ImageComments WHERE type IN (1, 3)
+         ORDER BY language = 'x-default'",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let Some(record) = records.get_mut(&row.get::<_, i64>(0)?) {
+            let comment: String = row.get::<_, Option<String>>(2)?.unwrap_or_default();
+            match row.get::<_, i64>(1)? {
+                3 => record.title = comment,
+                _ => record.description = comment,
+            }
+        }
+    }
+
+    let mut stmt = conn
+        .prepare("SELECT imageid, latitudeNumber, longitudeNumber, altitude FROM ImagePositions")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let Some(record) = records.get_mut(&row.get::<_, i64>(0)?) {
+            record.gps = position(row.get(1)?, row.get(2)?, row.get(3)?);
+        }
+    }
+    Ok(records.into_values().collect())
+}
+
+fn read_lightroom(catalog: &Path) -> Result<Vec<Record>> {
+    let conn = open(catalog)?;
+    let mut records: BTreeMap<i64, Record> = BTreeMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT Adobe_images.id_local, absolutePath, pathFromRoot, baseName, extension,
+                rating, colorLabels, pick
+         FROM Adobe_images
+         JOIN AgLibraryFile ON AgLibraryFile.id_local = rootFile
+         JOIN AgLibraryFolder ON AgLibraryFolder.id_local = folder
+         JOIN AgLibraryRootFolder ON AgLibraryRootFolder.id_local = rootFolder",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        // Folders end with a slash.
+        let path = format!(
+            "{}{}{}.{}",
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?
+        );
+        let pick = row.get::<_, Option<f64>>(7)?.unwrap_or(0.);
+        let marks = Marks {
+            rating: row
+                .get::<_, Option<f64>>(5)?
+                .unwrap_or(0.)
+                .clamp(0., Marks::MAX_RATING as f64) as u8,
+            label: row
+                .get::<_, Option<String>>(6)?
+                .and_then(|x| ColorLabel::from_name(&x)),
+            flag: if pick < 0. {
+                Flag::Reject
+            } else if pick > 0. {
+                Flag::Pick
+            } else {
+                Flag::Unflagged
+            },
+        };
+        let record = Record {
+            path: PathBuf::from(path),
+            marks,
+            ..Default::default()
+        };
+        records.insert(row.get(0)?, record);
+    }
+
+    let mut stmt = conn.prepare("SELECT id_local, parent, name FROM AgLibraryKeyword")?;
+    let nodes = stmt
+        .query_map([], |r| Ok((r.get(0)?, (r.get(1)?, r.get(2)?))))?
+        .collect::<rusqlite::Result<HashMap<i64, _>>>()?;
+    let paths = tree_paths(&nodes);
+    let mut stmt = conn.prepare("SELECT image, tag FROM AgLibraryKeywordImage ORDER BY image")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let (Some(record), Some(path)) = (
+            records.get_mut(&row.get::<_, i64>(0)?),
+            paths.get(&row.get::<_, i64>(1)?),
+        ) {
+            record.tags.push(path.clone());
+        }
+    }
+
+    // Captions, and titles in catalogs that have them.
+    let iptc = columns(&conn, "AgLibraryIPTC")?;
+    let has = |x: &str| iptc.iter().any(|c| c == x);
+    if has("caption") {
+        let title = if has("title") { "title" } else { "NULL" };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT image, {}, caption FROM AgLibraryIPTC",
+            title
+        ))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(record) = records.get_mut(&row.get::<_, i64>(0)?) {
+                record.title = row.get::<_, Option<String>>(1)?.unwrap_or_default();
+                record.description = row.get::<_, Option<String>>(2)?.unwrap_or_default();
+            }
+        }
+    }
+
+    // Lightroom keeps no altitude in the catalog.
+    let mut stmt = conn.prepare(
+        "SELECT image, gpsLatitude, gpsLongitude FROM AgHarvestedExifMetadata WHERE hasGPS",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if let Some(record) = records.get_mut(&row.get::<_, i64>(0)?) {
+            record.gps = position(row.get(1)?, row.get(2)?, None);
+        }
+    }
+    Ok(records.into_values().collect())
+}
+
+/// A field set differently in the catalog and the other program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    pub path: String,
+    pub field: &'static str,
+    pub catalog: String,
+    pub other: String,
+}
+
+/// Changes to the catalog from a source.
+#[derive(Clone, Debug, Default)]
+pub struct Plan {
+    /// Merged catalog records of the images that change, with the fields
+    /// that change.
+    pub updates: Vec<Pending>,
+    pub conflicts: Vec<Conflict>,
+    /// Images of the source that are not in the catalog.
+    pub missing: Vec<PathBuf>,
+    pub unchanged: usize,
+}
+
+/// Merges fields of one image, noting conflicts.
+struct Merge<'a> {
+    path: &'a str,
+    overwrite: bool,
+    conflicts: &'a mut Vec<Conflict>,
+}
+
+impl Merge<'_> {
+    /// Take `other` where `current` is unset, or where they differ if
+    /// overwriting.
+    fn field<T: PartialEq + Default>(
+        &mut self,
+        field: &'static str,
+        current: &mut T,
+        other: T,
+        show: impl Fn(&T) -> String,
+    ) {
+        if other == T::default() || other == *current {
+            return;
+        }
+        if *current != T::default() {
+            self.conflicts.push(Conflict {
+                path: self.path.to_owned(),
+                field,
+                catalog: show(current),
+                other: show(&other),
+            });
+            if !self.overwrite {
+                return;
+            }
+        }
+        *current = other;
+    }
+}
+
+fn show_flag(flag: &Flag) -> String {
+    match flag {
+        Flag::Unflagged => "none",
+        Flag::Pick => "pick",
+        Flag::Reject => "reject",
+    }
+    .to_owned()
+}
+
+/// Merge `records` with the catalog images at their paths. Tags are added;
+/// marks, captions and positions fill what the catalog lacks. Where both have a
+/// value and they differ, the catalog's is kept unless `overwrite`, and
+/// the conflict is listed.
+pub async fn plan(store: &dyn Datastore, records: Vec<Record>, overwrite: bool) -> Result<Plan> {
+    let mut plan = Plan::default();
+    // Merged so far, with the catalog record, as a path may come twice.
+    let mut merged: BTreeMap<String, (Image, Image)> = BTreeMap::new();
+    for record in records {
+        let path = record.path.to_string_lossy().into_owned();
+        if !merged.contains_key(&path) {
+            match store.get_image(&path).await? {
+                Some(image) => {
+                    merged.insert(path.clone(), (image.clone(), image));
+                }
+                None => {
+                    plan.missing.push(record.path);
+                    continue;
+                }
+            }
+        }
+        let (_, image) = merged.get_mut(&path).unwrap();
+        let mut merge = Merge {
+            path: &path,
+            overwrite,
+            conflicts: &mut plan.conflicts,
+        };
+        let marks = &mut image.marks;
+        merge.field("rating", &mut marks.rating, record.marks.rating, |x| {
+            x.to_string()
+        });
+        merge.field("label", &mut marks.label, record.marks.label, |x| {
+            x.map_or("none", ColorLabel::name).to_owned()
+        });
+        merge.field("flag", &mut marks.flag, record.marks.flag, show_flag);
+        merge.field("title", &mut image.title, record.title, |x| {
+            format!("{:?}", x)
+        });
+        merge.field(
+            "description",
+            &mut image.description,
+            record.description,
+            |x| format!("{:?}", x),
+        );
+        merge.field("position", &mut image.gps, record.gps, |x| match x {
+            Some(x) => format!("{:.6}, {:.6}", x.latitude, x.longitude),
+            None => "none".to_owned(),
+        });
+        for tag in record.tags {
+            if !image.tags.contains(&tag) {
+                image.tags.push(tag);
+            }
+        }
+    }
+    for (old, new) in merged.into_values() {
+        let fields = Fields::changed(&old, &new);
+        if old == new {
+            plan.unchanged += 1;
+        } else {
+            plan.updates.push(Pending { image: new, fields });
+        }
+    }
+    Ok(plan)
+}
+
+/// Write the updates of `plan` to the catalog.
+pub async fn apply(store: &dyn Datastore, plan: &Plan) -> Result<()> {
+    for update in &plan.updates {
+        store.put_image(&update.image).await?;
+        store.add_pending(&update.image.path, update.fields).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tables of digiKam's `digikam4.db` that are read, with their
+    /// columns as digiKam creates them.
+    const DIGIKAM_SCHEMA: &str = "
+        CREATE TABLE AlbumRoots (id INTEGER PRIMARY KEY, label TEXT,
+            status INTEGER NOT NULL, type INTEGER NOT NULL, identifier TEXT,
+            specificPath TEXT);
+        CREATE TABLE Albums (id INTEGER PRIMARY KEY, albumRoot INTEGER NOT NULL,
+            relativePath TEXT NOT NULL, date DATE, caption TEXT, collection TEXT,
+            icon INTEGER);
+        CREATE TABLE Images (id INTEGER PRIMARY KEY, album INTEGER,
+            name TEXT NOT NULL, status INTEGER NOT NULL, category INTEGER NOT NULL,
+            modificationDate DATETIME, fileSize INTEGER, uniqueHash TEXT,
+            manualOrder INTEGER);
+        CREATE TABLE ImageInformation (imageid INTEGER PRIMARY KEY, rating INTEGER,
+            creationDate DATETIME, digitizationDate DATETIME, orientation INTEGER,
+            width INTEGER, height INTEGER, format TEXT, colorDepth INTEGER,
+            colorModel INTEGER);
+        CREATE TABLE Tags (id INTEGER PRIMARY KEY, pid INTEGER, name TEXT NOT NULL,
+            icon INTEGER, iconkde TEXT);
+        CREATE TABLE ImageTags (imageid INTEGER NOT NULL, tagid INTEGER NOT NULL);
+        CREATE TABLE ImageComments (id INTEGER PRIMARY KEY, imageid INTEGER,
+            type INTEGER, language TEXT, author TEXT, date DATETIME, comment TEXT);
+        CREATE TABLE ImagePositions (imageid INTEGER PRIMARY KEY, latitude TEXT,
+            latitudeNumber REAL, longitude TEXT, longitudeNumber REAL, altitude REAL,
+            orientation REAL, tilt REAL, roll REAL, accuracy REAL, description TEXT);
+    ";
+
+    /// The tables of darktable's `library.db` and `data.db` that are read.
+    const DARKTABLE_SCHEMA: &str = "
+        CREATE TABLE film_rolls (id INTEGER PRIMARY KEY, access_timestamp INTEGER,
+            folder VARCHAR(1024) NOT NULL);
+        CREATE TABLE images (id INTEGER PRIMARY KEY, film_id INTEGER, width INTEGER,
+            height INTEGER, filename VARCHAR, flags INTEGER, latitude REAL,
+            longitude REAL, altitude REAL);
+        CREATE TABLE color_labels (imgid INTEGER, color INTEGER);
+        CREATE TABLE meta_data (id INTEGER, key INTEGER, value VARCHAR);
+        CREATE TABLE tagged_images (imgid INTEGER, tagid INTEGER, position INTEGER);
+    ";
+
+    fn record(path: &str, rating: u8, label: Option<ColorLabel>, flag: Flag) -> Record {
+        Record {
+            path: PathBuf::from(path),
+            marks: Marks {
+                rating,
+                label,
+                flag,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn darktable() {
+        let dir = std::env::temp_dir().join(format!("phany-darktable-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("library.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(DARKTABLE_SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO film_rolls VALUES (1, 0, '/photos/2024');
+             INSERT INTO images (id, film_id, filename, flags, latitude, longitude) VALUES
+                 (1, 1, 'a.nef', 3, 52.5, 13.25),
+                 (2, 1, 'b.nef', 6, NULL, NULL),
+                 (3, 1, 'c.nef', 12, NULL, NULL),
+                 (4, 1, 'd.nef', 5 | 16, NULL, NULL);
+             INSERT INTO color_labels VALUES (1, 2), (1, 0), (4, 7);
+             INSERT INTO meta_data VALUES (1, 0, 'me'), (1, 2, 'Tower'), (1, 3, 'At night');
+             INSERT INTO tagged_images VALUES (1, 1, 0), (1, 2, 0), (2, 2, 0);",
+        )
+        .unwrap();
+        drop(conn);
+        let conn = Connection::open(dir.join("data.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tags (id INTEGER PRIMARY KEY, name VARCHAR);
+             INSERT INTO tags VALUES (1, 'darktable|format|nef'), (2, 'Places|Berlin');",
+        )
+        .unwrap();
+        drop(conn);
+
+        assert_eq!(Source::detect(&path).unwrap(), Source::Darktable);
+        let records = Source::Darktable.read(&path, &[]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let berlin = vec!["Places|Berlin".to_owned()];
+        assert_eq!(
+            records,
+            vec![
+                Record {
+                    tags: berlin.clone(),
+                    title: "Tower".to_owned(),
+                    description: "At night".to_owned(),
+                    gps: Some(Gps {
+                        latitude: 52.5,
+                        longitude: 13.25,
+                        altitude: 0.,
+                    }),
+                    ..record(
+                        "/photos/2024/a.nef",
+                        3,
+                        Some(ColorLabel::Red),
+                        Flag::Unflagged
+                    )
+                },
+                // Six stars is the old reject, and bit 8 the new one.
+                Record {
+                    tags: berlin,
+                    ..record("/photos/2024/b.nef", 0, None, Flag::Reject)
+                },
+                record("/photos/2024/c.nef", 0, None, Flag::Reject),
+                record("/photos/2024/d.nef", 5, None, Flag::Unflagged),
+            ]
+        );
+    }
+
+    #[test]
+    fn sidecar_labels() {
+        let mut meta = Metadata::default();
+        meta.raw
+            .insert("Xmp.darktable.colorlabels".into(), "1, 3".into());
+        meta.raw.insert("Xmp.digiKam.ColorLabel".into(), "6".into());
+        meta.raw.insert("Xmp.digiKam.PickLabel".into(), "1".into());
+        let record = sidecar_record(&meta, &[]);
+        assert_eq!(record.marks.label, Some(ColorLabel::Yellow));
+        assert_eq!(record.marks.flag, Flag::Reject);
+
+        meta.raw.remove("Xmp.darktable.colorlabels");
+        assert_eq!(
+            sidecar_record(&meta, &[]).marks.label,
+            Some(ColorLabel::Purple)
+        );
+        // The standard tags win over the programs' own.
+        meta.label = Some("Green".into());
+        meta.rating = Some(2);
+        meta.raw.insert("Xmp.digiKam.PickLabel".into(), "3".into());
+        let record = sidecar_record(&meta, &[]);
+        assert_eq!(record.marks.label, Some(ColorLabel::Green));
+        assert_eq!(record.marks.rating, 2);
+        assert_eq!(record.marks.flag, Flag::Pick);
+    }
+
+    #[test]
+    fn sidecar_owners() {
+        let dir = std::env::temp_dir().join(format!("phany-sidecars-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "a.jpg",
+            "a.nef",
+            "a.xmp",
+            "b.jpg",
+            "b.nef",
+            "b.jpg.xmp",
+            "c.xmp",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(Source::detect(&dir).unwrap(), Source::Sidecars);
+        let records = Source::Sidecars.read(&dir, &[]).unwrap();
+        let root = dir.canonicalize().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // `a.xmp` is of every `a.*`, `b.jpg.xmp` of `b.jpg` alone, and
+        // `c.xmp` of nothing.
+        let paths: Vec<PathBuf> = records.into_iter().map(|x| x.path).collect();
+        assert_eq!(
+            paths,
+            [root.join("a.jpg"), root.join("a.nef"), root.join("b.jpg")]
+        );
+    }
+
+    #[test]
+    fn internal_tags() {
+        assert!(internal_tag("darktable|format|nef"));
+        assert!(internal_tag("darktable"));
+        assert!(internal_tag("_Digikam_Internal_Tags_|Color Label Red"));
+        assert!(!internal_tag("darktables"));
+        assert!(!internal_tag("Places|darktable"));
+    }
+
+    #[test]
+    fn digikam() {
+        let path = std::env::temp_dir().join(format!("phany-digikam-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(DIGIKAM_SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO AlbumRoots VALUES (1, 'Photos', 0, 1, 'volumeid:?path=/photos', '/');
+             INSERT INTO Albums VALUES (1, 1, '/2024', NULL, NULL, NULL, NULL);
+             INSERT INTO Images VALUES (1, 1, 'a.jpg', 1, 1, NULL, NULL, NULL, NULL);
+             INSERT INTO Images VALUES (2, 1, 'b.jpg', 1, 1, NULL, NULL, NULL, NULL);
+             INSERT INTO ImageInformation (imageid, rating) VALUES (1, 4), (2, -1);
+             INSERT INTO Tags (id, pid, name) VALUES
+                 (1, 0, '_Digikam_Internal_Tags_'),
+                 (2, 1, 'Color Label Red'),
+                 (3, 1, 'Color Label Magenta'),
+                 (4, 1, 'Pick Label Accepted'),
+                 (5, 1, 'Pick Label Rejected'),
+                 (6, 0, 'People'),
+                 (7, 6, 'Alice');
+             INSERT INTO ImageTags VALUES (1, 2), (1, 4), (1, 7), (2, 3), (2, 5);
+             INSERT INTO ImageComments (imageid, type, language, comment)
+                 VALUES (2, 3, 'x-default', 'Harbor');
+             INSERT INTO ImagePositions (imageid, latitudeNumber, longitudeNumber, altitude)
+                 VALUES (1, 48.5, 2.25, 35);",
+        )
+        .unwrap();
+        drop(conn);
+
+        assert_eq!(Source::detect(&path).unwrap(), Source::Digikam);
+        let records = Source::Digikam.read(&path, &[]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                Record {
+                    path: PathBuf::from("/photos/2024/a.jpg"),
+                    marks: Marks {
+                        rating: 4,
+                        label: Some(ColorLabel::Red),
+                        flag: Flag::Pick,
+                    },
+                    tags: vec!["People|Alice".to_owned()],
+                    gps: Some(Gps {
+                        latitude: 48.5,
+                        longitude: 2.25,
+                        altitude: 35.,
+                    }),
+                    ..Default::default()
+                },
+                Record {
+                    path: PathBuf::from("/photos/2024/b.jpg"),
+                    marks: Marks {
+                        rating: 0,
+                        label: Some(ColorLabel::Purple),
+                        flag: Flag::Reject,
+                    },
+                    title: "Harbor".to_owned(),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,803 @@
+//! Import a directory tree into a catalog.
+//!
+//! Files are recognised by their magic bytes. The size, mtime and a content
+//! fingerprint of each imported file are kept in the store, so a re-scan
+//! only reads files that changed, and drops images whose file is gone.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{anyhow, Result};
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+use super::datastore::{Collection, ColorLabel, Datastore, Flag, Image, Marks, Shot, SyncPolicy};
+use super::keywords::{self, Keyword};
+use crate::loader::magic::sniff_file;
+use crate::loader::meta::Metadata;
+use crate::loader::read_dimensions;
+
+/// Stamps are written back after this many changes, so that a cancelled
+/// import does not redo its work.
+const SAVE_EVERY: usize = 500;
+
+#[derive(Clone, Debug, Default)]
+pub struct ImportOptions {
+    /// Name of the collection; defaults to the folder name. A folder
+    /// imported before keeps its collection.
+    pub collection: Option<String>,
+    /// Globs files must match to be imported; empty imports all.
+    /// Globs are matched against the path relative to the folder and
+    /// against the file name.
+    pub include: Vec<String>,
+    /// Globs of files and directories to skip.
+    pub exclude: Vec<String>,
+    /// Read every file, even those unchanged since the last import.
+    pub full: bool,
+}
+
+/// Counts so far, sent after each file.
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    /// Files looked at.
+    pub files: usize,
+    /// Files that are images.
+    pub images: usize,
+    /// Images added or updated.
+    pub imported: usize,
+    /// Images unchanged since the last import.
+    pub unchanged: usize,
+    /// Images removed because their file is gone.
+    pub removed: usize,
+    pub failed: usize,
+    pub current: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportReport {
+    pub collection: Collection,
+    pub progress: Progress,
+    pub cancelled: bool,
+}
+
+/// Stops an import from another task or thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// A token cancelled by the first Ctrl-C, for command line runs. A
+    /// second Ctrl-C ends the process as usual.
+    pub fn on_interrupt() -> CancelToken {
+        static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+        let token = TOKEN.get_or_init(CancelToken::new).clone();
+        #[cfg(unix)]
+        {
+            extern "C" fn interrupted(_: libc::c_int) {
+                // Only an atomic store, which is safe in a signal handler.
+                if let Some(token) = TOKEN.get() {
+                    token.cancel();
+                }
+            }
+            // SAFETY: the action is fully initialised and the handler is
+            // async-signal-safe.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = interrupted as extern "C" fn(libc::c_int) as usize;
+                action.sa_flags = libc::SA_RESETHAND;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut()) != 0 {
+                    log::warn!("cannot catch Ctrl-C: {}", std::io::Error::last_os_error());
+                }
+            }
+        }
+        token
+    }
+}
+
+/// Bytes hashed at each end of a file for its fingerprint.
+const FINGERPRINT_SPAN: u64 = 64 << 10;
+
+/// File size and mtime at the last import.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Stamp {
+    pub size: u64,
+    /// Nanoseconds since the epoch.
+    pub mtime: i64,
+    /// Content fingerprint, to follow files that are moved.
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+impl Stamp {
+    pub fn of(meta: &std::fs::Metadata) -> Stamp {
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |x| x.as_nanos() as i64);
+        Stamp {
+            size: meta.len(),
+            mtime,
+            hash: None,
+        }
+    }
+
+    /// Whether the file is the same as when `self` was taken.
+    pub fn unchanged(&self, now: &Stamp) -> bool {
+        self.size == now.size && self.mtime == now.mtime
+    }
+}
+
+/// Hash of the size and both ends of a file. Reading whole RAW files would
+/// make imports of large archives far too slow; the ends hold the headers
+/// and differ between any two photos.
+pub(crate) fn fingerprint(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&size.to_le_bytes());
+    let mut buf = vec![0; FINGERPRINT_SPAN.min(size) as usize];
+    file.read_exact(&mut buf)?;
+    hasher.update(&buf);
+    if size > FINGERPRINT_SPAN {
+        let tail = FINGERPRINT_SPAN.min(size - FINGERPRINT_SPAN);
+        file.seek(SeekFrom::End(-(tail as i64)))?;
+        buf.truncate(tail as usize);
+        file.read_exact(&mut buf)?;
+        hasher.update(&buf);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Key of the stamps of `collection` in the store.
+fn stamps_key(collection: &str) -> String {
+    format!("import/{}", collection)
+}
+
+/// Stamps of the files of `collection` by path, empty if there are none.
+pub(crate) async fn load_stamps(
+    store: &dyn Datastore,
+    collection: &str,
+) -> Result<HashMap<String, Stamp>> {
+    Ok(match store.get(&stamps_key(collection)).await? {
+        Some(x) => serde_json::from_slice(&x).unwrap_or_else(|e| {
+            log::warn!(
+                "{}: bad import stamps, reading all files: {}",
+                collection,
+                e
+            );
+            HashMap::new()
+        }),
+        None => HashMap::new(),
+    })
+}
+
+pub(crate) async fn save_stamps(
+    store: &dyn Datastore,
+    collection: &str,
+    stamps: &HashMap<String, Stamp>,
+) -> Result<()> {
+    store
+        .set(&stamps_key(collection), &serde_json::to_vec(stamps)?)
+        .await
+}
+
+/// What the walker found for one file.
+enum Scanned {
+    NotImage(PathBuf),
+    Unchanged(PathBuf),
+    Image(PathBuf, Stamp, Box<Image>),
+    Failed(PathBuf, String),
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for p in patterns {
+        set.add(Glob::new(p).map_err(|e| anyhow!("bad glob `{}`: {}", p, e))?);
+    }
+    Ok(set.build()?)
+}
+
+fn matches(set: &GlobSet, root: &Path, path: &Path) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    set.is_match(relative) || path.file_name().is_some_and(|x| set.is_match(x))
+}
+
+/// Description from the first caption tag present.
+pub(crate) fn description(meta: &Metadata) -> String {
+    first_text(
+        meta,
+        &[
+            "Exif.Image.ImageDescription",
+            "Iptc.Application2.Caption",
+            "Xmp.dc.description",
+        ],
+    )
+}
+
+/// Title from the first title tag present.
+pub(crate) fn title(meta: &Metadata) -> String {
+    first_text(meta, &["Xmp.dc.title", "Iptc.Application2.ObjectName"])
+}
+
+fn first_text(meta: &Metadata, tags: &[&str]) -> String {
+    tags.iter()
+        .filter_map(|t| meta.raw.get(*t))
+        // Language alternatives read as `lang="x-default" text`.
+        .map(|x| match x.strip_prefix("lang=\"") {
+            Some(rest) => rest.split_once("\" ").map_or("", |x| x.1),
+            None => x.as_str(),
+        })
+        .map(|x| x.trim().trim_matches('\0'))
+        .find(|x| !x.is_empty())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Marks stored in the file's `xmp:Rating` and `xmp:Label`.
+pub(crate) fn marks(meta: &Metadata) -> Marks {
+    let rating = meta.rating.unwrap_or(0);
+    Marks {
+        rating: rating.clamp(0, Marks::MAX_RATING as i32) as u8,
+        label: meta.label.as_deref().and_then(ColorLabel::from_name),
+        flag: if rating < 0 {
+            Flag::Reject
+        } else {
+            Flag::Unflagged
+        },
+    }
+}
+
+/// Capture settings of `meta`, as searched by [`super::query`].
+pub(crate) fn shot(meta: &Metadata) -> Shot {
+    let camera = match (&meta.make, &meta.model) {
+        // Models often start with the make: `Canon`, `Canon EOS R5`.
+        (Some(make), Some(model))
+            if make.split_whitespace().next().is_some_and(|x| {
+                model
+                    .get(..x.len())
+                    .is_some_and(|m| m.eq_ignore_ascii_case(x))
+            }) =>
+        {
+            Some(model.clone())
+        }
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.clone().or_else(|| model.clone()),
+    };
+    Shot {
+        camera,
+        lens: meta.lens.clone(),
+        iso: meta.iso,
+        // As shown, `f/2.8`, so that `aperture:2.8` finds it.
+        aperture: meta.aperture.map(|x| (x * 10.).round() / 10.),
+        exposure: meta
+            .exposure_time
+            .filter(|x| x.den != 0)
+            .map(|x| x.as_f64()),
+        focal_length: meta.focal_length,
+        taken: meta.date_taken.map(|x| {
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                x.year, x.month, x.day, x.hour, x.minute, x.second
+            )
+        }),
+    }
+}
+
+/// Read the catalog record of an image file. `keywords` tells synonyms
+/// from tags, see [`keywords::from_xmp`].
+pub(crate) fn read_image(path: &Path, collection: &str, keywords: &[Keyword]) -> Result<Image> {
+    let meta = Metadata::read(path).unwrap_or_else(|e| {
+        log::debug!("{}: no metadata: {}", path.to_string_lossy(), e);
+        Metadata::default()
+    });
+    let size = match meta.width.zip(meta.height) {
+        Some(x) => x,
+        None => {
+            let (w, h) = read_dimensions(path)?;
+            (w as u32, h as u32)
+        }
+    };
+    Ok(Image {
+        path: path.to_string_lossy().into_owned(),
+        collection: collection.to_owned(),
+        size,
+        tags: keywords::from_xmp(&meta.keywords, &meta.hierarchical_keywords, keywords),
+        title: title(&meta),
+        description: description(&meta),
+        marks: marks(&meta),
+        shot: shot(&meta),
+        gps: meta.gps,
+    })
+}
+
+/// What scanning a file checks it against.
+struct Known {
+    stamps: HashMap<String, Stamp>,
+    collection: String,
+    keywords: Vec<Keyword>,
+}
+
+/// Walk `root` on the calling thread, sending a result for each file.
+fn walk(
+    root: PathBuf,
+    include: GlobSet,
+    exclude: GlobSet,
+    known: Known,
+    cancel: CancelToken,
+    mut tx: mpsc::Sender<Scanned>,
+) {
+    let entries = walkdir::WalkDir::new(&root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !matches(&exclude, &root, e.path()));
+    for entry in entries {
+        if cancel.is_cancelled() {
+            return;
+        }
+        let scanned = match entry {
+            Err(e) => Scanned::Failed(
+                e.path().map(Path::to_owned).unwrap_or_else(|| root.clone()),
+                e.to_string(),
+            ),
+            Ok(entry) if !entry.file_type().is_file() => continue,
+            Ok(entry) => {
+                let path = entry.into_path();
+                if !include.is_empty() && !matches(&include, &root, &path) {
+                    continue;
+                }
+                scan_file(path, &known)
+            }
+        };
+        if futures::executor::block_on(tx.send(scanned)).is_err() {
+            return;
+        }
+    }
+}
+
+fn scan_file(path: PathBuf, known: &Known) -> Scanned {
+    let stamp = match std::fs::metadata(&path) {
+        Ok(x) => Stamp::of(&x),
+        Err(e) => return Scanned::Failed(path, e.to_string()),
+    };
+    if known
+        .stamps
+        .get(path.to_string_lossy().as_ref())
+        .is_some_and(|x| x.unchanged(&stamp))
+    {
+        return Scanned::Unchanged(path);
+    }
+    match sniff_file(&path) {
+        Ok(Some(format)) if format.is_supported() => {}
+        Ok(_) => return Scanned::NotImage(path),
+        Err(e) => return Scanned::Failed(path, e.to_string()),
+    }
+    let hash = match fingerprint(&path) {
+        Ok(x) => x,
+        Err(e) => return Scanned::Failed(path, e.to_string()),
+    };
+    match read_image(&path, &known.collection, &known.keywords) {
+        Ok(image) => Scanned::Image(
+            path,
+            Stamp {
+                hash: Some(hash),
+                ..stamp
+            },
+            Box::new(image),
+        ),
+        Err(e) => Scanned::Failed(path, e.to_string()),
+    }
+}
+
+/// Pick the collection for `folder`: the one it was imported into before,
+/// or a new one named `name` or after the folder.
+async fn collection_for(
+    store: &dyn Datastore,
+    folder: &str,
+    name: Option<&str>,
+) -> Result<Collection> {
+    let collections = store.get_collections().await?;
+    if let Some(c) = collections.iter().find(|c| c.folder == folder) {
+        if name.is_some_and(|x| x != c.name) {
+            log::warn!("{} is already imported as {}", folder, c.name);
+        }
+        return Ok(c.clone());
+    }
+    let taken = |x: &str| collections.iter().any(|c| c.name == x);
+    let name = match name {
+        Some(x) if taken(x) => return Err(anyhow!("collection {} already exists", x)),
+        Some(x) => x.to_owned(),
+        None => {
+            let base = Path::new(folder)
+                .file_name()
+                .map_or_else(|| folder.to_owned(), |x| x.to_string_lossy().into_owned());
+            (1..)
+                .map(|i| match i {
+                    1 => base.clone(),
+                    i => format!("{} ({})", base, i),
+                })
+                .find(|x| !taken(x))
+                .unwrap()
+        }
+    };
+    let collection = Collection {
+        folder: folder.to_owned(),
+        name,
+        sync: SyncPolicy::default(),
+    };
+    store.put_collection(&collection).await?;
+    Ok(collection)
+}
+
+/// Store an image read from its file. A known image keeps its tags,
+/// captions, marks and position; those found in the file are added.
+pub(crate) async fn store_image(store: &dyn Datastore, mut image: Image) -> Result<()> {
+    if let Some(old) = store.get_image(&image.path).await? {
+        for tag in old.tags {
+            if !image.tags.contains(&tag) {
+                image.tags.push(tag);
+            }
+        }
+        if image.title.is_empty() {
+            image.title = old.title;
+        }
+        if image.description.is_empty() {
+            image.description = old.description;
+        }
+        if image.gps.is_none() {
+            image.gps = old.gps;
+        }
+        // Pick flags have no XMP tag, so they live only in the catalog.
+        if image.marks.rating == 0 {
+            image.marks.rating = old.marks.rating;
+        }
+        if image.marks.label.is_none() {
+            image.marks.label = old.marks.label;
+        }
+        if image.marks.flag == Flag::Unflagged {
+            image.marks.flag = old.marks.flag;
+        }
+    }
+    store.put_image(&image).await
+}
+
+/// Import the images under `folder` into `store`, calling `progress` after
+/// each file.
+///
+/// Known images keep their tags, captions and marks, see [`store_image`].
+/// Reading files is done on a separate thread.
+pub async fn import(
+    store: &dyn Datastore,
+    folder: &Path,
+    options: &ImportOptions,
+    cancel: &CancelToken,
+    mut progress: impl FnMut(&Progress) + Send,
+) -> Result<ImportReport> {
+    let root = folder
+        .canonicalize()
+        .map_err(|e| anyhow!("{}: {}", folder.to_string_lossy(), e))?;
+    if !root.is_dir() {
+        return Err(anyhow!("{}: not a directory", folder.to_string_lossy()));
+    }
+    let include = glob_set(&options.include)?;
+    let exclude = glob_set(&options.exclude)?;
+    let collection = collection_for(
+        store,
+        &root.to_string_lossy(),
+        options.collection.as_deref(),
+    )
+    .await?;
+
+    let mut stamps = load_stamps(store, &collection.name).await?;
+    if options.full {
+        stamps.clear();
+    }
+    let known = Known {
+        stamps: stamps.clone(),
+        collection: collection.name.clone(),
+        keywords: store.get_keywords().await?,
+    };
+
+    let (tx, mut rx) = mpsc::channel(64);
+    {
+        let (root, cancel) = (root.clone(), cancel.clone());
+        std::thread::Builder::new()
+            .name("import".into())
+            .spawn(move || walk(root, include, exclude, known, cancel, tx))?;
+    }
+
+    let mut state = Progress::default();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut dirty = 0;
+    while let Some(scanned) = rx.next().await {
+        state.files += 1;
+        let path = match scanned {
+            Scanned::NotImage(path) => path,
+            Scanned::Unchanged(path) => {
+                state.images += 1;
+                state.unchanged += 1;
+                seen.insert(path.to_string_lossy().into_owned());
+                path
+            }
+            Scanned::Image(path, stamp, image) => {
+                state.images += 1;
+                let key = image.path.clone();
+                match store_image(store, *image).await {
+                    Ok(()) => {
+                        state.imported += 1;
+                        stamps.insert(key.clone(), stamp);
+                        dirty += 1;
+                    }
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        state.failed += 1;
+                    }
+                }
+                seen.insert(key);
+                path
+            }
+            Scanned::Failed(path, e) => {
+                log::warn!("{}: {}", path.to_string_lossy(), e);
+                state.failed += 1;
+                path
+            }
+        };
+        state.current = Some(path);
+        progress(&state);
+        if dirty >= SAVE_EVERY {
+            save_stamps(store, &collection.name, &stamps).await?;
+            dirty = 0;
+        }
+        if cancel.is_cancelled() {
+            break;
+        }
+    }
+
+    let cancelled = cancel.is_cancelled();
+    // A partial walk cannot tell which files are gone.
+    if !cancelled {
+        // Paging by path is not disturbed by deleting what was already seen.
+        let mut pages = store.get_images_in_collection_iter(&collection.name);
+        while let Some(page) = pages.next().await {
+            for image in page? {
+                if !seen.contains(&image.path) {
+                    store.delete_image(&image.path).await?;
+                    stamps.remove(&image.path);
+                    state.removed += 1;
+                }
+            }
+        }
+        stamps.retain(|k, _| seen.contains(k));
+    }
+    save_stamps(store, &collection.name, &stamps).await?;
+    state.current = None;
+    progress(&state);
+
+    Ok(ImportReport {
+        collection,
+        progress: state,
+        cancelled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::db::memory::MemoryStore;
+
+    /// An empty folder for test `name`.
+    fn folder(name: &str) -> Result<PathBuf> {
+        let dir =
+            std::env::temp_dir().join(format!("phany-import-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir.canonicalize()?)
+    }
+
+    /// Write a gray PPM of `width` by 1 pixels.
+    fn ppm(path: &Path, width: usize) -> Result<()> {
+        let mut data = format!("P6\n{} 1\n255\n", width).into_bytes();
+        data.resize(data.len() + width * 3, 128);
+        Ok(std::fs::write(path, data)?)
+    }
+
+    fn run(store: &MemoryStore, dir: &Path, options: &ImportOptions) -> Result<ImportReport> {
+        block_on(import(store, dir, options, &CancelToken::new(), |_| {}))
+    }
+
+    fn paths(store: &MemoryStore, collection: &str) -> Result<Vec<String>> {
+        Ok(block_on(store.get_images_in_collection(collection))?
+            .into_iter()
+            .map(|x| x.path)
+            .collect())
+    }
+
+    #[test]
+    fn globs() -> Result<()> {
+        let root = Path::new("/photos");
+        let set = glob_set(&["*.jpg".to_owned(), "raw/**".to_owned()])?;
+        assert!(matches(&set, root, Path::new("/photos/a.jpg")));
+        assert!(matches(&set, root, Path::new("/photos/2024/b.jpg")));
+        assert!(matches(&set, root, Path::new("/photos/raw/c.nef")));
+        assert!(!matches(&set, root, Path::new("/photos/2024/raw/c.nef")));
+        assert!(!matches(&set, root, Path::new("/photos/a.jpg.xmp")));
+        assert_eq!(
+            glob_set(&["a[".to_owned()])
+                .unwrap_err()
+                .to_string()
+                .split(':')
+                .next(),
+            Some("bad glob `a[`")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn include_exclude() -> Result<()> {
+        let dir = folder("globs")?;
+        std::fs::create_dir(dir.join("skip"))?;
+        ppm(&dir.join("a.ppm"), 1)?;
+        ppm(&dir.join("b.pnm"), 1)?;
+        ppm(&dir.join("skip").join("c.ppm"), 1)?;
+        std::fs::write(dir.join("notes.ppm"), "not an image")?;
+
+        let store = MemoryStore::new();
+        let options = ImportOptions {
+            include: vec!["*.ppm".to_owned()],
+            exclude: vec!["skip".to_owned()],
+            ..Default::default()
+        };
+        let report = run(&store, &dir, &options)?;
+        let p = &report.progress;
+        assert!(!report.cancelled);
+        assert_eq!((p.files, p.images, p.imported, p.failed), (2, 1, 1, 0));
+        assert_eq!(
+            paths(&store, &report.collection.name)?,
+            [dir.join("a.ppm").to_string_lossy()]
+        );
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rescan() -> Result<()> {
+        let dir = folder("rescan")?;
+        let (a, b) = (dir.join("a.ppm"), dir.join("b.ppm"));
+        ppm(&a, 1)?;
+        ppm(&b, 2)?;
+        let store = MemoryStore::new();
+        let report = run(&store, &dir, &Default::default())?;
+        let name = report.collection.name;
+        assert_eq!(report.progress.imported, 2);
+
+        // Same size and mtime: not read again, even though it is no image.
+        let modified = std::fs::metadata(&b)?.modified()?;
+        let size = std::fs::metadata(&b)?.len() as usize;
+        std::fs::write(&b, vec![0; size])?;
+        std::fs::File::options()
+            .write(true)
+            .open(&b)?
+            .set_modified(modified)?;
+        let p = run(&store, &dir, &Default::default())?.progress;
+        assert_eq!((p.imported, p.unchanged, p.failed), (0, 2, 0));
+
+        // A changed file is read, a removed one dropped.
+        ppm(&a, 3)?;
+        std::fs::remove_file(&b)?;
+        let p = run(&store, &dir, &Default::default())?.progress;
+        assert_eq!((p.imported, p.unchanged, p.removed), (1, 0, 1));
+        let image = block_on(store.get_image(&a.to_string_lossy()))?.unwrap();
+        assert_eq!(image.size, (3, 1));
+        assert_eq!(paths(&store, &name)?, [a.to_string_lossy()]);
+        let stamps = block_on(load_stamps(&store, &name))?;
+        assert_eq!(stamps.len(), 1);
+        assert!(stamps[a.to_string_lossy().as_ref()].hash.is_some());
+
+        // A full import reads everything again.
+        let options = ImportOptions {
+            full: true,
+            ..Default::default()
+        };
+        let p = run(&store, &dir, &options)?.progress;
+        assert_eq!((p.imported, p.unchanged), (1, 0));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn cancelled() -> Result<()> {
+        let dir = folder("cancel")?;
+        ppm(&dir.join("a.ppm"), 1)?;
+        let store = MemoryStore::new();
+        let name = run(&store, &dir, &Default::default())?.collection.name;
+
+        // A cancelled walk removes nothing it did not get to.
+        ppm(&dir.join("b.ppm"), 1)?;
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let report = block_on(import(&store, &dir, &Default::default(), &cancel, |_| {}))?;
+        assert!(report.cancelled);
+        assert_eq!(report.progress.removed, 0);
+        assert_eq!(paths(&store, &name)?, [dir.join("a.ppm").to_string_lossy()]);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn store_image_merges() -> Result<()> {
+        let store = MemoryStore::new();
+        block_on(store.put_collection(&Collection {
+            folder: "/a".to_owned(),
+            name: "a".to_owned(),
+            sync: Default::default(),
+        }))?;
+        let old = Image {
+            path: "/a/1.jpg".to_owned(),
+            collection: "a".to_owned(),
+            size: (4, 3),
+            tags: vec!["Old".to_owned(), "Both".to_owned()],
+            title: "Old title".to_owned(),
+            description: "Old description".to_owned(),
+            marks: Marks {
+                rating: 3,
+                label: Some(ColorLabel::Red),
+                flag: Flag::Pick,
+            },
+            shot: Default::default(),
+            gps: None,
+        };
+        block_on(store.put_image(&old))?;
+
+        let read = Image {
+            size: (8, 6),
+            tags: vec!["Both".to_owned(), "New".to_owned()],
+            title: String::new(),
+            description: "New description".to_owned(),
+            marks: Marks::default(),
+            ..old.clone()
+        };
+        block_on(store_image(&store, read))?;
+        let image = block_on(store.get_image("/a/1.jpg"))?.unwrap();
+        assert_eq!(image.size, (8, 6));
+        assert_eq!(image.tags, ["Both", "New", "Old"]);
+        assert_eq!(image.title, "Old title");
+        assert_eq!(image.description, "New description");
+        assert_eq!(image.marks, old.marks);
+
+        // Marks in the file win over the catalog's.
+        let read = Image {
+            marks: Marks {
+                rating: 5,
+                label: Some(ColorLabel::Blue),
+                flag: Flag::Reject,
+            },
+            ..image
+        };
+        block_on(store_image(&store, read.clone()))?;
+        assert_eq!(
+            block_on(store.get_image("/a/1.jpg"))?.unwrap().marks,
+            read.marks
+        );
+        Ok(())
+    }
+}
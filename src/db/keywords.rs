@@ -0,0 +1,396 @@
+//! Hierarchical keywords.
+//!
+//! A keyword is a path of names joined by `|`, like `Places|Europe|Berlin`,
+//! as Lightroom writes `lr:hierarchicalSubject`. Image tags are keyword
+//! paths. The keyword tree of the datastore also holds keywords no image
+//! carries, and synonyms.
+
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+pub const SEPARATOR: char = '|';
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keyword {
+    pub path: String,
+    /// Other names of the keyword, for completion and `dc:subject`.
+    #[serde(default)]
+    pub synonyms: Vec<String>,
+}
+
+impl Keyword {
+    pub fn new(path: &str) -> Keyword {
+        Keyword {
+            path: path.to_owned(),
+            synonyms: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        name(&self.path)
+    }
+}
+
+/// Last name of `path`.
+pub fn name(path: &str) -> &str {
+    path.rsplit(SEPARATOR).next().unwrap_or(path)
+}
+
+pub fn parent(path: &str) -> Option<&str> {
+    path.rsplit_once(SEPARATOR).map(|x| x.0)
+}
+
+/// `path` and the paths above it, the top level first.
+pub fn ancestors(path: &str) -> Vec<&str> {
+    path.match_indices(SEPARATOR)
+        .map(|(i, _)| &path[..i])
+        .chain([path])
+        .collect()
+}
+
+/// Whether `path` is `root` or a keyword under it.
+pub fn is_under(path: &str, root: &str) -> bool {
+    moved(path, root, "").is_some()
+}
+
+/// `path` after moving `from`, and what is under it, to `to`.
+pub fn moved(path: &str, from: &str, to: &str) -> Option<String> {
+    let rest = path.strip_prefix(from)?;
+    (rest.is_empty() || rest.starts_with(SEPARATOR)).then(|| format!("{}{}", to, rest))
+}
+
+/// Path of `path` renamed to `name`, keeping its parent.
+pub fn renamed(path: &str, name: &str) -> String {
+    match parent(path) {
+        Some(parent) => format!("{}{}{}", parent, SEPARATOR, name),
+        None => name.to_owned(),
+    }
+}
+
+/// Path of `path` moved under `parent`, or to the top level.
+pub fn reparented(path: &str, parent: Option<&str>) -> String {
+    match parent {
+        Some(parent) => format!("{}{}{}", parent, SEPARATOR, name(path)),
+        None => name(path).to_owned(),
+    }
+}
+
+/// `path` with names trimmed and empty ones dropped, or `None` if nothing
+/// is left.
+pub fn normalize(path: &str) -> Option<String> {
+    let names: Vec<&str> = path
+        .split(SEPARATOR)
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect();
+    (!names.is_empty()).then(|| names.join(&SEPARATOR.to_string()))
+}
+
+/// Keywords matching the start of what was typed, best first: names, then
+/// synonyms, then names containing it. Input with a `|` matches paths.
+pub fn complete<'a>(keywords: &'a [Keyword], input: &str, limit: usize) -> Vec<&'a Keyword> {
+    let input = input.trim().to_lowercase();
+    if input.is_empty() {
+        return vec![];
+    }
+    let rank = |k: &Keyword| {
+        let name = k.name().to_lowercase();
+        if input.contains(SEPARATOR) {
+            k.path.to_lowercase().starts_with(&input).then_some(0)
+        } else if name.starts_with(&input) {
+            Some(0)
+        } else if k
+            .synonyms
+            .iter()
+            .any(|x| x.to_lowercase().starts_with(&input))
+        {
+            Some(1)
+        } else if name.contains(&input) {
+            Some(2)
+        } else {
+            None
+        }
+    };
+    let mut found: Vec<(u8, &Keyword)> = keywords
+        .iter()
+        .filter_map(|k| Some((rank(k)?, k)))
+        .collect();
+    // Shorter paths first within a rank: `Berlin` before `Berlin|Mitte`.
+    found.sort_by_key(|(rank, k)| (*rank, k.path.matches(SEPARATOR).count(), k.path.clone()));
+    found.into_iter().take(limit).map(|x| x.1).collect()
+}
+
+/// Keyword path for typed text: a known path, the keyword with that name or
+/// synonym if there is only one, or else the text as a new path.
+pub fn resolve(keywords: &[Keyword], input: &str) -> Option<String> {
+    let input = normalize(input)?;
+    if let Some(k) = keywords
+        .iter()
+        .find(|k| k.path.eq_ignore_ascii_case(&input))
+    {
+        return Some(k.path.clone());
+    }
+    let named: Vec<&Keyword> = keywords
+        .iter()
+        .filter(|k| {
+            k.name().eq_ignore_ascii_case(&input)
+                || k.synonyms.iter().any(|x| x.eq_ignore_ascii_case(&input))
+        })
+        .collect();
+    match named[..] {
+        [k] => Some(k.path.clone()),
+        _ => Some(input),
+    }
+}
+
+/// Tags from XMP keywords: the `hierarchical` paths, then the flat
+/// `subjects` that are not a name along one of them, nor a synonym of one
+/// of the `keywords` tagged, as [`to_xmp`] writes them.
+pub fn from_xmp(subjects: &[String], hierarchical: &[String], keywords: &[Keyword]) -> Vec<String> {
+    let mut tags: Vec<String> = hierarchical.iter().filter_map(|x| normalize(x)).collect();
+    let names: HashSet<&str> = tags.iter().flat_map(|x| x.split(SEPARATOR)).collect();
+    let mut flat: Vec<String> = subjects
+        .iter()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !names.contains(x))
+        .map(|x| x.replace(SEPARATOR, "/"))
+        .collect();
+    let synonyms: HashSet<&str> = keywords
+        .iter()
+        .filter(|k| tags.iter().any(|x| is_under(x, &k.path)) || flat.contains(&k.path))
+        .flat_map(|k| k.synonyms.iter().map(String::as_str))
+        .collect();
+    flat.retain(|x| !synonyms.contains(x.as_str()));
+    for tag in flat {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// `dc:subject` and `lr:hierarchicalSubject` values for `tags`: every name
+/// along each path with its synonyms, and the paths themselves.
+pub fn to_xmp(tags: &[String], keywords: &[Keyword]) -> (Vec<String>, Vec<String>) {
+    let mut subjects: Vec<String> = vec![];
+    let mut push = |x: &str| {
+        if !subjects.iter().any(|s| s == x) {
+            subjects.push(x.to_owned());
+        }
+    };
+    for tag in tags {
+        for path in ancestors(tag) {
+            push(name(path));
+            if let Some(k) = keywords.iter().find(|k| k.path == path) {
+                k.synonyms.iter().for_each(|x| push(x));
+            }
+        }
+    }
+    let hierarchical = tags
+        .iter()
+        .filter(|x| x.contains(SEPARATOR))
+        .cloned()
+        .collect();
+    (subjects, hierarchical)
+}
+
+/// Parse a Lightroom keyword list: one name per line, indented with a tab
+/// per level under its parent. `[Name]` is a category, read as a plain
+/// keyword, and `{Name}` a synonym of the keyword above it.
+pub fn parse_keyword_list(text: &str) -> Result<Vec<Keyword>> {
+    let mut keywords: Vec<Keyword> = vec![];
+    // Path of the last keyword at each depth.
+    let mut stack: Vec<String> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let name = line.trim_start_matches('\t');
+        let depth = line.len() - name.len();
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        if let Some(synonym) = name.strip_prefix('{').and_then(|x| x.strip_suffix('}')) {
+            let Some(parent) = stack.get(depth.wrapping_sub(1)) else {
+                return Err(anyhow!("line {}: synonym without a keyword", i + 1));
+            };
+            let synonym = synonym.trim();
+            if synonym.is_empty() {
+                return Err(anyhow!("line {}: empty synonym", i + 1));
+            }
+            if let Some(k) = keywords.iter_mut().rev().find(|k| k.path == *parent) {
+                k.synonyms.push(synonym.to_owned());
+            }
+            continue;
+        }
+        if depth > stack.len() {
+            return Err(anyhow!("line {}: indented too far", i + 1));
+        }
+        let name = name
+            .strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .unwrap_or(name)
+            .trim();
+        if name.is_empty() {
+            return Err(anyhow!("line {}: empty name", i + 1));
+        }
+        if name.contains(SEPARATOR) {
+            return Err(anyhow!("line {}: `{}` in a name", i + 1, SEPARATOR));
+        }
+        stack.truncate(depth);
+        let path = match stack.last() {
+            Some(parent) => format!("{}{}{}", parent, SEPARATOR, name),
+            None => name.to_owned(),
+        };
+        stack.push(path.clone());
+        keywords.push(Keyword::new(&path));
+    }
+    Ok(keywords)
+}
+
+/// Write keywords as a Lightroom keyword list, parents before children.
+pub fn write_keyword_list(keywords: &[Keyword]) -> String {
+    let mut sorted: Vec<&Keyword> = keywords.iter().collect();
+    sorted.sort_by_key(|k| {
+        k.path
+            .split(SEPARATOR)
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>()
+    });
+    let mut out = String::new();
+    let mut written: HashSet<&str> = HashSet::new();
+    for k in sorted {
+        for path in ancestors(&k.path) {
+            if !written.insert(path) {
+                continue;
+            }
+            let depth = path.matches(SEPARATOR).count();
+            out.push_str(&"\t".repeat(depth));
+            out.push_str(name(path));
+            out.push('\n');
+        }
+        for synonym in &k.synonyms {
+            out.push_str(&"\t".repeat(k.path.matches(SEPARATOR).count() + 1));
+            out.push_str(&format!("{{{}}}\n", synonym));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyword(path: &str, synonyms: &[&str]) -> Keyword {
+        Keyword {
+            path: path.to_owned(),
+            synonyms: synonyms.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn nesting() -> Result<()> {
+        let text = "Places\n\tGermany\n\t\tBerlin\r\n\tFrance\n\n[People]\n\tAlice\nAnimals\n";
+        assert_eq!(
+            parse_keyword_list(text)?,
+            [
+                keyword("Places", &[]),
+                keyword("Places|Germany", &[]),
+                keyword("Places|Germany|Berlin", &[]),
+                keyword("Places|France", &[]),
+                keyword("People", &[]),
+                keyword("People|Alice", &[]),
+                keyword("Animals", &[]),
+            ]
+        );
+        // A name may come back to any shallower level.
+        let keywords = parse_keyword_list("A\n\tB\n\t\tC\nD\n\tE\n")?;
+        assert_eq!(keywords[4].path, "D|E");
+        Ok(())
+    }
+
+    #[test]
+    fn synonyms() -> Result<()> {
+        let text = "People\n\tAlice\n\t\t{Ally}\n\t\t{ Al }\n\t{Folk}\n\tBob\n";
+        assert_eq!(
+            parse_keyword_list(text)?,
+            [
+                keyword("People", &["Folk"]),
+                keyword("People|Alice", &["Ally", "Al"]),
+                keyword("People|Bob", &[]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn malformed() {
+        for (text, error) in [
+            ("{Ally}\n", "line 1: synonym without a keyword"),
+            ("A\n\t\t{Ally}\n", "line 2: synonym without a keyword"),
+            ("A\n\t\tB\n", "line 2: indented too far"),
+            ("\tA\n", "line 1: indented too far"),
+            ("A\n\tB|C\n", "line 2: `|` in a name"),
+            ("A\n[]\n", "line 2: empty name"),
+            ("A\n\t{ }\n", "line 2: empty synonym"),
+        ] {
+            let result = parse_keyword_list(text);
+            assert_eq!(
+                result.map_err(|e| e.to_string()),
+                Err(error.to_owned()),
+                "{:?}",
+                text
+            );
+        }
+        assert_eq!(parse_keyword_list("").unwrap(), []);
+        assert_eq!(parse_keyword_list("\n\t\n").unwrap(), []);
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let keywords = [
+            keyword("places|Berlin", &[]),
+            keyword("People|Alice", &["Ally", "Al"]),
+            keyword("People", &["Folk"]),
+            keyword("Places", &[]),
+        ];
+        let text = write_keyword_list(&keywords);
+        assert_eq!(
+            text,
+            "People\n\t{Folk}\n\tAlice\n\t\t{Ally}\n\t\t{Al}\nPlaces\nplaces\n\tBerlin\n"
+        );
+        let mut read = parse_keyword_list(&text)?;
+        read.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut keywords = keywords.to_vec();
+        keywords.push(keyword("places", &[]));
+        keywords.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(read, keywords);
+        Ok(())
+    }
+
+    #[test]
+    fn xmp_round_trip() {
+        let keywords = [
+            keyword("Places", &[]),
+            keyword("Places|Europe", &["EU"]),
+            keyword("Places|Europe|Berlin", &["Berlino"]),
+            keyword("Cat", &["Feline"]),
+            keyword("Dog", &["Hound"]),
+        ];
+        let tags = vec![
+            "Places|Europe|Berlin".to_owned(),
+            "Cat".to_owned(),
+            "Loose".to_owned(),
+        ];
+        let (subjects, hierarchical) = to_xmp(&tags, &keywords);
+        assert_eq!(
+            subjects,
+            ["Places", "Europe", "EU", "Berlin", "Berlino", "Cat", "Feline", "Loose"]
+        );
+        assert_eq!(from_xmp(&subjects, &hierarchical, &keywords), tags);
+        // Synonyms of keywords not tagged are tags of their own.
+        let subjects = ["Hound".to_owned()];
+        assert_eq!(from_xmp(&subjects, &[], &keywords), ["Hound"]);
+    }
+}
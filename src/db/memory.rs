@@ -0,0 +1,647 @@
+//! In-memory catalog, for tests and sessions that write nothing to disk.
+//!
+//! Behaves like the SQLite store: images belong to an existing collection,
+//! deleting a collection deletes its images, and listings are sorted.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::datastore::{
+    Collection, Datastore, Fields, Image, Marks, Page, Pending, SmartCollection,
+};
+use super::keywords::{self, Keyword};
+use super::query::Query;
+
+/// Everything a [`MemoryStore`] holds, as written to a JSON snapshot.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    pub kv: BTreeMap<String, Vec<u8>>,
+    /// Collections by name.
+    pub collections: BTreeMap<String, Collection>,
+    /// Images by path.
+    pub images: BTreeMap<String, Image>,
+    /// Keyword tree by path.
+    pub keywords: BTreeMap<String, Keyword>,
+    /// Saved searches by name.
+    pub smart_collections: BTreeMap<String, SmartCollection>,
+    /// Fields of images waiting to be written back, by path.
+    pub pending: BTreeMap<String, Fields>,
+}
+
+impl Snapshot {
+    /// Add `tags` and the keywords above them to the tree.
+    fn add_keywords(&mut self, tags: &[String]) {
+        for tag in tags {
+            for path in keywords::ancestors(tag) {
+                self.keywords
+                    .entry(path.to_owned())
+                    .or_insert_with(|| Keyword::new(path));
+            }
+        }
+    }
+}
+
+/// [`Datastore`] kept in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    data: RwLock<Snapshot>,
+}
+
+/// Tags without repeats, keeping the first occurrence, like the SQLite store.
+fn dedup(tags: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        if !out.contains(tag) {
+            out.push(tag.clone());
+        }
+    }
+    out
+}
+
+/// Keys after `after`, or all keys.
+fn after_bound(after: Option<&str>) -> (Bound<&str>, Bound<&str>) {
+    match after {
+        Some(x) => (Bound::Excluded(x), Bound::Unbounded),
+        None => (Bound::Unbounded, Bound::Unbounded),
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn from_snapshot(snapshot: Snapshot) -> MemoryStore {
+        MemoryStore {
+            data: RwLock::new(snapshot),
+        }
+    }
+
+    /// A copy of the current contents.
+    pub fn snapshot(&self) -> Snapshot {
+        self.data.read().unwrap().clone()
+    }
+
+    /// Restore a snapshot written by [`MemoryStore::save`].
+    pub fn load(path: &Path) -> Result<MemoryStore> {
+        let file =
+            std::fs::File::open(path).map_err(|e| anyhow!("{}: {}", path.to_string_lossy(), e))?;
+        let snapshot = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| anyhow!("{}: {}", path.to_string_lossy(), e))?;
+        Ok(MemoryStore::from_snapshot(snapshot))
+    }
+
+    /// Write the contents as JSON, replacing `path` atomically.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&*self.data.read().unwrap())?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Datastore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.read().unwrap().kv.get(key).cloned())
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.data
+            .write()
+            .unwrap()
+            .kv
+            .insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.data.write().unwrap().kv.remove(key);
+        Ok(())
+    }
+
+    async fn get_collections(&self) -> Result<Vec<Collection>> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .collections
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_collections_page(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Collection>> {
+        let data = self.data.read().unwrap();
+        let items = data
+            .collections
+            .range::<str, _>(after_bound(after))
+            .take(limit)
+            .map(|(_, x)| x.clone())
+            .collect();
+        Ok(Page::new(items, limit, |x: &Collection| x.name.clone()))
+    }
+
+    async fn get_collection(&self, name: &str) -> Result<Option<Collection>> {
+        Ok(self.data.read().unwrap().collections.get(name).cloned())
+    }
+
+    async fn put_collection(&self, collection: &Collection) -> Result<()> {
+        self.data
+            .write()
+            .unwrap()
+            .collections
+            .insert(collection.name.clone(), collection.clone());
+        Ok(())
+    }
+
+    async fn delete_collection(&self, name: &str) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        if data.collections.remove(name).is_some() {
+            data.images.retain(|_, x| x.collection != name);
+            let Snapshot {
+                images, pending, ..
+            } = &mut *data;
+            pending.retain(|x, _| images.contains_key(x));
+        }
+        Ok(())
+    }
+
+    async fn get_images_in_collection(&self, collection: &str) -> Result<Vec<Image>> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .images
+            .values()
+            .filter(|x| x.collection == collection)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_images_page(
+        &self,
+        collection: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Image>> {
+        let data = self.data.read().unwrap();
+        let items = data
+            .images
+            .range::<str, _>(after_bound(after))
+            .map(|(_, x)| x)
+            .filter(|x| x.collection == collection)
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok(Page::new(items, limit, |x: &Image| x.path.clone()))
+    }
+
+    async fn get_image(&self, path: &str) -> Result<Option<Image>> {
+        Ok(self.data.read().unwrap().images.get(path).cloned())
+    }
+
+    async fn put_image(&self, image: &Image) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        if !data.collections.contains_key(&image.collection) {
+            return Err(anyhow!(
+                "{}: storing image: no collection {}",
+                image.path,
+                image.collection
+            ));
+        }
+        let image = Image {
+            tags: dedup(&image.tags),
+            ..image.clone()
+        };
+        data.add_keywords(&image.tags);
+        data.images.insert(image.path.clone(), image);
+        Ok(())
+    }
+
+    async fn delete_image(&self, path: &str) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        data.images.remove(path);
+        data.pending.remove(path);
+        Ok(())
+    }
+
+    async fn rename_image(&self, from: &str, to: &str, collection: &str) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        if !data.collections.contains_key(collection) {
+            return Err(anyhow!(
+                "{}: storing image: no collection {}",
+                to,
+                collection
+            ));
+        }
+        let mut image = data
+            .images
+            .remove(from)
+            .ok_or_else(|| anyhow!("{}: not in the catalog", from))?;
+        image.path = to.to_owned();
+        image.collection = collection.to_owned();
+        data.images.insert(to.to_owned(), image);
+        if from != to {
+            data.pending.remove(to);
+        }
+        if let Some(fields) = data.pending.remove(from) {
+            data.pending.insert(to.to_owned(), fields);
+        }
+        Ok(())
+    }
+
+    async fn set_tags(&self, path: &str, tags: &[String]) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        match data.images.get_mut(path) {
+            Some(image) => {
+                image.tags = dedup(tags);
+                data.add_keywords(tags);
+                Ok(())
+            }
+            None => Err(anyhow!("{}: not in the catalog", path)),
+        }
+    }
+
+    async fn set_description(&self, path: &str, description: &str) -> Result<()> {
+        match self.data.write().unwrap().images.get_mut(path) {
+            Some(image) => {
+                image.description = description.to_owned();
+                Ok(())
+            }
+            None => Err(anyhow!("{}: not in the catalog", path)),
+        }
+    }
+
+    async fn set_marks(&self, path: &str, marks: Marks) -> Result<()> {
+        match self.data.write().unwrap().images.get_mut(path) {
+            Some(image) => {
+                image.marks = marks;
+                Ok(())
+            }
+            None => Err(anyhow!("{}: not in the catalog", path)),
+        }
+    }
+
+    async fn get_images_with_tag(&self, tag: &str) -> Result<Vec<Image>> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .images
+            .values()
+            .filter(|x| x.tags.iter().any(|t| t == tag))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_keywords(&self) -> Result<Vec<Keyword>> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .keywords
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn put_keyword(&self, keyword: &Keyword) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        data.add_keywords(std::slice::from_ref(&keyword.path));
+        data.keywords.insert(
+            keyword.path.clone(),
+            Keyword {
+                synonyms: dedup(&keyword.synonyms),
+                ..keyword.clone()
+            },
+        );
+        Ok(())
+    }
+
+    async fn delete_keyword(&self, path: &str) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        data.keywords.retain(|k, _| !keywords::is_under(k, path));
+        for image in data.images.values_mut() {
+            image.tags.retain(|x| !keywords::is_under(x, path));
+        }
+        Ok(())
+    }
+
+    async fn move_keyword(&self, from: &str, to: &str) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+        if keywords::is_under(to, from) {
+            return Err(anyhow!("cannot move {} under itself", from));
+        }
+        let mut data = self.data.write().unwrap();
+        let moving: Vec<Keyword> = data
+            .keywords
+            .values()
+            .filter(|k| keywords::is_under(&k.path, from))
+            .cloned()
+            .collect();
+        data.add_keywords(&[to.to_owned()]);
+        for k in moving {
+            data.keywords.remove(&k.path);
+            let path = keywords::moved(&k.path, from, to).unwrap();
+            let target = data
+                .keywords
+                .entry(path.clone())
+                .or_insert_with(|| Keyword::new(&path));
+            target.synonyms = dedup(&[target.synonyms.clone(), k.synonyms].concat());
+        }
+        for image in data.images.values_mut() {
+            let tags: Vec<String> = image
+                .tags
+                .iter()
+                .map(|x| keywords::moved(x, from, to).unwrap_or_else(|| x.clone()))
+                .collect();
+            image.tags = dedup(&tags);
+        }
+        Ok(())
+    }
+
+    async fn get_smart_collections(&self) -> Result<Vec<SmartCollection>> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .smart_collections
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_smart_collection(&self, name: &str) -> Result<Option<SmartCollection>> {
+        Ok(self
+            .data
+            .read()
+            .unwrap()
+            .smart_collections
+            .get(name)
+            .cloned())
+    }
+
+    async fn put_smart_collection(&self, collection: &SmartCollection) -> Result<()> {
+        self.data
+            .write()
+            .unwrap()
+            .smart_collections
+            .insert(collection.name.clone(), collection.clone());
+        Ok(())
+    }
+
+    async fn delete_smart_collection(&self, name: &str) -> Result<()> {
+        self.data.write().unwrap().smart_collections.remove(name);
+        Ok(())
+    }
+
+    async fn find_images_page(
+        &self,
+        query: &Query,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Image>> {
+        let data = self.data.read().unwrap();
+        let items = data
+            .images
+            .range::<str, _>(after_bound(after))
+            .map(|(_, x)| x)
+            .filter(|x| query.matches(x))
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok(Page::new(items, limit, |x: &Image| x.path.clone()))
+    }
+
+    async fn add_pending(&self, path: &str, fields: Fields) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        if !data.images.contains_key(path) {
+            return Err(anyhow!("{}: not in the catalog", path));
+        }
+        let entry = data.pending.entry(path.to_owned()).or_default();
+        *entry = *entry | fields;
+        Ok(())
+    }
+
+    async fn clear_pending(&self, path: &str, fields: Fields) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        if let Some(entry) = data.pending.get_mut(path) {
+            *entry = entry.without(fields);
+            if entry.is_empty() {
+                data.pending.remove(path);
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_pending_page(&self, after: Option<&str>, limit: usize) -> Result<Page<Pending>> {
+        let data = self.data.read().unwrap();
+        let items = data
+            .pending
+            .range::<str, _>(after_bound(after))
+            .filter_map(|(path, &fields)| {
+                Some(Pending {
+                    image: data.images.get(path)?.clone(),
+                    fields,
+                })
+            })
+            .take(limit)
+            .collect();
+        Ok(Page::new(items, limit, |x: &Pending| x.image.path.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn image(path: &str, collection: &str, tags: &[&str]) -> Image {
+        Image {
+            path: path.to_owned(),
+            collection: collection.to_owned(),
+            size: (0, 0),
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+            title: String::new(),
+            description: String::new(),
+            marks: Marks::default(),
+            shot: Default::default(),
+            gps: None,
+        }
+    }
+
+    /// A store with the collections `a` and `b`.
+    fn store() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        for name in ["a", "b"] {
+            block_on(store.put_collection(&Collection {
+                folder: format!("/{}", name),
+                name: name.to_owned(),
+                sync: Default::default(),
+            }))?;
+        }
+        Ok(store)
+    }
+
+    fn paths(images: &[Image]) -> Vec<&str> {
+        images.iter().map(|x| x.path.as_str()).collect()
+    }
+
+    #[test]
+    fn paging() -> Result<()> {
+        let store = store()?;
+        for i in 0..7 {
+            block_on(store.put_image(&image(&format!("/a/{}.jpg", i), "a", &[])))?;
+        }
+        block_on(store.put_image(&image("/a/2b.jpg", "b", &[])))?;
+
+        let first = block_on(store.get_images_page("a", None, 3))?;
+        assert_eq!(paths(&first.items), ["/a/0.jpg", "/a/1.jpg", "/a/2.jpg"]);
+        assert_eq!(first.next.as_deref(), Some("/a/2.jpg"));
+        let second = block_on(store.get_images_page("a", first.next.as_deref(), 3))?;
+        // Images of other collections are skipped, not counted.
+        assert_eq!(paths(&second.items), ["/a/3.jpg", "/a/4.jpg", "/a/5.jpg"]);
+        let last = block_on(store.get_images_page("a", second.next.as_deref(), 3))?;
+        assert_eq!(paths(&last.items), ["/a/6.jpg"]);
+        assert_eq!(last.next, None);
+
+        let all: Vec<Image> = block_on(
+            store
+                .get_images_in_collection_iter("a")
+                .map(|x| x.unwrap())
+                .concat(),
+        );
+        assert_eq!(all, block_on(store.get_images_in_collection("a"))?);
+        assert_eq!(all.len(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn rename_and_delete() -> Result<()> {
+        let store = store()?;
+        block_on(store.put_image(&image("/a/x.jpg", "a", &["Places|Berlin"])))?;
+        block_on(store.put_image(&image("/a/y.jpg", "a", &[])))?;
+        block_on(store.add_pending("/a/x.jpg", Fields::TAGS))?;
+        assert!(block_on(store.put_image(&image("/c/z.jpg", "c", &[]))).is_err());
+
+        block_on(store.rename_image("/a/x.jpg", "/b/x.jpg", "b"))?;
+        assert_eq!(block_on(store.get_image("/a/x.jpg"))?, None);
+        let moved = block_on(store.get_image("/b/x.jpg"))?.unwrap();
+        assert_eq!(moved.collection, "b");
+        assert_eq!(moved.tags, ["Places|Berlin"]);
+        let pending = block_on(store.get_pending_page(None, 10))?;
+        assert_eq!(pending.items.len(), 1);
+        assert_eq!(pending.items[0].image.path, "/b/x.jpg");
+        assert_eq!(pending.items[0].fields, Fields::TAGS);
+
+        assert!(block_on(store.rename_image("/a/x.jpg", "/b/w.jpg", "b")).is_err());
+        assert!(block_on(store.rename_image("/a/y.jpg", "/c/y.jpg", "c")).is_err());
+        // Renaming onto itself keeps the pending fields.
+        block_on(store.rename_image("/b/x.jpg", "/b/x.jpg", "b"))?;
+        let pending = block_on(store.get_pending_page(None, 10))?;
+        assert_eq!(pending.items.len(), 1);
+        assert_eq!(pending.items[0].fields, Fields::TAGS);
+        // Renaming onto an image replaces it.
+        block_on(store.rename_image("/a/y.jpg", "/b/x.jpg", "b"))?;
+        assert_eq!(
+            paths(&block_on(store.get_images_in_collection("b"))?),
+            ["/b/x.jpg"]
+        );
+        assert!(block_on(store.get_image("/b/x.jpg"))?
+            .unwrap()
+            .tags
+            .is_empty());
+        assert!(block_on(store.get_pending_page(None, 10))?.items.is_empty());
+
+        block_on(store.put_image(&image("/b/v.jpg", "b", &[])))?;
+        block_on(store.add_pending("/b/v.jpg", Fields::MARKS))?;
+        block_on(store.delete_image("/b/v.jpg"))?;
+        assert_eq!(block_on(store.get_image("/b/v.jpg"))?, None);
+        assert!(block_on(store.get_pending_page(None, 10))?.items.is_empty());
+
+        block_on(store.delete_collection("b"))?;
+        assert!(block_on(store.get_images_in_collection("b"))?.is_empty());
+        assert_eq!(block_on(store.get_collection("b"))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn tags() -> Result<()> {
+        let store = store()?;
+        block_on(store.put_image(&image("/a/x.jpg", "a", &["People|Alice", "People|Alice"])))?;
+        block_on(store.put_image(&image("/a/y.jpg", "a", &[])))?;
+        block_on(store.set_tags("/a/y.jpg", &["People|Bob".into(), "Places".into()]))?;
+        assert!(block_on(store.set_tags("/a/z.jpg", &[])).is_err());
+
+        assert_eq!(
+            block_on(store.get_image("/a/x.jpg"))?.unwrap().tags,
+            ["People|Alice"]
+        );
+        let keywords: Vec<String> = block_on(store.get_keywords())?
+            .into_iter()
+            .map(|x| x.path)
+            .collect();
+        assert_eq!(keywords, ["People", "People|Alice", "People|Bob", "Places"]);
+        assert_eq!(
+            paths(&block_on(store.get_images_with_tag("People|Bob"))?),
+            ["/a/y.jpg"]
+        );
+        // Only the exact tag, not those under it.
+        assert!(block_on(store.get_images_with_tag("People"))?.is_empty());
+
+        block_on(store.put_keyword(&Keyword {
+            path: "People|Alice".into(),
+            synonyms: vec!["Ally".into()],
+        }))?;
+        block_on(store.put_keyword(&Keyword {
+            path: "Family|Alice".into(),
+            synonyms: vec!["Al".into(), "Ally".into()],
+        }))?;
+        // Moving onto an existing keyword merges them.
+        block_on(store.move_keyword("People", "Family"))?;
+        let keywords = block_on(store.get_keywords())?;
+        let alice = keywords.iter().find(|x| x.path == "Family|Alice").unwrap();
+        assert_eq!(alice.synonyms, ["Al", "Ally"]);
+        assert!(keywords.iter().all(|x| !x.path.starts_with("People")));
+        assert_eq!(
+            block_on(store.get_image("/a/x.jpg"))?.unwrap().tags,
+            ["Family|Alice"]
+        );
+        assert!(block_on(store.move_keyword("Family", "Family|Alice")).is_err());
+
+        block_on(store.delete_keyword("Family"))?;
+        assert_eq!(
+            block_on(store.get_image("/a/y.jpg"))?.unwrap().tags,
+            ["Places"]
+        );
+        assert!(block_on(store.get_image("/a/x.jpg"))?
+            .unwrap()
+            .tags
+            .is_empty());
+        let keywords: Vec<String> = block_on(store.get_keywords())?
+            .into_iter()
+            .map(|x| x.path)
+            .collect();
+        assert_eq!(keywords, ["Places"]);
+        Ok(())
+    }
+}
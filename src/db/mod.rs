@@ -1,3 +1,11 @@
 //! Image Collection
 
 pub mod datastore;
+pub mod external;
+pub mod import;
+pub mod keywords;
+pub mod memory;
+pub mod query;
+pub mod sqlite;
+pub mod sync;
+pub mod watch;
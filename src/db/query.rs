@@ -0,0 +1,616 @@
+//! Search queries over the catalog, the rules of smart collections.
+//!
+//! A query is terms combined with `AND`, `OR`, `NOT` and parentheses, like
+//! `camera:"X-T4" AND iso>3200 AND rating>=3 AND date:2025-06` or
+//! `tag:Places|Berlin NOT flag:reject`. Terms side by side must all match,
+//! and `-term` is `NOT term`. The operator words are upper case.
+//!
+//! A term is a field, an operator and a value, quoted if it has spaces:
+//!
+//! - `:` is contains for text, ignoring case, and for `tag` the keyword
+//!   anywhere along a path, so `tag:Berlin` finds `Places|Berlin|Mitte`.
+//! - `=` is equal, ignoring case, and `!=` its opposite.
+//! - `<`, `<=`, `>` and `>=` compare numbers and text.
+//! - `date` compares the time taken cut to the length of the value, so
+//!   `date:2025-06` is June 2025 and `date>2025-06` is after June.
+//! - A bare word or quoted text searches paths, titles, descriptions and
+//!   tags.
+//!
+//! Backends turn a [`Query`] into their own filters; [`Query::matches`] is
+//! the reference they follow. A missing value, like the ISO of a scan,
+//! matches no term.
+
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Result};
+
+use super::datastore::{ColorLabel, Flag, Image};
+use super::keywords::SEPARATOR;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// Every image; the empty query.
+    All,
+    Term(Term),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+/// `field op value`. `!=` is parsed as `NOT field=value`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Term {
+    pub field: Field,
+    pub op: Op,
+    pub value: Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// Path, title, description and tags.
+    Text,
+    Path,
+    Collection,
+    Title,
+    Description,
+    Camera,
+    Lens,
+    Tag,
+    Date,
+    Iso,
+    Aperture,
+    Exposure,
+    FocalLength,
+    Width,
+    Height,
+    Rating,
+    Label,
+    Flag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `:`
+    Has,
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(f64),
+    Label(Option<ColorLabel>),
+    Flag(Flag),
+}
+
+impl Field {
+    pub const ALL: [Field; 18] = [
+        Field::Text,
+        Field::Path,
+        Field::Collection,
+        Field::Title,
+        Field::Description,
+        Field::Camera,
+        Field::Lens,
+        Field::Tag,
+        Field::Date,
+        Field::Iso,
+        Field::Aperture,
+        Field::Exposure,
+        Field::FocalLength,
+        Field::Width,
+        Field::Height,
+        Field::Rating,
+        Field::Label,
+        Field::Flag,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Text => "text",
+            Field::Path => "path",
+            Field::Collection => "collection",
+            Field::Title => "title",
+            Field::Description => "description",
+            Field::Camera => "camera",
+            Field::Lens => "lens",
+            Field::Tag => "tag",
+            Field::Date => "date",
+            Field::Iso => "iso",
+            Field::Aperture => "aperture",
+            Field::Exposure => "exposure",
+            Field::FocalLength => "focal",
+            Field::Width => "width",
+            Field::Height => "height",
+            Field::Rating => "rating",
+            Field::Label => "label",
+            Field::Flag => "flag",
+        }
+    }
+
+    /// The field called `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Field> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.name().eq_ignore_ascii_case(name))
+    }
+
+    fn is_number(self) -> bool {
+        matches!(
+            self,
+            Field::Iso
+                | Field::Aperture
+                | Field::Exposure
+                | Field::FocalLength
+                | Field::Width
+                | Field::Height
+                | Field::Rating
+        )
+    }
+}
+
+impl Op {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Op::Has => ":",
+            Op::Eq => "=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+
+    /// Whether a value ordered `ord` against the term's value matches.
+    pub fn holds(self, ord: Ordering) -> bool {
+        match self {
+            Op::Has | Op::Eq => ord.is_eq(),
+            Op::Lt => ord.is_lt(),
+            Op::Le => ord.is_le(),
+            Op::Gt => ord.is_gt(),
+            Op::Ge => ord.is_ge(),
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query> {
+        let mut parser = Parser { text, pos: 0 };
+        if parser.peek().is_none() {
+            return Ok(Query::All);
+        }
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(c) => Err(anyhow!("unexpected `{}` at {}", c, parser.pos + 1)),
+        }
+    }
+
+    pub fn matches(&self, image: &Image) -> bool {
+        match self {
+            Query::All => true,
+            Query::Term(term) => term.matches(image),
+            Query::Not(query) => !query.matches(image),
+            Query::And(all) => all.iter().all(|x| x.matches(image)),
+            Query::Or(any) => any.iter().any(|x| x.matches(image)),
+        }
+    }
+}
+
+fn contains(text: &str, part: &str) -> bool {
+    text.to_ascii_lowercase()
+        .contains(&part.to_ascii_lowercase())
+}
+
+/// Whether the keyword path `tag` has the names of `part` in a row.
+pub fn tag_has(tag: &str, part: &str) -> bool {
+    let wrap = |x: &str| format!("{1}{0}{1}", x.to_ascii_lowercase(), SEPARATOR);
+    wrap(tag).contains(&wrap(part))
+}
+
+impl Term {
+    pub fn new(field: Field, op: Op, value: &str) -> Result<Term> {
+        let value = if field.is_number() {
+            Value::Number(
+                number(value)
+                    .ok_or_else(|| anyhow!("{}: `{}` is not a number", field.name(), value))?,
+            )
+        } else if field == Field::Label {
+            Value::Label(match value.to_ascii_lowercase().as_str() {
+                "none" => None,
+                _ => Some(ColorLabel::from_name(value).ok_or_else(|| {
+                    let names: Vec<&str> = ColorLabel::ALL.iter().map(|x| x.name()).collect();
+                    anyhow!("label: `{}` is not none or {}", value, names.join(", "))
+                })?),
+            })
+        } else if field == Field::Flag {
+            Value::Flag(match value.to_ascii_lowercase().as_str() {
+                "pick" | "picked" => Flag::Pick,
+                "reject" | "rejected" => Flag::Reject,
+                "none" | "unflagged" => Flag::Unflagged,
+                _ => return Err(anyhow!("flag: `{}` is not pick, reject or none", value)),
+            })
+        } else {
+            Value::Text(value.to_owned())
+        };
+        let allowed: &[Op] = match field {
+            Field::Text => &[Op::Has],
+            Field::Tag | Field::Label | Field::Flag => &[Op::Has, Op::Eq],
+            _ => &[Op::Has, Op::Eq, Op::Lt, Op::Le, Op::Gt, Op::Ge],
+        };
+        if !allowed.contains(&op) {
+            return Err(anyhow!("{}: cannot use `{}`", field.name(), op.symbol()));
+        }
+        Ok(Term { field, op, value })
+    }
+
+    pub fn matches(&self, image: &Image) -> bool {
+        let shot = &image.shot;
+        match (&self.value, self.field) {
+            (Value::Text(v), Field::Text) => {
+                contains(&image.path, v)
+                    || contains(&image.title, v)
+                    || contains(&image.description, v)
+                    || image.tags.iter().any(|x| contains(x, v))
+            }
+            (Value::Text(v), Field::Tag) => image.tags.iter().any(|x| match self.op {
+                Op::Eq => x.eq_ignore_ascii_case(v),
+                _ => tag_has(x, v),
+            }),
+            (Value::Text(v), Field::Date) => shot.taken.as_deref().is_some_and(|x| {
+                let cut: String = x.chars().take(v.chars().count()).collect();
+                self.op.holds(cut.as_str().cmp(v))
+            }),
+            (Value::Text(v), field) => {
+                let text = match field {
+                    Field::Path => Some(image.path.as_str()),
+                    Field::Collection => Some(image.collection.as_str()),
+                    Field::Title => Some(image.title.as_str()),
+                    Field::Description => Some(image.description.as_str()),
+                    Field::Camera => shot.camera.as_deref(),
+                    Field::Lens => shot.lens.as_deref(),
+                    _ => None,
+                };
+                text.is_some_and(|x| match self.op {
+                    Op::Has => contains(x, v),
+                    Op::Eq => x.eq_ignore_ascii_case(v),
+                    op => op.holds(x.cmp(v)),
+                })
+            }
+            (Value::Number(v), field) => {
+                let number = match field {
+                    Field::Iso => shot.iso.map(f64::from),
+                    Field::Aperture => shot.aperture,
+                    Field::Exposure => shot.exposure,
+                    Field::FocalLength => shot.focal_length,
+                    Field::Width => Some(image.size.0 as f64),
+                    Field::Height => Some(image.size.1 as f64),
+                    Field::Rating => Some(image.marks.rating as f64),
+                    _ => None,
+                };
+                number
+                    .and_then(|x| x.partial_cmp(v))
+                    .is_some_and(|x| self.op.holds(x))
+            }
+            (Value::Label(v), _) => image.marks.label == *v,
+            (Value::Flag(v), _) => image.marks.flag == *v,
+        }
+    }
+}
+
+/// A number, or a fraction like the exposure `1/250`.
+fn number(text: &str) -> Option<f64> {
+    let x = match text.split_once('/') {
+        Some((num, den)) => num.parse::<f64>().ok()? / den.parse::<f64>().ok()?,
+        None => text.parse().ok()?,
+    };
+    x.is_finite().then_some(x)
+}
+
+/// Recursive descent over `text`:
+///
+/// ```text
+/// or    = and ("OR" and)*
+/// and   = unary (["AND"] unary)*
+/// unary = "NOT" unary | "-" unary | "(" or ")" | term
+/// term  = name op value | word | "quoted"
+/// ```
+struct Parser<'a> {
+    text: &'a str,
+    /// Byte offset of the next character.
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    /// The next character after spaces.
+    fn peek(&mut self) -> Option<char> {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+        self.rest().chars().next()
+    }
+
+    /// Whether the operator word `word` comes next.
+    fn at_keyword(&mut self, word: &str) -> bool {
+        self.peek();
+        self.rest()
+            .strip_prefix(word)
+            .is_some_and(|x| x.is_empty() || x.starts_with(|c: char| c.is_whitespace() || c == '('))
+    }
+
+    /// Skip the operator word `word` if it comes next.
+    fn keyword(&mut self, word: &str) -> bool {
+        let found = self.at_keyword(word);
+        if found {
+            self.pos += word.len();
+        }
+        found
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &str {
+        let start = self.pos;
+        let len = self.rest().find(|c| !f(c)).unwrap_or(self.rest().len());
+        self.pos += len;
+        &self.text[start..self.pos]
+    }
+
+    fn or(&mut self) -> Result<Query> {
+        let mut any = vec![self.and()?];
+        while self.keyword("OR") {
+            any.push(self.and()?);
+        }
+        Ok(match any.len() {
+            1 => any.pop().unwrap(),
+            _ => Query::Or(any),
+        })
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut all = vec![self.unary()?];
+        loop {
+            if matches!(self.peek(), None | Some(')')) || self.at_keyword("OR") {
+                break;
+            }
+            self.keyword("AND");
+            all.push(self.unary()?);
+        }
+        Ok(match all.len() {
+            1 => all.pop().unwrap(),
+            _ => Query::And(all),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Query> {
+        if self.keyword("NOT") {
+            return Ok(Query::Not(Box::new(self.unary()?)));
+        }
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(Query::Not(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                self.pos += 1;
+                let query = self.or()?;
+                if self.peek() != Some(')') {
+                    return Err(anyhow!("missing `)` at {}", self.pos + 1));
+                }
+                self.pos += 1;
+                Ok(query)
+            }
+            None | Some(')') => Err(anyhow!("missing a term at {}", self.pos + 1)),
+            _ if self.at_keyword("AND") || self.at_keyword("OR") => {
+                Err(anyhow!("missing a term at {}", self.pos + 1))
+            }
+            _ => self.term(),
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let text = self.take_while(|c| c != '"').to_owned();
+        if !self.rest().starts_with('"') {
+            return Err(anyhow!("unclosed quote at {}", start + 1));
+        }
+        self.pos += 1;
+        Ok(text)
+    }
+
+    fn op(&mut self) -> Option<(Op, bool)> {
+        let ops = [
+            ("!=", Op::Eq, true),
+            ("<=", Op::Le, false),
+            (">=", Op::Ge, false),
+            (":", Op::Has, false),
+            ("=", Op::Eq, false),
+            ("<", Op::Lt, false),
+            (">", Op::Gt, false),
+        ];
+        let (symbol, op, not) = ops.into_iter().find(|x| self.rest().starts_with(x.0))?;
+        self.pos += symbol.len();
+        Some((op, not))
+    }
+
+    fn term(&mut self) -> Result<Query> {
+        if self.peek() == Some('"') {
+            let text = self.quoted()?;
+            return Ok(Query::Term(Term::new(Field::Text, Op::Has, &text)?));
+        }
+        let start = self.pos;
+        let name = self
+            .take_while(|c| !c.is_whitespace() && !"()\":<>=!".contains(c))
+            .to_owned();
+        let Some((op, not)) = self.op() else {
+            if let (true, Some(c)) = (name.is_empty(), self.rest().chars().next()) {
+                return Err(anyhow!("unexpected `{}` at {}", c, self.pos + 1));
+            }
+            return Ok(Query::Term(Term::new(Field::Text, Op::Has, &name)?));
+        };
+        let field = Field::from_name(&name)
+            .ok_or_else(|| anyhow!("unknown field `{}` at {}", name, start + 1))?;
+        let value = if self.rest().starts_with('"') {
+            self.quoted()?
+        } else {
+            self.take_while(|c| !c.is_whitespace() && c != '(' && c != ')')
+                .to_owned()
+        };
+        if value.is_empty() {
+            return Err(anyhow!("missing a value at {}", self.pos + 1));
+        }
+        let term = Query::Term(Term::new(field, op, &value)?);
+        Ok(if not {
+            Query::Not(Box::new(term))
+        } else {
+            term
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Field, op: Op, value: &str) -> Query {
+        Query::Term(Term::new(field, op, value).unwrap())
+    }
+
+    fn text(value: &str) -> Query {
+        term(Field::Text, Op::Has, value)
+    }
+
+    fn error(text: &str) -> String {
+        Query::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn precedence() -> Result<()> {
+        let (a, b, c) = (text("a"), text("b"), text("c"));
+        let ab = Query::And(vec![a.clone(), b.clone()]);
+        assert_eq!(Query::parse("")?, Query::All);
+        assert_eq!(Query::parse("  ")?, Query::All);
+        assert_eq!(Query::parse("a b")?, ab);
+        assert_eq!(Query::parse("a AND b")?, ab);
+        assert_eq!(
+            Query::parse("a b OR c")?,
+            Query::Or(vec![ab.clone(), c.clone()])
+        );
+        assert_eq!(
+            Query::parse("c OR a AND b")?,
+            Query::Or(vec![c.clone(), ab.clone()])
+        );
+        assert_eq!(
+            Query::parse("a (b OR c)")?,
+            Query::And(vec![a.clone(), Query::Or(vec![b.clone(), c.clone()])])
+        );
+        assert_eq!(
+            Query::parse("NOT a b")?,
+            Query::And(vec![Query::Not(Box::new(a.clone())), b.clone()])
+        );
+        assert_eq!(
+            Query::parse("-(a OR b)")?,
+            Query::Not(Box::new(Query::Or(vec![a.clone(), b.clone()])))
+        );
+        assert_eq!(
+            Query::parse("NOT -a")?,
+            Query::Not(Box::new(Query::Not(Box::new(a.clone()))))
+        );
+        // Operator words are upper case; others are search words.
+        assert_eq!(
+            Query::parse("a and b")?,
+            Query::And(vec![a.clone(), text("and"), b.clone()])
+        );
+        assert_eq!(Query::parse("ORDER")?, text("ORDER"));
+        Ok(())
+    }
+
+    #[test]
+    fn terms() -> Result<()> {
+        assert_eq!(
+            Query::parse("camera:\"X-T4 (2020)\"")?,
+            term(Field::Camera, Op::Has, "X-T4 (2020)")
+        );
+        assert_eq!(Query::parse("\"it's NOT OR\"")?, text("it's NOT OR"));
+        assert_eq!(
+            Query::parse("lens!=none")?,
+            Query::Not(Box::new(term(Field::Lens, Op::Eq, "none")))
+        );
+        assert_eq!(
+            Query::parse("ISO>=3200 Focal<50")?,
+            Query::And(vec![
+                term(Field::Iso, Op::Ge, "3200"),
+                term(Field::FocalLength, Op::Lt, "50"),
+            ])
+        );
+        assert_eq!(
+            Query::parse("exposure<=1/250")?,
+            Query::Term(Term {
+                field: Field::Exposure,
+                op: Op::Le,
+                value: Value::Number(0.004),
+            })
+        );
+        assert_eq!(
+            Query::parse("tag:Places|Berlin date>2025-06")?,
+            Query::And(vec![
+                term(Field::Tag, Op::Has, "Places|Berlin"),
+                term(Field::Date, Op::Gt, "2025-06"),
+            ])
+        );
+        assert_eq!(
+            Query::parse("(label=none)")?,
+            Query::Term(Term {
+                field: Field::Label,
+                op: Op::Eq,
+                value: Value::Label(None),
+            })
+        );
+        assert_eq!(
+            Query::parse("flag:picked")?,
+            Query::Term(Term {
+                field: Field::Flag,
+                op: Op::Has,
+                value: Value::Flag(Flag::Pick),
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(error("a)"), "unexpected `)` at 2");
+        assert_eq!(error("!a"), "unexpected `!` at 1");
+        assert_eq!(error("(a"), "missing `)` at 3");
+        assert_eq!(error("a AND"), "missing a term at 6");
+        assert_eq!(error("OR a"), "missing a term at 1");
+        assert_eq!(error("()"), "missing a term at 2");
+        assert_eq!(error("title:\"x"), "unclosed quote at 7");
+        assert_eq!(error("a \"b"), "unclosed quote at 3");
+        assert_eq!(error("colour:red"), "unknown field `colour` at 1");
+        assert_eq!(error("iso>"), "missing a value at 5");
+        assert_eq!(error("tag<x"), "tag: cannot use `<`");
+        assert_eq!(error("text=x"), "text: cannot use `=`");
+        assert_eq!(error("iso>lots"), "iso: `lots` is not a number");
+        assert_eq!(error("exposure:1/0"), "exposure: `1/0` is not a number");
+        assert!(error("label:pink").starts_with("label: `pink` is not none or"));
+        assert_eq!(
+            error("flag:maybe"),
+            "flag: `maybe` is not pick, reject or none"
+        );
+    }
+
+    #[test]
+    fn tags() {
+        assert!(tag_has("Places|Berlin|Mitte", "berlin"));
+        assert!(tag_has("Places|Berlin|Mitte", "Berlin|Mitte"));
+        assert!(!tag_has("Places|Berlin|Mitte", "Berl"));
+        assert!(!tag_has("Places|Berlin|Mitte", "Places|Mitte"));
+    }
+}
@@ -0,0 +1,1323 @@
+//! SQLite catalog.
+//!
+//! The connection lives on a worker thread; every call is sent there and
+//! awaited through a channel, so the async executor never blocks on disk.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Params, Row};
+
+use super::datastore::{
+    Collection, ColorLabel, Datastore, Fields, Flag, Image, Marks, Page, Pending, Shot,
+    SmartCollection, SyncPolicy,
+};
+use super::keywords::{self, Keyword};
+use super::query::{self, Field, Op, Query, Term};
+use crate::loader::meta::Gps;
+
+/// Schema changes, applied in order. `PRAGMA user_version` holds the number
+/// applied so far. Never edit an entry; append a new one.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "
+    CREATE TABLE kv (
+        key TEXT PRIMARY KEY,
+        value BLOB NOT NULL
+    );
+    CREATE TABLE collections (
+        name TEXT PRIMARY KEY,
+        folder TEXT NOT NULL
+    );
+    CREATE TABLE images (
+        path TEXT PRIMARY KEY,
+        collection TEXT NOT NULL REFERENCES collections(name)
+            ON DELETE CASCADE ON UPDATE CASCADE,
+        width INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        description TEXT NOT NULL DEFAULT ''
+    );
+    CREATE INDEX images_collection ON images(collection, path);
+    CREATE TABLE tags (
+        image TEXT NOT NULL REFERENCES images(path)
+            ON DELETE CASCADE ON UPDATE CASCADE,
+        tag TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        PRIMARY KEY (image, tag)
+    );
+    CREATE INDEX tags_tag ON tags(tag);
+    ",
+    // 2: culling marks; flag is -1 rejected, 0 unflagged, 1 picked
+    "
+    ALTER TABLE images ADD COLUMN rating INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE images ADD COLUMN label TEXT;
+    ALTER TABLE images ADD COLUMN flag INTEGER NOT NULL DEFAULT 0;
+    ",
+    // 3: keyword tree, filled from the tags and the paths above them
+    "
+    CREATE TABLE keywords (
+        path TEXT PRIMARY KEY
+    );
+    CREATE TABLE synonyms (
+        keyword TEXT NOT NULL REFERENCES keywords(path)
+            ON DELETE CASCADE ON UPDATE CASCADE,
+        synonym TEXT NOT NULL,
+        PRIMARY KEY (keyword, synonym)
+    );
+    WITH RECURSIVE k(path) AS (
+        SELECT DISTINCT tag FROM tags
+        UNION
+        -- Strip the last name: trailing non-separators, then the separator.
+        SELECT rtrim(rtrim(path, replace(path, '|', '')), '|') FROM k
+        WHERE instr(path, '|') > 0
+    )
+    INSERT OR IGNORE INTO keywords (path) SELECT path FROM k;
+    ",
+    // 4: capture settings and smart collections
+    "
+    ALTER TABLE images ADD COLUMN camera TEXT;
+    ALTER TABLE images ADD COLUMN lens TEXT;
+    ALTER TABLE images ADD COLUMN iso INTEGER;
+    ALTER TABLE images ADD COLUMN aperture REAL;
+    ALTER TABLE images ADD COLUMN exposure REAL;
+    ALTER TABLE images ADD COLUMN focal_length REAL;
+    ALTER TABLE images ADD COLUMN taken TEXT;
+    CREATE INDEX images_taken ON images(taken);
+    CREATE TABLE smart_collections (
+        name TEXT PRIMARY KEY,
+        query TEXT NOT NULL
+    );
+    -- Capture settings are read on import: have the next one read every file.
+    DELETE FROM kv WHERE key LIKE 'import/%';
+    ",
+    // 5: titles
+    "
+    ALTER TABLE images ADD COLUMN title TEXT NOT NULL DEFAULT '';
+    ",
+    // 6: GPS, write-back policies and edits waiting to be written back
+    "
+    ALTER TABLE images ADD COLUMN latitude REAL;
+    ALTER TABLE images ADD COLUMN longitude REAL;
+    ALTER TABLE images ADD COLUMN altitude REAL;
+    ALTER TABLE collections ADD COLUMN sync TEXT NOT NULL DEFAULT 'off';
+    CREATE TABLE pending (
+        path TEXT PRIMARY KEY REFERENCES images(path)
+            ON DELETE CASCADE ON UPDATE CASCADE,
+        fields INTEGER NOT NULL
+    );
+    ",
+];
+
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// [`Datastore`] backed by a SQLite database.
+pub struct SqliteStore {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl SqliteStore {
+    /// `catalog.db` under the platform data dir.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|x| x.join("phany").join("catalog.db"))
+    }
+
+    /// Open or create the catalog at `path`, migrating it to the current
+    /// schema.
+    pub fn open(path: &Path) -> Result<SqliteStore> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("opening {}", path.to_string_lossy()))?;
+        // WAL lets readers in other processes proceed during writes.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Self::start(conn)
+    }
+
+    /// A catalog that lives only as long as the store.
+    pub fn open_in_memory() -> Result<SqliteStore> {
+        Self::start(Connection::open_in_memory()?)
+    }
+
+    fn start(mut conn: Connection) -> Result<SqliteStore> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        let (jobs, rx) = mpsc::channel::<Job>();
+        std::thread::Builder::new()
+            .name("sqlite".into())
+            .spawn(move || {
+                // Ends when the store, and with it the sender, is dropped.
+                for job in rx {
+                    job(&mut conn);
+                }
+            })?;
+        Ok(SqliteStore { jobs })
+    }
+
+    /// Run `f` on the worker thread and wait for its result.
+    async fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.jobs
+            .send(Box::new(move |conn| {
+                let _ = tx.send(f(conn));
+            }))
+            .map_err(|_| anyhow!("datastore closed"))?;
+        rx.await.map_err(|_| anyhow!("datastore closed"))?
+    }
+}
+
+/// Apply the migrations `conn` has not seen, each in its own transaction.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "catalog schema {} is newer than {}",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)
+            .with_context(|| format!("catalog migration {}", i + 1))?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+        log::info!("catalog migrated to schema {}", i + 1);
+    }
+    Ok(())
+}
+
+fn image_row(row: &Row) -> rusqlite::Result<Image> {
+    Ok(Image {
+        path: row.get(0)?,
+        collection: row.get(1)?,
+        size: (row.get(2)?, row.get(3)?),
+        description: row.get(4)?,
+        tags: vec![],
+        marks: Marks {
+            rating: row.get(5)?,
+            label: row
+                .get::<_, Option<String>>(6)?
+                .and_then(|x| ColorLabel::from_name(&x)),
+            flag: match row.get::<_, i32>(7)? {
+                x if x < 0 => Flag::Reject,
+                0 => Flag::Unflagged,
+                _ => Flag::Pick,
+            },
+        },
+        shot: Shot {
+            camera: row.get(8)?,
+            lens: row.get(9)?,
+            iso: row.get(10)?,
+            aperture: row.get(11)?,
+            exposure: row.get(12)?,
+            focal_length: row.get(13)?,
+            taken: row.get(14)?,
+        },
+        title: row.get(15)?,
+        gps: match (row.get(16)?, row.get(17)?) {
+            (Some(latitude), Some(longitude)) => Some(Gps {
+                latitude,
+                longitude,
+                altitude: row.get::<_, Option<f64>>(18)?.unwrap_or(0.),
+            }),
+            _ => None,
+        },
+    })
+}
+
+const IMAGE_COLUMNS: &str = "path, collection, width, height, description, rating, label, flag, \
+                             camera, lens, iso, aperture, exposure, focal_length, taken, title, \
+                             latitude, longitude, altitude";
+
+fn flag_value(flag: Flag) -> i32 {
+    match flag {
+        Flag::Reject => -1,
+        Flag::Unflagged => 0,
+        Flag::Pick => 1,
+    }
+}
+
+/// Fill in the tags of `images`.
+fn load_tags(conn: &Connection, images: &mut [Image]) -> Result<()> {
+    // Bounded, to stay under the SQL variable limit.
+    for chunk in images.chunks_mut(500) {
+        let index: HashMap<String, usize> = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.path.clone(), i))
+            .collect();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT image, tag FROM tags WHERE image IN ({}) ORDER BY image, position",
+            vec!["?"; chunk.len()].join(", ")
+        ))?;
+        let mut rows = stmt.query(params_from_iter(chunk.iter().map(|x| x.path.clone())))?;
+        while let Some(row) = rows.next()? {
+            let path: String = row.get(0)?;
+            if let Some(&i) = index.get(&path) {
+                chunk[i].tags.push(row.get(1)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Images matching `filter`, a condition on the images table, in path
+/// order, at most `limit` of them.
+fn query_images(
+    conn: &Connection,
+    filter: &str,
+    params: impl Params,
+    limit: Option<usize>,
+) -> Result<Vec<Image>> {
+    let limit = limit.map_or_else(String::new, |x| format!(" LIMIT {}", x));
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {} FROM images WHERE {} ORDER BY path{}",
+        IMAGE_COLUMNS, filter, limit
+    ))?;
+    let mut images = stmt
+        .query_map(params, image_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    load_tags(conn, &mut images)?;
+    Ok(images)
+}
+
+/// SQL condition on the images table for `query`, as
+/// [`Query::matches`], with its values appended to `values`.
+fn query_sql(query: &Query, values: &mut Vec<Value>) -> String {
+    let mut join = |queries: &[Query], op: &str| {
+        let parts: Vec<String> = queries.iter().map(|x| query_sql(x, values)).collect();
+        format!("({})", parts.join(op))
+    };
+    match query {
+        Query::All => "1".to_owned(),
+        // A missing value is false, also under NOT.
+        Query::Term(term) => format!("coalesce({}, 0)", term_sql(term, values)),
+        Query::Not(query) => format!("NOT {}", query_sql(query, values)),
+        Query::And(all) => join(all, " AND "),
+        Query::Or(any) => join(any, " OR "),
+    }
+}
+
+fn term_sql(term: &Term, values: &mut Vec<Value>) -> String {
+    let mut bind = |value: Value| {
+        values.push(value);
+        format!("?{}", values.len())
+    };
+    let op = match term.op {
+        Op::Has | Op::Eq => "=",
+        op => op.symbol(),
+    };
+    let column = match term.field {
+        Field::Path => "path",
+        Field::Collection => "collection",
+        Field::Title => "title",
+        Field::Description => "description",
+        Field::Camera => "camera",
+        Field::Lens => "lens",
+        Field::Date => "taken",
+        Field::Iso => "iso",
+        Field::Aperture => "aperture",
+        Field::Exposure => "exposure",
+        Field::FocalLength => "focal_length",
+        Field::Width => "width",
+        Field::Height => "height",
+        Field::Rating => "rating",
+        Field::Label => "label",
+        Field::Flag => "flag",
+        Field::Text | Field::Tag => "",
+    };
+    // `lower` only folds ASCII, like `to_ascii_lowercase`.
+    let tags = |condition: String| {
+        format!(
+            "EXISTS (SELECT 1 FROM tags WHERE image = images.path AND {})",
+            condition
+        )
+    };
+    match (&term.value, term.field) {
+        (query::Value::Text(v), Field::Text) => {
+            let v = bind(v.to_ascii_lowercase().into());
+            format!(
+                "(instr(lower(path), {0}) > 0 OR instr(lower(title), {0}) > 0 \
+                 OR instr(lower(description), {0}) > 0 OR {1})",
+                v,
+                tags(format!("instr(lower(tag), {}) > 0", v))
+            )
+        }
+        (query::Value::Text(v), Field::Tag) if term.op == Op::Eq => tags(format!(
+            "lower(tag) = {}",
+            bind(v.to_ascii_lowercase().into())
+        )),
+        (query::Value::Text(v), Field::Tag) => {
+            let v = format!("{1}{0}{1}", v.to_ascii_lowercase(), keywords::SEPARATOR);
+            tags(format!(
+                "instr('{1}' || lower(tag) || '{1}', {0}) > 0",
+                bind(v.into()),
+                keywords::SEPARATOR
+            ))
+        }
+        (query::Value::Text(v), Field::Date) => {
+            let v = bind(v.clone().into());
+            format!("substr(taken, 1, length({0})) {1} {0}", v, op)
+        }
+        (query::Value::Text(v), _) => match term.op {
+            Op::Has => format!(
+                "instr(lower({}), {}) > 0",
+                column,
+                bind(v.to_ascii_lowercase().into())
+            ),
+            Op::Eq => format!(
+                "lower({}) = {}",
+                column,
+                bind(v.to_ascii_lowercase().into())
+            ),
+            _ => format!("{} {} {}", column, op, bind(v.clone().into())),
+        },
+        (query::Value::Number(v), _) => format!("{} {} {}", column, op, bind((*v).into())),
+        (query::Value::Label(Some(v)), _) => {
+            format!("label = {}", bind(v.name().to_owned().into()))
+        }
+        (query::Value::Label(None), _) => "label IS NULL".to_owned(),
+        (query::Value::Flag(v), _) => format!("flag = {}", bind(flag_value(*v).into())),
+    }
+}
+
+fn collection_row(row: &Row) -> rusqlite::Result<Collection> {
+    Ok(Collection {
+        folder: row.get(0)?,
+        name: row.get(1)?,
+        sync: SyncPolicy::from_name(&row.get::<_, String>(2)?).unwrap_or_default(),
+    })
+}
+
+fn smart_collection_row(row: &Row) -> rusqlite::Result<SmartCollection> {
+    Ok(SmartCollection {
+        name: row.get(0)?,
+        query: row.get(1)?,
+    })
+}
+
+fn write_tags(conn: &Connection, path: &str, tags: &[String]) -> Result<()> {
+    conn.execute("DELETE FROM tags WHERE image = ?1", [path])?;
+    let mut stmt = conn
+        .prepare_cached("INSERT OR IGNORE INTO tags (image, tag, position) VALUES (?1, ?2, ?3)")?;
+    for (i, tag) in tags.iter().enumerate() {
+        stmt.execute(params![path, tag, i])?;
+        add_keyword(conn, tag)?;
+    }
+    Ok(())
+}
+
+/// Add `path` and the keywords above it to the tree.
+fn add_keyword(conn: &Connection, path: &str) -> Result<()> {
+    let mut stmt = conn.prepare_cached("INSERT OR IGNORE INTO keywords (path) VALUES (?1)")?;
+    for path in keywords::ancestors(path) {
+        stmt.execute([path])?;
+    }
+    Ok(())
+}
+
+/// `column` with the keyword `?1` at its start replaced by `?2`.
+fn moved(column: &str) -> String {
+    format!("?2 || substr({}, length(?1) + 1)", column)
+}
+
+/// Condition on `column` for `?1` and the keywords under it.
+fn under(column: &str) -> String {
+    format!(
+        "({0} = ?1 OR substr({0}, 1, length(?1) + 1) = ?1 || '{1}')",
+        column,
+        keywords::SEPARATOR
+    )
+}
+
+#[async_trait]
+impl Datastore for SqliteStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = key.to_owned();
+        self.call(move |conn| {
+            Ok(conn
+                .query_row("SELECT value FROM kv WHERE key = ?1", [key], |r| r.get(0))
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        let (key, value) = (key.to_owned(), value.to_owned());
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO kv (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let key = key.to_owned();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM kv WHERE key = ?1", [key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_collections(&self) -> Result<Vec<Collection>> {
+        self.call(|conn| {
+            let mut stmt =
+                conn.prepare_cached("SELECT folder, name, sync FROM collections ORDER BY name")?;
+            let collections = stmt
+                .query_map([], collection_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(collections)
+        })
+        .await
+    }
+
+    async fn get_collections_page(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Collection>> {
+        let after = after.unwrap_or_default().to_owned();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT folder, name, sync FROM collections WHERE name > ?1 ORDER BY name LIMIT ?2",
+            )?;
+            let items = stmt
+                .query_map(params![after, limit], collection_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(Page::new(items, limit, |x: &Collection| x.name.clone()))
+        })
+        .await
+    }
+
+    async fn get_collection(&self, name: &str) -> Result<Option<Collection>> {
+        let name = name.to_owned();
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT folder, name, sync FROM collections WHERE name = ?1",
+                    [name],
+                    collection_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn put_collection(&self, collection: &Collection) -> Result<()> {
+        let collection = collection.clone();
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO collections (name, folder, sync) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(name) DO UPDATE SET
+                    folder = excluded.folder,
+                    sync = excluded.sync",
+                params![collection.name, collection.folder, collection.sync.name()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_collection(&self, name: &str) -> Result<()> {
+        let name = name.to_owned();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM collections WHERE name = ?1", [name])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_images_in_collection(&self, collection: &str) -> Result<Vec<Image>> {
+        let collection = collection.to_owned();
+        self.call(move |conn| query_images(conn, "collection = ?1", [collection], None))
+            .await
+    }
+
+    async fn get_images_page(
+        &self,
+        collection: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Image>> {
+        let (collection, after) = (collection.to_owned(), after.unwrap_or_default().to_owned());
+        self.call(move |conn| {
+            let items = query_images(
+                conn,
+                "collection = ?1 AND path > ?2",
+                [collection, after],
+                Some(limit),
+            )?;
+            Ok(Page::new(items, limit, |x: &Image| x.path.clone()))
+        })
+        .await
+    }
+
+    async fn get_image(&self, path: &str) -> Result<Option<Image>> {
+        let path = path.to_owned();
+        self.call(move |conn| Ok(query_images(conn, "path = ?1", [path], None)?.pop()))
+            .await
+    }
+
+    async fn put_image(&self, image: &Image) -> Result<()> {
+        let image = image.clone();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                &format!(
+                    "INSERT INTO images ({})
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)
+                     ON CONFLICT(path) DO UPDATE SET
+                        collection = excluded.collection,
+                        width = excluded.width,
+                        height = excluded.height,
+                        description = excluded.description,
+                        rating = excluded.rating,
+                        label = excluded.label,
+                        flag = excluded.flag,
+                        camera = excluded.camera,
+                        lens = excluded.lens,
+                        iso = excluded.iso,
+                        aperture = excluded.aperture,
+                        exposure = excluded.exposure,
+                        focal_length = excluded.focal_length,
+                        taken = excluded.taken,
+                        title = excluded.title,
+                        latitude = excluded.latitude,
+                        longitude = excluded.longitude,
+                        altitude = excluded.altitude",
+                    IMAGE_COLUMNS
+                ),
+                params![
+                    image.path,
+                    image.collection,
+                    image.size.0,
+                    image.size.1,
+                    image.description,
+                    image.marks.rating,
+                    image.marks.label.map(ColorLabel::name),
+                    flag_value(image.marks.flag),
+                    image.shot.camera,
+                    image.shot.lens,
+                    image.shot.iso,
+                    image.shot.aperture,
+                    image.shot.exposure,
+                    image.shot.focal_length,
+                    image.shot.taken,
+                    image.title,
+                    image.gps.map(|x| x.latitude),
+                    image.gps.map(|x| x.longitude),
+                    image.gps.map(|x| x.altitude),
+                ],
+            )
+            .with_context(|| format!("{}: storing image", image.path))?;
+            write_tags(&tx, &image.path, &image.tags)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_image(&self, path: &str) -> Result<()> {
+        let path = path.to_owned();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM images WHERE path = ?1", [path])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn rename_image(&self, from: &str, to: &str, collection: &str) -> Result<()> {
+        let (from, to, collection) = (from.to_owned(), to.to_owned(), collection.to_owned());
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            if from != to {
+                tx.execute("DELETE FROM images WHERE path = ?1", [&to])?;
+            }
+            // Tags follow through ON UPDATE CASCADE.
+            let n = tx.execute(
+                "UPDATE images SET path = ?2, collection = ?3 WHERE path = ?1",
+                [&from, &to, &collection],
+            )?;
+            if n == 0 {
+                return Err(anyhow!("{}: not in the catalog", from));
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_tags(&self, path: &str, tags: &[String]) -> Result<()> {
+        let (path, tags) = (path.to_owned(), tags.to_owned());
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let exists = tx
+                .query_row("SELECT 1 FROM images WHERE path = ?1", [&path], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if !exists {
+                return Err(anyhow!("{}: not in the catalog", path));
+            }
+            write_tags(&tx, &path, &tags)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_description(&self, path: &str, description: &str) -> Result<()> {
+        let (path, description) = (path.to_owned(), description.to_owned());
+        self.call(move |conn| {
+            let n = conn.execute(
+                "UPDATE images SET description = ?2 WHERE path = ?1",
+                [&path, &description],
+            )?;
+            if n == 0 {
+                return Err(anyhow!("{}: not in the catalog", path));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_marks(&self, path: &str, marks: Marks) -> Result<()> {
+        let path = path.to_owned();
+        self.call(move |conn| {
+            let n = conn.execute(
+                "UPDATE images SET rating = ?2, label = ?3, flag = ?4 WHERE path = ?1",
+                params![
+                    path,
+                    marks.rating,
+                    marks.label.map(ColorLabel::name),
+                    flag_value(marks.flag)
+                ],
+            )?;
+            if n == 0 {
+                return Err(anyhow!("{}: not in the catalog", path));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_images_with_tag(&self, tag: &str) -> Result<Vec<Image>> {
+        let tag = tag.to_owned();
+        self.call(move |conn| {
+            query_images(
+                conn,
+                "path IN (SELECT image FROM tags WHERE tag = ?1)",
+                [tag],
+                None,
+            )
+        })
+        .await
+    }
+
+    async fn get_keywords(&self) -> Result<Vec<Keyword>> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT path, synonym FROM keywords
+                 LEFT JOIN synonyms ON keyword = path
+                 ORDER BY path, synonym",
+            )?;
+            let mut rows = stmt.query([])?;
+            let mut keywords: Vec<Keyword> = vec![];
+            while let Some(row) = rows.next()? {
+                let path: String = row.get(0)?;
+                if keywords.last().is_none_or(|x| x.path != path) {
+                    keywords.push(Keyword::new(&path));
+                }
+                if let Some(synonym) = row.get::<_, Option<String>>(1)? {
+                    keywords.last_mut().unwrap().synonyms.push(synonym);
+                }
+            }
+            Ok(keywords)
+        })
+        .await
+    }
+
+    async fn put_keyword(&self, keyword: &Keyword) -> Result<()> {
+        let keyword = keyword.clone();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            add_keyword(&tx, &keyword.path)?;
+            tx.execute("DELETE FROM synonyms WHERE keyword = ?1", [&keyword.path])?;
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR IGNORE INTO synonyms (keyword, synonym) VALUES (?1, ?2)",
+            )?;
+            for synonym in &keyword.synonyms {
+                stmt.execute([&keyword.path, synonym])?;
+            }
+            drop(stmt);
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_keyword(&self, path: &str) -> Result<()> {
+        let path = path.to_owned();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(&format!("DELETE FROM tags WHERE {}", under("tag")), [&path])?;
+            tx.execute(
+                &format!("DELETE FROM keywords WHERE {}", under("path")),
+                [&path],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn move_keyword(&self, from: &str, to: &str) -> Result<()> {
+        let (from, to) = (from.to_owned(), to.to_owned());
+        if from == to {
+            return Ok(());
+        }
+        if keywords::is_under(&to, &from) {
+            return Err(anyhow!("cannot move {} under itself", from));
+        }
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            add_keyword(&tx, &to)?;
+            // Copy to the new paths, merging with what is there, then drop
+            // the old ones.
+            tx.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO keywords (path) SELECT {} FROM keywords WHERE {}",
+                    moved("path"),
+                    under("path")
+                ),
+                [&from, &to],
+            )?;
+            tx.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO synonyms (keyword, synonym)
+                     SELECT {}, synonym FROM synonyms WHERE {}",
+                    moved("keyword"),
+                    under("keyword")
+                ),
+                [&from, &to],
+            )?;
+            tx.execute(
+                &format!(
+                    "UPDATE OR IGNORE tags SET tag = {} WHERE {}",
+                    moved("tag"),
+                    under("tag")
+                ),
+                [&from, &to],
+            )?;
+            // Left over where the image already had the new tag.
+            tx.execute(&format!("DELETE FROM tags WHERE {}", under("tag")), [&from])?;
+            tx.execute(
+                &format!("DELETE FROM keywords WHERE {}", under("path")),
+                [&from],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_smart_collections(&self) -> Result<Vec<SmartCollection>> {
+        self.call(|conn| {
+            let mut stmt =
+                conn.prepare_cached("SELECT name, query FROM smart_collections ORDER BY name")?;
+            let collections = stmt
+                .query_map([], smart_collection_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(collections)
+        })
+        .await
+    }
+
+    async fn get_smart_collection(&self, name: &str) -> Result<Option<SmartCollection>> {
+        let name = name.to_owned();
+        self.call(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT name, query FROM smart_collections WHERE name = ?1",
+                    [name],
+                    smart_collection_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn put_smart_collection(&self, collection: &SmartCollection) -> Result<()> {
+        let collection = collection.clone();
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO smart_collections (name, query) VALUES (?1, ?2)
+                 ON CONFLICT(name) DO UPDATE SET query = excluded.query",
+                params![collection.name, collection.query],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_smart_collection(&self, name: &str) -> Result<()> {
+        let name = name.to_owned();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM smart_collections WHERE name = ?1", [name])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_images_page(
+        &self,
+        query: &Query,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<Image>> {
+        let (query, after) = (query.clone(), after.unwrap_or_default().to_owned());
+        self.call(move |conn| {
+            let mut values = vec![];
+            let filter = query_sql(&query, &mut values);
+            values.push(after.into());
+            let filter = format!("({}) AND path > ?{}", filter, values.len());
+            let items = query_images(conn, &filter, params_from_iter(values), Some(limit))?;
+            Ok(Page::new(items, limit, |x: &Image| x.path.clone()))
+        })
+        .await
+    }
+
+    async fn add_pending(&self, path: &str, fields: Fields) -> Result<()> {
+        let path = path.to_owned();
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO pending (path, fields) VALUES (?1, ?2)
+                 ON CONFLICT(path) DO UPDATE SET fields = fields | excluded.fields",
+                params![path, fields.0],
+            )
+            .map_err(|e| match e.sqlite_error_code() {
+                Some(rusqlite::ErrorCode::ConstraintViolation) => {
+                    anyhow!("{}: not in the catalog", path)
+                }
+                _ => e.into(),
+            })?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear_pending(&self, path: &str, fields: Fields) -> Result<()> {
+        let path = path.to_owned();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE pending SET fields = fields & ~?2 WHERE path = ?1",
+                params![path, fields.0],
+            )?;
+            tx.execute("DELETE FROM pending WHERE path = ?1 AND fields = 0", [path])?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_pending_page(&self, after: Option<&str>, limit: usize) -> Result<Page<Pending>> {
+        let after = after.unwrap_or_default().to_owned();
+        self.call(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT path, fields FROM pending WHERE path > ?1 ORDER BY path LIMIT ?2",
+            )?;
+            let fields: HashMap<String, u8> = stmt
+                .query_map(params![after, limit], |r| Ok((r.get(0)?, r.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let images = query_images(
+                conn,
+                "path IN (SELECT path FROM pending WHERE path > ?1 ORDER BY path LIMIT ?2)",
+                params![after, limit],
+                None,
+            )?;
+            let items = images
+                .into_iter()
+                .map(|image| Pending {
+                    fields: Fields(fields.get(&image.path).copied().unwrap_or_default()),
+                    image,
+                })
+                .collect();
+            Ok(Page::new(items, limit, |x: &Pending| x.image.path.clone()))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn image(path: &str, collection: &str, tags: &[&str]) -> Image {
+        Image {
+            path: path.to_owned(),
+            collection: collection.to_owned(),
+            size: (0, 0),
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+            title: String::new(),
+            description: String::new(),
+            marks: Marks::default(),
+            shot: Default::default(),
+            gps: None,
+        }
+    }
+
+    /// An in-memory store with the collections `a` and `b`.
+    fn store() -> Result<SqliteStore> {
+        let store = SqliteStore::open_in_memory()?;
+        for name in ["a", "b"] {
+            block_on(store.put_collection(&Collection {
+                folder: format!("/{}", name),
+                name: name.to_owned(),
+                sync: Default::default(),
+            }))?;
+        }
+        Ok(store)
+    }
+
+    fn tags(store: &SqliteStore, path: &str) -> Result<Vec<String>> {
+        Ok(block_on(store.get_image(path))?
+            .ok_or_else(|| anyhow!("{}: missing", path))?
+            .tags)
+    }
+
+    #[test]
+    fn migrate_old_schema() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(MIGRATIONS[0])?;
+        conn.execute_batch(
+            "INSERT INTO kv VALUES ('import/a', x'00'), ('theme', x'01');
+             INSERT INTO collections VALUES ('a', '/a');
+             INSERT INTO images VALUES ('/a/1.jpg', 'a', 3, 2, 'old');
+             INSERT INTO tags VALUES ('/a/1.jpg', 'Places|Europe|Berlin', 0);
+             PRAGMA user_version = 1;",
+        )?;
+        let store = SqliteStore::start(conn)?;
+
+        let image = block_on(store.get_image("/a/1.jpg"))?.unwrap();
+        assert_eq!(image.size, (3, 2));
+        assert_eq!(image.description, "old");
+        assert_eq!(image.tags, vec!["Places|Europe|Berlin"]);
+        assert_eq!(image.marks, Marks::default());
+        assert_eq!(image.title, "");
+        let keywords: Vec<String> = block_on(store.get_keywords())?
+            .into_iter()
+            .map(|x| x.path)
+            .collect();
+        assert_eq!(
+            keywords,
+            vec!["Places", "Places|Europe", "Places|Europe|Berlin"]
+        );
+        assert_eq!(block_on(store.get("import/a"))?, None);
+        assert_eq!(block_on(store.get("theme"))?, Some(vec![1]));
+        let collection = block_on(store.get_collection("a"))?.unwrap();
+        assert_eq!(collection.sync, SyncPolicy::default());
+        Ok(())
+    }
+
+    #[test]
+    fn newer_schema_refused() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1)?;
+        let e = SqliteStore::start(conn).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "catalog schema {} is newer than {}",
+                MIGRATIONS.len() + 1,
+                MIGRATIONS.len()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn kv() -> Result<()> {
+        let store = store()?;
+        assert_eq!(block_on(store.get("k"))?, None);
+        block_on(store.set("k", b"one"))?;
+        block_on(store.set("k", b"two"))?;
+        assert_eq!(block_on(store.get("k"))?, Some(b"two".to_vec()));
+        block_on(store.delete("k"))?;
+        assert_eq!(block_on(store.get("k"))?, None);
+        // Deleting a missing key is fine.
+        block_on(store.delete("k"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn delete_collection_cascades() -> Result<()> {
+        let store = store()?;
+        block_on(store.put_image(&image("/a/1.jpg", "a", &["x"])))?;
+        block_on(store.put_image(&image("/b/1.jpg", "b", &["x"])))?;
+        block_on(store.add_pending("/a/1.jpg", Fields::TAGS))?;
+        block_on(store.delete_collection("a"))?;
+
+        assert_eq!(block_on(store.get_image("/a/1.jpg"))?, None);
+        assert!(block_on(store.get_images_in_collection("a"))?.is_empty());
+        assert!(block_on(store.get_pending_page(None, 10))?.items.is_empty());
+        let tagged = block_on(store.get_images_with_tag("x"))?;
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].path, "/b/1.jpg");
+        Ok(())
+    }
+
+    #[test]
+    fn rename_keeps_tags() -> Result<()> {
+        let store = store()?;
+        block_on(store.put_image(&image("/a/1.jpg", "a", &["x", "y"])))?;
+        block_on(store.put_image(&image("/b/2.jpg", "b", &["z"])))?;
+        block_on(store.add_pending("/a/1.jpg", Fields::MARKS))?;
+
+        // Onto another image, which is replaced.
+        block_on(store.rename_image("/a/1.jpg", "/b/2.jpg", "b"))?;
+        assert_eq!(block_on(store.get_image("/a/1.jpg"))?, None);
+        let moved = block_on(store.get_image("/b/2.jpg"))?.unwrap();
+        assert_eq!(moved.collection, "b");
+        assert_eq!(moved.tags, vec!["x", "y"]);
+        let pending = block_on(store.get_pending_page(None, 10))?.items;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].image.path, "/b/2.jpg");
+        assert_eq!(pending[0].fields, Fields::MARKS);
+
+        // Onto itself, in another collection.
+        block_on(store.rename_image("/b/2.jpg", "/b/2.jpg", "a"))?;
+        let same = block_on(store.get_image("/b/2.jpg"))?.unwrap();
+        assert_eq!(same.collection, "a");
+        assert_eq!(same.tags, vec!["x", "y"]);
+        assert_eq!(block_on(store.get_pending_page(None, 10))?.items.len(), 1);
+
+        let e = block_on(store.rename_image("/a/gone.jpg", "/a/3.jpg", "a")).unwrap_err();
+        assert_eq!(e.to_string(), "/a/gone.jpg: not in the catalog");
+        Ok(())
+    }
+
+    #[test]
+    fn move_keyword_merges() -> Result<()> {
+        let store = store()?;
+        block_on(store.put_image(&image("/a/1.jpg", "a", &["Places|Berlin", "Cities|Berlin"])))?;
+        block_on(store.put_image(&image("/a/2.jpg", "a", &["Places|Berlin|Mitte"])))?;
+        block_on(store.put_keyword(&Keyword {
+            path: "Places|Berlin".to_owned(),
+            synonyms: vec!["Berlino".to_owned()],
+        }))?;
+        block_on(store.put_keyword(&Keyword {
+            path: "Cities|Berlin".to_owned(),
+            synonyms: vec!["BER".to_owned()],
+        }))?;
+
+        block_on(store.move_keyword("Places|Berlin", "Cities|Berlin"))?;
+        assert_eq!(tags(&store, "/a/1.jpg")?, vec!["Cities|Berlin"]);
+        assert_eq!(tags(&store, "/a/2.jpg")?, vec!["Cities|Berlin|Mitte"]);
+        let keywords = block_on(store.get_keywords())?;
+        let paths: Vec<&str> = keywords.iter().map(|x| x.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["Cities", "Cities|Berlin", "Cities|Berlin|Mitte", "Places"]
+        );
+        assert_eq!(keywords[1].synonyms, vec!["BER", "Berlino"]);
+
+        let e = block_on(store.move_keyword("Cities", "Cities|Berlin")).unwrap_err();
+        assert_eq!(e.to_string(), "cannot move Cities under itself");
+        Ok(())
+    }
+
+    #[test]
+    fn pending() -> Result<()> {
+        let store = store()?;
+        block_on(store.put_image(&image("/a/1.jpg", "a", &[])))?;
+        block_on(store.put_image(&image("/a/2.jpg", "a", &[])))?;
+        block_on(store.add_pending("/a/1.jpg", Fields::MARKS))?;
+        block_on(store.add_pending("/a/1.jpg", Fields::TAGS))?;
+        block_on(store.add_pending("/a/2.jpg", Fields::TITLE))?;
+        let e = block_on(store.add_pending("/a/gone.jpg", Fields::TAGS)).unwrap_err();
+        assert_eq!(e.to_string(), "/a/gone.jpg: not in the catalog");
+
+        let page = block_on(store.get_pending_page(None, 1))?;
+        assert_eq!(page.items[0].image.path, "/a/1.jpg");
+        assert_eq!(page.items[0].fields, Fields::MARKS | Fields::TAGS);
+        let page = block_on(store.get_pending_page(page.next.as_deref(), 1))?;
+        assert_eq!(page.items[0].image.path, "/a/2.jpg");
+        assert_eq!(page.items[0].fields, Fields::TITLE);
+
+        block_on(store.clear_pending("/a/1.jpg", Fields::MARKS))?;
+        block_on(store.clear_pending("/a/2.jpg", Fields::TITLE))?;
+        let items = block_on(store.get_pending_page(None, 10))?.items;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].fields, Fields::TAGS);
+        block_on(store.clear_pending("/a/1.jpg", Fields::TAGS))?;
+        assert!(block_on(store.get_pending_page(None, 10))?.items.is_empty());
+        Ok(())
+    }
+
+    fn sql(text: &str) -> (String, Vec<Value>) {
+        let mut values = vec![];
+        let sql = query_sql(&Query::parse(text).unwrap(), &mut values);
+        (sql, values)
+    }
+
+    #[test]
+    fn query_sql_binds_values() {
+        assert_eq!(sql(""), ("1".to_owned(), vec![]));
+        assert_eq!(
+            sql("title:\"It's\" OR tag=\"x'); DROP TABLE images; --\""),
+            (
+                "(coalesce(instr(lower(title), ?1) > 0, 0) OR coalesce(EXISTS \
+                 (SELECT 1 FROM tags WHERE image = images.path AND lower(tag) = ?2), 0))"
+                    .to_owned(),
+                vec![
+                    Value::Text("it's".to_owned()),
+                    Value::Text("x'); drop table images; --".to_owned()),
+                ]
+            )
+        );
+        assert_eq!(
+            sql("-iso>=3200 date<2025-06 label:none flag:reject"),
+            (
+                "(NOT coalesce(iso >= ?1, 0) AND coalesce(substr(taken, 1, length(?2)) < ?2, 0) \
+                 AND coalesce(label IS NULL, 0) AND coalesce(flag = ?3, 0))"
+                    .to_owned(),
+                vec![
+                    Value::Real(3200.0),
+                    Value::Text("2025-06".to_owned()),
+                    Value::Integer(-1),
+                ]
+            )
+        );
+        assert_eq!(
+            sql("tag:Places|Berlin"),
+            (
+                "coalesce(EXISTS (SELECT 1 FROM tags WHERE image = images.path AND \
+                 instr('|' || lower(tag) || '|', ?1) > 0), 0)"
+                    .to_owned(),
+                vec![Value::Text("|places|berlin|".to_owned())]
+            )
+        );
+    }
+
+    #[test]
+    fn query_sql_never_inlines_input() {
+        for input in ["it's", "' OR 1=1 --", "100%", "a_b", "x\\y", "\u{0};"] {
+            for field in ["", "path:", "title=", "camera>", "tag:", "tag=", "date:"] {
+                let (sql, values) = sql(&format!("{}\"{}\"", field, input));
+                assert!(!sql.contains(input), "{} in {}", input, sql);
+                assert!(
+                    values.iter().any(|x| match x {
+                        Value::Text(x) =>
+                            x.contains(&input.to_ascii_lowercase()) || x.contains(input),
+                        _ => false,
+                    }),
+                    "{} not bound in {:?}",
+                    input,
+                    values
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn find_images_as_matches() -> Result<()> {
+        let store = SqliteStore::open_in_memory()?;
+        block_on(store.put_collection(&Collection {
+            folder: "/a".to_owned(),
+            name: "a".to_owned(),
+            sync: Default::default(),
+        }))?;
+        let image = |path: &str, title: &str, tags: &[&str], shot: Shot, marks: Marks| Image {
+            path: path.to_owned(),
+            collection: "a".to_owned(),
+            size: (0, 0),
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+            title: title.to_owned(),
+            description: String::new(),
+            marks,
+            shot,
+            gps: None,
+        };
+        let images = [
+            image(
+                "/a/it's.jpg",
+                "It's 100% done",
+                &["Places|Berlin|Mitte"],
+                Shot {
+                    camera: Some("X-T4".to_owned()),
+                    ..Default::default()
+                },
+                Marks::default(),
+            ),
+            image(
+                "/a/a_b.jpg",
+                "a_b",
+                &["Places|Berlinale"],
+                Shot {
+                    iso: Some(6400),
+                    taken: Some("2025-06-03T10:00:00".to_owned()),
+                    ..Default::default()
+                },
+                Marks {
+                    flag: Flag::Reject,
+                    ..Default::default()
+                },
+            ),
+            image(
+                "/a/plain.jpg",
+                "",
+                &[],
+                Default::default(),
+                Default::default(),
+            ),
+        ];
+        for image in &images {
+            block_on(store.put_image(image))?;
+        }
+
+        for text in [
+            "\"it's\"",
+            "100%",
+            "_",
+            "title:\"%\"",
+            "tag:berlin",
+            "tag=\"x'); DROP TABLE images; --\"",
+            "text:\"' OR 1=1 --\"",
+            "camera!=x-t4",
+            "-iso>3200",
+            "date:2025-06",
+            "flag:reject OR label:none",
+            "path>\"/a/b\"",
+        ] {
+            let query = Query::parse(text)?;
+            let found = block_on(store.find_images_page(&query, None, 10))?;
+            let found: Vec<&str> = found.items.iter().map(|x| x.path.as_str()).collect();
+            let mut expected: Vec<&str> = images
+                .iter()
+                .filter(|x| query.matches(x))
+                .map(|x| x.path.as_str())
+                .collect();
+            expected.sort();
+            assert_eq!(found, expected, "{}", text);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Writing catalog edits back to image files.
+//!
+//! Edits are noted in the catalog as pending fields, see
+//! [`Datastore::add_pending`], and written into the XMP of the image file
+//! or of a sidecar as the [`SyncPolicy`] of the image's collection says. A
+//! file another program changed since phany last read or wrote it is a
+//! conflict, and is left alone unless forced, so that its changes can be
+//! imported first.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use futures::StreamExt;
+
+use super::datastore::{Datastore, Fields, Pending, SyncPolicy};
+use super::import::{fingerprint, load_stamps, save_stamps, CancelToken, Stamp};
+use super::keywords::{self, Keyword};
+use crate::loader::meta::XmpUpdate;
+
+/// What became of a pending image.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The file written.
+    Written(PathBuf),
+    /// The policy of its collection is off; it stays pending.
+    Skipped,
+    /// The file changed since phany last read or wrote it; it stays
+    /// pending.
+    Conflict(PathBuf),
+    Failed(String),
+}
+
+/// Counts so far, sent after each image.
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    pub total: usize,
+    pub done: usize,
+    pub written: usize,
+    pub skipped: usize,
+    pub conflicts: usize,
+    pub failed: usize,
+}
+
+/// Sidecar of the image at `path`: `a.jpg.xmp` or `a.xmp`, whichever
+/// exists, else a new `a.jpg.xmp`, which unlike `a.xmp` is not shared with
+/// `a.nef`.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut full = path.as_os_str().to_owned();
+    full.push(".xmp");
+    let full = PathBuf::from(full);
+    let short = path.with_extension("xmp");
+    if !full.exists() && short.exists() {
+        short
+    } else {
+        full
+    }
+}
+
+/// XMP values of the pending fields of an image. Pick flags have no XMP
+/// tag; rejects are written as a rating of -1.
+pub fn xmp_update(pending: &Pending, keywords: &[Keyword]) -> XmpUpdate {
+    let (image, fields) = (&pending.image, pending.fields);
+    let mut update = XmpUpdate::default();
+    if fields.contains(Fields::MARKS) {
+        update.rating = Some(image.marks.xmp_rating());
+        update.label = Some(image.marks.label.map(|x| x.name().to_owned()));
+    }
+    if fields.contains(Fields::TAGS) {
+        update.keywords = Some(keywords::to_xmp(&image.tags, keywords));
+    }
+    if fields.contains(Fields::TITLE) {
+        update.title = Some(image.title.clone());
+    }
+    if fields.contains(Fields::DESCRIPTION) {
+        update.description = Some(image.description.clone());
+    }
+    // A removed position is left in the file.
+    if fields.contains(Fields::GPS) {
+        update.gps = image.gps;
+    }
+    update
+}
+
+/// Stamps of the files of a collection, as phany last saw them.
+#[derive(Default)]
+struct Stamps {
+    /// Of image files, at import or after writing them.
+    files: HashMap<String, Stamp>,
+    /// Of sidecars, after writing them.
+    sidecars: HashMap<String, Stamp>,
+    dirty: bool,
+}
+
+/// Key of the sidecar stamps of `collection` in the store.
+fn sidecar_stamps_key(collection: &str) -> String {
+    format!("sync/{}", collection)
+}
+
+/// Stamp of the file at `path` with its fingerprint.
+fn stamp(path: &Path) -> Result<Stamp> {
+    Ok(Stamp {
+        hash: Some(fingerprint(path)?),
+        ..Stamp::of(&std::fs::metadata(path)?)
+    })
+}
+
+/// Whether the file at `path` changed since `known` was taken. A file
+/// touched without changing has not. An image file with no stamp has not,
+/// but an existing `sidecar` with none was written by another program.
+fn changed(known: Option<&Stamp>, path: &Path, sidecar: bool) -> Result<bool> {
+    let Some(known) = known else {
+        return Ok(sidecar && path.exists());
+    };
+    let now = match std::fs::metadata(path) {
+        Ok(x) => Stamp::of(&x),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if known.unchanged(&now) {
+        return Ok(false);
+    }
+    Ok(match &known.hash {
+        Some(hash) => fingerprint(path)? != *hash,
+        None => true,
+    })
+}
+
+/// Writes pending edits, keeping stamps of the files it writes so that
+/// later changes by other programs show as conflicts. The stamps are
+/// saved by [`Writer::finish`].
+pub struct Writer<'a> {
+    store: &'a dyn Datastore,
+    keywords: Vec<Keyword>,
+    /// Write over conflicting files.
+    force: bool,
+    policies: HashMap<String, SyncPolicy>,
+    stamps: HashMap<String, Stamps>,
+}
+
+impl<'a> Writer<'a> {
+    /// `keywords` gives the synonyms written along with tags.
+    pub fn new(store: &'a dyn Datastore, keywords: Vec<Keyword>, force: bool) -> Writer<'a> {
+        Writer {
+            store,
+            keywords,
+            force,
+            policies: HashMap::new(),
+            stamps: HashMap::new(),
+        }
+    }
+
+    async fn policy(&mut self, collection: &str) -> Result<SyncPolicy> {
+        if let Some(&policy) = self.policies.get(collection) {
+            return Ok(policy);
+        }
+        let policy = self
+            .store
+            .get_collection(collection)
+            .await?
+            .map(|x| x.sync)
+            .unwrap_or_default();
+        self.policies.insert(collection.to_owned(), policy);
+        Ok(policy)
+    }
+
+    async fn stamps(&mut self, collection: &str) -> Result<&mut Stamps> {
+        if !self.stamps.contains_key(collection) {
+            let sidecars = match self.store.get(&sidecar_stamps_key(collection)).await? {
+                Some(x) => serde_json::from_slice(&x).unwrap_or_else(|e| {
+                    log::warn!("{}: bad sidecar stamps: {}", collection, e);
+                    HashMap::new()
+                }),
+                None => HashMap::new(),
+            };
+            let stamps = Stamps {
+                files: load_stamps(self.store, collection).await?,
+                sidecars,
+                dirty: false,
+            };
+            self.stamps.insert(collection.to_owned(), stamps);
+        }
+        Ok(self.stamps.get_mut(collection).unwrap())
+    }
+
+    /// Write the pending fields of an image as the policy of its
+    /// collection says, or as `fallback` if that is off, and note them as
+    /// written.
+    pub async fn write(&mut self, pending: &Pending, fallback: SyncPolicy) -> Result<Outcome> {
+        let image = &pending.image;
+        let policy = match self.policy(&image.collection).await? {
+            SyncPolicy::Off => fallback,
+            x => x,
+        };
+        let path = Path::new(&image.path);
+        let target = match policy {
+            SyncPolicy::Off => return Ok(Outcome::Skipped),
+            SyncPolicy::Embedded => path.to_owned(),
+            SyncPolicy::Sidecar => sidecar_path(path),
+        };
+        let update = xmp_update(pending, &self.keywords);
+        let force = self.force;
+        let stamps = self.stamps(&image.collection).await?;
+        let known = match policy {
+            SyncPolicy::Sidecar => &mut stamps.sidecars,
+            _ => &mut stamps.files,
+        };
+        let key = target.to_string_lossy().into_owned();
+        let sidecar = policy == SyncPolicy::Sidecar;
+        if !force && changed(known.get(&key), &target, sidecar)? {
+            return Ok(Outcome::Conflict(target));
+        }
+        let written = match policy {
+            SyncPolicy::Sidecar => update.write_sidecar(&target),
+            _ => update.write(&target),
+        };
+        if let Err(e) = written {
+            return Ok(Outcome::Failed(e.to_string()));
+        }
+        known.insert(key, stamp(&target)?);
+        stamps.dirty = true;
+        // Fields edited again while writing stay pending.
+        let edited = match self.store.get_image(&image.path).await? {
+            Some(current) => Fields::changed(image, &current),
+            None => Fields::NONE,
+        };
+        self.store
+            .clear_pending(&image.path, pending.fields.without(edited))
+            .await?;
+        Ok(Outcome::Written(target))
+    }
+
+    /// Save the stamps of the files written.
+    pub async fn finish(self) -> Result<()> {
+        for (collection, stamps) in self.stamps {
+            if stamps.dirty {
+                save_stamps(self.store, &collection, &stamps.files).await?;
+                self.store
+                    .set(
+                        &sidecar_stamps_key(&collection),
+                        &serde_json::to_vec(&stamps.sidecars)?,
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write every pending edit as the policy of its collection says, unless
+/// cancelled. `progress` is called after each image.
+pub async fn write_pending(
+    store: &dyn Datastore,
+    force: bool,
+    cancel: &CancelToken,
+    mut progress: impl FnMut(&Progress, &Pending, &Outcome) + Send,
+) -> Result<Progress> {
+    let mut pending: Vec<Pending> = vec![];
+    let mut pages = store.get_pending_iter();
+    while let Some(page) = pages.next().await {
+        pending.extend(page?);
+    }
+    drop(pages);
+
+    let mut writer = Writer::new(store, store.get_keywords().await?, force);
+    let mut p = Progress {
+        total: pending.len(),
+        ..Default::default()
+    };
+    let mut result = Ok(());
+    for item in &pending {
+        if cancel.is_cancelled() {
+            break;
+        }
+        let outcome = match writer.write(item, SyncPolicy::Off).await {
+            Ok(x) => x,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        };
+        match outcome {
+            Outcome::Written(_) => p.written += 1,
+            Outcome::Skipped => p.skipped += 1,
+            Outcome::Conflict(_) => p.conflicts += 1,
+            Outcome::Failed(_) => p.failed += 1,
+        }
+        p.done += 1;
+        progress(&p, item, &outcome);
+    }
+    // Stamps of what was written are kept even after an error.
+    writer.finish().await?;
+    result.map(|_| p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unstamped_sidecar() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("phany-sync-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("a.jpg.xmp");
+        let _ = std::fs::remove_file(&path);
+        assert!(!changed(None, &path, true)?);
+        std::fs::write(&path, "<x:xmpmeta/>")?;
+        assert!(changed(None, &path, true)?);
+        assert!(!changed(None, &path, false)?);
+        let known = stamp(&path)?;
+        assert!(!changed(Some(&known), &path, true)?);
+        std::fs::write(&path, "<x:xmpmeta></x:xmpmeta>")?;
+        assert!(changed(Some(&known), &path, true)?);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
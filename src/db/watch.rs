@@ -0,0 +1,612 @@
+//! Follow changes made to watched folders outside phany.
+//!
+//! Files in imported collections are kept in sync with the catalog. A file
+//! that disappears is only dropped after a grace period: if a file with the
+//! same content fingerprint shows up meanwhile, the record is moved to it
+//! and keeps its tags. This catches moves between folders and file managers
+//! that copy and delete, not just renames the OS reports as such.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::channel::mpsc as channel;
+use futures::executor::block_on;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use super::datastore::{Collection, Datastore};
+use super::import::{fingerprint, load_stamps, read_image, save_stamps, store_image, Stamp};
+use crate::loader::magic::sniff_file;
+
+/// How often pending changes are looked at.
+const TICK: Duration = Duration::from_millis(250);
+/// Time a file must be left alone before it is read; writes come in bursts.
+const QUIET: Duration = Duration::from_secs(1);
+/// Time a removed file waits for its content to reappear elsewhere.
+const GRACE: Duration = Duration::from_secs(3);
+
+/// A change to an image file, after it has been applied to the catalog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    /// Contents of the file changed.
+    Updated(PathBuf),
+    Removed(PathBuf),
+    /// A file or a folder was moved.
+    Renamed(PathBuf, PathBuf),
+}
+
+/// Watches folders until dropped.
+pub struct Watcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl Watcher {
+    /// Watch the folders of `collections` recursively, syncing them to
+    /// `store`, and `folders` without their subfolders. Changes are sent on
+    /// the returned channel.
+    pub fn start(
+        store: Option<Arc<dyn Datastore>>,
+        collections: Vec<Collection>,
+        folders: Vec<PathBuf>,
+    ) -> Result<(Watcher, channel::UnboundedReceiver<Change>)> {
+        let (events, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(events)?;
+        let collections = if store.is_some() { collections } else { vec![] };
+        for c in &collections {
+            if let Err(e) = watcher.watch(Path::new(&c.folder), RecursiveMode::Recursive) {
+                log::warn!("cannot watch {}: {}", c.folder, e);
+            }
+        }
+        for folder in &folders {
+            if collections.iter().any(|c| folder.starts_with(&c.folder)) {
+                continue;
+            }
+            if let Err(e) = watcher.watch(folder, RecursiveMode::NonRecursive) {
+                log::warn!("cannot watch {}: {}", folder.to_string_lossy(), e);
+            }
+        }
+
+        let (tx, changes) = channel::unbounded();
+        let mut tracker = Tracker::new(store, collections, tx);
+        std::thread::Builder::new()
+            .name("watch".into())
+            .spawn(move || loop {
+                match rx.recv_timeout(TICK) {
+                    Ok(Ok(event)) => tracker.event(event, Instant::now()),
+                    Ok(Err(e)) => log::warn!("watch: {}", e),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    // The watcher was dropped.
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+                tracker.flush(Instant::now());
+            })?;
+        Ok((Watcher { _watcher: watcher }, changes))
+    }
+}
+
+struct Removal {
+    path: PathBuf,
+    hash: Option<String>,
+    at: Instant,
+}
+
+/// State of the watch thread.
+struct Tracker {
+    store: Option<Arc<dyn Datastore>>,
+    collections: Vec<Collection>,
+    /// Import stamps by collection, loaded on first use.
+    stamps: HashMap<String, HashMap<String, Stamp>>,
+    /// Collections whose stamps changed since they were last saved.
+    changed: HashSet<String>,
+    /// Files written to, with the time of the last write.
+    dirty: HashMap<PathBuf, Instant>,
+    removed: Vec<Removal>,
+    tx: channel::UnboundedSender<Change>,
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+impl Tracker {
+    fn new(
+        store: Option<Arc<dyn Datastore>>,
+        collections: Vec<Collection>,
+        tx: channel::UnboundedSender<Change>,
+    ) -> Tracker {
+        Tracker {
+            store,
+            collections,
+            stamps: HashMap::new(),
+            changed: HashSet::new(),
+            dirty: HashMap::new(),
+            removed: vec![],
+            tx,
+        }
+    }
+
+    /// Collection whose folder holds `path`, the innermost one if nested.
+    fn collection_of(&self, path: &Path) -> Option<String> {
+        self.collections
+            .iter()
+            .filter(|c| path.starts_with(&c.folder))
+            .max_by_key(|c| c.folder.len())
+            .map(|c| c.name.clone())
+    }
+
+    /// Stamps of `collection`, to be saved with the next flush.
+    fn stamps_mut(&mut self, collection: &str) -> &mut HashMap<String, Stamp> {
+        self.changed.insert(collection.to_owned());
+        self.stamps(collection)
+    }
+
+    fn stamps(&mut self, collection: &str) -> &mut HashMap<String, Stamp> {
+        if !self.stamps.contains_key(collection) {
+            let stamps = match self.store {
+                Some(ref store) => block_on(load_stamps(store.as_ref(), collection))
+                    .unwrap_or_else(|e| {
+                        log::warn!("watch: {}", e);
+                        HashMap::new()
+                    }),
+                None => HashMap::new(),
+            };
+            self.stamps.insert(collection.to_owned(), stamps);
+        }
+        self.stamps.get_mut(collection).unwrap()
+    }
+
+    /// Catalog paths at or under `path`.
+    fn known_under(&mut self, path: &Path) -> Vec<(PathBuf, Stamp)> {
+        let Some(collection) = self.collection_of(path) else {
+            return vec![];
+        };
+        self.stamps(&collection)
+            .iter()
+            .filter(|(k, _)| Path::new(k).starts_with(path))
+            .map(|(k, v)| (PathBuf::from(k), v.clone()))
+            .collect()
+    }
+
+    fn send(&self, change: Change) {
+        let _ = self.tx.unbounded_send(change);
+    }
+
+    fn event(&mut self, event: notify::Event, now: Instant) {
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                self.renamed(&event.paths[0], &event.paths[1], now);
+            }
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                for path in event.paths {
+                    self.gone(path, now);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(_)) => {
+                for path in event.paths {
+                    if path.exists() {
+                        self.written(path, now);
+                    } else {
+                        self.gone(path, now);
+                    }
+                }
+            }
+            EventKind::Create(_) | EventKind::Modify(_) => {
+                for path in event.paths {
+                    self.written(path, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `path` was created or written; read it once it settles.
+    fn written(&mut self, path: PathBuf, now: Instant) {
+        if path.is_dir() {
+            // A folder moved in from outside: its files come without events.
+            for entry in walkdir::WalkDir::new(&path).into_iter().flatten() {
+                if entry.file_type().is_file() {
+                    self.dirty.insert(entry.into_path(), now);
+                }
+            }
+        } else {
+            self.dirty.insert(path, now);
+        }
+    }
+
+    /// `path`, a file or a folder, was removed or moved away.
+    fn gone(&mut self, path: PathBuf, now: Instant) {
+        self.dirty.retain(|k, _| !k.starts_with(&path));
+        let known = self.known_under(&path);
+        if known.is_empty() {
+            self.removed.push(Removal {
+                path,
+                hash: None,
+                at: now,
+            });
+        }
+        for (path, stamp) in known {
+            self.removed.push(Removal {
+                path,
+                hash: stamp.hash,
+                at: now,
+            });
+        }
+    }
+
+    /// The OS reported a rename of `from`, a file or a folder, to `to`.
+    fn renamed(&mut self, from: &Path, to: &Path, now: Instant) {
+        // Drop what the From and To halves of this rename queued.
+        self.removed.retain(|x| !x.path.starts_with(from));
+        self.dirty.retain(|k, _| !k.starts_with(to));
+        let known = self.known_under(from);
+        if known.is_empty() {
+            // Not in the catalog; pick up images moved into a collection.
+            if self.collection_of(to).is_some() {
+                self.written(to.to_owned(), now);
+            }
+        }
+        for (path, _) in known {
+            let target = match path.strip_prefix(from) {
+                Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                _ => to.to_owned(),
+            };
+            self.move_record(&path, &target);
+        }
+        self.send(Change::Renamed(from.to_owned(), to.to_owned()));
+    }
+
+    /// Move the catalog record of `from` to `to`, or drop it if `to` is
+    /// outside every collection.
+    fn move_record(&mut self, from: &Path, to: &Path) {
+        let Some(ref store) = self.store else {
+            return;
+        };
+        let store = store.clone();
+        let (Some(old), new) = (self.collection_of(from), self.collection_of(to)) else {
+            return;
+        };
+        let Some(stamp) = self.stamps(&old).remove(&key(from)) else {
+            return;
+        };
+        self.changed.insert(old);
+        let result = match new {
+            Some(ref new) => block_on(store.rename_image(&key(from), &key(to), new)),
+            None => block_on(store.delete_image(&key(from))),
+        };
+        match result {
+            Ok(()) => {
+                if let Some(new) = new {
+                    let stamp = std::fs::metadata(to)
+                        .map(|x| Stamp {
+                            hash: stamp.hash.clone(),
+                            ..Stamp::of(&x)
+                        })
+                        .unwrap_or(stamp);
+                    self.stamps_mut(&new).insert(key(to), stamp);
+                }
+            }
+            Err(e) => log::warn!("watch: {}", e),
+        }
+    }
+
+    /// Apply changes that have settled.
+    fn flush(&mut self, now: Instant) {
+        let ready: Vec<PathBuf> = self
+            .dirty
+            .iter()
+            .filter(|(_, at)| now.duration_since(**at) >= QUIET)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for path in ready {
+            self.dirty.remove(&path);
+            self.read(path);
+        }
+
+        let (expired, pending): (Vec<Removal>, Vec<Removal>) = std::mem::take(&mut self.removed)
+            .into_iter()
+            .partition(|x| now.duration_since(x.at) >= GRACE);
+        self.removed = pending;
+        for removal in &expired {
+            self.drop_record(&removal.path);
+        }
+
+        self.save();
+    }
+
+    /// Read a settled file into the catalog.
+    fn read(&mut self, path: PathBuf) {
+        let Ok(meta) = std::fs::metadata(&path) else {
+            return;
+        };
+        if !meta.is_file() || !matches!(sniff_file(&path), Ok(Some(x)) if x.is_supported()) {
+            return;
+        }
+        let hash = fingerprint(&path).ok();
+        if let Some(i) = self
+            .removed
+            .iter()
+            .position(|x| x.hash.is_some() && x.hash == hash)
+        {
+            let removal = self.removed.remove(i);
+            self.move_record(&removal.path, &path);
+            self.send(Change::Renamed(removal.path, path));
+            return;
+        }
+
+        let (Some(collection), Some(store)) = (self.collection_of(&path), self.store.clone())
+        else {
+            self.send(Change::Added(path));
+            return;
+        };
+        let stamp = Stamp {
+            hash,
+            ..Stamp::of(&meta)
+        };
+        let known = self.stamps(&collection).get(&key(&path)).cloned();
+        if known.as_ref().is_some_and(|x| x.unchanged(&stamp)) {
+            return;
+        }
+        let stored = block_on(store.get_keywords())
+            .and_then(|keywords| read_image(&path, &collection, &keywords))
+            .and_then(|image| block_on(store_image(store.as_ref(), image)));
+        match stored {
+            Ok(()) => {
+                self.stamps_mut(&collection).insert(key(&path), stamp);
+                self.send(match known {
+                    Some(_) => Change::Updated(path),
+                    None => Change::Added(path),
+                });
+            }
+            Err(e) => log::warn!("watch: {}: {}", path.to_string_lossy(), e),
+        }
+    }
+
+    /// Forget a file that is gone for good.
+    fn drop_record(&mut self, path: &Path) {
+        if let (Some(collection), Some(store)) = (self.collection_of(path), self.store.clone()) {
+            if self.stamps(&collection).remove(&key(path)).is_some() {
+                self.changed.insert(collection);
+                if let Err(e) = block_on(store.delete_image(&key(path))) {
+                    log::warn!("watch: {}", e);
+                }
+            }
+        }
+        self.send(Change::Removed(path.to_owned()));
+    }
+
+    /// Save the stamps of the collections changed.
+    fn save(&mut self) {
+        let Some(ref store) = self.store else {
+            return;
+        };
+        for collection in self.changed.drain() {
+            let Some(stamps) = self.stamps.get(&collection) else {
+                continue;
+            };
+            if let Err(e) = block_on(save_stamps(store.as_ref(), &collection, stamps)) {
+                log::warn!("watch: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+    use notify::Event;
+
+    use super::*;
+    use crate::db::datastore::Image;
+    use crate::db::memory::MemoryStore;
+
+    struct Setup {
+        dir: PathBuf,
+        store: Arc<MemoryStore>,
+        tracker: Tracker,
+        changes: channel::UnboundedReceiver<Change>,
+        start: Instant,
+    }
+
+    /// A tracker of the collection `a` in an empty folder for test `name`.
+    fn setup(name: &str) -> Result<Setup> {
+        let dir = std::env::temp_dir().join(format!("phany-watch-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a"))?;
+        let dir = dir.canonicalize()?;
+        let collection = Collection {
+            folder: key(&dir.join("a")),
+            name: "a".to_owned(),
+            sync: Default::default(),
+        };
+        let store = Arc::new(MemoryStore::new());
+        block_on(store.put_collection(&collection))?;
+        let (tx, changes) = channel::unbounded();
+        let tracker = Tracker::new(Some(store.clone()), vec![collection], tx);
+        Ok(Setup {
+            dir,
+            store,
+            tracker,
+            changes,
+            start: Instant::now(),
+        })
+    }
+
+    impl Setup {
+        fn at(&self, secs: u64) -> Instant {
+            self.start + Duration::from_secs(secs)
+        }
+
+        fn event(&mut self, kind: EventKind, paths: &[&Path], secs: u64) {
+            let event = paths
+                .iter()
+                .fold(Event::new(kind), |e, x| e.add_path(x.to_path_buf()));
+            self.tracker.event(event, self.at(secs));
+        }
+
+        fn flush(&mut self, secs: u64) -> Vec<Change> {
+            self.tracker.flush(self.at(secs));
+            let mut changes = vec![];
+            while let Ok(Some(x)) = self.changes.try_next() {
+                changes.push(x);
+            }
+            changes
+        }
+
+        /// Create image files and read them in.
+        fn add(&mut self, paths: &[&Path], secs: u64) -> Result<()> {
+            for (i, path) in paths.iter().enumerate() {
+                std::fs::create_dir_all(path.parent().unwrap())?;
+                ppm(path, i + 1)?;
+                self.event(EventKind::Create(CreateKind::File), &[path], secs);
+            }
+            let changes = self.flush(secs + QUIET.as_secs());
+            assert_eq!(changes.len(), paths.len());
+            Ok(())
+        }
+
+        fn image(&self, path: &Path) -> Option<Image> {
+            block_on(self.store.get_image(&key(path))).unwrap()
+        }
+
+        fn stamps(&self) -> HashMap<String, Stamp> {
+            block_on(load_stamps(self.store.as_ref(), "a")).unwrap()
+        }
+    }
+
+    impl Drop for Setup {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Write a gray PPM of `width` by 1 pixels.
+    fn ppm(path: &Path, width: usize) -> Result<()> {
+        let mut data = format!("P6\n{} 1\n255\n", width).into_bytes();
+        data.resize(data.len() + width * 3, 128);
+        Ok(std::fs::write(path, data)?)
+    }
+
+    #[test]
+    fn read_once_settled() -> Result<()> {
+        let mut s = setup("settled")?;
+        let a = s.dir.join("a").join("1.ppm");
+        ppm(&a, 1)?;
+        s.event(EventKind::Create(CreateKind::File), &[&a], 0);
+        assert!(s.flush(0).is_empty());
+        assert!(s.image(&a).is_none());
+        assert_eq!(s.flush(1), [Change::Added(a.clone())]);
+        assert_eq!(s.image(&a).unwrap().size, (1, 1));
+        assert!(s.stamps().contains_key(&key(&a)));
+
+        // Writes push the read back.
+        ppm(&a, 2)?;
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        s.event(modify, &[&a], 2);
+        s.event(modify, &[&a], 3);
+        assert!(s.flush(3).is_empty());
+        assert_eq!(s.flush(4), [Change::Updated(a.clone())]);
+        assert_eq!(s.image(&a).unwrap().size, (2, 1));
+
+        // Touching an unchanged file reads nothing.
+        s.event(modify, &[&a], 5);
+        assert!(s.flush(6).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn moved_by_content() -> Result<()> {
+        let mut s = setup("content")?;
+        let (from, to) = (s.dir.join("a").join("1.ppm"), s.dir.join("a/sub/2.ppm"));
+        s.add(&[&from], 0)?;
+        block_on(s.store.set_tags(&key(&from), &["Kept".to_owned()]))?;
+
+        // Copied, then deleted, as a file manager moving across devices.
+        std::fs::create_dir_all(to.parent().unwrap())?;
+        std::fs::copy(&from, &to)?;
+        std::fs::remove_file(&from)?;
+        s.event(EventKind::Create(CreateKind::File), &[&to], 10);
+        s.event(EventKind::Remove(RemoveKind::File), &[&from], 10);
+        assert_eq!(s.flush(11), [Change::Renamed(from.clone(), to.clone())]);
+        assert!(s.image(&from).is_none());
+        assert_eq!(s.image(&to).unwrap().tags, ["Kept"]);
+        let stamps = s.stamps();
+        assert!(!stamps.contains_key(&key(&from)));
+        assert!(stamps[&key(&to)].hash.is_some());
+
+        // Nothing is left to expire.
+        assert!(s.flush(20).is_empty());
+        assert!(s.image(&to).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn removed_after_grace() -> Result<()> {
+        let mut s = setup("grace")?;
+        let a = s.dir.join("a").join("1.ppm");
+        s.add(&[&a], 0)?;
+        std::fs::remove_file(&a)?;
+        s.event(EventKind::Remove(RemoveKind::File), &[&a], 10);
+        assert!(s.flush(12).is_empty());
+        assert!(s.image(&a).is_some());
+        assert_eq!(s.flush(13), [Change::Removed(a.clone())]);
+        assert!(s.image(&a).is_none());
+        assert!(s.stamps().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn folder_renamed() -> Result<()> {
+        let mut s = setup("folder")?;
+        let (from, to) = (s.dir.join("a").join("old"), s.dir.join("a").join("new"));
+        s.add(&[&from.join("1.ppm"), &from.join("deep/2.ppm")], 0)?;
+        block_on(
+            s.store
+                .set_tags(&key(&from.join("1.ppm")), &["Kept".to_owned()]),
+        )?;
+
+        std::fs::rename(&from, &to)?;
+        let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        s.event(rename, &[&from, &to], 10);
+        assert_eq!(s.flush(10), [Change::Renamed(from.clone(), to.clone())]);
+        assert!(s.image(&from.join("1.ppm")).is_none());
+        assert_eq!(s.image(&to.join("1.ppm")).unwrap().tags, ["Kept"]);
+        assert!(s.image(&to.join("deep/2.ppm")).is_some());
+        let mut stamps: Vec<String> = s.stamps().into_keys().collect();
+        stamps.sort();
+        assert_eq!(
+            stamps,
+            [key(&to.join("1.ppm")), key(&to.join("deep/2.ppm"))]
+        );
+
+        // Nothing was queued to read or remove.
+        assert!(s.flush(20).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn moved_out_of_collections() -> Result<()> {
+        let mut s = setup("out")?;
+        let (from, to) = (s.dir.join("a").join("1.ppm"), s.dir.join("1.ppm"));
+        s.add(&[&from], 0)?;
+
+        std::fs::rename(&from, &to)?;
+        let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        s.event(rename, &[&from, &to], 10);
+        assert_eq!(s.flush(10), [Change::Renamed(from.clone(), to.clone())]);
+        assert!(s.image(&from).is_none());
+        assert!(s.image(&to).is_none());
+        assert!(s.stamps().is_empty());
+
+        // Moved back in, it is read as a new image.
+        std::fs::rename(&to, &from)?;
+        s.event(rename, &[&to, &from], 20);
+        assert_eq!(s.flush(20), [Change::Renamed(to.clone(), from.clone())]);
+        assert_eq!(s.flush(21), [Change::Added(from.clone())]);
+        assert!(s.image(&from).is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,739 @@
+//! Color space conversions.
+//!
+//! Colors go through CIE XYZ relative to D50, the connection space of ICC
+//! profiles. RGB and HSL are set by their primaries and transfer function,
+//! XYZ, Lab and LCh by their white point. JzAzBz and Oklab are defined
+//! relative to D65.
+
+use anyhow::{anyhow, Result};
+
+use super::image::{ColorSpace, Encoding, Image};
+
+/// Applies to column vectors.
+pub type Matrix = [[f32; 3]; 3];
+type Matrix64 = [[f64; 3]; 3];
+
+/// Luminance, in cd/m², given to XYZ with a Y of 1 when converted to
+/// JzAzBz, which takes absolute values: the reference white of ITU-R BT.2408.
+pub const JZAZBZ_WHITE: f32 = 203.;
+
+/// Standard illuminants, as white points.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Illuminant {
+    D50,
+    D65,
+}
+
+impl Illuminant {
+    /// Chromaticity coordinates.
+    pub fn xy(self) -> [f64; 2] {
+        match self {
+            Illuminant::D50 => [0.3457, 0.3585],
+            Illuminant::D65 => [0.3127, 0.3290],
+        }
+    }
+
+    /// XYZ with a Y of 1.
+    pub fn xyz(self) -> [f64; 3] {
+        xy_to_xyz(self.xy())
+    }
+}
+
+/// Primaries of an RGB space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Primaries {
+    #[default]
+    Srgb,
+    /// Same primaries and white as sRGB.
+    Rec709,
+    DisplayP3,
+    Rec2020,
+    ProPhoto,
+}
+
+impl Primaries {
+    /// Chromaticity coordinates of red, green and blue.
+    pub fn xy(self) -> [[f64; 2]; 3] {
+        match self {
+            Primaries::Srgb | Primaries::Rec709 => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]],
+            Primaries::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+            Primaries::Rec2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+            Primaries::ProPhoto => [[0.7347, 0.2653], [0.1596, 0.8404], [0.0366, 0.0001]],
+        }
+    }
+
+    pub fn white(self) -> Illuminant {
+        match self {
+            Primaries::ProPhoto => Illuminant::D50,
+            _ => Illuminant::D65,
+        }
+    }
+
+    /// From linear RGB to XYZ relative to the white of the primaries.
+    pub fn to_xyz(self) -> Matrix {
+        to_f32(self.to_xyz64())
+    }
+
+    fn to_xyz64(self) -> Matrix64 {
+        let [r, g, b] = self.xy().map(xy_to_xyz);
+        let primaries = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        // Scale the primaries so that they add up to the white.
+        let scale = apply64(&inverse(&primaries), self.white().xyz());
+        primaries.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]])
+    }
+}
+
+/// Bradford chromatic adaptation of XYZ from one white to another.
+pub fn bradford(from: Illuminant, to: Illuminant) -> Matrix {
+    to_f32(bradford64(from, to))
+}
+
+fn bradford64(from: Illuminant, to: Illuminant) -> Matrix64 {
+    const CONE: Matrix64 = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let (source, target) = (apply64(&CONE, from.xyz()), apply64(&CONE, to.xyz()));
+    let gain = [
+        [target[0] / source[0], 0., 0.],
+        [0., target[1] / source[1], 0.],
+        [0., 0., target[2] / source[2]],
+    ];
+    multiply(&inverse(&CONE), &multiply(&gain, &CONE))
+}
+
+/// XYZ relative to D50 of linear RGB with `primaries`.
+fn rgb_to_d50(primaries: Primaries) -> Matrix64 {
+    multiply(
+        &bradford64(primaries.white(), Illuminant::D50),
+        &primaries.to_xyz64(),
+    )
+}
+
+pub fn transform(matrix: &Matrix, v: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn apply64(matrix: &Matrix64, v: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn multiply(a: &Matrix64, b: &Matrix64) -> Matrix64 {
+    let mut out = [[0.; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn inverse(m: &Matrix64) -> Matrix64 {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *m;
+    let cofactors = [
+        [e * i - f * h, c * h - b * i, b * f - c * e],
+        [f * g - d * i, a * i - c * g, c * d - a * f],
+        [d * h - e * g, b * g - a * h, a * e - b * d],
+    ];
+    let det = a * cofactors[0][0] + b * cofactors[1][0] + c * cofactors[2][0];
+    cofactors.map(|row| row.map(|x| x / det))
+}
+
+fn to_f32(m: Matrix64) -> Matrix {
+    m.map(|row| row.map(|x| x as f32))
+}
+
+fn xy_to_xyz([x, y]: [f64; 2]) -> [f64; 3] {
+    [x / y, 1., (1. - x - y) / y]
+}
+
+/// `x` to the power `e`, mirrored for negative values so that colors out
+/// of gamut survive a round trip.
+fn signed_powf(x: f32, e: f32) -> f32 {
+    x.signum() * x.abs().powf(e)
+}
+
+/// Decode the piecewise sRGB transfer function.
+pub fn srgb_to_linear(x: f32) -> f32 {
+    let v = x.abs();
+    let v = if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    };
+    v.copysign(x)
+}
+
+/// Encode with the piecewise sRGB transfer function.
+pub fn linear_to_srgb(x: f32) -> f32 {
+    let v = x.abs();
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    };
+    v.copysign(x)
+}
+
+/// Decode a pure power law of exponent `gamma`, 2.2 for instance.
+pub fn gamma_to_linear(x: f32, gamma: f32) -> f32 {
+    signed_powf(x, gamma)
+}
+
+pub fn linear_to_gamma(x: f32, gamma: f32) -> f32 {
+    signed_powf(x, 1. / gamma)
+}
+
+/// Decode a sample of `encoding`.
+pub fn to_linear(x: f32, encoding: Encoding) -> f32 {
+    match encoding {
+        Encoding::Linear => x,
+        Encoding::Srgb => srgb_to_linear(x),
+        Encoding::Gamma(gamma) => gamma_to_linear(x, gamma),
+    }
+}
+
+/// Encode a linear sample with `encoding`.
+pub fn from_linear(x: f32, encoding: Encoding) -> f32 {
+    match encoding {
+        Encoding::Linear => x,
+        Encoding::Srgb => linear_to_srgb(x),
+        Encoding::Gamma(gamma) => linear_to_gamma(x, gamma),
+    }
+}
+
+const LAB_EPSILON: f32 = 216. / 24389.;
+const LAB_KAPPA: f32 = 24389. / 27.;
+
+/// CIE Lab of XYZ relative to `white`.
+pub fn xyz_to_lab(xyz: [f32; 3], white: Illuminant) -> [f32; 3] {
+    let f = |t: f32| {
+        if t > LAB_EPSILON {
+            t.cbrt()
+        } else {
+            (LAB_KAPPA * t + 16.) / 116.
+        }
+    };
+    let w = white.xyz();
+    let [fx, fy, fz] = [0, 1, 2].map(|i| f(xyz[i] / w[i] as f32));
+    [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)]
+}
+
+pub fn lab_to_xyz([l, a, b]: [f32; 3], white: Illuminant) -> [f32; 3] {
+    let fy = (l + 16.) / 116.;
+    let (fx, fz) = (fy + a / 500., fy - b / 200.);
+    let f = |t: f32| {
+        if t.powi(3) > LAB_EPSILON {
+            t.powi(3)
+        } else {
+            (116. * t - 16.) / LAB_KAPPA
+        }
+    };
+    let y = if l > LAB_KAPPA * LAB_EPSILON {
+        fy.powi(3)
+    } else {
+        l / LAB_KAPPA
+    };
+    let w = white.xyz();
+    [f(fx) * w[0] as f32, y, f(fz) * w[2] as f32]
+}
+
+/// Polar form of Lab or Oklab: lightness, chroma and hue in degrees.
+pub fn lab_to_lch([l, a, b]: [f32; 3]) -> [f32; 3] {
+    [l, a.hypot(b), b.atan2(a).to_degrees().rem_euclid(360.)]
+}
+
+pub fn lch_to_lab([l, c, h]: [f32; 3]) -> [f32; 3] {
+    let (sin, cos) = h.to_radians().sin_cos();
+    [l, c * cos, c * sin]
+}
+
+/// Hue in degrees, saturation and lightness from 0 to 1, of RGB from 0 to
+/// 1. Grays have a hue of 0.
+pub fn rgb_to_hsl([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let l = (max + min) / 2.;
+    let d = max - min;
+    if d == 0. {
+        return [0., 0., l];
+    }
+    let s = d / (1. - (2. * l - 1.).abs());
+    let h = if max == r {
+        ((g - b) / d).rem_euclid(6.)
+    } else if max == g {
+        (b - r) / d + 2.
+    } else {
+        (r - g) / d + 4.
+    };
+    [h * 60., s, l]
+}
+
+pub fn hsl_to_rgb([h, s, l]: [f32; 3]) -> [f32; 3] {
+    let c = (1. - (2. * l - 1.).abs()) * s;
+    let h = h.rem_euclid(360.) / 60.;
+    let x = c * (1. - (h.rem_euclid(2.) - 1.).abs());
+    let m = l - c / 2.;
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.),
+        1 => (x, c, 0.),
+        2 => (0., c, x),
+        3 => (0., x, c),
+        4 => (x, 0., c),
+        _ => (c, 0., x),
+    };
+    [r + m, g + m, b + m]
+}
+
+/// From XYZ relative to D65 to LMS, then from its cube root to Oklab.
+const OKLAB_LMS: Matrix64 = [
+    [0.8189330101, 0.3618667424, -0.1288597137],
+    [0.0329845436, 0.9293118715, 0.0361456387],
+    [0.0482003018, 0.2643662691, 0.6338517070],
+];
+const OKLAB_LAB: Matrix64 = [
+    [0.2104542553, 0.7936177850, -0.0040720468],
+    [1.9779984951, -2.4285922050, 0.4505937099],
+    [0.0259040371, 0.7827717662, -0.8086757660],
+];
+const OKLAB_LMS_INVERSE: Matrix64 = [
+    [1.2270138511, -0.5577999807, 0.2812561490],
+    [-0.0405801784, 1.1122568696, -0.0716766787],
+    [-0.0763812845, -0.4214819784, 1.5861632204],
+];
+const OKLAB_LAB_INVERSE: Matrix64 = [
+    [1., 0.3963377774, 0.2158037573],
+    [1., -0.1055613458, -0.0638541728],
+    [1., -0.0894841775, -1.2914855480],
+];
+
+/// Oklab of XYZ relative to D65.
+pub fn xyz_to_oklab(xyz: [f32; 3]) -> [f32; 3] {
+    let lms = apply64(&OKLAB_LMS, xyz.map(f64::from)).map(f64::cbrt);
+    apply64(&OKLAB_LAB, lms).map(|x| x as f32)
+}
+
+pub fn oklab_to_xyz(lab: [f32; 3]) -> [f32; 3] {
+    let lms = apply64(&OKLAB_LAB_INVERSE, lab.map(f64::from)).map(|x| x.powi(3));
+    apply64(&OKLAB_LMS_INVERSE, lms).map(|x| x as f32)
+}
+
+// JzAzBz constants, from Safdar et al., "Perceptually uniform color space
+// for image signals including high dynamic range and wide gamut", 2017.
+const JZ_B: f64 = 1.15;
+const JZ_G: f64 = 0.66;
+const JZ_C1: f64 = 3424. / 4096.;
+const JZ_C2: f64 = 2413. / 128.;
+const JZ_C3: f64 = 2392. / 128.;
+const JZ_N: f64 = 2610. / 16384.;
+const JZ_P: f64 = 1.7 * 2523. / 32.;
+const JZ_D: f64 = -0.56;
+const JZ_D0: f64 = 1.6295499532821566e-11;
+/// Peak luminance of the PQ curve, in cd/m².
+const JZ_PEAK: f64 = 10000.;
+const JZ_LMS: Matrix64 = [
+    [0.41478972, 0.579999, 0.0146480],
+    [-0.2015100, 1.120649, 0.0531008],
+    [-0.0166008, 0.264800, 0.6684799],
+];
+const JZ_IAB: Matrix64 = [
+    [0.5, 0.5, 0.],
+    [3.524000, -4.066708, 0.542708],
+    [0.199076, 1.096799, -1.295875],
+];
+
+/// JzAzBz of absolute XYZ relative to D65, in cd/m².
+pub fn xyz_to_jzazbz(xyz: [f32; 3]) -> [f32; 3] {
+    let [x, y, z] = xyz.map(f64::from);
+    let x1 = JZ_B * x - (JZ_B - 1.) * z;
+    let y1 = JZ_G * y - (JZ_G - 1.) * x;
+    let pq = |v: f64| {
+        let t = v.signum() * (v.abs() / JZ_PEAK).powf(JZ_N);
+        ((JZ_C1 + JZ_C2 * t) / (1. + JZ_C3 * t)).powf(JZ_P)
+    };
+    let lms = apply64(&JZ_LMS, [x1, y1, z]).map(pq);
+    let [i, a, b] = apply64(&JZ_IAB, lms);
+    let jz = (1. + JZ_D) * i / (1. + JZ_D * i) - JZ_D0;
+    [jz, a, b].map(|v| v as f32)
+}
+
+pub fn jzazbz_to_xyz(jab: [f32; 3]) -> [f32; 3] {
+    let [jz, a, b] = jab.map(f64::from);
+    let jz = jz + JZ_D0;
+    let i = jz / (1. + JZ_D - JZ_D * jz);
+    let inverse_pq = |v: f64| {
+        let v = v.powf(1. / JZ_P);
+        let t = (JZ_C1 - v) / (JZ_C3 * v - JZ_C2);
+        JZ_PEAK * t.signum() * t.abs().powf(1. / JZ_N)
+    };
+    let lms = apply64(&inverse(&JZ_IAB), [i, a, b]).map(inverse_pq);
+    let [x1, y1, z] = apply64(&inverse(&JZ_LMS), lms);
+    let x = (x1 + (JZ_B - 1.) * z) / JZ_B;
+    let y = (y1 + (JZ_G - 1.) * x) / JZ_G;
+    [x, y, z].map(|v| v as f32)
+}
+
+/// A color space with what converting it takes: the primaries and transfer
+/// function of RGB and HSL, and the white point of XYZ, Lab and LCh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Space {
+    pub color_space: ColorSpace,
+    pub primaries: Primaries,
+    /// Transfer function of RGB and HSL. `ColorSpace::Linear` is linear RGB
+    /// whatever this says.
+    pub encoding: Encoding,
+    pub white: Illuminant,
+}
+
+impl Space {
+    /// `color_space` with sRGB primaries, linear values and a D50 white.
+    pub fn new(color_space: ColorSpace) -> Self {
+        Self {
+            color_space,
+            primaries: Primaries::default(),
+            encoding: Encoding::Linear,
+            white: Illuminant::D50,
+        }
+    }
+
+    pub fn with_primaries(mut self, primaries: Primaries) -> Self {
+        self.primaries = primaries;
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn with_white(mut self, white: Illuminant) -> Self {
+        self.white = white;
+        self
+    }
+
+    /// Encoding of images in this space.
+    fn image_encoding(&self) -> Encoding {
+        match self.color_space {
+            ColorSpace::RGB | ColorSpace::HSL => self.encoding,
+            _ => Encoding::Linear,
+        }
+    }
+
+    /// From the values `decode` gives to XYZ relative to D50.
+    fn to_d50(self) -> Matrix64 {
+        match self.color_space {
+            ColorSpace::Linear | ColorSpace::RGB | ColorSpace::HSL => rgb_to_d50(self.primaries),
+            ColorSpace::XYZ | ColorSpace::LAB | ColorSpace::LCH => {
+                bradford64(self.white, Illuminant::D50)
+            }
+            ColorSpace::JzAzBz | ColorSpace::Oklab => bradford64(Illuminant::D65, Illuminant::D50),
+        }
+    }
+
+    /// A color of this space as linear RGB, or XYZ relative to the white
+    /// of the space.
+    fn decode(&self, color: [f32; 3]) -> [f32; 3] {
+        match self.color_space {
+            ColorSpace::Linear | ColorSpace::XYZ => color,
+            ColorSpace::RGB => color.map(|x| to_linear(x, self.encoding)),
+            ColorSpace::HSL => hsl_to_rgb(color).map(|x| to_linear(x, self.encoding)),
+            ColorSpace::LAB => lab_to_xyz(color, self.white),
+            ColorSpace::LCH => lab_to_xyz(lch_to_lab(color), self.white),
+            ColorSpace::JzAzBz => jzazbz_to_xyz(color).map(|x| x / JZAZBZ_WHITE),
+            ColorSpace::Oklab => oklab_to_xyz(color),
+        }
+    }
+
+    fn encode(&self, value: [f32; 3]) -> [f32; 3] {
+        match self.color_space {
+            ColorSpace::Linear | ColorSpace::XYZ => value,
+            ColorSpace::RGB => value.map(|x| from_linear(x, self.encoding)),
+            ColorSpace::HSL => rgb_to_hsl(value.map(|x| from_linear(x, self.encoding))),
+            ColorSpace::LAB => xyz_to_lab(value, self.white),
+            ColorSpace::LCH => lab_to_lch(xyz_to_lab(value, self.white)),
+            ColorSpace::JzAzBz => xyz_to_jzazbz(value.map(|x| x * JZAZBZ_WHITE)),
+            ColorSpace::Oklab => xyz_to_oklab(value),
+        }
+    }
+}
+
+/// Converts colors from one space to another, with the matrices worked
+/// out once.
+#[derive(Clone, Debug)]
+pub struct Conversion {
+    from: Space,
+    to: Space,
+    /// From the decoded values of `from` to those of `to`.
+    matrix: Matrix,
+}
+
+impl Conversion {
+    pub fn new(from: Space, to: Space) -> Self {
+        let matrix = multiply(&inverse(&to.to_d50()), &from.to_d50());
+        Self {
+            from,
+            to,
+            matrix: to_f32(matrix),
+        }
+    }
+
+    pub fn convert(&self, color: [f32; 3]) -> [f32; 3] {
+        if self.from == self.to {
+            return color;
+        }
+        self.to
+            .encode(transform(&self.matrix, self.from.decode(color)))
+    }
+
+    /// Convert the first three channels of an image in the source space,
+    /// leaving alpha alone.
+    pub fn convert_image(&self, image: &Image<f32>) -> Result<Image<f32>> {
+        if image.channels() < 3 {
+            return Err(anyhow!(
+                "cannot convert the colors of {} channels",
+                image.channels()
+            ));
+        }
+        if image.color_space() != self.from.color_space {
+            return Err(anyhow!(
+                "image is in {:?}, not {:?}",
+                image.color_space(),
+                self.from.color_space
+            ));
+        }
+        let mut out = image
+            .map(|&x| x)
+            .with_color_space(self.to.color_space)
+            .with_encoding(self.to.image_encoding());
+        for pixel in out.pixels_mut() {
+            let color = self.convert([pixel[0], pixel[1], pixel[2]]);
+            pixel[..3].copy_from_slice(&color);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3], tolerance: f32) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a - e).abs() <= tolerance,
+                "{:?} is not within {} of {:?}",
+                actual,
+                tolerance,
+                expected
+            );
+        }
+    }
+
+    /// Colors in and around the sRGB gamut, as linear RGB.
+    fn samples() -> Vec<[f32; 3]> {
+        let mut colors = vec![[1.2, -0.1, 0.5], [0.02, 0.01, 0.005]];
+        let steps = [0.05, 0.3, 0.6, 1.];
+        for r in steps {
+            for g in steps {
+                for b in steps {
+                    colors.push([r, g, b]);
+                }
+            }
+        }
+        colors
+    }
+
+    fn srgb() -> Space {
+        Space::new(ColorSpace::Linear)
+    }
+
+    fn column(m: &Matrix, i: usize) -> [f32; 3] {
+        [m[0][i], m[1][i], m[2][i]]
+    }
+
+    #[test]
+    fn srgb_transfer() {
+        assert!((srgb_to_linear(0.5) - 0.214_041_1).abs() < 1e-6);
+        assert!((linear_to_srgb(0.003_130_8) - 0.040_45).abs() < 1e-6);
+        for i in -10..=120 {
+            let x = i as f32 / 100.;
+            assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-6);
+            assert!((gamma_to_linear(linear_to_gamma(x, 2.2), 2.2) - x).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn primaries() {
+        // Red of each space, from the published matrices.
+        assert_close(
+            column(&Primaries::Srgb.to_xyz(), 0),
+            [0.4124, 0.2126, 0.0193],
+            1e-4,
+        );
+        assert_close(
+            column(&Primaries::Rec709.to_xyz(), 0),
+            [0.4124, 0.2126, 0.0193],
+            1e-4,
+        );
+        assert_close(
+            column(&Primaries::DisplayP3.to_xyz(), 0),
+            [0.4866, 0.2290, 0.],
+            1e-4,
+        );
+        assert_close(
+            column(&Primaries::Rec2020.to_xyz(), 0),
+            [0.6370, 0.2627, 0.],
+            1e-4,
+        );
+        assert_close(
+            column(&Primaries::ProPhoto.to_xyz(), 0),
+            [0.7977, 0.2880, 0.],
+            1e-4,
+        );
+        for primaries in [
+            Primaries::Srgb,
+            Primaries::DisplayP3,
+            Primaries::Rec2020,
+            Primaries::ProPhoto,
+        ] {
+            let white = primaries.white().xyz().map(|x| x as f32);
+            assert_close(transform(&primaries.to_xyz(), [1., 1., 1.]), white, 1e-5);
+        }
+    }
+
+    #[test]
+    fn bradford_adaptation() {
+        let m = bradford(Illuminant::D65, Illuminant::D50);
+        let expected = [
+            [1.0478112, 0.0228866, -0.0501270],
+            [0.0295424, 0.9904844, -0.0170491],
+            [-0.0092345, 0.0150436, 0.7521316],
+        ];
+        for (row, expected) in m.iter().zip(expected) {
+            assert_close(*row, expected, 5e-4);
+        }
+        let d65 = Illuminant::D65.xyz().map(|x| x as f32);
+        let d50 = Illuminant::D50.xyz().map(|x| x as f32);
+        assert_close(transform(&m, d65), d50, 1e-6);
+    }
+
+    #[test]
+    fn lab() {
+        let red = transform(&to_f32(rgb_to_d50(Primaries::Srgb)), [1., 0., 0.]);
+        assert_close(
+            xyz_to_lab(red, Illuminant::D50),
+            [54.2917, 80.8125, 69.8851],
+            0.02,
+        );
+        let red = transform(&Primaries::Srgb.to_xyz(), [1., 0., 0.]);
+        assert_close(
+            xyz_to_lab(red, Illuminant::D65),
+            [53.2408, 80.0925, 67.2032],
+            0.02,
+        );
+        let white = Illuminant::D50.xyz().map(|x| x as f32);
+        assert_close(xyz_to_lab(white, Illuminant::D50), [100., 0., 0.], 1e-4);
+        assert_close(lab_to_lch([50., 0., 10.]), [50., 10., 90.], 1e-4);
+        assert_close(lch_to_lab([50., 10., 180.]), [50., -10., 0.], 1e-4);
+    }
+
+    #[test]
+    fn hsl() {
+        assert_close(rgb_to_hsl([1., 0., 0.]), [0., 1., 0.5], 1e-6);
+        assert_close(rgb_to_hsl([0.25, 0.5, 0.75]), [210., 0.5, 0.5], 1e-5);
+        assert_close(rgb_to_hsl([0.4, 0.4, 0.4]), [0., 0., 0.4], 1e-6);
+        assert_close(hsl_to_rgb([120., 1., 0.25]), [0., 0.5, 0.], 1e-6);
+    }
+
+    #[test]
+    fn oklab() {
+        // From the table in Björn Ottosson's introduction of Oklab.
+        assert_close(xyz_to_oklab([0.950, 1., 1.089]), [1., 0., 0.], 1e-3);
+        assert_close(xyz_to_oklab([1., 0., 0.]), [0.450, 1.236, -0.019], 1e-3);
+        assert_close(xyz_to_oklab([0., 1., 0.]), [0.922, -0.671, 0.263], 1e-3);
+        assert_close(xyz_to_oklab([0., 0., 1.]), [0.153, -1.415, -0.449], 1e-3);
+        let red = transform(&Primaries::Srgb.to_xyz(), [1., 0., 0.]);
+        assert_close(xyz_to_oklab(red), [0.627955, 0.224863, 0.125846], 1e-4);
+    }
+
+    #[test]
+    fn jzazbz() {
+        // From the documentation of colour-science.
+        let xyz = [0.20654008, 0.12197225, 0.05136952];
+        assert_close(xyz_to_jzazbz(xyz), [0.0053504, 0.0092430, 0.0052600], 1e-6);
+        assert_close(jzazbz_to_xyz(xyz_to_jzazbz(xyz)), xyz, 1e-6);
+    }
+
+    #[test]
+    fn round_trips() {
+        let spaces = [
+            srgb().with_primaries(Primaries::DisplayP3),
+            srgb().with_primaries(Primaries::Rec2020),
+            srgb().with_primaries(Primaries::ProPhoto),
+            Space::new(ColorSpace::RGB).with_encoding(Encoding::Srgb),
+            Space::new(ColorSpace::RGB).with_encoding(Encoding::Gamma(2.2)),
+            Space::new(ColorSpace::HSL).with_encoding(Encoding::Srgb),
+            Space::new(ColorSpace::XYZ),
+            Space::new(ColorSpace::XYZ).with_white(Illuminant::D65),
+            Space::new(ColorSpace::LAB),
+            Space::new(ColorSpace::LAB).with_white(Illuminant::D65),
+            Space::new(ColorSpace::LCH),
+            Space::new(ColorSpace::JzAzBz),
+            Space::new(ColorSpace::Oklab),
+        ];
+        for space in spaces {
+            let there = Conversion::new(srgb(), space);
+            let back = Conversion::new(space, srgb());
+            for color in samples() {
+                // HSL is only defined within the gamut.
+                if space.color_space == ColorSpace::HSL
+                    && color.iter().any(|x| !(0. ..=1.).contains(x))
+                {
+                    continue;
+                }
+                let converted = there.convert(color);
+                assert_close(back.convert(converted), color, 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn conversions_agree() {
+        // sRGB red to D50 Lab, directly and through other spaces.
+        let red = Space::new(ColorSpace::RGB).with_encoding(Encoding::Srgb);
+        let lab = Space::new(ColorSpace::LAB);
+        let expected = Conversion::new(red, lab).convert([1., 0., 0.]);
+        assert_close(expected, [54.2917, 80.8125, 69.8851], 0.02);
+        for via in [
+            Space::new(ColorSpace::Oklab),
+            Space::new(ColorSpace::JzAzBz),
+            srgb().with_primaries(Primaries::Rec2020),
+        ] {
+            let color = Conversion::new(red, via).convert([1., 0., 0.]);
+            assert_close(Conversion::new(via, lab).convert(color), expected, 1e-2);
+        }
+    }
+
+    #[test]
+    fn images() {
+        let image = Image::from_vec(2, 1, 4, vec![1., 0., 0., 0.5, 0.5, 0.5, 0.5, 1.])
+            .unwrap()
+            .with_color_space(ColorSpace::RGB)
+            .with_encoding(Encoding::Srgb);
+        let from = Space::new(ColorSpace::RGB).with_encoding(Encoding::Srgb);
+        let lab = Conversion::new(from, Space::new(ColorSpace::LAB))
+            .convert_image(&image)
+            .unwrap();
+        assert_eq!(lab.color_space(), ColorSpace::LAB);
+        assert_eq!(lab.encoding(), Encoding::Linear);
+        assert_eq!(lab.pixel(0, 0)[3], 0.5);
+        assert_close(
+            lab.pixel(1, 0)[..3].try_into().unwrap(),
+            [53.389, 0., 0.],
+            0.01,
+        );
+        assert!(Conversion::new(Space::new(ColorSpace::HSL), from)
+            .convert_image(&image)
+            .is_err());
+    }
+}
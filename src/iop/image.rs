@@ -1,6 +1,6 @@
-//! Image container and processor definition
+//! Image container
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 pub trait Primitive: Copy {}
 pub trait IntPrimitive: Primitive {}
@@ -28,41 +28,776 @@ impl<T: Primitive> Pixel for (T, T) {}
 impl<T: Primitive> Pixel for (T, T, T) {}
 impl<T: Primitive> Pixel for (T, T, T, T) {}
 
+/// Sample types with a nominal range, mapped to 0 to 1 as floats.
+pub trait Normalize: Primitive {
+    fn to_unit(self) -> f32;
+    /// Floats out of 0 to 1 are clamped for integer types.
+    fn from_unit(x: f32) -> Self;
+}
+
+impl Normalize for u8 {
+    fn to_unit(self) -> f32 {
+        self as f32 / 255.
+    }
+
+    fn from_unit(x: f32) -> Self {
+        (x.clamp(0., 1.) * 255.).round() as u8
+    }
+}
+
+impl Normalize for u16 {
+    fn to_unit(self) -> f32 {
+        self as f32 / 65535.
+    }
+
+    fn from_unit(x: f32) -> Self {
+        (x.clamp(0., 1.) * 65535.).round() as u16
+    }
+}
+
+impl Normalize for f32 {
+    fn to_unit(self) -> f32 {
+        self
+    }
+
+    fn from_unit(x: f32) -> Self {
+        x
+    }
+}
+
 #[non_exhaustive]
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ColorSpace {
     /// Linear value.
     Linear,
+    #[default]
     RGB,
     HSL,
     LCH,
     LAB,
     JzAzBz,
+    XYZ,
+    Oklab,
 }
 
 #[non_exhaustive]
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Encoding {
+    #[default]
     Linear,
+    /// The piecewise sRGB transfer function.
+    Srgb,
     Gamma(f32),
 }
 
+/// How [`Image::pad`] fills the border.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Border {
+    /// Default samples, zero for numbers.
+    Zero,
+    /// Repeat the edge pixels.
+    Clamp,
+    /// Reflect the image about its edge pixels.
+    Mirror,
+}
+
+/// Interleaved image buffer. Rows start `stride` samples apart, which may
+/// leave padding after each row.
 #[derive(Debug, Clone)]
 pub struct Image<T: Pixel> {
+    width: usize,
+    height: usize,
+    channels: usize,
+    stride: usize,
+    color_space: ColorSpace,
+    encoding: Encoding,
     image: Vec<T>,
 }
 
-pub trait ImageOp<T: Pixel, U: Pixel> {
-    const NAME: &'static str;
-    const DESCRIPTION: &'static str;
+impl<T: Pixel> Image<T> {
+    /// An image of default samples, zero for numbers.
+    pub fn new(width: usize, height: usize, channels: usize) -> Self
+    where
+        T: Default,
+    {
+        Self::from_vec(
+            width,
+            height,
+            channels,
+            vec![T::default(); width * height * channels],
+        )
+        .expect("buffer matches dimensions")
+    }
+
+    /// Wraps interleaved samples of `channels` per pixel.
+    pub fn from_vec(width: usize, height: usize, channels: usize, image: Vec<T>) -> Result<Self> {
+        Self::from_vec_strided(width, height, channels, width * channels, image)
+    }
+
+    /// Wraps interleaved samples whose rows start `stride` samples apart.
+    pub fn from_vec_strided(
+        width: usize,
+        height: usize,
+        channels: usize,
+        stride: usize,
+        image: Vec<T>,
+    ) -> Result<Self> {
+        if stride < width * channels {
+            return Err(anyhow!(
+                "stride {} is shorter than a row of {}x{}",
+                stride,
+                width,
+                channels
+            ));
+        }
+        let expected = stride * height;
+        if image.len() != expected {
+            return Err(anyhow!(
+                "buffer holds {} samples, {}x{}x{} with stride {} needs {}",
+                image.len(),
+                width,
+                height,
+                channels,
+                stride,
+                expected
+            ));
+        }
+        Ok(Self {
+            width,
+            height,
+            channels,
+            stride,
+            color_space: ColorSpace::default(),
+            encoding: Encoding::default(),
+            image,
+        })
+    }
+
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Samples per pixel.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Samples from the start of a row to the start of the next.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Rows have no padding.
+    pub fn is_packed(&self) -> bool {
+        self.stride == self.width * self.channels
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// All samples, row padding included.
+    pub fn as_slice(&self) -> &[T] {
+        &self.image
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.image
+    }
+
+    /// Samples without row padding.
+    pub fn into_vec(self) -> Vec<T> {
+        if self.is_packed() {
+            return self.image;
+        }
+        self.rows().flatten().cloned().collect()
+    }
+
+    /// The image with rows `stride` samples apart, padded with `fill`.
+    pub fn to_stride(&self, stride: usize, fill: T) -> Result<Self> {
+        let mut image = Vec::with_capacity(stride * self.height);
+        for row in self.rows() {
+            image.extend_from_slice(row);
+            image.resize(image.len() + stride.saturating_sub(row.len()), fill.clone());
+        }
+        Ok(
+            Self::from_vec_strided(self.width, self.height, self.channels, stride, image)?
+                .with_color_space(self.color_space)
+                .with_encoding(self.encoding),
+        )
+    }
+
+    /// Samples of row `y`, without padding.
+    pub fn row(&self, y: usize) -> &[T] {
+        let start = y * self.stride;
+        &self.image[start..start + self.width * self.channels]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [T] {
+        let start = y * self.stride;
+        &mut self.image[start..start + self.width * self.channels]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.height).map(|y| self.row(y))
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        let len = self.width * self.channels;
+        self.image
+            .chunks_mut(self.stride.max(1))
+            .take(self.height)
+            .map(move |x| &mut x[..len])
+    }
+
+    /// Samples of the pixel at `x`, `y`.
+    pub fn pixel(&self, x: usize, y: usize) -> &[T] {
+        check_pixel(x, y, self.width, self.height);
+        let start = y * self.stride + x * self.channels;
+        &self.image[start..start + self.channels]
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut [T] {
+        check_pixel(x, y, self.width, self.height);
+        let start = y * self.stride + x * self.channels;
+        &mut self.image[start..start + self.channels]
+    }
+
+    /// Pixels in row order, each as its samples.
+    pub fn pixels(&self) -> impl Iterator<Item = &[T]> {
+        let channels = self.channels.max(1);
+        self.rows().flat_map(move |x| x.chunks_exact(channels))
+    }
+
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        let channels = self.channels.max(1);
+        self.rows_mut()
+            .flat_map(move |x| x.chunks_exact_mut(channels))
+    }
+
+    /// The whole image, borrowed.
+    pub fn as_view(&self) -> ImageView<'_, T> {
+        ImageView {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            stride: self.stride,
+            color_space: self.color_space,
+            encoding: self.encoding,
+            data: &self.image,
+        }
+    }
+
+    /// The `width` by `height` rectangle at `x`, `y`, borrowed.
+    pub fn view(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<ImageView<'_, T>> {
+        self.as_view().view(x, y, width, height)
+    }
+
+    /// The `width` by `height` rectangle at `x`, `y`, borrowed mutably.
+    pub fn view_mut(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<ImageViewMut<'_, T>> {
+        let span = span(
+            self.width,
+            self.height,
+            self.channels,
+            self.stride,
+            x,
+            y,
+            width,
+            height,
+        )?;
+        Ok(ImageViewMut {
+            width,
+            height,
+            channels: self.channels,
+            // Rows of an empty view all start at its empty data.
+            stride: if span.is_empty() { 0 } else { self.stride },
+            color_space: self.color_space,
+            encoding: self.encoding,
+            data: &mut self.image[span],
+        })
+    }
+
+    /// The image grown by `border` pixels on every side.
+    pub fn pad(&self, border: usize, mode: Border) -> Self
+    where
+        T: Default,
+    {
+        let (width, height) = (self.width + 2 * border, self.height + 2 * border);
+        let mut image = Vec::with_capacity(width * height * self.channels);
+        let blank = vec![T::default(); self.channels];
+        for y in 0..height {
+            let sy = source(y as isize - border as isize, self.height, mode);
+            for x in 0..width {
+                let sx = source(x as isize - border as isize, self.width, mode);
+                match (sx, sy) {
+                    (Some(sx), Some(sy)) => image.extend_from_slice(self.pixel(sx, sy)),
+                    _ => image.extend_from_slice(&blank),
+                }
+            }
+        }
+        Self::from_vec(width, height, self.channels, image)
+            .expect("buffer matches dimensions")
+            .with_color_space(self.color_space)
+            .with_encoding(self.encoding)
+    }
+
+    /// A packed image of `f` applied to every sample, with the same tags.
+    pub fn map<U: Pixel>(&self, f: impl Fn(&T) -> U) -> Image<U> {
+        let image = self.rows().flatten().map(f).collect();
+        Image::from_vec(self.width, self.height, self.channels, image)
+            .expect("buffer matches dimensions")
+            .with_color_space(self.color_space)
+            .with_encoding(self.encoding)
+    }
+
+    /// The image in another sample type, scaling through 0 to 1. Values
+    /// are rescaled as they are: the encoding is kept, not linearized.
+    pub fn convert<U: Normalize>(&self) -> Image<U>
+    where
+        T: Normalize,
+    {
+        self.map(|&x| U::from_unit(x.to_unit()))
+    }
+}
+
+/// Range of `data` covering the `width` by `height` rectangle at `x`, `y`
+/// of an image laid out as given. An empty rectangle covers nothing.
+#[allow(clippy::too_many_arguments)]
+fn span(
+    image_width: usize,
+    image_height: usize,
+    channels: usize,
+    stride: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Result<std::ops::Range<usize>> {
+    if x + width > image_width || y + height > image_height {
+        return Err(anyhow!(
+            "{}x{} at {},{} is out of {}x{}",
+            width,
+            height,
+            x,
+            y,
+            image_width,
+            image_height
+        ));
+    }
+    if width == 0 || height == 0 {
+        return Ok(0..0);
+    }
+    let start = y * stride + x * channels;
+    Ok(start..start + (height - 1) * stride + width * channels)
+}
+
+/// Panics unless `x`, `y` is a pixel of a `width` by `height` image, as
+/// the samples found otherwise may be of another pixel or padding.
+fn check_pixel(x: usize, y: usize, width: usize, height: usize) {
+    assert!(
+        x < width && y < height,
+        "pixel {},{} is out of {}x{}",
+        x,
+        y,
+        width,
+        height
+    );
+}
+
+/// Index of the pixel shown at `i` along an axis of `len` pixels, or `None`
+/// for blank.
+fn source(i: isize, len: usize, mode: Border) -> Option<usize> {
+    if (0..len as isize).contains(&i) {
+        return Some(i as usize);
+    }
+    if len == 0 {
+        return None;
+    }
+    match mode {
+        Border::Zero => None,
+        Border::Clamp => Some(i.clamp(0, len as isize - 1) as usize),
+        Border::Mirror if len == 1 => Some(0),
+        Border::Mirror => {
+            let period = 2 * (len as isize - 1);
+            let i = i.rem_euclid(period);
+            Some(if i < len as isize { i } else { period - i } as usize)
+        }
+    }
+}
+
+/// A rectangle of an image, borrowed.
+#[derive(Debug)]
+pub struct ImageView<'a, T: Pixel> {
+    width: usize,
+    height: usize,
+    channels: usize,
+    stride: usize,
+    color_space: ColorSpace,
+    encoding: Encoding,
+    /// From the first sample of the rectangle to the last.
+    data: &'a [T],
+}
+
+impl<T: Pixel> Clone for ImageView<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Pixel> Copy for ImageView<'_, T> {}
+
+impl<'a, T: Pixel> ImageView<'a, T> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn row(&self, y: usize) -> &'a [T] {
+        let start = y * self.stride;
+        &self.data[start..start + self.width * self.channels]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [T]> + '_ {
+        (0..self.height).map(|y| self.row(y))
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> &'a [T] {
+        check_pixel(x, y, self.width, self.height);
+        let start = y * self.stride + x * self.channels;
+        &self.data[start..start + self.channels]
+    }
+
+    /// A rectangle of this view, at `x`, `y` within it.
+    pub fn view(&self, x: usize, y: usize, width: usize, height: usize) -> Result<Self> {
+        let span = span(
+            self.width,
+            self.height,
+            self.channels,
+            self.stride,
+            x,
+            y,
+            width,
+            height,
+        )?;
+        Ok(Self {
+            width,
+            height,
+            // Rows of an empty view all start at its empty data.
+            stride: if span.is_empty() { 0 } else { self.stride },
+            data: &self.data[span],
+            ..*self
+        })
+    }
+
+    /// Copy into a packed image, with the same tags.
+    pub fn to_image(&self) -> Image<T> {
+        let image = self.rows().flatten().cloned().collect();
+        Image::from_vec(self.width, self.height, self.channels, image)
+            .expect("buffer matches dimensions")
+            .with_color_space(self.color_space)
+            .with_encoding(self.encoding)
+    }
+}
+
+/// A rectangle of an image, borrowed mutably.
+#[derive(Debug)]
+pub struct ImageViewMut<'a, T: Pixel> {
+    width: usize,
+    height: usize,
+    channels: usize,
+    stride: usize,
+    color_space: ColorSpace,
+    encoding: Encoding,
+    data: &'a mut [T],
+}
+
+impl<T: Pixel> ImageViewMut<'_, T> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn as_view(&self) -> ImageView<'_, T> {
+        ImageView {
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            stride: self.stride,
+            color_space: self.color_space,
+            encoding: self.encoding,
+            data: self.data,
+        }
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [T] {
+        let start = y * self.stride;
+        &mut self.data[start..start + self.width * self.channels]
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> {
+        let len = self.width * self.channels;
+        self.data
+            .chunks_mut(self.stride.max(1))
+            .take(self.height)
+            .map(move |x| &mut x[..len])
+    }
+
+    pub fn pixel_mut(&mut self, x: usize, y: usize) -> &mut [T] {
+        check_pixel(x, y, self.width, self.height);
+        let start = y * self.stride + x * self.channels;
+        &mut self.data[start..start + self.channels]
+    }
+
+    /// Copy `source`, of the same size, into the rectangle.
+    pub fn copy_from(&mut self, source: &ImageView<T>) -> Result<()> {
+        if (source.width, source.height, source.channels)
+            != (self.width, self.height, self.channels)
+        {
+            return Err(anyhow!(
+                "cannot copy {}x{}x{} into {}x{}x{}",
+                source.width,
+                source.height,
+                source.channels,
+                self.width,
+                self.height,
+                self.channels
+            ));
+        }
+        for (to, from) in self.rows_mut().zip(source.rows()) {
+            to.clone_from_slice(from);
+        }
+        Ok(())
+    }
+
+    /// Set every pixel to `value`, one sample per channel.
+    pub fn fill(&mut self, value: &[T]) -> Result<()> {
+        if value.len() != self.channels {
+            return Err(anyhow!(
+                "cannot fill {} channels with {} samples",
+                self.channels,
+                value.len()
+            ));
+        }
+        let channels = self.channels.max(1);
+        for pixel in self.rows_mut().flat_map(|x| x.chunks_exact_mut(channels)) {
+            pixel.clone_from_slice(value);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width` by `height` image of 2 channels, each pixel holding its
+    /// coordinates.
+    fn coordinates(width: usize, height: usize) -> Image<u8> {
+        let image = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [x as u8, y as u8]))
+            .collect();
+        Image::from_vec(width, height, 2, image).unwrap()
+    }
+
+    #[test]
+    fn view_bounds() {
+        let img = coordinates(5, 4);
+        let view = img.view(1, 2, 3, 2).unwrap();
+        assert_eq!((view.width(), view.height()), (3, 2));
+        assert_eq!(view.pixel(0, 0), [1, 2]);
+        assert_eq!(view.pixel(2, 1), [3, 3]);
+        assert_eq!(view.row(1), [1, 3, 2, 3, 3, 3]);
+        let inner = view.view(1, 1, 2, 1).unwrap();
+        assert_eq!(inner.to_image().into_vec(), [2, 3, 3, 3]);
+
+        assert!(img.view(0, 0, 5, 4).is_ok());
+        let empty = img.view(5, 4, 0, 0).unwrap();
+        assert_eq!(empty.rows().count(), 0);
+        let empty = img.view(5, 1, 0, 3).unwrap();
+        assert!(empty.rows().all(|x| x.is_empty()));
+        assert_eq!(empty.to_image().as_slice().len(), 0);
+        assert!(img.view(3, 0, 3, 1).is_err());
+        assert!(img.view(0, 1, 1, 4).is_err());
+        assert!(view.view(1, 0, 3, 1).is_err());
+    }
+
+    #[test]
+    fn views_keep_tags() {
+        let mut img = coordinates(4, 3)
+            .with_color_space(ColorSpace::Linear)
+            .with_encoding(Encoding::Gamma(2.2));
+        let copy = img
+            .view(1, 1, 2, 1)
+            .unwrap()
+            .view(1, 0, 1, 1)
+            .unwrap()
+            .to_image();
+        assert_eq!(copy.color_space(), ColorSpace::Linear);
+        assert_eq!(copy.encoding(), Encoding::Gamma(2.2));
+        let view = img.view_mut(0, 0, 2, 2).unwrap();
+        assert_eq!(view.as_view().to_image().encoding(), Encoding::Gamma(2.2));
+    }
+
+    /// Past the end of a row, with padding to read from.
+    #[test]
+    #[should_panic(expected = "pixel 3,0 is out of 3x2")]
+    fn pixel_in_padding() {
+        coordinates(3, 2).to_stride(8, 0).unwrap().pixel(3, 0);
+    }
+
+    /// Past the end of a row of a view, into the next row of its image.
+    #[test]
+    #[should_panic(expected = "pixel 2,0 is out of 2x2")]
+    fn view_pixel_out_of_bounds() {
+        coordinates(4, 3).view(1, 1, 2, 2).unwrap().pixel(2, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel 0,2 is out of 2x2")]
+    fn view_mut_pixel_out_of_bounds() {
+        coordinates(4, 4)
+            .view_mut(0, 0, 2, 2)
+            .unwrap()
+            .pixel_mut(0, 2);
+    }
+
+    #[test]
+    fn view_mut_writes_through() {
+        let mut img = coordinates(4, 3);
+        let mut view = img.view_mut(1, 1, 2, 2).unwrap();
+        view.fill(&[9, 9]).unwrap();
+        assert!(view.fill(&[9]).is_err());
+        assert!(view.fill(&[9, 9, 9]).is_err());
+        view.pixel_mut(1, 1).copy_from_slice(&[7, 7]);
+        assert_eq!(img.pixel(0, 1), [0, 1]);
+        assert_eq!(img.pixel(1, 1), [9, 9]);
+        assert_eq!(img.pixel(2, 2), [7, 7]);
+        assert_eq!(img.pixel(3, 2), [3, 2]);
+
+        let source = coordinates(2, 2);
+        let mut view = img.view_mut(2, 0, 2, 2).unwrap();
+        view.copy_from(&source.as_view()).unwrap();
+        assert!(view.copy_from(&coordinates(1, 2).as_view()).is_err());
+        assert_eq!(img.row(0), [0, 0, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(img.row(1), [0, 1, 9, 9, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn stride_and_padding() {
+        assert!(Image::from_vec_strided(3, 2, 2, 5, vec![0u8; 10]).is_err());
+        assert!(Image::from_vec_strided(3, 2, 2, 8, vec![0u8; 15]).is_err());
+
+        let img = coordinates(3, 2).to_stride(8, 255).unwrap();
+        assert_eq!(img.stride(), 8);
+        assert!(!img.is_packed());
+        assert_eq!(img.as_slice().len(), 16);
+        assert_eq!(img.as_slice()[6..8], [255, 255]);
+        assert_eq!(img.row(1), [0, 1, 1, 1, 2, 1]);
+        assert_eq!(img.pixel(2, 1), [2, 1]);
+        assert_eq!(img.pixels().count(), 6);
+        assert_eq!(img.view(1, 0, 2, 2).unwrap().row(1), [1, 1, 2, 1]);
+        let packed = img.clone().into_vec();
+        assert_eq!(packed, coordinates(3, 2).into_vec());
+
+        let mut img = img;
+        for pixel in img.pixels_mut() {
+            pixel[0] += 10;
+        }
+        // Padding is left alone.
+        assert_eq!(img.as_slice()[6..8], [255, 255]);
+        assert_eq!(img.row(0), [10, 0, 11, 0, 12, 0]);
+    }
+
+    #[test]
+    fn pad_borders() {
+        let img = Image::from_vec(3, 1, 1, vec![1u8, 2, 3]).unwrap();
+        let row = |mode| img.pad(2, mode).row(2).to_vec();
+        assert_eq!(row(Border::Zero), [0, 0, 1, 2, 3, 0, 0]);
+        assert_eq!(row(Border::Clamp), [1, 1, 1, 2, 3, 3, 3]);
+        assert_eq!(row(Border::Mirror), [3, 2, 1, 2, 3, 2, 1]);
+        let padded = img.pad(1, Border::Clamp);
+        assert_eq!((padded.width(), padded.height()), (5, 3));
+        assert_eq!(padded.row(0), padded.row(1));
+    }
+
+    #[test]
+    fn sample_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let img = Image::from_vec(16, 16, 1, bytes.clone())
+            .unwrap()
+            .with_encoding(Encoding::Srgb);
+        let floats = img.convert::<f32>();
+        assert_eq!(floats.encoding(), Encoding::Srgb);
+        assert_eq!(floats.pixel(15, 15), [1.]);
+        assert_eq!(floats.convert::<u8>().into_vec(), bytes);
 
-    fn input_format() -> &'static [()];
-    fn output_format() -> &'static [()];
+        let words: Vec<u16> = (0..=u16::MAX)
+            .step_by(257)
+            .chain([1, 32767, 65534])
+            .collect();
+        let img = Image::from_vec(words.len(), 1, 1, words.clone()).unwrap();
+        assert_eq!(img.convert::<f32>().convert::<u16>().into_vec(), words);
+        assert_eq!(
+            img.convert::<u8>().convert::<u16>().into_vec()[..256],
+            words[..256]
+        );
 
-    fn pipe_inplace(&self, img: &mut Image<T>) -> Result<()>;
-    fn pipe_inplace_fast(&self, img: &mut Image<T>) -> Result<()>;
-    fn pipe(&self, img: &mut Image<T>, out: &mut Image<U>);
-    fn pipe_fast(&self, img: &mut Image<T>, out: &mut Image<U>);
+        // Out of range floats clamp.
+        let img = Image::from_vec(2, 1, 1, vec![-0.5f32, 1.5]).unwrap();
+        assert_eq!(img.convert::<u8>().into_vec(), [0, 255]);
+    }
 }
@@ -1,4 +1,6 @@
 //! Image processing pipeline
 
+pub mod color;
 pub mod image;
+pub mod ops;
 pub mod pipeline;
@@ -0,0 +1,128 @@
+//! Built-in operations.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::image::{Image, Pixel};
+use super::pipeline::{Buffer, Format, Operation, Registry, Sample};
+
+/// A registry of the built-in operations.
+pub fn registry() -> Registry {
+    let mut registry = Registry::default();
+    registry.register(|| Box::new(Exposure::default()));
+    registry.register(|| Box::new(Invert));
+    registry
+}
+
+/// Channels holding color, leaving alpha alone.
+fn color_channels(channels: usize) -> usize {
+    match channels {
+        2 | 4 => channels - 1,
+        x => x,
+    }
+}
+
+/// Brighten or darken by a number of stops.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Exposure {
+    /// Stops; each doubles the brightness.
+    pub ev: f32,
+}
+
+impl Operation for Exposure {
+    fn name(&self) -> &'static str {
+        "exposure"
+    }
+
+    fn description(&self) -> &'static str {
+        "Brighten or darken by a number of stops"
+    }
+
+    fn inputs(&self) -> &'static [Sample] {
+        &[Sample::F32]
+    }
+
+    fn output(&self, input: Format) -> Result<Format> {
+        Ok(input)
+    }
+
+    fn params(&self) -> Value {
+        serde_json::to_value(self).expect("plain struct")
+    }
+
+    fn set_params(&mut self, params: Value) -> Result<()> {
+        *self = serde_json::from_value(params)?;
+        Ok(())
+    }
+
+    fn process(&self, input: &Buffer) -> Result<Buffer> {
+        let Buffer::F32(image) = input else {
+            return Err(anyhow!("needs floats, got {}", input.format()));
+        };
+        let gain = self.ev.exp2();
+        let color = color_channels(image.channels());
+        let mut out = image.clone();
+        for pixel in out.pixels_mut() {
+            for x in &mut pixel[..color] {
+                *x *= gain;
+            }
+        }
+        Ok(Buffer::F32(out))
+    }
+}
+
+/// Negative of the image.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Invert;
+
+impl Invert {
+    fn invert<T: Pixel + Copy>(image: &Image<T>, f: impl Fn(T) -> T) -> Image<T> {
+        let color = color_channels(image.channels());
+        let mut out = image.clone();
+        for pixel in out.pixels_mut() {
+            for x in &mut pixel[..color] {
+                *x = f(*x);
+            }
+        }
+        out
+    }
+}
+
+impl Operation for Invert {
+    fn name(&self) -> &'static str {
+        "invert"
+    }
+
+    fn description(&self) -> &'static str {
+        "Negative of the image"
+    }
+
+    fn inputs(&self) -> &'static [Sample] {
+        &[Sample::F32, Sample::U16, Sample::U8]
+    }
+
+    fn output(&self, input: Format) -> Result<Format> {
+        Ok(input)
+    }
+
+    fn params(&self) -> Value {
+        Value::Null
+    }
+
+    fn set_params(&mut self, params: Value) -> Result<()> {
+        match params {
+            Value::Null => Ok(()),
+            x => Err(anyhow!("takes no parameters, got {}", x)),
+        }
+    }
+
+    fn process(&self, input: &Buffer) -> Result<Buffer> {
+        Ok(match input {
+            Buffer::U8(x) => Buffer::U8(Self::invert(x, |v| u8::MAX - v)),
+            Buffer::U16(x) => Buffer::U16(Self::invert(x, |v| u16::MAX - v)),
+            Buffer::F32(x) => Buffer::F32(Self::invert(x, |v| 1. - v)),
+        })
+    }
+}
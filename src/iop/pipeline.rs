@@ -1 +1,789 @@
+//! Non-destructive processing pipeline.
+//!
+//! A [`Pipeline`] runs an ordered list of [`Stage`]s over an input image.
+//! Each stage wraps a type-erased [`Operation`] with serializable
+//! parameters, and can be disabled or blended onto its input. Buffers are
+//! converted between stages when an operation does not take the format the
+//! previous one wrote. The output of every stage is cached, so that a
+//! change only recomputes the stages from the changed one on.
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::image::{Image, Normalize};
+
+/// Sample type of a buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Sample {
+    U8,
+    U16,
+    /// Nominally 0 to 1, though operations may go beyond.
+    F32,
+}
+
+/// Layout of a buffer, as negotiated between operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Format {
+    pub sample: Sample,
+    pub channels: usize,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}x{}", self.sample, self.channels)
+    }
+}
+
+/// An image of any sample type.
+#[derive(Debug, Clone)]
+pub enum Buffer {
+    U8(Image<u8>),
+    U16(Image<u16>),
+    F32(Image<f32>),
+}
+
+impl Buffer {
+    pub fn format(&self) -> Format {
+        let (sample, channels) = match self {
+            Buffer::U8(x) => (Sample::U8, x.channels()),
+            Buffer::U16(x) => (Sample::U16, x.channels()),
+            Buffer::F32(x) => (Sample::F32, x.channels()),
+        };
+        Format { sample, channels }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            Buffer::U8(x) => x.width(),
+            Buffer::U16(x) => x.width(),
+            Buffer::F32(x) => x.width(),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match self {
+            Buffer::U8(x) => x.height(),
+            Buffer::U16(x) => x.height(),
+            Buffer::F32(x) => x.height(),
+        }
+    }
+
+    /// The buffer with samples of type `sample`, scaling integers to 0 to
+    /// 1 and back. Floats are clamped when converted to integers.
+    pub fn convert(&self, sample: Sample) -> Buffer {
+        if self.format().sample == sample {
+            return self.clone();
+        }
+        match sample {
+            Sample::U8 => Buffer::U8(self.to()),
+            Sample::U16 => Buffer::U16(self.to()),
+            Sample::F32 => Buffer::F32(self.to()),
+        }
+    }
+
+    fn to<T: Normalize>(&self) -> Image<T> {
+        match self {
+            Buffer::U8(x) => x.convert(),
+            Buffer::U16(x) => x.convert(),
+            Buffer::F32(x) => x.convert(),
+        }
+    }
+}
+
+/// An image operation with its parameters, usable as a trait object so
+/// that a pipeline can hold any mix of them.
+pub trait Operation: Send + Sync {
+    /// Name the operation is stored and registered under, `exposure`.
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+
+    /// Sample types the operation reads, most preferred first. Buffers of
+    /// other types are converted to the first.
+    fn inputs(&self) -> &'static [Sample];
+    /// Format written for an `input` of an accepted sample type, or an
+    /// error if the operation cannot take it.
+    fn output(&self, input: Format) -> Result<Format>;
+
+    fn params(&self) -> Value;
+    /// Replace the parameters; on error they are left as they were.
+    fn set_params(&mut self, params: Value) -> Result<()>;
+
+    /// Process `input`, which is in a format the operation accepts.
+    fn process(&self, input: &Buffer) -> Result<Buffer>;
+}
+
+/// How a stage's output is combined with its input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Blend {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Lighten,
+    Darken,
+    Difference,
+    Add,
+}
+
+impl Blend {
+    /// Blend sample `b` of the stage output onto `a` of its input.
+    fn apply(self, a: f32, b: f32) -> f32 {
+        match self {
+            Blend::Normal => b,
+            Blend::Multiply => a * b,
+            Blend::Screen => 1. - (1. - a) * (1. - b),
+            Blend::Overlay if a < 0.5 => 2. * a * b,
+            Blend::Overlay => 1. - 2. * (1. - a) * (1. - b),
+            Blend::Lighten => a.max(b),
+            Blend::Darken => a.min(b),
+            Blend::Difference => (a - b).abs(),
+            Blend::Add => a + b,
+        }
+    }
+}
+
+/// Blend `top` onto `base` at `opacity`, in floats.
+fn blend(base: &Buffer, top: &Buffer, mode: Blend, opacity: f32) -> Result<Buffer> {
+    let (Buffer::F32(a), Buffer::F32(b)) = (base.convert(Sample::F32), top.convert(Sample::F32))
+    else {
+        unreachable!("converted to floats");
+    };
+    if (a.width(), a.height(), a.channels()) != (b.width(), b.height(), b.channels()) {
+        return Err(anyhow!(
+            "cannot blend {}x{} {} onto {}x{} {}",
+            b.width(),
+            b.height(),
+            top.format(),
+            a.width(),
+            a.height(),
+            base.format()
+        ));
+    }
+    let samples = a
+        .rows()
+        .zip(b.rows())
+        .flat_map(|(a, b)| a.iter().zip(b))
+        .map(|(&a, &b)| a + opacity * (mode.apply(a, b) - a))
+        .collect();
+    Ok(Buffer::F32(
+        Image::from_vec(a.width(), a.height(), a.channels(), samples)?
+            .with_color_space(a.color_space())
+            .with_encoding(a.encoding()),
+    ))
+}
+
+/// An operation in a pipeline, with its cached output.
+pub struct Stage {
+    op: Box<dyn Operation>,
+    enabled: bool,
+    opacity: f32,
+    blend: Blend,
+    /// Output as last computed; `None` once stale.
+    cache: Option<Arc<Buffer>>,
+}
+
+impl Stage {
+    pub fn new(op: Box<dyn Operation>) -> Stage {
+        Stage {
+            op,
+            enabled: true,
+            opacity: 1.,
+            blend: Blend::Normal,
+            cache: None,
+        }
+    }
+
+    pub fn op(&self) -> &dyn Operation {
+        &*self.op
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    pub fn blend(&self) -> Blend {
+        self.blend
+    }
+
+    /// Whether the output must be blended onto the input.
+    fn blends(&self) -> bool {
+        self.opacity < 1. || self.blend != Blend::Normal
+    }
+
+    /// Sample type `input` is converted to before this stage.
+    fn input_sample(&self, input: Format) -> Sample {
+        let accepted = self.op.inputs();
+        if accepted.contains(&input.sample) {
+            input.sample
+        } else {
+            accepted.first().copied().unwrap_or(input.sample)
+        }
+    }
+
+    /// Format written for `input`.
+    fn output(&self, input: Format) -> Result<Format> {
+        if !self.enabled {
+            return Ok(input);
+        }
+        let converted = Format {
+            sample: self.input_sample(input),
+            ..input
+        };
+        let output = self
+            .op
+            .output(converted)
+            .map_err(|e| anyhow!("{}: {}", self.op.name(), e))?;
+        if !self.blends() {
+            return Ok(output);
+        }
+        if output.channels != input.channels {
+            return Err(anyhow!(
+                "{}: cannot blend {} onto {}",
+                self.op.name(),
+                output,
+                input
+            ));
+        }
+        Ok(Format {
+            sample: Sample::F32,
+            ..output
+        })
+    }
+
+    fn run(&self, input: &Arc<Buffer>) -> Result<Arc<Buffer>> {
+        if !self.enabled {
+            return Ok(input.clone());
+        }
+        let sample = self.input_sample(input.format());
+        let output = if sample == input.format().sample {
+            self.op.process(input)
+        } else {
+            self.op.process(&input.convert(sample))
+        }
+        .map_err(|e| anyhow!("{}: {}", self.op.name(), e))?;
+        if !self.blends() {
+            return Ok(Arc::new(output));
+        }
+        let blended = blend(input, &output, self.blend, self.opacity.clamp(0., 1.))
+            .map_err(|e| anyhow!("{}: {}", self.op.name(), e))?;
+        Ok(Arc::new(blended))
+    }
+}
+
+/// A stage as saved: the operation by name, and its parameters.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageSpec {
+    pub op: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default = "opaque")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend: Blend,
+    #[serde(default)]
+    pub params: Value,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn opaque() -> f32 {
+    1.
+}
+
+/// Makes operations by name, to load saved pipelines.
+#[derive(Default)]
+pub struct Registry {
+    ops: BTreeMap<&'static str, fn() -> Box<dyn Operation>>,
+}
+
+impl Registry {
+    /// Add an operation made with default parameters by `make`, under the
+    /// name it reports.
+    pub fn register(&mut self, make: fn() -> Box<dyn Operation>) {
+        self.ops.insert(make().name(), make);
+    }
+
+    /// Names of the operations, in order.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.ops.keys().copied()
+    }
+
+    /// A new operation with default parameters.
+    pub fn make(&self, name: &str) -> Result<Box<dyn Operation>> {
+        self.ops
+            .get(name)
+            .map(|make| make())
+            .ok_or_else(|| anyhow!("unknown operation {}", name))
+    }
+}
+
+/// Stages run in order over an input image, caching their outputs.
+#[derive(Default)]
+pub struct Pipeline {
+    input: Option<Arc<Buffer>>,
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Set the image processed, invalidating every stage.
+    pub fn set_input(&mut self, input: Arc<Buffer>) {
+        self.input = Some(input);
+        self.invalidate(0);
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Drop the cached outputs from stage `from` on.
+    fn invalidate(&mut self, from: usize) {
+        for stage in self.stages.iter_mut().skip(from) {
+            stage.cache = None;
+        }
+    }
+
+    fn stage_mut(&mut self, index: usize) -> Result<&mut Stage> {
+        let len = self.stages.len();
+        self.stages
+            .get_mut(index)
+            .ok_or_else(|| anyhow!("no stage {} of {}", index, len))
+    }
+
+    /// Insert `op` at `index`, or at the end.
+    pub fn insert(&mut self, index: usize, op: Box<dyn Operation>) {
+        let index = index.min(self.stages.len());
+        self.stages.insert(index, Stage::new(op));
+        self.invalidate(index);
+    }
+
+    pub fn push(&mut self, op: Box<dyn Operation>) {
+        self.insert(usize::MAX, op);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<Box<dyn Operation>> {
+        self.stage_mut(index)?;
+        let stage = self.stages.remove(index);
+        self.invalidate(index);
+        Ok(stage.op)
+    }
+
+    /// Move the stage at `from` to `to`.
+    pub fn reorder(&mut self, from: usize, to: usize) -> Result<()> {
+        self.stage_mut(from)?;
+        let stage = self.stages.remove(from);
+        let to = to.min(self.stages.len());
+        self.stages.insert(to, stage);
+        self.invalidate(from.min(to));
+        Ok(())
+    }
+
+    /// Replace the parameters of a stage.
+    pub fn set_params(&mut self, index: usize, params: Value) -> Result<()> {
+        let stage = self.stage_mut(index)?;
+        if stage.op.params() == params {
+            return Ok(());
+        }
+        stage.op.set_params(params)?;
+        self.invalidate(index);
+        Ok(())
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<()> {
+        let stage = self.stage_mut(index)?;
+        if stage.enabled != enabled {
+            stage.enabled = enabled;
+            self.invalidate(index);
+        }
+        Ok(())
+    }
+
+    /// Blend a stage onto its input with `mode` at `opacity`, 0 to 1.
+    pub fn set_blend(&mut self, index: usize, mode: Blend, opacity: f32) -> Result<()> {
+        let stage = self.stage_mut(index)?;
+        if (stage.blend, stage.opacity) != (mode, opacity) {
+            stage.blend = mode;
+            stage.opacity = opacity;
+            self.invalidate(index);
+        }
+        Ok(())
+    }
+
+    /// Output format of every stage, checking that each can take what the
+    /// one before writes.
+    pub fn formats(&self) -> Result<Vec<Format>> {
+        let input = self
+            .input
+            .as_ref()
+            .ok_or_else(|| anyhow!("no input image"))?;
+        let mut format = input.format();
+        let mut formats = vec![];
+        for stage in &self.stages {
+            format = stage.output(format)?;
+            formats.push(format);
+        }
+        Ok(formats)
+    }
+
+    /// The processed image. Only the stages whose output is not cached
+    /// are run.
+    pub fn process(&mut self) -> Result<Arc<Buffer>> {
+        let input = self
+            .input
+            .clone()
+            .ok_or_else(|| anyhow!("no input image"))?;
+        // Stages are invalidated from a point on, so the first stale one
+        // follows the last cached.
+        let start = self
+            .stages
+            .iter()
+            .position(|x| x.cache.is_none())
+            .unwrap_or(self.stages.len());
+        let mut buffer = match start {
+            0 => input,
+            i => self.stages[i - 1].cache.clone().expect("cached"),
+        };
+        for stage in &mut self.stages[start..] {
+            buffer = stage.run(&buffer)?;
+            stage.cache = Some(buffer.clone());
+        }
+        Ok(buffer)
+    }
+
+    /// The stages as saved.
+    pub fn specs(&self) -> Vec<StageSpec> {
+        self.stages
+            .iter()
+            .map(|x| StageSpec {
+                op: x.op.name().to_owned(),
+                enabled: x.enabled,
+                opacity: x.opacity,
+                blend: x.blend,
+                params: x.op.params(),
+            })
+            .collect()
+    }
+
+    /// A pipeline of saved stages, with operations made by `registry`.
+    pub fn from_specs(specs: &[StageSpec], registry: &Registry) -> Result<Pipeline> {
+        let mut pipeline = Pipeline::new();
+        for spec in specs {
+            let mut op = registry.make(&spec.op)?;
+            if !spec.params.is_null() {
+                op.set_params(spec.params.clone())
+                    .map_err(|e| anyhow!("{}: {}", spec.op, e))?;
+            }
+            pipeline.stages.push(Stage {
+                enabled: spec.enabled,
+                opacity: spec.opacity,
+                blend: spec.blend,
+                ..Stage::new(op)
+            });
+        }
+        Ok(pipeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::iop::ops;
+
+    /// Adds `add` to every sample, counting its runs.
+    struct Count {
+        add: f32,
+        runs: Arc<AtomicUsize>,
+    }
+
+    impl Operation for Count {
+        fn name(&self) -> &'static str {
+            "count"
+        }
+
+        fn description(&self) -> &'static str {
+            "Add to every sample"
+        }
+
+        fn inputs(&self) -> &'static [Sample] {
+            &[Sample::F32]
+        }
+
+        fn output(&self, input: Format) -> Result<Format> {
+            Ok(input)
+        }
+
+        fn params(&self) -> Value {
+            self.add.into()
+        }
+
+        fn set_params(&mut self, params: Value) -> Result<()> {
+            self.add = serde_json::from_value(params)?;
+            Ok(())
+        }
+
+        fn process(&self, input: &Buffer) -> Result<Buffer> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            let Buffer::F32(image) = input else {
+                return Err(anyhow!("needs floats, got {}", input.format()));
+            };
+            let mut out = image.clone();
+            out.pixels_mut().flatten().for_each(|x| *x += self.add);
+            Ok(Buffer::F32(out))
+        }
+    }
+
+    /// The first channel of bytes alone.
+    struct First;
+
+    impl Operation for First {
+        fn name(&self) -> &'static str {
+            "first"
+        }
+
+        fn description(&self) -> &'static str {
+            "First channel"
+        }
+
+        fn inputs(&self) -> &'static [Sample] {
+            &[Sample::U8]
+        }
+
+        fn output(&self, input: Format) -> Result<Format> {
+            match input.sample {
+                Sample::U8 => Ok(Format {
+                    channels: 1,
+                    ..input
+                }),
+                x => Err(anyhow!("needs bytes, got {:?}", x)),
+            }
+        }
+
+        fn params(&self) -> Value {
+            Value::Null
+        }
+
+        fn set_params(&mut self, _: Value) -> Result<()> {
+            Ok(())
+        }
+
+        fn process(&self, input: &Buffer) -> Result<Buffer> {
+            let Buffer::U8(image) = input else {
+                return Err(anyhow!("needs bytes, got {}", input.format()));
+            };
+            let samples = image.pixels().map(|x| x[0]).collect();
+            Ok(Buffer::U8(Image::from_vec(
+                image.width(),
+                image.height(),
+                1,
+                samples,
+            )?))
+        }
+    }
+
+    fn floats(samples: Vec<f32>) -> Arc<Buffer> {
+        let width = samples.len();
+        Arc::new(Buffer::F32(Image::from_vec(width, 1, 1, samples).unwrap()))
+    }
+
+    fn samples(buffer: &Buffer) -> Vec<f32> {
+        match buffer.convert(Sample::F32) {
+            Buffer::F32(x) => x.into_vec(),
+            _ => unreachable!("converted to floats"),
+        }
+    }
+
+    fn processed(pipeline: &mut Pipeline) -> Result<Vec<f32>> {
+        let output = pipeline.process()?;
+        Ok(samples(&output))
+    }
+
+    /// A pipeline adding 1, 2 and 3 to a zero, with the run counters of
+    /// its stages.
+    fn counting() -> (Pipeline, Vec<Arc<AtomicUsize>>) {
+        let mut pipeline = Pipeline::new();
+        let mut counters = vec![];
+        for add in [1., 2., 3.] {
+            let runs = Arc::new(AtomicUsize::new(0));
+            counters.push(runs.clone());
+            pipeline.push(Box::new(Count { add, runs }));
+        }
+        pipeline.set_input(floats(vec![0.]));
+        (pipeline, counters)
+    }
+
+    fn runs(counters: &[Arc<AtomicUsize>]) -> Vec<usize> {
+        counters.iter().map(|x| x.load(Ordering::SeqCst)).collect()
+    }
+
+    #[test]
+    fn reruns_from_change() -> Result<()> {
+        let (mut pipeline, counters) = counting();
+        assert_eq!(processed(&mut pipeline)?, [6.]);
+        assert_eq!(runs(&counters), [1, 1, 1]);
+        pipeline.process()?;
+        assert_eq!(runs(&counters), [1, 1, 1]);
+
+        pipeline.set_params(1, 4.0.into())?;
+        assert_eq!(processed(&mut pipeline)?, [8.]);
+        assert_eq!(runs(&counters), [1, 2, 2]);
+        // The same parameters change nothing.
+        pipeline.set_params(1, 4.0.into())?;
+        pipeline.process()?;
+        assert_eq!(runs(&counters), [1, 2, 2]);
+        assert!(pipeline.set_params(1, "x".into()).is_err());
+        assert!(pipeline.set_params(3, 1.into()).is_err());
+        assert_eq!(pipeline.stages()[1].op().params(), 4.);
+
+        // Disabled, a stage passes its input on without running.
+        pipeline.set_enabled(2, false)?;
+        assert_eq!(processed(&mut pipeline)?, [5.]);
+        assert_eq!(runs(&counters), [1, 2, 2]);
+        pipeline.set_enabled(2, true)?;
+
+        pipeline.set_blend(2, Blend::Normal, 0.5)?;
+        assert_eq!(processed(&mut pipeline)?, [6.5]);
+        assert_eq!(runs(&counters), [1, 2, 3]);
+        pipeline.set_blend(2, Blend::Normal, 0.5)?;
+        pipeline.process()?;
+        assert_eq!(runs(&counters), [1, 2, 3]);
+
+        // Stages 1 and 2 trade places; the first is left alone.
+        pipeline.reorder(2, 1)?;
+        pipeline.process()?;
+        assert_eq!(runs(&counters), [1, 3, 4]);
+        assert!(pipeline.reorder(3, 0).is_err());
+
+        pipeline.remove(0)?;
+        assert_eq!(processed(&mut pipeline)?, [5.5]);
+        assert_eq!(runs(&counters), [1, 4, 5]);
+
+        pipeline.set_input(floats(vec![1.]));
+        pipeline.process()?;
+        assert_eq!(runs(&counters), [1, 5, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn negotiates_formats() -> Result<()> {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut pipeline = Pipeline::new();
+        assert!(pipeline.formats().is_err());
+        assert!(pipeline.process().is_err());
+        pipeline.push(Box::new(Count { add: 0.25, runs }));
+        pipeline.push(Box::new(First));
+        let input = Image::from_vec(2, 1, 3, vec![0u8, 255, 255, 255, 0, 0])?;
+        pipeline.set_input(Arc::new(Buffer::U8(input)));
+        let formats = pipeline.formats()?;
+        let format = |sample, channels| Format { sample, channels };
+        assert_eq!(formats, [format(Sample::F32, 3), format(Sample::U8, 1)]);
+        let output = pipeline.process()?;
+        assert_eq!(output.format(), formats[1]);
+        let Buffer::U8(ref image) = *output else {
+            panic!("not bytes: {}", output.format());
+        };
+        assert_eq!(image.as_slice(), [64, 255]);
+
+        // Blended, a stage writes floats, and must keep the channels.
+        pipeline.set_blend(0, Blend::Screen, 1.)?;
+        assert_eq!(pipeline.formats()?[0], format(Sample::F32, 3));
+        pipeline.set_blend(1, Blend::Multiply, 1.)?;
+        let error = pipeline.formats().unwrap_err().to_string();
+        assert!(error.starts_with("first: cannot blend"), "{}", error);
+        assert!(pipeline.process().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn blend_modes() {
+        let (a, b) = (0.25, 0.5);
+        for (mode, expected) in [
+            (Blend::Normal, 0.5),
+            (Blend::Multiply, 0.125),
+            (Blend::Screen, 0.625),
+            (Blend::Overlay, 0.25),
+            (Blend::Lighten, 0.5),
+            (Blend::Darken, 0.25),
+            (Blend::Difference, 0.25),
+            (Blend::Add, 0.75),
+        ] {
+            assert_eq!(mode.apply(a, b), expected, "{:?}", mode);
+        }
+        // Overlay screens over a light base.
+        assert_eq!(Blend::Overlay.apply(0.75, 0.5), 0.75);
+
+        let base = floats(vec![0.25, 1.]);
+        let top = floats(vec![0.75, 0.]);
+        let blended = blend(&base, &top, Blend::Normal, 0.5).unwrap();
+        assert_eq!(samples(&blended), [0.5, 0.5]);
+        assert!(blend(&base, &floats(vec![0.]), Blend::Normal, 1.).is_err());
+    }
+
+    #[test]
+    fn specs_round_trip() -> Result<()> {
+        let registry = ops::registry();
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["exposure", "invert"]);
+        let specs: Vec<StageSpec> = serde_json::from_str(
+            r#"[
+                {"op": "exposure", "params": {"ev": 1.5}, "blend": "screen", "opacity": 0.5},
+                {"op": "invert", "enabled": false}
+            ]"#,
+        )?;
+        assert_eq!(specs[1].opacity, 1.);
+        assert_eq!(specs[1].blend, Blend::Normal);
+        let pipeline = Pipeline::from_specs(&specs, &registry)?;
+        assert_eq!(pipeline.len(), 2);
+        assert!(!pipeline.stages()[1].enabled());
+        assert_eq!(pipeline.stages()[0].blend(), Blend::Screen);
+        assert_eq!(pipeline.specs(), specs);
+        let json = serde_json::to_string(&pipeline.specs())?;
+        assert_eq!(serde_json::from_str::<Vec<StageSpec>>(&json)?, specs);
+
+        let spec = |json| serde_json::from_str::<Vec<StageSpec>>(json).unwrap();
+        assert!(Pipeline::from_specs(&spec(r#"[{"op": "blur"}]"#), &registry).is_err());
+        let bad = spec(r#"[{"op": "invert", "params": 1}]"#);
+        assert!(Pipeline::from_specs(&bad, &registry).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sample_conversion() {
+        let bytes = Buffer::U8(Image::from_vec(3, 1, 1, vec![0, 51, 255]).unwrap());
+        assert!(matches!(bytes.convert(Sample::U8), Buffer::U8(_)));
+        assert_eq!(samples(&bytes), [0., 0.2, 1.]);
+        let Buffer::U16(words) = bytes.convert(Sample::U16) else {
+            panic!("not words");
+        };
+        assert_eq!(words.as_slice(), [0, 13107, 65535]);
+
+        let Buffer::U8(clamped) = floats(vec![-1., 0.5, 2.]).convert(Sample::U8) else {
+            panic!("not bytes");
+        };
+        assert_eq!(clamped.as_slice(), [0, 128, 255]);
+    }
+}
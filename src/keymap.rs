@@ -0,0 +1,641 @@
+//! Keyboard actions and their bindings.
+//!
+//! Every command reachable from the keyboard is an [`Action`]. A [`Keymap`]
+//! is built from a profile modeled on a common viewer, with per-action
+//! overrides from the config.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use iced::keyboard::key::Named;
+use iced::keyboard::{Key, Modifiers};
+use serde::{Deserialize, Serialize};
+
+use crate::components::image::ViewerEvent;
+use crate::components::viewer::Scaling;
+use crate::db::datastore::{ColorLabel, Flag, Mark};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    ZoomIn,
+    ZoomOut,
+    ZoomOriginal,
+    ZoomChange,
+    Fit,
+    Fill,
+    FitWidth,
+    FitHeight,
+    RotateCW,
+    RotateCCW,
+    FlipHorizontal,
+    FlipVertical,
+    Reset,
+    Open,
+    Save,
+    SaveAs,
+    Export,
+    Fullscreen,
+    About,
+    Preferences,
+    Info,
+    Exit,
+    Next,
+    Previous,
+    First,
+    Last,
+    Rate0,
+    Rate1,
+    Rate2,
+    Rate3,
+    Rate4,
+    Rate5,
+    LabelRed,
+    LabelYellow,
+    LabelGreen,
+    LabelBlue,
+    LabelPurple,
+    Pick,
+    Reject,
+    Unflag,
+    EditTags,
+    /// Write catalog edits not yet in the files.
+    WritePending,
+    /// Show the library grid of the current folder or collection.
+    Library,
+    /// Show or hide the filmstrip under the viewer.
+    Filmstrip,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ZoomOriginal,
+        Action::ZoomChange,
+        Action::Fit,
+        Action::Fill,
+        Action::FitWidth,
+        Action::FitHeight,
+        Action::RotateCW,
+        Action::RotateCCW,
+        Action::FlipHorizontal,
+        Action::FlipVertical,
+        Action::Reset,
+        Action::Open,
+        Action::Save,
+        Action::SaveAs,
+        Action::Export,
+        Action::Fullscreen,
+        Action::About,
+        Action::Preferences,
+        Action::Info,
+        Action::Exit,
+        Action::Next,
+        Action::Previous,
+        Action::First,
+        Action::Last,
+        Action::Rate0,
+        Action::Rate1,
+        Action::Rate2,
+        Action::Rate3,
+        Action::Rate4,
+        Action::Rate5,
+        Action::LabelRed,
+        Action::LabelYellow,
+        Action::LabelGreen,
+        Action::LabelBlue,
+        Action::LabelPurple,
+        Action::Pick,
+        Action::Reject,
+        Action::Unflag,
+        Action::EditTags,
+        Action::WritePending,
+        Action::Library,
+        Action::Filmstrip,
+    ];
+
+    /// The viewer event this action sends, if it is handled by the viewer.
+    pub fn viewer_event(self) -> Option<ViewerEvent> {
+        Some(match self {
+            Action::ZoomIn => ViewerEvent::ZoomIn,
+            Action::ZoomOut => ViewerEvent::ZoomOut,
+            Action::ZoomOriginal => ViewerEvent::ZoomOriginal,
+            Action::ZoomChange => ViewerEvent::ZoomChange,
+            Action::Fit => ViewerEvent::SetScaling(Scaling::Fit),
+            Action::Fill => ViewerEvent::SetScaling(Scaling::Fill),
+            Action::FitWidth => ViewerEvent::SetScaling(Scaling::FitWidth),
+            Action::FitHeight => ViewerEvent::SetScaling(Scaling::FitHeight),
+            Action::RotateCW => ViewerEvent::RotateCW,
+            Action::RotateCCW => ViewerEvent::RotateCCW,
+            Action::FlipHorizontal => ViewerEvent::FlipHorizontal,
+            Action::FlipVertical => ViewerEvent::FlipVertical,
+            Action::Reset => ViewerEvent::Reset,
+            Action::Open => ViewerEvent::Open,
+            Action::Save => ViewerEvent::Save,
+            Action::SaveAs => ViewerEvent::SaveAs,
+            Action::Export => ViewerEvent::Export,
+            Action::Fullscreen => ViewerEvent::Fullscreen,
+            Action::About => ViewerEvent::About,
+            Action::Preferences => ViewerEvent::Preferences,
+            Action::Info => ViewerEvent::Info,
+            Action::Exit => ViewerEvent::Exit,
+            Action::EditTags => ViewerEvent::EditTags,
+            Action::Next | Action::Previous | Action::First | Action::Last => return None,
+            // See `mark`.
+            Action::Rate0
+            | Action::Rate1
+            | Action::Rate2
+            | Action::Rate3
+            | Action::Rate4
+            | Action::Rate5
+            | Action::LabelRed
+            | Action::LabelYellow
+            | Action::LabelGreen
+            | Action::LabelBlue
+            | Action::LabelPurple
+            | Action::Pick
+            | Action::Reject
+            | Action::Unflag => return None,
+            // Runs in the background, see `MainUI`.
+            Action::WritePending => return None,
+            Action::Library | Action::Filmstrip => return None,
+        })
+    }
+
+    /// The mark this action sets on the current image.
+    pub fn mark(self) -> Option<Mark> {
+        Some(match self {
+            Action::Rate0 => Mark::Rating(0),
+            Action::Rate1 => Mark::Rating(1),
+            Action::Rate2 => Mark::Rating(2),
+            Action::Rate3 => Mark::Rating(3),
+            Action::Rate4 => Mark::Rating(4),
+            Action::Rate5 => Mark::Rating(5),
+            Action::LabelRed => Mark::Label(Some(ColorLabel::Red)),
+            Action::LabelYellow => Mark::Label(Some(ColorLabel::Yellow)),
+            Action::LabelGreen => Mark::Label(Some(ColorLabel::Green)),
+            Action::LabelBlue => Mark::Label(Some(ColorLabel::Blue)),
+            Action::LabelPurple => Mark::Label(Some(ColorLabel::Purple)),
+            Action::Pick => Mark::Flag(Flag::Pick),
+            Action::Reject => Mark::Flag(Flag::Reject),
+            Action::Unflag => Mark::Flag(Flag::Unflagged),
+            _ => return None,
+        })
+    }
+}
+
+/// A key with modifiers, written like `Ctrl+Shift+Left` or `+`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyChord {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+    /// Lowercase character, or the name of a named key.
+    pub key: String,
+}
+
+/// Named keys that can be bound, with the names used in the config.
+const NAMED: &[(&str, Named)] = &[
+    ("Left", Named::ArrowLeft),
+    ("Right", Named::ArrowRight),
+    ("Up", Named::ArrowUp),
+    ("Down", Named::ArrowDown),
+    ("PageUp", Named::PageUp),
+    ("PageDown", Named::PageDown),
+    ("Home", Named::Home),
+    ("End", Named::End),
+    ("Space", Named::Space),
+    ("Enter", Named::Enter),
+    ("Escape", Named::Escape),
+    ("Tab", Named::Tab),
+    ("Backspace", Named::Backspace),
+    ("Delete", Named::Delete),
+    ("Insert", Named::Insert),
+    ("F1", Named::F1),
+    ("F2", Named::F2),
+    ("F3", Named::F3),
+    ("F4", Named::F4),
+    ("F5", Named::F5),
+    ("F6", Named::F6),
+    ("F7", Named::F7),
+    ("F8", Named::F8),
+    ("F9", Named::F9),
+    ("F10", Named::F10),
+    ("F11", Named::F11),
+    ("F12", Named::F12),
+];
+
+impl KeyChord {
+    /// The chord of a key press, or `None` for keys that cannot be bound.
+    pub fn from_event(key: &Key, modifiers: Modifiers) -> Option<KeyChord> {
+        let (key, shift) = match key {
+            Key::Named(named) => (
+                NAMED.iter().find(|(_, x)| x == named)?.0.to_owned(),
+                modifiers.shift(),
+            ),
+            Key::Character(c) => {
+                let c = c.to_lowercase();
+                // Shift is part of symbols like `+`, so it only counts for letters.
+                let shift = modifiers.shift() && c.chars().all(char::is_alphabetic);
+                (c, shift)
+            }
+            Key::Unidentified => return None,
+        };
+        Some(KeyChord {
+            ctrl: modifiers.control(),
+            alt: modifiers.alt(),
+            shift,
+            logo: modifiers.logo(),
+            key,
+        })
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut chord = KeyChord {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            logo: false,
+            key: String::new(),
+        };
+        // A trailing `+` is the key itself, as in `Ctrl++`.
+        let (mods, key) = match s.strip_suffix('+') {
+            Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest, "+"),
+            _ => match s.rsplit_once('+') {
+                Some((mods, key)) => (mods, key),
+                None => ("", s),
+            },
+        };
+        for m in mods.split('+').filter(|x| !x.is_empty()) {
+            match m.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => chord.ctrl = true,
+                "alt" => chord.alt = true,
+                "shift" => chord.shift = true,
+                "super" | "logo" | "meta" => chord.logo = true,
+                _ => return Err(anyhow!("unknown modifier `{}` in `{}`", m, s)),
+            }
+        }
+        if let Some((name, _)) = NAMED.iter().find(|(x, _)| x.eq_ignore_ascii_case(key)) {
+            chord.key = (*name).to_owned();
+        } else if key.chars().count() == 1 {
+            chord.key = key.to_lowercase();
+            if !key.chars().all(char::is_alphabetic) {
+                chord.shift = false;
+            }
+        } else {
+            return Err(anyhow!("unknown key `{}` in `{}`", key, s));
+        }
+        Ok(chord)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (on, name) in [
+            (self.ctrl, "Ctrl+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+            (self.logo, "Super+"),
+        ] {
+            if on {
+                write!(f, "{}", name)?;
+            }
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+/// Built-in binding sets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Profile {
+    #[default]
+    Phany,
+    /// GNOME Image Viewer (eog / Loupe).
+    Gnome,
+    /// KDE Gwenview.
+    Gwenview,
+    IrfanView,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [
+        Profile::Phany,
+        Profile::Gnome,
+        Profile::Gwenview,
+        Profile::IrfanView,
+    ];
+
+    /// Culling and tagging keys, as in Lightroom and darktable.
+    fn culling() -> Vec<(Action, &'static [&'static str])> {
+        use Action::*;
+        vec![
+            (Rate0, &["0"]),
+            (Rate1, &["1"]),
+            (Rate2, &["2"]),
+            (Rate3, &["3"]),
+            (Rate4, &["4"]),
+            (Rate5, &["5"]),
+            (LabelRed, &["6"]),
+            (LabelYellow, &["7"]),
+            (LabelGreen, &["8"]),
+            (LabelBlue, &["9"]),
+            (Pick, &["p"]),
+            (Reject, &["x"]),
+            (Unflag, &["u"]),
+            (EditTags, &["t"]),
+            (WritePending, &["Ctrl+Alt+s"]),
+            (Library, &["Escape", "g"]),
+        ]
+    }
+
+    pub fn bindings(self) -> Vec<(Action, Vec<&'static str>)> {
+        use Action::*;
+        let common: Vec<(Action, &'static [&'static str])> = vec![
+            (Next, &["Right", "PageDown"]),
+            (Previous, &["Left", "PageUp"]),
+            (First, &["Home"]),
+            (Last, &["End"]),
+            (Filmstrip, &["F6"]),
+        ];
+        let specific: Vec<(Action, &'static [&'static str])> = match self {
+            Profile::Phany => vec![
+                (ZoomIn, &["Ctrl+=", "+"]),
+                (ZoomOut, &["Ctrl+-", "-"]),
+                (ZoomOriginal, &["Ctrl+0"]),
+                (ZoomChange, &["z"]),
+                (Fit, &["f"]),
+                (Fill, &["Shift+f"]),
+                (FitWidth, &["w"]),
+                (FitHeight, &["h"]),
+                (RotateCW, &["r"]),
+                (RotateCCW, &["Shift+r"]),
+                (FlipHorizontal, &["m"]),
+                (FlipVertical, &["Shift+m"]),
+                (Reset, &["Ctrl+r"]),
+                (Open, &["Ctrl+o"]),
+                (Save, &["Ctrl+s"]),
+                (SaveAs, &["Ctrl+Shift+s"]),
+                (Export, &["Ctrl+e"]),
+                (Fullscreen, &["F11"]),
+                (About, &["F1"]),
+                (Preferences, &["Ctrl+,"]),
+                (Info, &["i"]),
+                (Exit, &["Ctrl+q"]),
+            ],
+            Profile::Gnome => vec![
+                (ZoomIn, &["Ctrl+=", "+"]),
+                (ZoomOut, &["Ctrl+-", "-"]),
+                (ZoomOriginal, &["1"]),
+                (ZoomChange, &["Ctrl+0"]),
+                (Fit, &["f"]),
+                (RotateCW, &["Ctrl+r"]),
+                (RotateCCW, &["Ctrl+Shift+r"]),
+                (Open, &["Ctrl+o"]),
+                (SaveAs, &["Ctrl+Shift+s"]),
+                (Fullscreen, &["F11"]),
+                (About, &["F1"]),
+                (Preferences, &["Ctrl+,"]),
+                (Info, &["Alt+Enter", "F9"]),
+                (Exit, &["Ctrl+q", "Ctrl+w"]),
+            ],
+            Profile::Gwenview => vec![
+                (ZoomIn, &["Ctrl+=", "+"]),
+                (ZoomOut, &["Ctrl+-", "-"]),
+                (ZoomOriginal, &["Ctrl+0"]),
+                (Fit, &["f"]),
+                (Fill, &["Ctrl+Shift+x"]),
+                (RotateCW, &["Ctrl+r"]),
+                (RotateCCW, &["Ctrl+Shift+r"]),
+                (FlipHorizontal, &["Ctrl+Shift+m"]),
+                (FlipVertical, &["Ctrl+Shift+v"]),
+                (Reset, &["Ctrl+Shift+z"]),
+                (Open, &["Ctrl+o"]),
+                (Save, &["Ctrl+s"]),
+                (SaveAs, &["Ctrl+Shift+s"]),
+                (Export, &["Ctrl+e"]),
+                (Fullscreen, &["Ctrl+Shift+f", "F11"]),
+                (About, &["F1"]),
+                (Preferences, &["Ctrl+Shift+,"]),
+                (Info, &["F4"]),
+                (Exit, &["Ctrl+q"]),
+            ],
+            Profile::IrfanView => vec![
+                (ZoomIn, &["+"]),
+                (ZoomOut, &["-"]),
+                (ZoomOriginal, &["Ctrl+h"]),
+                (ZoomChange, &["Shift+f"]),
+                (Fit, &["Ctrl+Shift+w"]),
+                (RotateCW, &["r"]),
+                (RotateCCW, &["l"]),
+                (FlipHorizontal, &["h"]),
+                (FlipVertical, &["v"]),
+                (Open, &["o"]),
+                (Save, &["Ctrl+s"]),
+                (SaveAs, &["s"]),
+                (Export, &["Ctrl+e"]),
+                (Fullscreen, &["Enter"]),
+                (About, &["F1"]),
+                (Preferences, &["p"]),
+                (Info, &["i"]),
+                (Exit, &["Escape"]),
+            ],
+        };
+        // Keys a profile uses for something else win over culling and tagging keys.
+        let taken: Vec<KeyChord> = specific
+            .iter()
+            .flat_map(|(_, x)| x.iter())
+            .map(|x| x.parse().expect("built-in chord"))
+            .collect();
+        let culling = Self::culling().into_iter().filter_map(|(action, x)| {
+            let free: Vec<&'static str> = x
+                .iter()
+                .copied()
+                .filter(|x| !taken.contains(&x.parse().expect("built-in chord")))
+                .collect();
+            (!free.is_empty()).then_some((action, free))
+        });
+        common
+            .into_iter()
+            .map(|(action, x)| (action, x.to_vec()))
+            .chain(culling)
+            .chain(specific.into_iter().map(|(action, x)| (action, x.to_vec())))
+            .collect()
+    }
+}
+
+/// Key bindings as stored in the config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyConfig {
+    pub profile: Profile,
+    /// Bindings replacing those of the profile, as action name to chords.
+    /// An empty list unbinds the action.
+    pub bindings: BTreeMap<String, Vec<String>>,
+}
+
+/// Resolved bindings.
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    keys: BTreeMap<KeyChord, Vec<Action>>,
+}
+
+impl Keymap {
+    /// Build the keymap of `config`. Invalid entries are skipped with a
+    /// warning, and keys bound to several actions are reported.
+    pub fn new(config: &KeyConfig) -> Keymap {
+        let mut actions: BTreeMap<Action, Vec<KeyChord>> = BTreeMap::new();
+        for (action, chords) in config.profile.bindings() {
+            actions.insert(
+                action,
+                chords
+                    .iter()
+                    .map(|x| x.parse().expect("built-in chord"))
+                    .collect(),
+            );
+        }
+        let mut user: BTreeMap<Action, Vec<KeyChord>> = BTreeMap::new();
+        for (name, chords) in &config.bindings {
+            let Some(action) = Action::ALL.iter().find(|x| format!("{:?}", x) == *name) else {
+                log::warn!("keys: unknown action `{}`", name);
+                continue;
+            };
+            let chords = chords
+                .iter()
+                .filter_map(|x| {
+                    x.parse()
+                        .map_err(|e| log::warn!("keys: {}: {}", name, e))
+                        .ok()
+                })
+                .collect();
+            user.insert(*action, chords);
+        }
+        // A key the user binds is taken from the profile action it had.
+        for chords in actions.values_mut() {
+            chords.retain(|x| !user.values().flatten().any(|y| x == y));
+        }
+        actions.extend(user);
+
+        let mut keymap = Keymap::default();
+        for (action, chords) in actions {
+            for chord in chords {
+                keymap.keys.entry(chord).or_default().push(action);
+            }
+        }
+        for (chord, actions) in keymap.conflicts() {
+            log::warn!(
+                "keys: {} is bound to {:?}, using {:?}",
+                chord,
+                actions,
+                actions[0]
+            );
+        }
+        keymap
+    }
+
+    /// Keys bound to more than one action.
+    pub fn conflicts(&self) -> Vec<(&KeyChord, &[Action])> {
+        self.keys
+            .iter()
+            .filter(|(_, x)| x.len() > 1)
+            .map(|(k, x)| (k, x.as_slice()))
+            .collect()
+    }
+
+    /// The action bound to a key press.
+    pub fn resolve(&self, key: &Key, modifiers: Modifiers) -> Option<Action> {
+        let chord = KeyChord::from_event(key, modifiers)?;
+        self.keys.get(&chord).and_then(|x| x.first()).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_bind_keys_once() {
+        for profile in Profile::ALL {
+            let mut seen: BTreeMap<KeyChord, Action> = BTreeMap::new();
+            for (action, chords) in profile.bindings() {
+                for chord in chords.iter() {
+                    let chord: KeyChord = chord.parse().unwrap();
+                    if let Some(other) = seen.insert(chord.clone(), action) {
+                        panic!(
+                            "{:?}: {} is bound to {:?} and {:?}",
+                            profile, chord, other, action
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn culling_keys_kept_unless_taken() {
+        for profile in Profile::ALL {
+            let keymap = Keymap::new(&KeyConfig {
+                profile,
+                ..Default::default()
+            });
+            let bindings = profile.bindings();
+            for (action, chords) in Profile::culling() {
+                for chord in chords.iter() {
+                    let chord: KeyChord = chord.parse().unwrap();
+                    let taken = bindings.iter().any(|(other, x)| {
+                        *other != action
+                            && x.iter().any(|x| x.parse::<KeyChord>().unwrap() == chord)
+                    });
+                    if !taken {
+                        let bound = keymap.keys.get(&chord).map(|x| x[0]);
+                        assert_eq!(bound, Some(action), "{:?}: {}", profile, chord);
+                    }
+                }
+            }
+        }
+        // Escape quits IrfanView, but g still opens the library.
+        let keymap = Keymap::new(&KeyConfig {
+            profile: Profile::IrfanView,
+            ..Default::default()
+        });
+        let escape: KeyChord = "Escape".parse().unwrap();
+        let g: KeyChord = "g".parse().unwrap();
+        assert_eq!(keymap.keys[&escape], vec![Action::Exit]);
+        assert_eq!(keymap.keys[&g], vec![Action::Library]);
+    }
+
+    #[test]
+    fn user_bindings_win() {
+        let chord = |x: &str| x.parse::<KeyChord>().unwrap();
+        let bindings = [
+            ("FlipHorizontal", vec!["r".to_owned()]),
+            ("Fit", vec!["Ctrl+f".to_owned()]),
+        ];
+        let keymap = Keymap::new(&KeyConfig {
+            profile: Profile::Phany,
+            bindings: bindings
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v))
+                .collect(),
+        });
+        assert!(keymap.conflicts().is_empty());
+        // r moves from RotateCW, which keeps no key, and m is unbound.
+        assert_eq!(keymap.keys[&chord("r")], vec![Action::FlipHorizontal]);
+        assert!(!keymap
+            .keys
+            .values()
+            .flatten()
+            .any(|x| *x == Action::RotateCW));
+        assert!(!keymap.keys.contains_key(&chord("m")));
+        // f is replaced, Shift+f is untouched.
+        assert_eq!(keymap.keys[&chord("Ctrl+f")], vec![Action::Fit]);
+        assert!(!keymap.keys.contains_key(&chord("f")));
+        assert_eq!(keymap.keys[&chord("Shift+f")], vec![Action::Fill]);
+    }
+}
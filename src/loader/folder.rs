@@ -0,0 +1,331 @@
+//! Sibling images of the opened file, for next/previous navigation.
+
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::meta::{read_date_taken, DateTime};
+use super::{is_raw, IMAGE_EXTENSIONS};
+
+/// Order of images in a folder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum SortMode {
+    /// File name, with digit runs compared as numbers.
+    #[default]
+    Name,
+    /// Modification time.
+    Modified,
+    /// EXIF date taken; files without one go last.
+    DateTaken,
+    /// File size.
+    Size,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    path: PathBuf,
+    name: String,
+    modified: SystemTime,
+    size: u64,
+    date_taken: Option<DateTime>,
+}
+
+/// Images of one directory in display order, with a cursor.
+#[derive(Debug, Clone)]
+pub struct FolderIndex {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+    current: usize,
+    sort: SortMode,
+    reverse: bool,
+}
+
+/// Whether phany can open `path`, judged by extension.
+pub fn is_image(path: &Path) -> bool {
+    is_raw(path)
+        || path
+            .extension()
+            .and_then(|x| x.to_str())
+            .is_some_and(|x| IMAGE_EXTENSIONS.contains(&x.to_ascii_lowercase().as_str()))
+}
+
+/// Compare names so that `img2` sorts before `img10`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut da = String::new();
+                while let Some(c) = a.next_if(|c| c.is_ascii_digit()) {
+                    da.push(c);
+                }
+                let mut db = String::new();
+                while let Some(c) = b.next_if(|c| c.is_ascii_digit()) {
+                    db.push(c);
+                }
+                let ta = da.trim_start_matches('0');
+                let tb = db.trim_start_matches('0');
+                let ord = ta
+                    .len()
+                    .cmp(&tb.len())
+                    .then_with(|| ta.cmp(tb))
+                    .then_with(|| da.len().cmp(&db.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ord = x
+                    .to_lowercase()
+                    .cmp(y.to_lowercase())
+                    .then_with(|| x.cmp(&y));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+impl Entry {
+    fn read(path: &Path) -> Option<Entry> {
+        let meta = std::fs::metadata(path).ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        Some(Entry {
+            name: path.file_name()?.to_string_lossy().into_owned(),
+            path: path.to_owned(),
+            modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            size: meta.len(),
+            date_taken: None,
+        })
+    }
+}
+
+impl FolderIndex {
+    /// Index the images next to `file`, or inside it if it is a directory.
+    /// The cursor starts at `file`, or at the first image.
+    pub fn scan(file: &Path, sort: SortMode, reverse: bool) -> Result<FolderIndex> {
+        let (dir, selected) = if file.is_dir() {
+            (file.to_owned(), None)
+        } else {
+            let dir = match file.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_owned(),
+                _ => PathBuf::from("."),
+            };
+            (dir, Some(file.to_owned()))
+        };
+        // Absolute, to match paths reported by the file watcher.
+        let dir = dir.canonicalize().unwrap_or(dir);
+
+        let mut entries = vec![];
+        for e in std::fs::read_dir(&dir)? {
+            let e = e?;
+            let path = e.path();
+            if !is_image(&path) {
+                continue;
+            }
+            let Ok(meta) = e.metadata() else {
+                continue;
+            };
+            if !meta.is_file() {
+                continue;
+            }
+            entries.push(Entry {
+                name: e.file_name().to_string_lossy().into_owned(),
+                path,
+                modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                size: meta.len(),
+                date_taken: None,
+            });
+        }
+        if entries.is_empty() {
+            return Err(anyhow!("{}: no images", dir.to_string_lossy()));
+        }
+
+        let mut index = FolderIndex {
+            dir,
+            entries,
+            current: 0,
+            sort,
+            reverse,
+        };
+        index.sort();
+        if let Some(selected) = selected {
+            index.select(&selected);
+        }
+        Ok(index)
+    }
+
+    fn sort(&mut self) {
+        if self.sort == SortMode::DateTaken {
+            for e in self.entries.iter_mut().filter(|e| e.date_taken.is_none()) {
+                e.date_taken = read_date_taken(&e.path);
+            }
+        }
+        let sort = self.sort;
+        self.entries.sort_by(|a, b| {
+            let ord = match sort {
+                SortMode::Name => Ordering::Equal,
+                SortMode::Modified => a.modified.cmp(&b.modified),
+                SortMode::Size => a.size.cmp(&b.size),
+                SortMode::DateTaken => match (a.date_taken, b.date_taken) {
+                    (Some(x), Some(y)) => x.cmp(&y),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                },
+            };
+            ord.then_with(|| natural_cmp(&a.name, &b.name))
+        });
+        if self.reverse {
+            self.entries.reverse();
+        }
+    }
+
+    /// Change ordering, keeping the cursor on the same file.
+    pub fn set_sort(&mut self, sort: SortMode, reverse: bool) {
+        let current = self.current_path().to_owned();
+        self.sort = sort;
+        self.reverse = reverse;
+        self.sort();
+        self.select(&current);
+    }
+
+    pub fn sort_mode(&self) -> (SortMode, bool) {
+        (self.sort, self.reverse)
+    }
+
+    /// Move the cursor to `path` if it is in the index.
+    pub fn select(&mut self, path: &Path) -> bool {
+        let found = self
+            .entries
+            .iter()
+            .position(|e| e.path == path || e.path.file_name() == path.file_name());
+        if let Some(i) = found {
+            self.current = i;
+        }
+        found.is_some()
+    }
+
+    /// Add `path` if it is an image in this folder, or refresh it if it is
+    /// known. The cursor stays on the same file. Returns whether the index
+    /// changed.
+    pub fn insert(&mut self, path: &Path) -> bool {
+        if path.parent() != Some(self.dir.as_path()) || !is_image(path) {
+            return false;
+        }
+        let Some(entry) = Entry::read(path) else {
+            return false;
+        };
+        let current = (!self.is_empty()).then(|| self.current_path().to_owned());
+        self.entries.retain(|e| e.path != path);
+        self.entries.push(entry);
+        self.sort();
+        if let Some(current) = current {
+            self.select(&current);
+        }
+        true
+    }
+
+    /// Drop `path`, or everything under it if it is a folder. A cursor on
+    /// a dropped file moves to the next one. Returns whether the index
+    /// changed.
+    pub fn remove(&mut self, path: &Path) -> bool {
+        let before = self.entries[..self.current]
+            .iter()
+            .filter(|e| e.path.starts_with(path))
+            .count();
+        let len = self.entries.len();
+        self.entries.retain(|e| !e.path.starts_with(path));
+        self.current = (self.current - before).min(self.entries.len().saturating_sub(1));
+        self.entries.len() != len
+    }
+
+    /// Follow a rename of a file or a folder. Returns whether the index
+    /// changed.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> bool {
+        let moved = |p: &Path| match p.strip_prefix(from) {
+            Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+            _ => to.to_owned(),
+        };
+        if self.dir.starts_with(from) {
+            self.dir = moved(&self.dir);
+            for e in self.entries.iter_mut() {
+                e.path = moved(&e.path);
+            }
+            return true;
+        }
+        if !self.entries.iter().any(|e| e.path == from) {
+            return self.insert(to);
+        }
+        if to.parent() != Some(self.dir.as_path()) || !is_image(to) {
+            return self.remove(from);
+        }
+        let current = self.current_path().to_owned();
+        let current = if current == from {
+            to.to_owned()
+        } else {
+            current
+        };
+        for e in self.entries.iter_mut().filter(|e| e.path == from) {
+            e.path = to.to_owned();
+            e.name = to
+                .file_name()
+                .map_or_else(String::new, |x| x.to_string_lossy().into_owned());
+        }
+        self.sort();
+        self.select(&current);
+        true
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Zero-based position of the cursor.
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    pub fn current_path(&self) -> &Path {
+        &self.entries[self.current].path
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter().map(|e| e.path.as_path())
+    }
+
+    /// Move the cursor by `delta`, stopping at either end.
+    /// Returns whether it moved.
+    pub fn step(&mut self, delta: isize) -> bool {
+        let target = (self.current as isize + delta).clamp(0, self.len() as isize - 1) as usize;
+        self.jump(target)
+    }
+
+    /// Move the cursor to `position`. Returns whether it moved.
+    pub fn jump(&mut self, position: usize) -> bool {
+        let position = position.min(self.len() - 1);
+        let moved = position != self.current;
+        self.current = position;
+        moved
+    }
+}
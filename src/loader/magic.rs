@@ -0,0 +1,182 @@
+//! Detect image formats from the first bytes of a file, so files with a
+//! missing or wrong extension are still recognised.
+
+use std::io::Read;
+use std::path::Path;
+
+use super::is_raw;
+
+/// Bytes needed by [`sniff`].
+pub const SNIFF_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    Png,
+    Bmp,
+    Qoi,
+    Ppm,
+    Farbfeld,
+    Hdr,
+    Psd,
+    JpegXl,
+    /// Camera RAW; TIFF based ones are told from plain TIFF by extension.
+    Raw,
+    Tiff,
+    Gif,
+    WebP,
+    Heif,
+    Avif,
+}
+
+impl Format {
+    /// Whether phany can open files of this format.
+    pub fn is_supported(self) -> bool {
+        !matches!(
+            self,
+            Format::Tiff | Format::Gif | Format::WebP | Format::Heif | Format::Avif
+        )
+    }
+}
+
+/// Format of a file starting with `head`.
+pub fn sniff(head: &[u8]) -> Option<Format> {
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| head.get(offset..).is_some_and(|x| x.starts_with(magic));
+    Some(if starts(&[0xff, 0xd8, 0xff]) {
+        Format::Jpeg
+    } else if starts(b"\x89PNG\r\n\x1a\n") {
+        Format::Png
+    } else if starts(b"BM") && head.len() >= 14 {
+        Format::Bmp
+    } else if starts(b"qoif") {
+        Format::Qoi
+    } else if head.len() >= 3
+        && head[0] == b'P'
+        && b"1234567fF".contains(&head[1])
+        && head[2].is_ascii_whitespace()
+    {
+        Format::Ppm
+    } else if starts(b"farbfeld") {
+        Format::Farbfeld
+    } else if starts(b"#?RADIANCE") || starts(b"#?RGBE") {
+        Format::Hdr
+    } else if starts(b"8BPS") {
+        Format::Psd
+    } else if starts(&[0xff, 0x0a]) || starts(b"\0\0\0\x0cJXL \r\n\x87\n") {
+        Format::JpegXl
+    } else if starts(b"IIRO") || starts(b"IIRS") || starts(b"MMOR") {
+        // Olympus ORF
+        Format::Raw
+    } else if starts(b"IIU\0") {
+        // Panasonic RW2
+        Format::Raw
+    } else if starts(b"II*\0") || starts(b"MM\0*") {
+        Format::Tiff
+    } else if starts(b"FUJIFILMCCD-RAW") || starts(b"FOVb") || starts(b"\0MRM") {
+        Format::Raw
+    } else if at(6, b"HEAPCCDR") {
+        // Canon CRW
+        Format::Raw
+    } else if at(4, b"ftypcrx ") {
+        // Canon CR3
+        Format::Raw
+    } else if at(4, b"ftypavif") || at(4, b"ftypavis") {
+        Format::Avif
+    } else if at(4, b"ftyp")
+        && [b"heic", b"heix", b"mif1", b"msf1"]
+            .iter()
+            .any(|x| at(8, *x))
+    {
+        Format::Heif
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        Format::Gif
+    } else if starts(b"RIFF") && at(8, b"WEBP") {
+        Format::WebP
+    } else {
+        return None;
+    })
+}
+
+/// Format of the file at `path`, reading only its first bytes.
+pub fn sniff_file(path: &Path) -> std::io::Result<Option<Format>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(sniff(&head).map(|format| match format {
+        // Most RAW formats are TIFF inside.
+        Format::Tiff if is_raw(path) => Format::Raw,
+        x => x,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_heads() {
+        let cases: &[(&[u8], Option<Format>)] = &[
+            (&[0xff, 0xd8, 0xff, 0xe0], Some(Format::Jpeg)),
+            (b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR", Some(Format::Png)),
+            (b"BM\0\0\0\0\0\0\0\0\0\0\0\0", Some(Format::Bmp)),
+            (b"BM", None),
+            (b"qoif\0\0\0\x01", Some(Format::Qoi)),
+            (b"P6\n2 1\n255\n", Some(Format::Ppm)),
+            (b"Pf 2 1", Some(Format::Ppm)),
+            (b"P6x", None),
+            (b"farbfeld", Some(Format::Farbfeld)),
+            (b"#?RADIANCE\n", Some(Format::Hdr)),
+            (b"8BPS\0\x01", Some(Format::Psd)),
+            (&[0xff, 0x0a, 0xfa], Some(Format::JpegXl)),
+            (b"\0\0\0\x0cJXL \r\n\x87\n", Some(Format::JpegXl)),
+            (b"IIRO\x08\0\0\0", Some(Format::Raw)),
+            (b"IIU\0\x08\0\0\0", Some(Format::Raw)),
+            (b"FUJIFILMCCD-RAW 0201", Some(Format::Raw)),
+            (b"II\x1a\0\0\0HEAPCCDR", Some(Format::Raw)),
+            (b"\0\0\0\x18ftypcrx \0\0\0\x01", Some(Format::Raw)),
+            (b"II*\0\x08\0\0\0", Some(Format::Tiff)),
+            (b"MM\0*\0\0\0\x08", Some(Format::Tiff)),
+            (b"\0\0\0\x1cftypavif", Some(Format::Avif)),
+            (b"\0\0\0\x18ftypheic", Some(Format::Heif)),
+            (b"\0\0\0\x18ftypmp42", None),
+            (b"GIF89a", Some(Format::Gif)),
+            (b"RIFF\0\0\0\0WEBPVP8 ", Some(Format::WebP)),
+            (b"RIFF\0\0\0\0WAVE", None),
+            (b"", None),
+            (b"hello", None),
+        ];
+        for (head, format) in cases {
+            assert_eq!(sniff(head), *format, "{:?}", String::from_utf8_lossy(head));
+        }
+    }
+
+    #[test]
+    fn supported() {
+        assert!(Format::Jpeg.is_supported());
+        assert!(Format::Raw.is_supported());
+        assert!(!Format::Tiff.is_supported());
+        assert!(!Format::Heif.is_supported());
+    }
+
+    #[test]
+    fn sniff_files() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("phany-magic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let tiff = b"II*\0\x08\0\0\0";
+        for (name, data, format) in [
+            ("a.tif", &tiff[..], Some(Format::Tiff)),
+            ("a.NEF", &tiff[..], Some(Format::Raw)),
+            // The extension is ignored for other formats.
+            ("a.txt", &[0xff, 0xd8, 0xff, 0xdb][..], Some(Format::Jpeg)),
+            ("a.jpg", &b"not an image"[..], None),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, data)?;
+            assert_eq!(sniff_file(&path)?, format, "{}", name);
+        }
+        assert!(sniff_file(&dir.join("missing.jpg")).is_err());
+        std::fs::remove_dir_all(&dir)
+    }
+}
@@ -1,4 +1,578 @@
+//! Image metadata, read from EXIF, IPTC and XMP by exiv2.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Metadata {}
+/// Typed view over the metadata phany cares about, plus every tag as text.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+    pub exposure_time: Option<Ratio>,
+    pub aperture: Option<f64>,
+    pub iso: Option<u32>,
+    pub focal_length: Option<f64>,
+    pub date_taken: Option<DateTime>,
+    pub gps: Option<Gps>,
+    pub orientation: Orientation,
+    pub rating: Option<i32>,
+    /// Color label name, as in `xmp:Label`.
+    pub label: Option<String>,
+    pub keywords: Vec<String>,
+    /// Keyword paths from `lr:hierarchicalSubject`, like `Places|Berlin`.
+    pub hierarchical_keywords: Vec<String>,
+    pub copyright: Option<String>,
+    /// Every EXIF, IPTC and XMP tag, keyed by its exiv2 name
+    /// (e.g. `Exif.Photo.FNumber`).
+    pub raw: BTreeMap<String, String>,
+}
+
+/// An exact fraction, as stored in EXIF rationals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ratio {
+    pub num: i32,
+    pub den: i32,
+}
+
+impl Ratio {
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+impl fmt::Display for Ratio {
+    /// Shutter-speed style: `1/250`, `0.5`, `30`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.den == 0 {
+            return write!(f, "?");
+        }
+        let v = self.as_f64();
+        if v < 1. && self.num != 0 {
+            write!(f, "1/{:.0}", 1. / v)
+        } else {
+            write!(f, "{}", v)
+        }
+    }
+}
+
+/// Local date and time, with the UTC offset if the file records one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// Offset from UTC in minutes.
+    pub offset: Option<i16>,
+}
+
+impl DateTime {
+    /// Parse the EXIF form `YYYY:MM:DD HH:MM:SS` and the XMP (ISO 8601)
+    /// form `YYYY-MM-DDTHH:MM:SS[.sss][±HH:MM|Z]`.
+    pub fn parse(s: &str) -> Option<DateTime> {
+        let s = s.trim();
+        fn num<T: std::str::FromStr>(s: &str, r: std::ops::Range<usize>) -> Option<T> {
+            s.get(r)?.parse().ok()
+        }
+        let year = num(s, 0..4)?;
+        let month = num(s, 5..7)?;
+        let day = num(s, 8..10)?;
+        let (hour, minute, second) = if s.len() >= 16 {
+            (
+                num(s, 11..13)?,
+                num(s, 14..16)?,
+                num(s, 17..19).unwrap_or(0),
+            )
+        } else {
+            (0, 0, 0)
+        };
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        let tail = s.get(19..).unwrap_or("");
+        let tail = tail.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            offset: parse_offset(tail),
+        })
+    }
+}
+
+/// Parse `+HH:MM`, `-HH:MM` or `Z`.
+fn parse_offset(s: &str) -> Option<i16> {
+    let s = s.trim();
+    if s == "Z" {
+        return Some(0);
+    }
+    let sign = match s.get(0..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let h: i16 = s.get(1..3)?.parse().ok()?;
+    let m: i16 = s.get(4..6).and_then(|x| x.parse().ok()).unwrap_or(0);
+    Some(sign * (h * 60 + m))
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if let Some(offset) = self.offset {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let offset = offset.abs();
+            write!(f, " {}{:02}:{:02}", sign, offset / 60, offset % 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// GPS position in decimal degrees; altitude in meters.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+}
+
+/// EXIF orientation, i.e. how the stored pixels must be transformed for display.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    /// Transpose: rotate 90° clockwise, then flip horizontally.
+    Rotate90FlipHorizontal,
+    Rotate90,
+    /// Transverse: rotate 90° clockwise, then flip vertically.
+    Rotate90FlipVertical,
+    Rotate270,
+}
+
+impl Orientation {
+    /// Map the EXIF `Orientation` value (1-8).
+    pub fn from_exif(v: u16) -> Orientation {
+        match v {
+            2 => Orientation::FlipHorizontal,
+            3 => Orientation::Rotate180,
+            4 => Orientation::FlipVertical,
+            5 => Orientation::Rotate90FlipHorizontal,
+            6 => Orientation::Rotate90,
+            7 => Orientation::Rotate90FlipVertical,
+            8 => Orientation::Rotate270,
+            _ => Orientation::Normal,
+        }
+    }
+
+    /// Split into `(quarter turns clockwise, mirrored)`, where the image is
+    /// first mirrored horizontally and then rotated.
+    fn to_parts(self) -> (u8, bool) {
+        match self {
+            Orientation::Normal => (0, false),
+            Orientation::Rotate90 => (1, false),
+            Orientation::Rotate180 => (2, false),
+            Orientation::Rotate270 => (3, false),
+            Orientation::FlipHorizontal => (0, true),
+            Orientation::Rotate90FlipVertical => (1, true),
+            Orientation::FlipVertical => (2, true),
+            Orientation::Rotate90FlipHorizontal => (3, true),
+        }
+    }
+
+    fn from_parts(turns: u8, mirrored: bool) -> Orientation {
+        match (turns % 4, mirrored) {
+            (0, false) => Orientation::Normal,
+            (1, false) => Orientation::Rotate90,
+            (2, false) => Orientation::Rotate180,
+            (3, false) => Orientation::Rotate270,
+            (0, true) => Orientation::FlipHorizontal,
+            (1, true) => Orientation::Rotate90FlipVertical,
+            (2, true) => Orientation::FlipVertical,
+            _ => Orientation::Rotate90FlipHorizontal,
+        }
+    }
+
+    /// Whether width and height trade places.
+    pub fn swaps_axes(self) -> bool {
+        self.to_parts().0 % 2 == 1
+    }
+
+    /// This orientation followed by a quarter turn clockwise.
+    pub fn rotate_cw(self) -> Orientation {
+        let (turns, mirrored) = self.to_parts();
+        Orientation::from_parts(turns + 1, mirrored)
+    }
+
+    /// This orientation followed by a quarter turn counter-clockwise.
+    pub fn rotate_ccw(self) -> Orientation {
+        let (turns, mirrored) = self.to_parts();
+        Orientation::from_parts(turns + 3, mirrored)
+    }
+
+    /// This orientation followed by a horizontal flip.
+    pub fn flip_horizontal(self) -> Orientation {
+        let (turns, mirrored) = self.to_parts();
+        Orientation::from_parts(4 - turns, !mirrored)
+    }
+
+    /// This orientation followed by a vertical flip.
+    pub fn flip_vertical(self) -> Orientation {
+        let (turns, mirrored) = self.to_parts();
+        Orientation::from_parts(6 - turns, !mirrored)
+    }
+
+    pub fn to_exif(self) -> u16 {
+        match self {
+            Orientation::Normal => 1,
+            Orientation::FlipHorizontal => 2,
+            Orientation::Rotate180 => 3,
+            Orientation::FlipVertical => 4,
+            Orientation::Rotate90FlipHorizontal => 5,
+            Orientation::Rotate90 => 6,
+            Orientation::Rotate90FlipVertical => 7,
+            Orientation::Rotate270 => 8,
+        }
+    }
+}
+
+impl From<rexiv2::Orientation> for Orientation {
+    fn from(o: rexiv2::Orientation) -> Self {
+        use rexiv2::Orientation as R;
+        match o {
+            R::Unspecified | R::Normal => Orientation::Normal,
+            R::HorizontalFlip => Orientation::FlipHorizontal,
+            R::Rotate180 => Orientation::Rotate180,
+            R::VerticalFlip => Orientation::FlipVertical,
+            R::Rotate90HorizontalFlip => Orientation::Rotate90FlipHorizontal,
+            R::Rotate90 => Orientation::Rotate90,
+            R::Rotate90VerticalFlip => Orientation::Rotate90FlipVertical,
+            R::Rotate270 => Orientation::Rotate270,
+        }
+    }
+}
+
+/// Date taken, preferring the original capture time.
+fn date_taken(meta: &rexiv2::Metadata) -> Option<DateTime> {
+    ["Xmp.exif.DateTimeOriginal", "Exif.Photo.DateTimeOriginal"]
+        .iter()
+        .filter(|t| meta.has_tag(t))
+        .find_map(|t| meta.get_tag_string(t).ok())
+        .or_else(|| meta.get_tag_string("Exif.Image.DateTime").ok())
+        .and_then(|s| DateTime::parse(&s))
+        .map(|mut d| {
+            if d.offset.is_none() {
+                d.offset = ["Exif.Photo.OffsetTimeOriginal", "Exif.Photo.OffsetTime"]
+                    .iter()
+                    .find_map(|t| meta.get_tag_string(t).ok())
+                    .and_then(|s| parse_offset(&s));
+            }
+            d
+        })
+}
+
+/// Read only the date taken of a file, for sorting.
+pub fn read_date_taken(path: impl AsRef<Path>) -> Option<DateTime> {
+    let meta = rexiv2::Metadata::new_from_path(path.as_ref()).ok()?;
+    date_taken(&meta)
+}
+
+/// Values to write into XMP; `None` leaves a tag as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XmpUpdate {
+    /// `xmp:Rating`, where -1 means rejected.
+    pub rating: Option<i32>,
+    /// `xmp:Label`; `Some(None)` removes it.
+    pub label: Option<Option<String>>,
+    /// `dc:subject` and `lr:hierarchicalSubject`; empty lists remove them.
+    pub keywords: Option<(Vec<String>, Vec<String>)>,
+    /// `dc:title`; empty removes it.
+    pub title: Option<String>,
+    /// `dc:description`; empty removes it.
+    pub description: Option<String>,
+    pub gps: Option<Gps>,
+}
+
+/// Empty XMP packet, to start a new sidecar from.
+const EMPTY_XMP: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"/>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+";
+
+impl XmpUpdate {
+    /// Write into the metadata of the image file at `path`. EXIF and IPTC
+    /// copies of the title, description and keywords are kept in step when
+    /// the file has them, as readers prefer those.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let meta = rexiv2::Metadata::new_from_path(path)?;
+        if !meta.supports_xmp() {
+            return Err(anyhow!(
+                "{}: format cannot hold XMP",
+                path.to_string_lossy()
+            ));
+        }
+        self.apply(&meta, true)?;
+        meta.save_to_file(path)?;
+        Ok(())
+    }
+
+    /// Write into the XMP sidecar at `path`, which is created if missing.
+    /// Tags other programs keep there are left alone.
+    pub fn write_sidecar(&self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            std::fs::write(path, EMPTY_XMP)?;
+        }
+        let meta = rexiv2::Metadata::new_from_path(path)?;
+        self.apply(&meta, false)?;
+        meta.save_to_file(path)?;
+        Ok(())
+    }
+
+    fn apply(&self, meta: &rexiv2::Metadata, embedded: bool) -> Result<()> {
+        // Tags to set, or clear when empty, along with the XMP one.
+        let copies = |tags: &[&'static str]| -> Vec<&'static str> {
+            tags.iter()
+                .copied()
+                .filter(|t| embedded && meta.has_tag(t))
+                .collect()
+        };
+        let text = |tags: &[&str], value: &str| -> Result<()> {
+            for tag in tags {
+                meta.clear_tag(tag);
+                if !value.is_empty() {
+                    meta.set_tag_string(tag, value)?;
+                }
+            }
+            Ok(())
+        };
+        let list = |tags: &[&str], values: &[String]| -> Result<()> {
+            let values: Vec<&str> = values.iter().map(String::as_str).collect();
+            for tag in tags {
+                meta.clear_tag(tag);
+                if !values.is_empty() {
+                    meta.set_tag_multiple_strings(tag, &values)?;
+                }
+            }
+            Ok(())
+        };
+
+        if let Some(rating) = self.rating {
+            meta.set_tag_numeric("Xmp.xmp.Rating", rating)?;
+        }
+        if let Some(label) = &self.label {
+            text(&["Xmp.xmp.Label"], label.as_deref().unwrap_or_default())?;
+        }
+        if let Some((subjects, hierarchical)) = &self.keywords {
+            let mut tags = vec!["Xmp.dc.subject"];
+            tags.extend(copies(&["Iptc.Application2.Keywords"]));
+            list(&tags, subjects)?;
+            list(&["Xmp.lr.hierarchicalSubject"], hierarchical)?;
+        }
+        if let Some(title) = &self.title {
+            let mut tags = vec!["Xmp.dc.title"];
+            tags.extend(copies(&["Iptc.Application2.ObjectName"]));
+            text(&tags, title)?;
+        }
+        if let Some(description) = &self.description {
+            let mut tags = vec!["Xmp.dc.description"];
+            tags.extend(copies(&[
+                "Exif.Image.ImageDescription",
+                "Iptc.Application2.Caption",
+            ]));
+            text(&tags, description)?;
+        }
+        // exiv2 copies EXIF GPS tags into the XMP of sidecars when saving.
+        if let Some(gps) = self.gps {
+            meta.set_gps_info(&rexiv2::GpsInfo {
+                longitude: gps.longitude,
+                latitude: gps.latitude,
+                altitude: gps.altitude,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `xmp:Rating`, where -1 means rejected, and `xmp:Label` into the
+/// file at `path`. No label removes `xmp:Label`.
+pub fn write_rating(path: &Path, rating: i32, label: Option<&str>) -> Result<()> {
+    XmpUpdate {
+        rating: Some(rating),
+        label: Some(label.map(str::to_owned)),
+        ..Default::default()
+    }
+    .write(path)
+}
+
+/// Write `dc:subject` and `lr:hierarchicalSubject` into the file at
+/// `path`; empty lists remove the tags.
+pub fn write_keywords(path: &Path, subjects: &[String], hierarchical: &[String]) -> Result<()> {
+    XmpUpdate {
+        keywords: Some((subjects.to_vec(), hierarchical.to_vec())),
+        ..Default::default()
+    }
+    .write(path)
+}
+
+impl Metadata {
+    /// Read metadata of a file.
+    pub fn read(path: impl AsRef<Path>) -> Result<Metadata> {
+        let meta = rexiv2::Metadata::new_from_path(path.as_ref())?;
+        Ok(Self::from_exiv2(&meta))
+    }
+
+    /// Read metadata of an in-memory file.
+    pub fn from_buffer(data: &[u8]) -> Result<Metadata> {
+        let meta = rexiv2::Metadata::new_from_buffer(data)?;
+        Ok(Self::from_exiv2(&meta))
+    }
+
+    /// Human readable values of the typed fields that are present.
+    pub fn summary(&self) -> Vec<(&'static str, String)> {
+        let mut out = vec![];
+        let mut push = |k, v: Option<String>| {
+            if let Some(v) = v {
+                out.push((k, v));
+            }
+        };
+        push(
+            "Dimensions",
+            self.width
+                .zip(self.height)
+                .map(|(w, h)| format!("{} x {}", w, h)),
+        );
+        push("Make", self.make.clone());
+        push("Model", self.model.clone());
+        push("Lens", self.lens.clone());
+        push("Exposure", self.exposure_time.map(|x| format!("{} s", x)));
+        push("Aperture", self.aperture.map(|x| format!("f/{:.1}", x)));
+        push("ISO", self.iso.map(|x| x.to_string()));
+        push(
+            "Focal length",
+            self.focal_length.map(|x| format!("{:.0} mm", x)),
+        );
+        push("Date taken", self.date_taken.map(|x| x.to_string()));
+        push(
+            "GPS",
+            self.gps
+                .map(|g| format!("{:.6}, {:.6}, {:.0} m", g.latitude, g.longitude, g.altitude)),
+        );
+        push("Orientation", Some(format!("{:?}", self.orientation)));
+        push("Rating", self.rating.map(|x| x.to_string()));
+        push("Label", self.label.clone());
+        push(
+            "Keywords",
+            (!self.keywords.is_empty()).then(|| self.keywords.join(", ")),
+        );
+        push("Copyright", self.copyright.clone());
+        out
+    }
+
+    pub fn from_exiv2(meta: &rexiv2::Metadata) -> Metadata {
+        let string = |tags: &[&str]| {
+            tags.iter()
+                .filter(|t| meta.has_tag(t))
+                .find_map(|t| meta.get_tag_interpreted_string(t).ok())
+                .map(|s| s.trim().trim_matches('\0').to_owned())
+                .filter(|s| !s.is_empty())
+        };
+
+        let mut raw = BTreeMap::new();
+        let tags = [
+            meta.get_exif_tags(),
+            meta.get_iptc_tags(),
+            meta.get_xmp_tags(),
+        ];
+        for tag in tags.into_iter().flatten().flatten() {
+            if let Ok(v) = meta
+                .get_tag_interpreted_string(&tag)
+                .or_else(|_| meta.get_tag_string(&tag))
+            {
+                raw.insert(tag, v);
+            }
+        }
+
+        let mut keywords: Vec<String> = vec![];
+        for tag in ["Xmp.dc.subject", "Iptc.Application2.Keywords"] {
+            if meta.has_tag(tag) {
+                for k in meta.get_tag_multiple_strings(tag).unwrap_or_default() {
+                    if !k.is_empty() && !keywords.contains(&k) {
+                        keywords.push(k);
+                    }
+                }
+            }
+        }
+
+        let hierarchical_keywords = if meta.has_tag("Xmp.lr.hierarchicalSubject") {
+            meta.get_tag_multiple_strings("Xmp.lr.hierarchicalSubject")
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+
+        let date_taken = date_taken(meta);
+
+        let rating = ["Xmp.xmp.Rating", "Exif.Image.Rating"]
+            .iter()
+            .find(|t| meta.has_tag(t))
+            .map(|t| meta.get_tag_numeric(t));
+
+        let positive = |v: i32| (v > 0).then_some(v as u32);
+
+        Metadata {
+            width: positive(meta.get_pixel_width()),
+            height: positive(meta.get_pixel_height()),
+            make: string(&["Exif.Image.Make", "Xmp.tiff.Make"]),
+            model: string(&["Exif.Image.Model", "Xmp.tiff.Model"]),
+            lens: string(&[
+                "Exif.Photo.LensModel",
+                "Xmp.aux.Lens",
+                "Exif.Canon.LensModel",
+            ]),
+            exposure_time: meta.get_exposure_time().map(|r| Ratio {
+                num: *r.numer(),
+                den: *r.denom(),
+            }),
+            aperture: meta.get_fnumber(),
+            iso: meta.get_iso_speed().and_then(positive),
+            focal_length: meta.get_focal_length(),
+            date_taken,
+            gps: meta.get_gps_info().map(|g| Gps {
+                latitude: g.latitude,
+                longitude: g.longitude,
+                altitude: g.altitude,
+            }),
+            orientation: meta.get_orientation().into(),
+            rating,
+            label: string(&["Xmp.xmp.Label"]),
+            keywords,
+            hierarchical_keywords,
+            copyright: string(&[
+                "Exif.Image.Copyright",
+                "Xmp.dc.rights",
+                "Iptc.Application2.Copyright",
+            ]),
+            raw,
+        }
+    }
+}
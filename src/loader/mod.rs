@@ -1,5 +1,362 @@
 //! Load a image file for image and metadata.
 //!
+//! Decoding is done by `zune-image`, which covers JPEG, PNG, PPM/PFM, QOI,
+//! farbfeld and JPEG-XL (plus BMP, PSD and HDR).
+//!
+//! RAW files are not developed here; their largest embedded JPEG preview is
+//! shown instead.
+pub mod folder;
+pub mod magic;
 pub mod meta;
+pub mod thumbnail;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use iced::widget::image::Handle;
+use zune_core::bit_depth::BitDepth as ZBitDepth;
+use zune_core::colorspace::ColorSpace as ZColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_image::image::Image as ZImage;
+
+use crate::iop::image::{Encoding, Image, Primitive};
+use meta::{Metadata, Orientation};
+
+/// Extensions of formats decoded by `zune-image`.
+pub const IMAGE_EXTENSIONS: &[&str] = &[
+    "bmp", "farbfeld", "ff", "hdr", "jfif", "jpe", "jpeg", "jpg", "jxl", "pam", "pbm", "pfm", "pgm",
+    "png", "pnm", "ppm", "psd", "qoi",
+];
+
+/// Extensions of camera RAW formats, opened through their embedded preview.
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "cr3", "crw", "dng", "erf", "iiq", "kdc", "mrw", "nef", "nrw", "orf",
+    "pef", "raf", "rw2", "sr2", "srf", "srw", "x3f",
+];
+
+/// Whether `path` looks like a camera RAW file.
+pub fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| RAW_EXTENSIONS.contains(&x.to_ascii_lowercase().as_str()))
+}
+
+/// Whether an embedded preview is a JPEG; others are TIFF or raw data we
+/// cannot decode.
+pub(crate) fn is_jpeg(preview: &rexiv2::PreviewImage) -> bool {
+    matches!(preview.get_media_type(), Ok(rexiv2::MediaType::Jpeg))
+}
+
+/// Width and height of a file read from its header, without decoding.
+pub fn read_dimensions(path: &Path) -> Result<(usize, usize)> {
+    // Headers, EXIF blocks included, fit in the first MiB.
+    let mut data = vec![];
+    std::fs::File::open(path)?
+        .take(1 << 20)
+        .read_to_end(&mut data)?;
+    let (format, data) = zune_image::codecs::guess_format(data.as_slice())
+        .ok_or_else(|| anyhow!("{}: unknown format", path.to_string_lossy()))?;
+    let header = format
+        .get_decoder(data)
+        .and_then(|mut x| x.read_headers())
+        .map_err(|e| anyhow!("{}: {:?}", path.to_string_lossy(), e))?
+        .ok_or_else(|| anyhow!("{}: no dimensions in header", path.to_string_lossy()))?;
+    Ok(header.get_dimensions())
+}
+
+/// Sample depth of a decoded image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+    Float32,
+}
+
+/// Channel layout of interleaved samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Luma,
+    LumaA,
+    RGB,
+    RGBA,
+}
+
+impl ChannelLayout {
+    pub fn channels(self) -> usize {
+        match self {
+            ChannelLayout::Luma => 1,
+            ChannelLayout::LumaA => 2,
+            ChannelLayout::RGB => 3,
+            ChannelLayout::RGBA => 4,
+        }
+    }
+
+    pub fn has_alpha(self) -> bool {
+        matches!(self, ChannelLayout::LumaA | ChannelLayout::RGBA)
+    }
+}
+
+/// Decoded samples, kept in the depth of the source file.
+#[derive(Debug, Clone)]
+pub enum Pixels {
+    U8(Image<u8>),
+    U16(Image<u16>),
+    F32(Image<f32>),
+}
+
+/// A decoded image owned by phany.
+#[derive(Debug, Clone)]
+pub struct LoadedImage {
+    pub path: Option<PathBuf>,
+    pub width: usize,
+    pub height: usize,
+    pub depth: BitDepth,
+    pub layout: ChannelLayout,
+    pub pixels: Pixels,
+    pub metadata: Metadata,
+    /// Size of the file read, in bytes.
+    pub file_size: Option<u64>,
+    /// Pixels come from a preview embedded in a RAW file, not the RAW data.
+    pub preview: bool,
+}
+
+impl LoadedImage {
+    /// Read and decode a file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if is_raw(path) {
+            return Self::open_raw_preview(path);
+        }
+        let data = std::fs::read(path)?;
+        let mut image =
+            Self::from_bytes(&data).map_err(|e| anyhow!("{}: {}", path.to_string_lossy(), e))?;
+        image.path = Some(path.to_owned());
+        image.file_size = Some(data.len() as u64);
+        image.metadata = Metadata::read(path).unwrap_or_else(|e| {
+            log::warn!("{}: no metadata: {}", path.to_string_lossy(), e);
+            Metadata::default()
+        });
+        Ok(image)
+    }
+
+    /// Open the largest JPEG preview embedded in a RAW file.
+    ///
+    /// Like any other image, the preview is kept as stored; the RAW's
+    /// orientation is in `metadata` and is applied for display.
+    pub fn open_raw_preview(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let meta = rexiv2::Metadata::new_from_path(path)?;
+        let previews = meta
+            .get_preview_images()
+            .ok_or_else(|| anyhow!("{}: no embedded preview", path.to_string_lossy()))?;
+        let largest = previews
+            .iter()
+            .filter(|x| is_jpeg(x))
+            .max_by_key(|x| x.get_width() as u64 * x.get_height() as u64)
+            .ok_or_else(|| anyhow!("{}: no embedded JPEG preview", path.to_string_lossy()))?;
+        let data = largest.get_data()?;
+
+        let metadata = Metadata::from_exiv2(&meta);
+        let mut image = Self::decode(&data)
+            .map_err(|e| anyhow!("{}: preview: {}", path.to_string_lossy(), e))?;
+        image.path = Some(path.to_owned());
+        image.metadata = metadata;
+        image.file_size = std::fs::metadata(path).ok().map(|x| x.len());
+        image.preview = true;
+        Ok(image)
+    }
+
+    /// Decode an in-memory file. The format is guessed from magic bytes.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut image = Self::decode(data)?;
+        image.metadata = Metadata::from_buffer(data).unwrap_or_default();
+        Ok(image)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut image = ZImage::read(
+            data,
+            DecoderOptions::new_fast()
+                .set_max_width(1 << 16)
+                .set_max_height(1 << 16),
+        )
+        .map_err(|e| anyhow!("decode failed: {:?}", e))?;
+
+        let layout = match image.colorspace() {
+            ZColorSpace::Luma => ChannelLayout::Luma,
+            ZColorSpace::LumaA => ChannelLayout::LumaA,
+            ZColorSpace::RGB => ChannelLayout::RGB,
+            ZColorSpace::RGBA | ZColorSpace::BGRA | ZColorSpace::ARGB => {
+                image
+                    .convert_color(ZColorSpace::RGBA)
+                    .map_err(|e| anyhow!("color conversion failed: {:?}", e))?;
+                ChannelLayout::RGBA
+            }
+            _ => {
+                image
+                    .convert_color(ZColorSpace::RGB)
+                    .map_err(|e| anyhow!("color conversion failed: {:?}", e))?;
+                ChannelLayout::RGB
+            }
+        };
+
+        let (width, height) = image.dimensions();
+        let channels = layout.channels();
+        let (depth, pixels) = match image.depth() {
+            ZBitDepth::Eight => (
+                BitDepth::Eight,
+                Pixels::U8(
+                    Image::from_vec(
+                        width,
+                        height,
+                        channels,
+                        first_frame(image.flatten_frames::<u8>())?,
+                    )?
+                    .with_encoding(Encoding::Srgb),
+                ),
+            ),
+            ZBitDepth::Sixteen => (
+                BitDepth::Sixteen,
+                Pixels::U16(
+                    Image::from_vec(
+                        width,
+                        height,
+                        channels,
+                        first_frame(image.flatten_frames::<u16>())?,
+                    )?
+                    .with_encoding(Encoding::Srgb),
+                ),
+            ),
+            ZBitDepth::Float32 => (
+                BitDepth::Float32,
+                Pixels::F32(
+                    Image::from_vec(
+                        width,
+                        height,
+                        channels,
+                        first_frame(image.flatten_frames::<f32>())?,
+                    )?
+                    .with_encoding(Encoding::Linear),
+                ),
+            ),
+            d => return Err(anyhow!("unsupported bit depth {:?}", d)),
+        };
+
+        Ok(Self {
+            path: None,
+            width,
+            height,
+            depth,
+            layout,
+            pixels,
+            metadata: Metadata::default(),
+            file_size: None,
+            preview: false,
+        })
+    }
+
+    /// Transform pixels so that the image is upright for `orientation`.
+    pub fn oriented(mut self, orientation: Orientation) -> Self {
+        if orientation == Orientation::Normal {
+            return self;
+        }
+        self.pixels = match self.pixels {
+            Pixels::U8(img) => Pixels::U8(orient(&img, orientation)),
+            Pixels::U16(img) => Pixels::U16(orient(&img, orientation)),
+            Pixels::F32(img) => Pixels::F32(orient(&img, orientation)),
+        };
+        if orientation.swaps_axes() {
+            std::mem::swap(&mut self.width, &mut self.height);
+        }
+        self
+    }
+
+    /// Convert to 8-bit RGBA, the format the renderer takes.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        match &self.pixels {
+            Pixels::U8(img) => expand_rgba(img.as_slice(), self.layout, |x| x),
+            Pixels::U16(img) => expand_rgba(img.as_slice(), self.layout, |x| (x >> 8) as u8),
+            Pixels::F32(img) => expand_rgba(img.as_slice(), self.layout, |x| {
+                (x.clamp(0., 1.) * 255. + 0.5) as u8
+            }),
+        }
+    }
+
+    /// Build a render handle from the decoded pixels.
+    pub fn handle(&self) -> Handle {
+        Handle::from_pixels(self.width as u32, self.height as u32, self.to_rgba8())
+    }
+
+    /// Build a render handle showing the image transformed by `orientation`.
+    pub fn oriented_handle(&self, orientation: Orientation) -> Handle {
+        if orientation == Orientation::Normal {
+            return self.handle();
+        }
+        let rgba = Image::from_vec(self.width, self.height, 4, self.to_rgba8())
+            .expect("RGBA buffer matches dimensions");
+        let rgba = orient(&rgba, orientation);
+        Handle::from_pixels(rgba.width() as u32, rgba.height() as u32, rgba.into_vec())
+    }
+}
+
+fn orient<T: Primitive>(img: &Image<T>, orientation: Orientation) -> Image<T> {
+    let (w, h, c) = (img.width(), img.height(), img.channels());
+    let (ow, oh) = if orientation.swaps_axes() {
+        (h, w)
+    } else {
+        (w, h)
+    };
+    let mut out = Vec::with_capacity(w * h * c);
+    for y in 0..oh {
+        for x in 0..ow {
+            // Source coordinate of output pixel (x, y).
+            let (sx, sy) = match orientation {
+                Orientation::Normal => (x, y),
+                Orientation::FlipHorizontal => (w - 1 - x, y),
+                Orientation::Rotate180 => (w - 1 - x, h - 1 - y),
+                Orientation::FlipVertical => (x, h - 1 - y),
+                Orientation::Rotate90FlipHorizontal => (y, x),
+                Orientation::Rotate90 => (y, h - 1 - x),
+                Orientation::Rotate90FlipVertical => (w - 1 - y, h - 1 - x),
+                Orientation::Rotate270 => (w - 1 - y, x),
+            };
+            out.extend_from_slice(img.pixel(sx, sy));
+        }
+    }
+    Image::from_vec(ow, oh, c, out)
+        .expect("orientation keeps sample count")
+        .with_color_space(img.color_space())
+        .with_encoding(img.encoding())
+}
+
+fn first_frame<T>(frames: Vec<Vec<T>>) -> Result<Vec<T>> {
+    frames
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("image has no frames"))
+}
 
-pub struct LoadedImage {}
+fn expand_rgba<T: Copy>(samples: &[T], layout: ChannelLayout, f: impl Fn(T) -> u8) -> Vec<u8> {
+    let channels = layout.channels();
+    let mut out = Vec::with_capacity(samples.len() / channels * 4);
+    for px in samples.chunks_exact(channels) {
+        match layout {
+            ChannelLayout::Luma => {
+                let l = f(px[0]);
+                out.extend_from_slice(&[l, l, l, 255]);
+            }
+            ChannelLayout::LumaA => {
+                let l = f(px[0]);
+                out.extend_from_slice(&[l, l, l, f(px[1])]);
+            }
+            ChannelLayout::RGB => {
+                out.extend_from_slice(&[f(px[0]), f(px[1]), f(px[2]), 255]);
+            }
+            ChannelLayout::RGBA => {
+                out.extend_from_slice(&[f(px[0]), f(px[1]), f(px[2]), f(px[3])]);
+            }
+        }
+    }
+    out
+}
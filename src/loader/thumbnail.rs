@@ -0,0 +1,580 @@
+//! Thumbnails, made on a pool of background threads and cached on disk.
+//!
+//! A thumbnail is taken from the smallest preview embedded in the file that
+//! is large enough, else from the decoded image. Cached thumbnails are PNG
+//! files named by the content hash of their source, so a touched, moved or
+//! copied file keeps its thumbnail and an edited one gets a new one. Hashes
+//! are remembered by path, size and mtime so unchanged files are not read.
+//! The cache is kept under a size limit by removing the thumbnails used
+//! least recently, at startup and as new ones are written.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use futures::channel::oneshot;
+use iced::widget::image::Handle;
+use zune_core::colorspace::ColorSpace as ZColorSpace;
+use zune_image::codecs::ImageFormat;
+use zune_image::image::Image as ZImage;
+
+use super::meta::Orientation;
+use super::{is_jpeg, is_raw, orient, LoadedImage};
+use crate::db::import::{fingerprint, Stamp};
+use crate::iop::image::Image;
+
+/// Default size limit of the cache, in bytes.
+const CACHE_LIMIT: u64 = 512 * 1024 * 1024;
+
+/// Thumbnail sizes, named as in the freedesktop thumbnail spec.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Size {
+    /// 128 pixels.
+    Normal,
+    /// 256 pixels.
+    Large,
+    /// 512 pixels.
+    XLarge,
+}
+
+impl Size {
+    pub const ALL: [Size; 3] = [Size::Normal, Size::Large, Size::XLarge];
+
+    /// Length of the longer side.
+    pub fn pixels(self) -> u32 {
+        match self {
+            Size::Normal => 128,
+            Size::Large => 256,
+            Size::XLarge => 512,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Size::Normal => "normal",
+            Size::Large => "large",
+            Size::XLarge => "x-large",
+        }
+    }
+
+    /// The smallest size at least `pixels` long, or the largest.
+    pub fn fitting(pixels: u32) -> Size {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.pixels() >= pixels)
+            .unwrap_or(Size::XLarge)
+    }
+}
+
+/// An upright thumbnail in 8-bit RGBA.
+#[derive(Clone, Debug)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Thumbnail {
+    pub fn handle(&self) -> Handle {
+        Handle::from_pixels(self.width, self.height, self.rgba.clone())
+    }
+
+    fn to_png(&self) -> Result<Vec<u8>> {
+        ZImage::from_u8(
+            &self.rgba,
+            self.width as usize,
+            self.height as usize,
+            ZColorSpace::RGBA,
+        )
+        .write_to_vec(ImageFormat::PNG)
+        .map_err(|e| anyhow!("encode failed: {:?}", e))
+    }
+
+    fn from_png(data: &[u8]) -> Result<Thumbnail> {
+        let image = LoadedImage::decode(data)?;
+        Ok(Thumbnail {
+            width: image.width as u32,
+            height: image.height as u32,
+            rgba: image.to_rgba8(),
+        })
+    }
+}
+
+/// Make a thumbnail of the file at `path` fitting in `size`. RAW files
+/// need an embedded preview.
+pub fn generate(path: &Path, size: Size) -> Result<Thumbnail> {
+    let pixels = size.pixels() as usize;
+    let meta = rexiv2::Metadata::new_from_path(path).ok();
+    let orientation = meta
+        .as_ref()
+        .map_or(Orientation::Normal, |x| x.get_orientation().into());
+    let preview = meta
+        .as_ref()
+        .and_then(|x| embedded_preview(x, pixels, is_raw(path)));
+    let data = match preview {
+        Some(x) => x,
+        None if is_raw(path) => {
+            return Err(anyhow!("{}: no embedded preview", path.to_string_lossy()))
+        }
+        None => std::fs::read(path)?,
+    };
+    let image =
+        LoadedImage::decode(&data).map_err(|e| anyhow!("{}: {}", path.to_string_lossy(), e))?;
+    let (width, height, rgba) = shrink(&image.to_rgba8(), image.width, image.height, pixels);
+    let small = Image::from_vec(width, height, 4, rgba)?;
+    let small = orient(&small, orientation);
+    Ok(Thumbnail {
+        width: small.width() as u32,
+        height: small.height() as u32,
+        rgba: small.into_vec(),
+    })
+}
+
+/// Data of the smallest embedded JPEG preview at least `pixels` long, or of
+/// the largest if `any` is set.
+fn embedded_preview(meta: &rexiv2::Metadata, pixels: usize, any: bool) -> Option<Vec<u8>> {
+    let previews: Vec<_> = meta
+        .get_preview_images()?
+        .into_iter()
+        .filter(is_jpeg)
+        .collect();
+    let long = |x: &rexiv2::PreviewImage| x.get_width().max(x.get_height()) as usize;
+    let preview = previews
+        .iter()
+        .filter(|x| long(x) >= pixels)
+        .min_by_key(|x| long(x))
+        .or_else(|| any.then(|| previews.iter().max_by_key(|x| long(x)))?)?;
+    preview.get_data().ok()
+}
+
+/// Scale RGBA pixels down to fit in `pixels` by averaging the source pixels
+/// under each target pixel. Smaller images are kept as they are.
+fn shrink(rgba: &[u8], width: usize, height: usize, pixels: usize) -> (usize, usize, Vec<u8>) {
+    let long = width.max(height);
+    if long <= pixels {
+        return (width, height, rgba.to_vec());
+    }
+    let (w, h) = (
+        (width * pixels / long).max(1),
+        (height * pixels / long).max(1),
+    );
+    let mut out = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        let (y0, y1) = (
+            y * height / h,
+            ((y + 1) * height / h).max(y * height / h + 1),
+        );
+        for x in 0..w {
+            let (x0, x1) = (x * width / w, ((x + 1) * width / w).max(x * width / w + 1));
+            let mut sum = [0u32; 4];
+            for sy in y0..y1 {
+                let row = &rgba[(sy * width + x0) * 4..(sy * width + x1) * 4];
+                for px in row.chunks_exact(4) {
+                    for c in 0..4 {
+                        sum[c] += px[c] as u32;
+                    }
+                }
+            }
+            let n = ((y1 - y0) * (x1 - x0)) as u32;
+            out.extend(sum.map(|x| ((x + n / 2) / n) as u8));
+        }
+    }
+    (w, h, out)
+}
+
+/// Thumbnails on disk, by content hash and size.
+pub struct Cache {
+    dir: PathBuf,
+    /// Size the cache is pruned down to, in bytes.
+    limit: u64,
+    /// Bytes written since the cache was last pruned.
+    written: AtomicU64,
+    /// Content hashes of files, with their stamp when hashed.
+    hashes: Mutex<HashMap<PathBuf, (Stamp, String)>>,
+}
+
+impl Cache {
+    /// Where thumbnails are kept, if the platform has a cache folder.
+    pub fn default_dir() -> Option<PathBuf> {
+        Some(dirs::cache_dir()?.join("phany").join("thumbnails"))
+    }
+
+    pub fn new(dir: PathBuf) -> Cache {
+        Cache {
+            dir,
+            limit: CACHE_LIMIT,
+            written: AtomicU64::new(0),
+            hashes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the size the cache is kept under, in bytes.
+    pub fn with_limit(mut self, limit: u64) -> Cache {
+        self.limit = limit;
+        self
+    }
+
+    /// Remove the thumbnails used least recently until the cache is under
+    /// its limit. Returns the bytes removed.
+    pub fn prune(&self) -> Result<u64> {
+        self.written.store(0, Ordering::Relaxed);
+        let mut files = vec![];
+        let mut total = 0;
+        for entry in walkdir::WalkDir::new(&self.dir) {
+            let entry = match entry {
+                Ok(x) => x,
+                // Not made yet.
+                Err(e) if e.depth() == 0 => return Ok(0),
+                Err(e) => {
+                    log::warn!("{}", e);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let meta = entry.metadata()?;
+            total += meta.len();
+            let used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((used, meta.len(), entry.into_path()));
+        }
+        files.sort();
+        let mut removed = 0;
+        for (_, len, file) in files {
+            if total - removed <= self.limit {
+                break;
+            }
+            match std::fs::remove_file(&file) {
+                Ok(()) => removed += len,
+                Err(e) => log::warn!("{}: {}", file.to_string_lossy(), e),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Content hash of the file at `path`, read again only if the file
+    /// changed size or mtime.
+    fn hash(&self, path: &Path) -> Result<String> {
+        let now = Stamp::of(&std::fs::metadata(path)?);
+        if let Some((stamp, hash)) = self.hashes.lock().unwrap().get(path) {
+            if stamp.unchanged(&now) {
+                return Ok(hash.clone());
+            }
+        }
+        let hash = fingerprint(path)?;
+        self.hashes
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), (now, hash.clone()));
+        Ok(hash)
+    }
+
+    fn file(&self, hash: &str, size: Size) -> PathBuf {
+        self.dir
+            .join(size.name())
+            .join(&hash[..2])
+            .join(format!("{}.png", hash))
+    }
+
+    /// The cached thumbnail of `path`, made and stored if missing.
+    pub fn get(&self, path: &Path, size: Size) -> Result<Thumbnail> {
+        let file = self.file(&self.hash(path)?, size);
+        if let Ok(data) = std::fs::read(&file) {
+            match Thumbnail::from_png(&data) {
+                Ok(x) => {
+                    // The modification time tells how recently it was used.
+                    let touched = std::fs::File::options()
+                        .write(true)
+                        .open(&file)
+                        .and_then(|x| x.set_modified(SystemTime::now()));
+                    if let Err(e) = touched {
+                        log::debug!("{}: {}", file.to_string_lossy(), e);
+                    }
+                    return Ok(x);
+                }
+                Err(e) => log::warn!("{}: {}", file.to_string_lossy(), e),
+            }
+        }
+        let thumbnail = generate(path, size)?;
+        match store(&file, &thumbnail) {
+            Ok(len) => {
+                // Pruning walks the whole cache, so it waits for a share of
+                // the limit to be written.
+                let written = self.written.fetch_add(len, Ordering::Relaxed) + len;
+                if written > self.limit / 8 {
+                    if let Err(e) = self.prune() {
+                        log::warn!("pruning thumbnails: {}", e);
+                    }
+                }
+            }
+            Err(e) => log::warn!("{}: {}", file.to_string_lossy(), e),
+        }
+        Ok(thumbnail)
+    }
+}
+
+/// Write `thumbnail` to `file` whole, through a temporary file, so other
+/// threads never read half of it. Returns the bytes written.
+fn store(file: &Path, thumbnail: &Thumbnail) -> Result<u64> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let temp = file.with_extension(format!("{:?}.tmp", std::thread::current().id()));
+    let png = thumbnail.to_png()?;
+    std::fs::write(&temp, &png)?;
+    std::fs::rename(&temp, file)?;
+    Ok(png.len() as u64)
+}
+
+/// Order in which queued thumbnails are made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Background,
+    /// Shown on screen now.
+    Visible,
+}
+
+type Reply = oneshot::Sender<Result<Arc<Thumbnail>, String>>;
+
+struct Job {
+    path: PathBuf,
+    size: Size,
+    priority: Priority,
+    /// Order of arrival, earlier first within a priority.
+    sequence: u64,
+    /// Requests of this thumbnail, answered together.
+    replies: Vec<Reply>,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: Vec<Job>,
+    sequence: u64,
+    stopped: bool,
+}
+
+struct Shared {
+    cache: Cache,
+    queue: Mutex<Queue>,
+    wake: Condvar,
+}
+
+/// Stops the workers when the last [`Thumbnailer`] is dropped.
+struct Pool(Arc<Shared>);
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().stopped = true;
+        self.0.wake.notify_all();
+    }
+}
+
+/// Makes thumbnails on a fixed number of threads, visible ones first.
+#[derive(Clone)]
+pub struct Thumbnailer {
+    shared: Arc<Shared>,
+    _pool: Arc<Pool>,
+}
+
+impl Thumbnailer {
+    /// Start `workers` threads making thumbnails into `cache`, and another
+    /// pruning it.
+    pub fn new(cache: Cache, workers: usize) -> Thumbnailer {
+        let shared = Arc::new(Shared {
+            cache,
+            queue: Mutex::new(Queue::default()),
+            wake: Condvar::new(),
+        });
+        {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("thumbnail-prune".into())
+                .spawn(move || match shared.cache.prune() {
+                    Ok(0) => {}
+                    Ok(x) => log::info!("removed {} bytes of old thumbnails", x),
+                    Err(e) => log::warn!("pruning thumbnails: {}", e),
+                })
+                .expect("spawning a thumbnail thread");
+        }
+        for i in 0..workers.max(1) {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name(format!("thumbnail-{}", i))
+                .spawn(move || work(&shared))
+                .expect("spawning a thumbnail thread");
+        }
+        Thumbnailer {
+            shared: shared.clone(),
+            _pool: Arc::new(Pool(shared)),
+        }
+    }
+
+    /// A thumbnailer on the default cache folder, with a thread per core
+    /// up to four, or `None` if there is no cache folder.
+    pub fn with_defaults() -> Option<Thumbnailer> {
+        let workers = std::thread::available_parallelism().map_or(2, |x| x.get().min(4));
+        Some(Thumbnailer::new(Cache::new(Cache::default_dir()?), workers))
+    }
+
+    /// Queue a thumbnail of the file at `path`. Asking again for one still
+    /// queued raises its priority and shares the result.
+    pub fn get(
+        &self,
+        path: PathBuf,
+        size: Size,
+        priority: Priority,
+    ) -> impl std::future::Future<Output = Result<Arc<Thumbnail>, String>> + Send + 'static {
+        let (reply, result) = oneshot::channel();
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.sequence += 1;
+            let sequence = queue.sequence;
+            match queue
+                .jobs
+                .iter_mut()
+                .find(|x| x.path == path && x.size == size)
+            {
+                Some(job) => {
+                    job.priority = job.priority.max(priority);
+                    job.replies.push(reply);
+                }
+                None => queue.jobs.push(Job {
+                    path,
+                    size,
+                    priority,
+                    sequence,
+                    replies: vec![reply],
+                }),
+            }
+        }
+        self.shared.wake.notify_one();
+        async move { result.await.unwrap_or_else(|_| Err("cancelled".to_owned())) }
+    }
+
+    /// Set the priority of the queued thumbnails: visible if `visible`
+    /// holds for their path, else background.
+    pub fn prioritize(&self, visible: impl Fn(&Path) -> bool) {
+        for job in &mut self.shared.queue.lock().unwrap().jobs {
+            job.priority = if visible(&job.path) {
+                Priority::Visible
+            } else {
+                Priority::Background
+            };
+        }
+    }
+
+    /// Drop the queued thumbnails not wanted anymore; their requests end
+    /// as cancelled.
+    pub fn retain(&self, wanted: impl Fn(&Path) -> bool) {
+        self.shared
+            .queue
+            .lock()
+            .unwrap()
+            .jobs
+            .retain(|x| wanted(&x.path));
+    }
+}
+
+/// Make queued thumbnails, most urgent first, until stopped.
+fn work(shared: &Shared) {
+    loop {
+        let job = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if queue.stopped {
+                    return;
+                }
+                let next = queue
+                    .jobs
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_, x)| (x.priority, std::cmp::Reverse(x.sequence)))
+                    .map(|x| x.0);
+                match next {
+                    Some(i) => break queue.jobs.swap_remove(i),
+                    None => queue = shared.wake.wait(queue).unwrap(),
+                }
+            }
+        };
+        let result = shared
+            .cache
+            .get(&job.path, job.size)
+            .map(Arc::new)
+            .map_err(|e| e.to_string());
+        for reply in job.replies {
+            let _ = reply.send(result.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pixels of a `width` by `height` image, each of value `f(x, y)`.
+    fn gray(width: usize, height: usize, f: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let v = f(x, y);
+                [v, v, v, 255]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shrink_keeps_aspect_ratio() {
+        let (w, h, out) = shrink(&gray(400, 100, |_, _| 0), 400, 100, 128);
+        assert_eq!((w, h), (128, 32));
+        assert_eq!(out.len(), w * h * 4);
+        let (w, h, _) = shrink(&gray(90, 300, |_, _| 0), 90, 300, 128);
+        assert_eq!((w, h), (38, 128));
+        // A sliver keeps a pixel across.
+        let (w, h, _) = shrink(&gray(1000, 2, |_, _| 0), 1000, 2, 128);
+        assert_eq!((w, h), (128, 1));
+    }
+
+    #[test]
+    fn shrink_does_not_upscale() {
+        let rgba = gray(100, 60, |x, y| (x + y) as u8);
+        assert_eq!(shrink(&rgba, 100, 60, 128), (100, 60, rgba.clone()));
+        assert_eq!(shrink(&rgba, 100, 60, 100), (100, 60, rgba));
+    }
+
+    #[test]
+    fn shrink_averages() {
+        // Columns alternate 0 and 100; halving averages each pair.
+        let rgba = gray(4, 2, |x, _| if x % 2 == 0 { 0 } else { 100 });
+        let (w, h, out) = shrink(&rgba, 4, 2, 2);
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(out, [50, 50, 50, 255, 50, 50, 50, 255]);
+    }
+
+    #[test]
+    fn prune_least_recent() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("phany-thumbnails-{}", std::process::id()));
+        let cache = Cache::new(dir.clone()).with_limit(250);
+        assert_eq!(cache.prune()?, 0);
+        let now = SystemTime::now();
+        for (i, name) in ["a", "b", "c"].iter().enumerate() {
+            let file = dir.join("normal").join(name).join(format!("{}.png", name));
+            std::fs::create_dir_all(file.parent().unwrap())?;
+            std::fs::write(&file, [0; 100])?;
+            let used = now - std::time::Duration::from_secs(100 * (3 - i as u64));
+            std::fs::File::options()
+                .write(true)
+                .open(&file)?
+                .set_modified(used)?;
+        }
+        assert_eq!(cache.prune()?, 100);
+        assert!(!dir.join("normal/a/a.png").exists());
+        assert!(dir.join("normal/b/b.png").exists());
+        assert!(dir.join("normal/c/c.png").exists());
+        assert_eq!(cache.prune()?, 0);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -11,19 +11,123 @@ pub mod components;
 pub mod config;
 pub mod db;
 pub mod iop;
+pub mod keymap;
 pub mod loader;
 pub mod ui;
 
+use config::Config;
+use db::datastore::{Datastore, Fields, Image, Pending, SmartCollection, SyncPolicy};
+use db::external::{self, Source};
+use db::import::{import, CancelToken, ImportOptions};
+use db::keywords;
+use db::query::{Field, Op, Query, Term};
+use db::sqlite::SqliteStore;
+use db::sync::{self, Outcome};
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use iced_aw::BOOTSTRAP_FONT_BYTES;
+use loader::meta::Metadata;
 
 fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
     let arg = Args::parse();
+    rexiv2::initialize().expect("exiv2 initialization failed");
+
+    if arg.info {
+        let Some(ref file) = arg.file else {
+            eprintln!("--info needs a file");
+            std::process::exit(2);
+        };
+        match Metadata::read(file) {
+            Ok(meta) => {
+                for (k, v) in meta.summary() {
+                    println!("{:<16}{}", k, v);
+                }
+                for (k, v) in &meta.raw {
+                    println!("{}\t{}", k, v);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", file.to_string_lossy(), e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(ref folder) = arg.import {
+        if let Err(e) = import_folder(folder, &arg) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(ref source) = arg.import_marks {
+        if let Err(e) = import_marks(source, &arg) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if arg.import_keywords.is_some()
+        || arg.export_keywords.is_some()
+        || arg.move_keyword.is_some()
+        || arg.delete_keyword.is_some()
+    {
+        if let Err(e) = edit_keywords(&arg) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if arg.find.is_some() || arg.save_query.is_some() || arg.delete_query.is_some() {
+        if let Err(e) = search(&arg) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if arg.sync_policy.is_some() || arg.write_pending {
+        if let Err(e) = write_back(&arg) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(ref collection) = arg.list {
+        if let Err(e) = list(collection.as_deref()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config = Config::load();
+    let geometry = &config.window;
+    let window = iced::window::Settings {
+        size: iced::Size::new(
+            geometry.width.max(320) as f32,
+            geometry.height.max(240) as f32,
+        ),
+        position: match (geometry.x, geometry.y) {
+            (Some(x), Some(y)) => {
+                iced::window::Position::Specific(iced::Point::new(x as f32, y as f32))
+            }
+            _ => iced::window::Position::default(),
+        },
+        // The config is saved before closing.
+        exit_on_close_request: false,
+        ..Default::default()
+    };
 
     MainUI::run(Settings {
-        flags: arg
-            .file
-            .map(|x| x.to_string_lossy().into_owned())
-            .unwrap_or("".to_owned()),
+        flags: (arg, config),
+        window,
         fonts: vec![BOOTSTRAP_FONT_BYTES.into()],
         default_font: iced::Font {
             family: Family::Name("Noto Sans"),
@@ -34,3 +138,253 @@ fn main() {
     })
     .unwrap();
 }
+
+fn open_catalog() -> anyhow::Result<SqliteStore> {
+    let path = SqliteStore::default_path().ok_or_else(|| anyhow::anyhow!("no data dir"))?;
+    SqliteStore::open(&path)
+}
+
+/// Import `folder` into the catalog, printing progress.
+fn import_folder(folder: &std::path::Path, arg: &Args) -> anyhow::Result<()> {
+    let store = open_catalog()?;
+    let options = ImportOptions {
+        include: arg.include.clone(),
+        exclude: arg.exclude.clone(),
+        ..Default::default()
+    };
+    let cancel = CancelToken::on_interrupt();
+    let report = futures::executor::block_on(import(&store, folder, &options, &cancel, |p| {
+        eprint!("\r{} images, {} imported", p.images, p.imported)
+    }))?;
+    let p = report.progress;
+    eprintln!();
+    println!(
+        "{}: {} images, {} imported, {} unchanged, {} removed, {} failed{}",
+        report.collection.name,
+        p.images,
+        p.imported,
+        p.unchanged,
+        p.removed,
+        p.failed,
+        if report.cancelled { ", cancelled" } else { "" }
+    );
+    Ok(())
+}
+
+/// Merge what another program knows about catalog images, printing the
+/// conflicts, or with `--dry-run` everything that would change.
+fn import_marks(path: &std::path::Path, arg: &Args) -> anyhow::Result<()> {
+    let store = open_catalog()?;
+    let source = Source::detect(path)?;
+    let keywords = futures::executor::block_on(store.get_keywords())?;
+    let records = source.read(path, &keywords)?;
+    let count = records.len();
+    let plan = futures::executor::block_on(external::plan(&store, records, arg.overwrite))?;
+    if arg.dry_run {
+        for update in &plan.updates {
+            println!("update\t{}", update.image.path);
+        }
+        for path in &plan.missing {
+            println!("missing\t{}", path.to_string_lossy());
+        }
+    }
+    for c in &plan.conflicts {
+        println!(
+            "conflict\t{}\t{}: catalog {}, {} {}",
+            c.path,
+            c.field,
+            c.catalog,
+            source.name(),
+            c.other
+        );
+    }
+    if !arg.dry_run {
+        futures::executor::block_on(external::apply(&store, &plan))?;
+    }
+    println!(
+        "{}: {} images, {} {}, {} unchanged, {} not in the catalog, {} conflicts ({})",
+        source.name(),
+        count,
+        plan.updates.len(),
+        if arg.dry_run { "to update" } else { "updated" },
+        plan.unchanged,
+        plan.missing.len(),
+        plan.conflicts.len(),
+        if arg.overwrite {
+            "theirs taken"
+        } else {
+            "catalog kept"
+        }
+    );
+    Ok(())
+}
+
+/// Set the sync policy of a collection, or write the pending edits,
+/// printing progress and the files left alone.
+fn write_back(arg: &Args) -> anyhow::Result<()> {
+    let store = open_catalog()?;
+    futures::executor::block_on(async {
+        if let Some([ref name, ref policy]) = arg.sync_policy.as_deref() {
+            let Some(mut collection) = store.get_collection(name).await? else {
+                anyhow::bail!("no collection {}", name);
+            };
+            collection.sync = SyncPolicy::from_name(policy).ok_or_else(|| {
+                anyhow::anyhow!("{}: not one of off, embedded or sidecar", policy)
+            })?;
+            store.put_collection(&collection).await?;
+        }
+        if arg.write_pending {
+            let show = |p: &sync::Progress, item: &Pending, outcome: &Outcome| {
+                match outcome {
+                    Outcome::Conflict(file) => eprintln!(
+                        "\rconflict\t{}: changed since last read",
+                        file.to_string_lossy()
+                    ),
+                    Outcome::Failed(e) => eprintln!("\rfailed\t{}: {}", item.image.path, e),
+                    _ => {}
+                }
+                eprint!("\r{}/{} images, {} written", p.done, p.total, p.written)
+            };
+            let p = sync::write_pending(&store, arg.force, &CancelToken::new(), show).await?;
+            eprintln!();
+            println!(
+                "{} written, {} pending in collections that are off, {} conflicts, {} failed",
+                p.written, p.skipped, p.conflicts, p.failed
+            );
+            if p.conflicts > 0 {
+                println!("import what changed in the conflicting files, or overwrite with --force");
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Print the collections and smart collections in the catalog, or the
+/// images of `collection`, a page at a time.
+fn list(collection: Option<&str>) -> anyhow::Result<()> {
+    let store = open_catalog()?;
+    futures::executor::block_on(async {
+        match collection {
+            Some(name) if store.get_collection(name).await?.is_some() => {
+                print_images(store.get_images_in_collection_iter(name)).await
+            }
+            Some(name) => {
+                let Some(smart) = store.get_smart_collection(name).await? else {
+                    anyhow::bail!("no collection {}", name);
+                };
+                print_images(store.find_images_iter(&smart.parse()?)).await
+            }
+            None => {
+                let mut pages = store.get_collections_iter();
+                while let Some(page) = pages.next().await {
+                    for c in page? {
+                        println!("{}\t{}", c.name, c.folder);
+                    }
+                }
+                for c in store.get_smart_collections().await? {
+                    println!("{}\t({})", c.name, c.query);
+                }
+                Ok(())
+            }
+        }
+    })
+}
+
+async fn print_images(mut pages: BoxStream<'_, anyhow::Result<Vec<Image>>>) -> anyhow::Result<()> {
+    while let Some(page) = pages.next().await {
+        for image in page? {
+            println!("{}\t{}x{}", image.path, image.size.0, image.size.1);
+        }
+    }
+    Ok(())
+}
+
+/// Run the search options of `arg` on the catalog.
+fn search(arg: &Args) -> anyhow::Result<()> {
+    let store = open_catalog()?;
+    futures::executor::block_on(async {
+        if let Some([ref name, ref query]) = arg.save_query.as_deref() {
+            Query::parse(query).map_err(|e| anyhow::anyhow!("{}: {}", query, e))?;
+            if store.get_collection(name).await?.is_some() {
+                anyhow::bail!("{} is a folder collection", name);
+            }
+            store
+                .put_smart_collection(&SmartCollection {
+                    name: name.clone(),
+                    query: query.clone(),
+                })
+                .await?;
+        }
+        if let Some(ref name) = arg.delete_query {
+            store.delete_smart_collection(name).await?;
+        }
+        if let Some(ref query) = arg.find {
+            let query = Query::parse(query).map_err(|e| anyhow::anyhow!("{}: {}", query, e))?;
+            print_images(store.find_images_iter(&query)).await?;
+        }
+        Ok(())
+    })
+}
+
+/// Run the keyword options of `arg` on the catalog.
+fn edit_keywords(arg: &Args) -> anyhow::Result<()> {
+    let store = open_catalog()?;
+    futures::executor::block_on(async {
+        if let Some(ref file) = arg.import_keywords {
+            let text = std::fs::read_to_string(file)
+                .map_err(|e| anyhow::anyhow!("{}: {}", file.to_string_lossy(), e))?;
+            let list = keywords::parse_keyword_list(&text)
+                .map_err(|e| anyhow::anyhow!("{}: {}", file.to_string_lossy(), e))?;
+            let known = store.get_keywords().await?;
+            for mut keyword in list.iter().cloned() {
+                // Synonyms already in the catalog are kept.
+                if let Some(old) = known.iter().find(|x| x.path == keyword.path) {
+                    for synonym in &old.synonyms {
+                        if !keyword.synonyms.contains(synonym) {
+                            keyword.synonyms.push(synonym.clone());
+                        }
+                    }
+                }
+                store.put_keyword(&keyword).await?;
+            }
+            println!("{} keywords imported", list.len());
+        }
+        if let Some([ref from, ref to]) = arg.move_keyword.as_deref() {
+            let to = keywords::normalize(to).ok_or_else(|| anyhow::anyhow!("empty keyword"))?;
+            let retagged = tagged_under(&store, from).await?;
+            store.move_keyword(from, &to).await?;
+            for path in retagged {
+                store.add_pending(&path, Fields::TAGS).await?;
+            }
+        }
+        if let Some(ref path) = arg.delete_keyword {
+            let retagged = tagged_under(&store, path).await?;
+            store.delete_keyword(path).await?;
+            for path in retagged {
+                store.add_pending(&path, Fields::TAGS).await?;
+            }
+        }
+        if let Some(ref file) = arg.export_keywords {
+            let text = keywords::write_keyword_list(&store.get_keywords().await?);
+            std::fs::write(file, text)
+                .map_err(|e| anyhow::anyhow!("{}: {}", file.to_string_lossy(), e))?;
+        }
+        Ok(())
+    })
+}
+
+/// Paths of the images tagged with `keyword` or a keyword under it.
+async fn tagged_under(store: &dyn Datastore, keyword: &str) -> anyhow::Result<Vec<String>> {
+    let query = Query::Term(Term::new(Field::Tag, Op::Has, keyword)?);
+    let mut paths = vec![];
+    let mut pages = store.find_images_iter(&query);
+    while let Some(page) = pages.next().await {
+        paths.extend(
+            page?
+                .into_iter()
+                .filter(|x| x.tags.iter().any(|t| keywords::is_under(t, keyword)))
+                .map(|x| x.path),
+        );
+    }
+    Ok(paths)
+}
@@ -1,26 +1,56 @@
+use std::collections::HashSet;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::components::image::ViewerUI;
+use crate::components::filmstrip::{scrollable_id as filmstrip_id, Filmstrip, FilmstripState};
+use crate::components::grid::{
+    grid as grid_view, loose_image, scrollable_id as grid_id, GridEvent, GridState, Source,
+};
+use crate::components::image::{ViewerEvent, ViewerUI};
+use crate::components::tags::input_id as tag_input_id;
 use crate::components::viewer::Viewer;
 use crate::iop::image::Image;
+use crate::cli::Args;
+use crate::config::{Config, ThemeChoice};
+use crate::db::datastore::{
+    self, Collection, ColorLabel, Datastore, Fields, Mark, Marks, Pending, SyncPolicy, PAGE_SIZE,
+};
+use crate::db::import::{marks, read_image, CancelToken};
+use crate::db::keywords::{self, Keyword};
+use crate::db::memory::MemoryStore;
+use crate::db::sqlite::SqliteStore;
+use crate::db::sync::{self, Outcome, Writer};
+use crate::db::watch::{Change, Watcher};
+use crate::keymap::{Action, Keymap};
+use crate::loader::folder::{FolderIndex, SortMode};
+use crate::loader::meta::{write_keywords, write_rating, Orientation};
+use crate::loader::thumbnail::{Priority, Size as ThumbnailSize, Thumbnailer};
+use crate::loader::LoadedImage;
 use iced::advanced::widget::Text;
 use iced::advanced::Widget;
 use iced::alignment;
 use iced::event;
 use iced::executor;
+use iced::futures::channel::{mpsc, oneshot};
+use iced::futures::{SinkExt, StreamExt};
 use iced::keyboard;
 use iced::keyboard::key;
 use iced::keyboard::Key;
+use iced::subscription::{self, Subscription};
 use iced::theme;
+use iced::window;
 use iced::widget::button;
 use iced::widget::component;
 use iced::widget::image::Handle;
+use iced::widget::scrollable::Viewport;
 use iced::widget::text::Shaping;
 use iced::widget::Space;
 use iced::widget::{
-    checkbox, column, container, horizontal_space, image, radio, row, scrollable, slider, text,
-    text_input, toggler, vertical_space,
+    checkbox, column, container, horizontal_space, image, progress_bar, radio, row, scrollable,
+    slider, text, text_input, toggler, vertical_space,
 };
 use iced::widget::{Button, Column, Container, Slider};
 use iced::Application;
@@ -33,98 +63,1270 @@ use iced::Vector;
 use iced::{Color, Element, Length, Renderer, Sandbox, Settings};
 use iced_aw::{Bootstrap, BOOTSTRAP_FONT, BOOTSTRAP_FONT_BYTES};
 
+/// Time after writing XMP during which changes to the file are our own.
+const OWN_WRITE: Duration = Duration::from_secs(5);
+
 #[derive(Default)]
 pub struct MainUI {
     viewer: Option<image::Handle>,
+    image: Option<Arc<LoadedImage>>,
     filename: Option<String>,
+    error: Option<String>,
+    /// File being shown or loaded.
+    path: Option<PathBuf>,
+    folder: Option<FolderIndex>,
+    /// Orientation the current image is displayed in.
+    orientation: Orientation,
+    /// Apply the EXIF orientation when an image is opened.
+    auto_orient: bool,
+    /// Count of images opened, to reset the view for each.
+    generation: u64,
+    config: Config,
+    keymap: Keymap,
+    /// Last action forwarded to the viewer, with its sequence number.
+    command: Option<(u64, ViewerEvent)>,
+    fullscreen: bool,
+    catalog: Option<Arc<dyn Datastore>>,
+    /// Collections of the catalog, kept in sync while phany runs.
+    collections: Vec<Collection>,
+    /// Marks of the current image, once known.
+    marks: Option<Marks>,
+    /// Tags of the current image, if it can be tagged.
+    tags: Option<Vec<String>>,
+    keywords: Arc<Vec<Keyword>>,
+    /// Last file whose XMP was written, and when.
+    marked: Option<(PathBuf, Instant)>,
+    saver: Saver,
+    /// Progress of writing the pending edits, while it runs.
+    syncing: Option<sync::Progress>,
+    /// Count of pending edit writes started, to tell their subscriptions apart.
+    sync_runs: u64,
+    /// Library grid, once opened. The viewer steps through its images.
+    grid: Option<GridState>,
+    /// The grid is shown rather than the viewer.
+    browsing: bool,
+    /// Count of grids opened, to tell their listings apart.
+    grid_runs: u64,
+    thumbnailer: Option<Thumbnailer>,
+    filmstrip: FilmstripState,
+    /// Modifier keys held, for clicks in the grid.
+    modifiers: keyboard::Modifiers,
 }
 
 #[derive(Debug, Clone)]
 pub enum MainEvent {
-    Ready(image::Handle),
-    ZoomIn,
-    ZoomOut,
-    ZoomOriginal
+    Ready(Arc<LoadedImage>, Orientation, image::Handle),
+    Rendered(Arc<LoadedImage>, Orientation, image::Handle),
+    LoadFailed(PathBuf, String),
+    Indexed(Result<FolderIndex, String>),
+    Collections(Vec<Collection>),
+    /// Catalog record of a file.
+    Record(PathBuf, Box<datastore::Image>),
+    Keywords(Vec<Keyword>),
+    /// Result of saving an edit of the current image.
+    Saved(Result<(), String>),
+    SetTags(Vec<String>),
+    /// A watched file changed on disk.
+    FileChanged(Change),
+    /// Progress of writing the pending edits.
+    Syncing(sync::Progress),
+    Synced(Result<sync::Progress, String>),
+    Grid(GridEvent),
+    /// Images listed for the grid opened as the given run.
+    Listed(u64, Vec<datastore::Image>),
+    ListDone(u64, Result<(), String>),
+    Thumbnail(String, ThumbnailSize, Result<Handle, String>),
+    FilmstripScrolled(Viewport),
+    /// Open the image at this position of the folder or grid.
+    Jump(usize),
+    Modifiers(keyboard::Modifiers),
+    /// Key press not handled by a widget.
+    Key(Key, keyboard::Modifiers),
+    Action(Action),
+    RotateCW,
+    RotateCCW,
+    FlipHorizontal,
+    FlipVertical,
+    WindowResized(u32, u32),
+    WindowMoved(i32, i32),
+    CloseRequested(window::Id),
+}
+
+impl MainUI {
+    /// Start loading `path` in the background.
+    fn load(&mut self, path: PathBuf) -> Command<MainEvent> {
+        self.filename = path
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned());
+        self.path = Some(path.clone());
+        let auto_orient = self.auto_orient;
+        let load = Command::perform(
+            async move {
+                let file = path.clone();
+                let result = unblock(move || {
+                    LoadedImage::open(&file).map(|img| {
+                        let orientation = if auto_orient {
+                            img.metadata.orientation
+                        } else {
+                            Orientation::Normal
+                        };
+                        let handle = img.oriented_handle(orientation);
+                        (Arc::new(img), orientation, handle)
+                    })
+                })
+                .await
+                .and_then(|x| x);
+                (path, result)
+            },
+            |(path, x)| match x {
+                Ok((img, orientation, handle)) => MainEvent::Ready(img, orientation, handle),
+                Err(e) => MainEvent::LoadFailed(path, e.to_string()),
+            },
+        );
+        Command::batch([load, self.reveal_filmstrip()])
+    }
+
+    /// Re-render the current image in a new orientation.
+    fn reorient(&mut self, f: impl FnOnce(Orientation) -> Orientation) -> Command<MainEvent> {
+        let Some(ref img) = self.image else {
+            return Command::none();
+        };
+        self.orientation = f(self.orientation);
+        let (img, orientation) = (img.clone(), self.orientation);
+        Command::perform(
+            unblock(move || {
+                let handle = img.oriented_handle(orientation);
+                (img, orientation, handle)
+            }),
+            |x| match x {
+                Ok((img, orientation, handle)) => MainEvent::Rendered(img, orientation, handle),
+                Err(e) => MainEvent::Saved(Err(e.to_string())),
+            },
+        )
+    }
+
+    /// Move through the folder and load the new current image.
+    fn navigate(&mut self, f: impl FnOnce(&mut FolderIndex) -> bool) -> Command<MainEvent> {
+        let Some(ref mut folder) = self.folder else {
+            return Command::none();
+        };
+        if f(folder) {
+            let path = folder.current_path().to_owned();
+            self.load(path)
+        } else {
+            Command::none()
+        }
+    }
+
+    /// Follow a change on disk in the folder being browsed.
+    fn file_changed(&mut self, change: Change) -> Command<MainEvent> {
+        let Some(ref mut folder) = self.folder else {
+            return Command::none();
+        };
+        let before = folder.current_path().to_owned();
+        let renamed = matches!(change, Change::Renamed(..));
+        // Writing XMP touches the file; the image is the same.
+        let own_write = self
+            .marked
+            .as_ref()
+            .is_some_and(|(path, at)| *path == before && at.elapsed() < OWN_WRITE);
+        let updated = change == Change::Updated(before.clone()) && !own_write;
+        match change {
+            Change::Added(ref path) | Change::Updated(ref path) => folder.insert(path),
+            Change::Removed(ref path) => folder.remove(path),
+            Change::Renamed(ref from, ref to) => folder.rename(from, to),
+        };
+        if folder.is_empty() {
+            self.folder = None;
+            self.viewer = None;
+            self.image = None;
+            self.path = None;
+            self.error = Some("No images left".to_owned());
+            return Command::none();
+        }
+        let after = folder.current_path().to_owned();
+        if after == before && !updated {
+            return Command::none();
+        }
+        if renamed {
+            // Same file under a new name; the decoded image is still valid.
+            self.filename = after
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned());
+            self.path = Some(after);
+            Command::none()
+        } else {
+            self.load(after)
+        }
+    }
+
+    /// Watch the folder being browsed and the catalog collections.
+    fn watch(&self) -> Subscription<MainEvent> {
+        let folders: Vec<PathBuf> = self.folder.iter().map(|x| x.dir().to_owned()).collect();
+        if folders.is_empty() && self.collections.is_empty() {
+            return Subscription::none();
+        }
+        let id = (
+            folders.clone(),
+            self.collections
+                .iter()
+                .map(|x| x.folder.clone())
+                .collect::<Vec<_>>(),
+        );
+        let (store, collections) = (self.catalog.clone(), self.collections.clone());
+        subscription::channel(id, 64, move |mut output| async move {
+            match Watcher::start(store, collections, folders) {
+                // The watcher stops when the subscription is dropped.
+                Ok((_watcher, mut changes)) => {
+                    while let Some(change) = changes.next().await {
+                        if output.send(MainEvent::FileChanged(change)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(e) => log::warn!("cannot watch folders: {}", e),
+            }
+            iced::futures::future::pending().await
+        })
+    }
+
+    /// Write the pending catalog edits, while `syncing` is set.
+    fn write_pending(&self) -> Subscription<MainEvent> {
+        let (Some(store), Some(_)) = (self.catalog.clone(), &self.syncing) else {
+            return Subscription::none();
+        };
+        let id = ("write-pending", self.sync_runs);
+        subscription::channel(id, 64, move |mut output| async move {
+            let mut progress = output.clone();
+            let show = |p: &sync::Progress, item: &Pending, outcome: &Outcome| {
+                match outcome {
+                    Outcome::Conflict(file) => log::warn!(
+                        "{}: changed since last read, not written",
+                        file.to_string_lossy()
+                    ),
+                    Outcome::Failed(e) => log::warn!("{}: {}", item.image.path, e),
+                    _ => {}
+                }
+                // Progress may be dropped when the window lags behind.
+                let _ = progress.try_send(MainEvent::Syncing(p.clone()));
+            };
+            let result = sync::write_pending(&*store, false, &CancelToken::new(), show).await;
+            let _ = output
+                .send(MainEvent::Synced(result.map_err(|e| e.to_string())))
+                .await;
+            iced::futures::future::pending().await
+        })
+    }
+
+    /// Look up the current image in the catalog.
+    fn load_record(&self) -> Command<MainEvent> {
+        let (Some(store), Some(path)) = (self.catalog.clone(), self.path.clone()) else {
+            return Command::none();
+        };
+        Command::perform(
+            async move {
+                let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+                let image = store.get_image(&key.to_string_lossy()).await;
+                (path, image)
+            },
+            |(path, image)| match image {
+                Ok(Some(image)) => MainEvent::Record(path, Box::new(image)),
+                Ok(None) => MainEvent::Saved(Ok(())),
+                Err(e) => MainEvent::Saved(Err(e.to_string())),
+            },
+        )
+    }
+
+    /// Change a mark of the current image and save it.
+    fn mark(&mut self, mark: Mark) -> Command<MainEvent> {
+        let (Some(path), Some(marks)) = (self.path.clone(), self.marks) else {
+            return Command::none();
+        };
+        // A label key on an image with that label clears it.
+        let mark = match mark {
+            Mark::Label(label) if label == marks.label => Mark::Label(None),
+            mark => mark,
+        };
+        let marks = marks.with(mark);
+        self.marks = Some(marks);
+        if let Some(ref mut grid) = self.grid {
+            grid.set_marks(&path.to_string_lossy(), marks);
+        }
+        Command::perform(self.save(path, Edit::Marks(marks)), MainEvent::Saved)
+    }
+
+    /// Change a mark of the images selected in the grid and save them.
+    fn mark_selection(&mut self, mark: Mark) -> Command<MainEvent> {
+        let Some(ref mut grid) = self.grid else {
+            return Command::none();
+        };
+        // As for a single image, a label key clears the label it sets.
+        let changed = grid.mark_selection(|marks| match mark {
+            Mark::Label(label) if label == marks.label => marks.with(Mark::Label(None)),
+            mark => marks.with(mark),
+        });
+        let mut saves = vec![];
+        for (path, marks) in changed {
+            let path = PathBuf::from(path);
+            if self.path.as_ref() == Some(&path) {
+                self.marks = Some(marks);
+            }
+            saves.push(Command::perform(
+                self.save(path, Edit::Marks(marks)),
+                MainEvent::Saved,
+            ));
+        }
+        Command::batch(saves)
+    }
+
+    /// Replace the tags of the current image and save them.
+    fn set_tags(&mut self, tags: Vec<String>) -> Command<MainEvent> {
+        let Some(path) = self.path.clone() else {
+            return Command::none();
+        };
+        self.tags = Some(tags.clone());
+        let save = self.save(path, Edit::Tags(tags));
+        let store = self.catalog.clone();
+        Command::perform(
+            async move {
+                save.await?;
+                // New tags were added to the keyword tree.
+                match store {
+                    Some(store) => store.get_keywords().await.map_err(|e| e.to_string()),
+                    None => Ok(vec![]),
+                }
+            },
+            |x| match x {
+                Ok(keywords) => MainEvent::Keywords(keywords),
+                Err(e) => MainEvent::Saved(Err(e)),
+            },
+        )
+    }
+
+    /// Store `edit` of the image at `path` in the catalog, if the image is
+    /// there, and in its XMP if mirroring is on.
+    fn save(
+        &mut self,
+        path: PathBuf,
+        edit: Edit,
+    ) -> impl std::future::Future<Output = Result<(), String>> {
+        let mirror = self.config.catalog.mirror_xmp;
+        if mirror {
+            self.marked = Some((path.clone(), Instant::now()));
+        }
+        let (store, keywords) = (self.catalog.clone(), self.keywords.clone());
+        let saved = self.saver.run(move || {
+            futures::executor::block_on(save(store, &path, edit, &keywords, mirror))
+                .map_err(|e| format!("{}: {}", path.to_string_lossy(), e))
+        });
+        async move { saved.await.map_err(|e| e.to_string())? }
+    }
+
+    /// Show the library grid of `source`, listing its images anew.
+    fn open_grid(&mut self, source: Source) -> Command<MainEvent> {
+        let library = &self.config.library;
+        let mut grid = GridState::new(
+            source,
+            library.sort,
+            library.reverse,
+            library.thumbnail_size,
+        );
+        let window = &self.config.window;
+        grid.resized(window.width as f32, window.height as f32);
+        self.grid = Some(grid);
+        self.grid_runs += 1;
+        self.browsing = true;
+        scrollable::scroll_to(grid_id(), Default::default())
+    }
+
+    /// Show the grid again, at the image being viewed. The first time it
+    /// is the grid of the folder being browsed.
+    fn show_grid(&mut self) -> Command<MainEvent> {
+        let Some(ref mut grid) = self.grid else {
+            let dir = match (&self.folder, &self.path) {
+                (Some(folder), _) => folder.dir().to_owned(),
+                (None, Some(path)) => match path.parent() {
+                    Some(dir) => dir.to_owned(),
+                    None => return Command::none(),
+                },
+                (None, None) => return Command::none(),
+            };
+            return self.open_grid(Source::Folder(dir));
+        };
+        if let Some(ref path) = self.path {
+            if !grid.select_path(&path.to_string_lossy()) {
+                if let Ok(path) = path.canonicalize() {
+                    grid.select_path(&path.to_string_lossy());
+                }
+            }
+        }
+        grid.reveal_cursor();
+        let offset = grid.offset();
+        self.browsing = true;
+        Command::batch([
+            scrollable::scroll_to(grid_id(), offset),
+            self.request_thumbnails(),
+        ])
+    }
+
+    /// List the images of the grid, while it is loading.
+    fn list_grid(&self) -> Subscription<MainEvent> {
+        let Some(source) = self
+            .grid
+            .as_ref()
+            .filter(|x| x.loading)
+            .map(|x| x.source.clone())
+        else {
+            return Subscription::none();
+        };
+        let (store, run) = (self.catalog.clone(), self.grid_runs);
+        subscription::channel(("grid", run), 4, move |mut output| async move {
+            let result = list_images(store, &source, run, &mut output).await;
+            let _ = output
+                .send(MainEvent::ListDone(run, result.map_err(|e| e.to_string())))
+                .await;
+            iced::futures::future::pending().await
+        })
+    }
+
+    /// Ask for the thumbnails of the grid images in view, ahead of those
+    /// scrolled past.
+    fn request_thumbnails(&mut self) -> Command<MainEvent> {
+        let (Some(grid), Some(thumbnailer)) = (self.grid.as_mut(), self.thumbnailer.as_ref())
+        else {
+            return Command::none();
+        };
+        if !self.browsing {
+            return Command::none();
+        }
+        let visible: HashSet<PathBuf> = grid
+            .visible()
+            .map(|i| PathBuf::from(&grid.image(i).path))
+            .collect();
+        thumbnailer.prioritize(|x| visible.contains(x));
+        grid.forget();
+        let size = grid.thumbnail_tier();
+        Command::batch(
+            grid.missing_thumbnails()
+                .into_iter()
+                .map(|path| load_thumbnail(thumbnailer, path, size)),
+        )
+    }
+
+    /// Scroll the grid to its cursor if it is out of view.
+    fn reveal(&mut self) -> Command<MainEvent> {
+        let Some(ref mut grid) = self.grid else {
+            return Command::none();
+        };
+        if !grid.reveal_cursor() {
+            return Command::none();
+        }
+        let offset = grid.offset();
+        Command::batch([
+            scrollable::scroll_to(grid_id(), offset),
+            self.request_thumbnails(),
+        ])
+    }
+
+    /// Open the image under the grid cursor in the viewer.
+    fn open_cursor(&mut self) -> Command<MainEvent> {
+        let Some(path) = self
+            .grid
+            .as_ref()
+            .and_then(|x| Some(PathBuf::from(&x.image(x.cursor()?).path)))
+        else {
+            return Command::none();
+        };
+        self.browsing = false;
+        if self.path.as_ref() == Some(&path) && self.image.is_some() {
+            return self.reveal_filmstrip();
+        }
+        if let Some(ref mut folder) = self.folder {
+            folder.select(&path);
+        }
+        self.load(path)
+    }
+
+    /// Move the grid cursor; the viewer follows it unless the grid is
+    /// shown.
+    fn step_grid(
+        &mut self,
+        f: impl FnOnce(&mut GridState) -> Option<String>,
+    ) -> Command<MainEvent> {
+        let Some(ref mut grid) = self.grid else {
+            return Command::none();
+        };
+        let before = grid.cursor();
+        if f(grid).is_none() {
+            return Command::none();
+        }
+        if self.browsing {
+            self.reveal()
+        } else if grid.cursor() != before {
+            self.open_cursor()
+        } else {
+            Command::none()
+        }
+    }
+
+    /// Handle the keys moving through the grid, which are not bindings.
+    fn grid_key(
+        &mut self,
+        key: &Key,
+        modifiers: keyboard::Modifiers,
+    ) -> Option<Command<MainEvent>> {
+        let grid = self.grid.as_mut()?;
+        let (columns, page) = (grid.columns() as isize, grid.page() as isize);
+        let delta = match key {
+            Key::Named(key::Named::ArrowLeft) => -1,
+            Key::Named(key::Named::ArrowRight) => 1,
+            Key::Named(key::Named::ArrowUp) => -columns,
+            Key::Named(key::Named::ArrowDown) => columns,
+            Key::Named(key::Named::PageUp) => -page,
+            Key::Named(key::Named::PageDown) => page,
+            Key::Named(key::Named::Home) => isize::MIN,
+            Key::Named(key::Named::End) => isize::MAX,
+            Key::Named(key::Named::Enter) => return Some(self.open_cursor()),
+            Key::Character(c) if c.as_str() == "a" && modifiers.command() => {
+                grid.select_all();
+                return Some(Command::none());
+            }
+            _ => return None,
+        };
+        grid.move_cursor(delta, modifiers.shift());
+        Some(self.reveal())
+    }
+
+    fn grid_event(&mut self, event: GridEvent) -> Command<MainEvent> {
+        let Some(ref mut grid) = self.grid else {
+            return Command::none();
+        };
+        let library = &mut self.config.library;
+        match event {
+            GridEvent::Scrolled(viewport) => grid.scrolled(viewport),
+            GridEvent::Clicked(i) => {
+                if grid.click(i, self.modifiers) {
+                    return self.open_cursor();
+                }
+            }
+            GridEvent::Filter(filter) => grid.set_filter(filter),
+            GridEvent::Sort(sort) => {
+                grid.set_sort(sort, grid.reverse);
+                library.sort = sort;
+            }
+            GridEvent::Reverse(reverse) => {
+                grid.set_sort(grid.sort, reverse);
+                library.reverse = reverse;
+            }
+            GridEvent::ThumbnailSize(size) => {
+                grid.thumbnail_size = size;
+                library.thumbnail_size = size;
+            }
+        }
+        self.request_thumbnails()
+    }
+
+    /// Length of the images the viewer steps through, the grid's or the
+    /// folder's, and the position of the current one.
+    fn sequence(&self) -> Option<(usize, usize)> {
+        match (&self.grid, &self.folder) {
+            (Some(grid), _) => Some((grid.len(), grid.cursor()?)),
+            (None, Some(folder)) => Some((folder.len(), folder.position())),
+            (None, None) => None,
+        }
+    }
+
+    /// Paths of the images at `range` of the sequence.
+    fn sequence_paths(&self, range: Range<usize>) -> Vec<String> {
+        match (&self.grid, &self.folder) {
+            (Some(grid), _) => range.map(|i| grid.image(i).path.clone()).collect(),
+            (None, Some(folder)) => folder
+                .paths()
+                .skip(range.start)
+                .take(range.len())
+                .map(|x| x.to_string_lossy().into_owned())
+                .collect(),
+            (None, None) => vec![],
+        }
+    }
+
+    /// The part of the filmstrip in view, if it is shown.
+    fn filmstrip(&self) -> Option<Filmstrip> {
+        if !self.config.viewer.filmstrip {
+            return None;
+        }
+        let (len, current) = self.sequence()?;
+        let visible = self.filmstrip.visible(len);
+        let paths = self.sequence_paths(visible.clone());
+        Some(self.filmstrip.strip(paths, visible.start, len, current))
+    }
+
+    /// Scroll the filmstrip to the current image.
+    fn reveal_filmstrip(&mut self) -> Command<MainEvent> {
+        let Some((_, current)) = self.sequence() else {
+            return Command::none();
+        };
+        if !self.config.viewer.filmstrip || self.browsing {
+            return Command::none();
+        }
+        let offset = self.filmstrip.reveal(current);
+        Command::batch([
+            scrollable::scroll_to(filmstrip_id(), offset),
+            self.request_filmstrip(),
+        ])
+    }
+
+    /// Ask for the filmstrip thumbnails in view, and forget those far out
+    /// of it.
+    fn request_filmstrip(&mut self) -> Command<MainEvent> {
+        let (Some((len, _)), Some(thumbnailer)) = (self.sequence(), self.thumbnailer.clone())
+        else {
+            return Command::none();
+        };
+        if !self.config.viewer.filmstrip || self.browsing {
+            return Command::none();
+        }
+        let visible = self.sequence_paths(self.filmstrip.visible(len));
+        let near: HashSet<String> = self
+            .sequence_paths(self.filmstrip.near(len))
+            .into_iter()
+            .collect();
+        self.filmstrip.forget(|x| near.contains(x));
+        let shown: HashSet<&Path> = visible.iter().map(Path::new).collect();
+        thumbnailer.prioritize(|x| shown.contains(x));
+        Command::batch(
+            self.filmstrip
+                .missing_thumbnails(&visible)
+                .into_iter()
+                .map(|path| load_thumbnail(&thumbnailer, path, ThumbnailSize::Normal)),
+        )
+    }
+
+    /// Save the config and close the window.
+    fn close(&mut self, id: window::Id) -> Command<MainEvent> {
+        if let Err(e) = self.config.save() {
+            log::error!("saving config: {}", e);
+        }
+        window::close(id)
+    }
+
+    /// Run `action`; viewer actions are passed on to the viewer.
+    fn perform(&mut self, action: Action) -> Command<MainEvent> {
+        match action {
+            Action::Next if self.grid.is_some() => self.step_grid(|x| x.move_cursor(1, false)),
+            Action::Previous if self.grid.is_some() => self.step_grid(|x| x.move_cursor(-1, false)),
+            Action::First if self.grid.is_some() => self.step_grid(|x| x.jump(0)),
+            Action::Last if self.grid.is_some() => self.step_grid(|x| x.jump(usize::MAX)),
+            Action::Next => self.navigate(|x| x.step(1)),
+            Action::Previous => self.navigate(|x| x.step(-1)),
+            Action::First => self.navigate(|x| x.jump(0)),
+            Action::Last => self.navigate(|x| x.jump(usize::MAX)),
+            Action::Exit => self.close(window::Id::MAIN),
+            Action::Library if !self.browsing => self.show_grid(),
+            Action::Filmstrip => {
+                self.config.viewer.filmstrip = !self.config.viewer.filmstrip;
+                self.reveal_filmstrip()
+            }
+            Action::WritePending => {
+                if self.catalog.is_none() {
+                    log::warn!("no catalog to write edits from");
+                } else if self.syncing.is_none() {
+                    self.sync_runs += 1;
+                    self.syncing = Some(sync::Progress::default());
+                }
+                Command::none()
+            }
+            Action::Fullscreen => {
+                self.fullscreen = !self.fullscreen;
+                let mode = if self.fullscreen {
+                    window::Mode::Fullscreen
+                } else {
+                    window::Mode::Windowed
+                };
+                window::change_mode(window::Id::MAIN, mode)
+            }
+            action => {
+                if let Some(mark) = action.mark() {
+                    return if self.browsing {
+                        self.mark_selection(mark)
+                    } else {
+                        self.mark(mark)
+                    };
+                }
+                // The viewer is hidden; its actions would apply on return.
+                if self.browsing {
+                    return Command::none();
+                }
+                if let Some(event) = action.viewer_event() {
+                    let sequence = self.command.as_ref().map_or(0, |x| x.0) + 1;
+                    self.command = Some((sequence, event));
+                }
+                if action == Action::EditTags && self.tags.is_some() {
+                    return text_input::focus(tag_input_id());
+                }
+                Command::none()
+            }
+        }
+    }
+
+    /// The viewer, or what keeps it from showing.
+    fn content(&self) -> Element<'_, MainEvent> {
+        if let Some(grid) = self.grid.as_ref().filter(|_| self.browsing) {
+            return grid_view(grid).map(MainEvent::Grid);
+        }
+        if let Some(ref handle) = self.viewer {
+            let mut viewer = ViewerUI::default()
+                .set_handle(handle.clone())
+                .set_scaling(self.config.viewer.default_scaling)
+                .set_scale_limits(
+                    self.config.viewer.min_scale,
+                    self.config.viewer.max_scale,
+                    self.config.viewer.scale_step,
+                )
+                .set_generation(self.generation);
+            if let Some(ref image) = self.image {
+                viewer = viewer.set_image(image.clone());
+            }
+            if let Some((sequence, ref event)) = self.command {
+                viewer = viewer.set_command(sequence, event.clone());
+            }
+            if let Some(marks) = self.marks {
+                viewer = viewer.set_marks(marks);
+            }
+            if let Some(ref tags) = self.tags {
+                viewer = viewer.set_tags(tags.clone(), self.keywords.clone());
+            }
+            if let Some(strip) = self.filmstrip() {
+                viewer = viewer.set_filmstrip(strip);
+            }
+            component(viewer)
+        } else {
+            container(
+                text(self.error.as_deref().unwrap_or("Loading..."))
+                    .size(36)
+                    .shaping(Shaping::Advanced)
+                    .horizontal_alignment(alignment::Horizontal::Center)
+                    .vertical_alignment(alignment::Vertical::Center),
+            )
+            .into()
+        }
+    }
 }
 
 impl Application for MainUI {
     type Message = MainEvent;
     type Executor = executor::Default;
     type Theme = Theme;
-    type Flags = String;
+    type Flags = (Args, Config);
 
-    fn new(file: Self::Flags) -> (Self, Command<Self::Message>) {
-        let name = Path::new(&file);
-        let filename = name.file_name().map_or("", |x| x.to_str().unwrap_or("?"));
-
-        let s = Self {
-            filename: Some(filename.to_owned()),
+    fn new((args, config): Self::Flags) -> (Self, Command<Self::Message>) {
+        // Command line options override the config for this session only.
+        let sort = args.sort.unwrap_or(config.folder.sort);
+        let reverse = args.reverse.unwrap_or(config.folder.reverse);
+        let mut s = Self {
+            auto_orient: config.viewer.auto_orient && !args.no_auto_orient,
+            keymap: Keymap::new(&config.keys),
+            catalog: Some(open_catalog(args.no_catalog)),
+            thumbnailer: Thumbnailer::with_defaults(),
+            config,
             ..Default::default()
         };
-        (
-            s,
+        if s.thumbnailer.is_none() {
+            log::warn!("no cache folder, thumbnails are not shown");
+        }
+        let collections = match s.catalog {
+            Some(ref store) => {
+                let (collections, keywords) = (store.clone(), store.clone());
+                Command::batch([
+                    Command::perform(async move { collections.get_collections().await }, |x| {
+                        MainEvent::Collections(x.unwrap_or_else(|e| {
+                            log::warn!("catalog: {}", e);
+                            vec![]
+                        }))
+                    }),
+                    Command::perform(async move { keywords.get_keywords().await }, |x| {
+                        MainEvent::Keywords(x.unwrap_or_else(|e| {
+                            log::warn!("catalog: {}", e);
+                            vec![]
+                        }))
+                    }),
+                ])
+            }
+            None => Command::none(),
+        };
+        match args.library {
+            Some(Some(name)) => {
+                let grid = s.open_grid(Source::Collection(name));
+                return (s, Command::batch([grid, collections]));
+            }
+            Some(None) => {
+                let dir = match args.file {
+                    Some(ref file) if file.is_dir() => file.clone(),
+                    Some(ref file) => file.parent().map(Path::to_owned).unwrap_or_default(),
+                    None => PathBuf::from("."),
+                };
+                let grid = s.open_grid(Source::Folder(dir));
+                return (s, Command::batch([grid, collections]));
+            }
+            None => {}
+        }
+        let Some(file) = args.file else {
+            s.error = Some("No image opened".to_owned());
+            return (s, collections);
+        };
+
+        let scan = {
+            let file = file.clone();
             Command::perform(
-                async {
-                    let viewer = image::Handle::from_path(file);
-                    viewer
+                async move {
+                    unblock(move || FolderIndex::scan(&file, sort, reverse))
+                        .await
+                        .and_then(|x| x)
+                        .map_err(|e| e.to_string())
                 },
-                |x| MainEvent::Ready(x),
-            ),
-        )
+                MainEvent::Indexed,
+            )
+        };
+        // A folder is opened once it is indexed.
+        if file.is_dir() {
+            return (s, Command::batch([scan, collections]));
+        }
+        let load = s.load(file);
+        (s, Command::batch([load, scan, collections]))
     }
 
     fn title(&self) -> String {
+        if let Some(grid) = self.grid.as_ref().filter(|_| self.browsing) {
+            return format!("{} - phany", grid.source);
+        }
+        let position = self
+            .sequence()
+            .map(|(len, i)| format!(" ({} / {})", i + 1, len))
+            .unwrap_or_default();
         self.filename
             .as_deref()
-            .map(|x| format!("{} - phany", x))
+            .map(|x| format!("{}{} - phany", x, position))
             .unwrap_or("phany".to_owned())
     }
 
     fn subscription(&self) -> iced::Subscription<Self::Message> {
-        event::listen_with(|e, s| match e {
-            Event::Keyboard(keyboard::Event::KeyPressed {
-                key,
-                location,
-                modifiers,
-                text,
-            }) => {
-                if (key == Key::Character("0".into())) && modifiers.control() {
-                    Some(MainEvent::ZoomOriginal)
-                } else if (key == Key::Character("=".into())) && modifiers.control() {
-                    Some(MainEvent::ZoomIn)
-                } else if (key == Key::Character("-".into())) && modifiers.control() {
-                    Some(MainEvent::ZoomOut)
-                } else {
-                    None
-                }
+        let events = event::listen_with(|e, status| match e {
+            // Keys taken by a widget, e.g. typing in a text input, are not bindings.
+            Event::Keyboard(keyboard::Event::KeyPressed { key, modifiers, .. })
+                if status == event::Status::Ignored =>
+            {
+                Some(MainEvent::Key(key, modifiers))
             }
+            Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+                Some(MainEvent::Modifiers(modifiers))
+            }
+            Event::Window(id, window::Event::CloseRequested) => Some(MainEvent::CloseRequested(id)),
+            Event::Window(_, window::Event::Resized { width, height }) => {
+                Some(MainEvent::WindowResized(width, height))
+            }
+            Event::Window(_, window::Event::Moved { x, y }) => Some(MainEvent::WindowMoved(x, y)),
             _ => None,
-        })
+        });
+        Subscription::batch([events, self.watch(), self.write_pending(), self.list_grid()])
+    }
+
+    fn theme(&self) -> Theme {
+        match self.config.theme {
+            ThemeChoice::Light => Theme::Light,
+            ThemeChoice::Dark => Theme::Dark,
+        }
     }
 
     fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
         match message {
-            MainEvent::Ready(handle) => {
+            MainEvent::Ready(img, orientation, handle) => {
+                // Drop results of files navigated away from.
+                if img.path != self.path {
+                    return Command::none();
+                }
                 self.viewer = Some(handle);
+                self.marks = Some(marks(&img.metadata));
+                self.tags = self.config.catalog.mirror_xmp.then(|| {
+                    let meta = &img.metadata;
+                    keywords::from_xmp(&meta.keywords, &meta.hierarchical_keywords, &self.keywords)
+                });
+                self.image = Some(img);
+                self.orientation = orientation;
+                self.generation += 1;
+                if let Some(ref path) = self.path {
+                    self.config.add_recent(path);
+                }
+                self.error = None;
+                // The catalog knows flags the file has no tag for.
+                return self.load_record();
+            }
+            MainEvent::Rendered(img, orientation, handle) => {
+                let current = self.image.as_ref().is_some_and(|x| Arc::ptr_eq(x, &img));
+                if current && orientation == self.orientation {
+                    self.viewer = Some(handle);
+                }
+            }
+            MainEvent::LoadFailed(path, e) => {
+                log::error!("{}", e);
+                if Some(path) == self.path {
+                    self.viewer = None;
+                    self.image = None;
+                    self.error = Some(e);
+                }
             }
+            MainEvent::Indexed(Ok(mut folder)) => {
+                let command = match self.path {
+                    Some(ref path) => {
+                        folder.select(path);
+                        Command::none()
+                    }
+                    None => {
+                        let path = folder.current_path().to_owned();
+                        self.load(path)
+                    }
+                };
+                self.folder = Some(folder);
+                return Command::batch([command, self.reveal_filmstrip()]);
+            }
+            MainEvent::Collections(collections) => self.collections = collections,
+            MainEvent::Record(path, image) if self.path.as_ref() == Some(&path) => {
+                self.marks = Some(image.marks);
+                self.tags = Some(image.tags);
+            }
+            MainEvent::Keywords(keywords) => self.keywords = Arc::new(keywords),
+            MainEvent::Saved(Err(e)) => log::warn!("{}", e),
+            MainEvent::SetTags(tags) => return self.set_tags(tags),
+            MainEvent::FileChanged(change) => return self.file_changed(change),
+            // Late progress of a finished run is dropped.
+            MainEvent::Syncing(progress) if self.syncing.is_some() => {
+                self.syncing = Some(progress);
+            }
+            MainEvent::Synced(result) => {
+                self.syncing = None;
+                match result {
+                    Ok(p) if p.conflicts + p.failed > 0 => log::warn!(
+                        "{} edits written, {} conflicts, {} failed",
+                        p.written,
+                        p.conflicts,
+                        p.failed
+                    ),
+                    Ok(p) => log::info!("{} edits written", p.written),
+                    Err(e) => log::warn!("writing edits: {}", e),
+                }
+            }
+            MainEvent::Grid(event) => return self.grid_event(event),
+            MainEvent::Listed(run, images) if run == self.grid_runs => {
+                let Some(ref mut grid) = self.grid else {
+                    return Command::none();
+                };
+                grid.extend(images);
+                // A folder grid opens at the image being viewed.
+                let found = match self.path {
+                    Some(ref path) if grid.cursor().is_none() => {
+                        grid.select_path(&path.to_string_lossy())
+                    }
+                    _ => false,
+                };
+                return if found {
+                    Command::batch([self.reveal(), self.request_thumbnails()])
+                } else {
+                    self.request_thumbnails()
+                };
+            }
+            MainEvent::ListDone(run, result) if run == self.grid_runs => {
+                if let Some(ref mut grid) = self.grid {
+                    grid.loading = false;
+                    if let Err(e) = result {
+                        log::warn!("{}: {}", grid.source, e);
+                    }
+                }
+            }
+            MainEvent::Thumbnail(path, size, Ok(handle)) => {
+                if size == ThumbnailSize::Normal {
+                    self.filmstrip.set_thumbnail(path.clone(), handle.clone());
+                }
+                if let Some(ref mut grid) = self.grid {
+                    grid.set_thumbnail(path, size, handle);
+                }
+            }
+            MainEvent::Thumbnail(path, _, Err(e)) => log::debug!("{}: no thumbnail: {}", path, e),
+            MainEvent::FilmstripScrolled(viewport) => {
+                self.filmstrip.scrolled(viewport);
+                return self.request_filmstrip();
+            }
+            MainEvent::Jump(i) if self.grid.is_some() => return self.step_grid(|x| x.jump(i)),
+            MainEvent::Jump(i) => return self.navigate(|x| x.jump(i)),
+            MainEvent::Modifiers(modifiers) => self.modifiers = modifiers,
+            MainEvent::Indexed(Err(e)) => {
+                log::warn!("{}", e);
+                if self.path.is_none() {
+                    self.error = Some(e);
+                }
+            }
+            MainEvent::Key(key, modifiers) => {
+                if self.browsing {
+                    if let Some(command) = self.grid_key(&key, modifiers) {
+                        return command;
+                    }
+                }
+                if let Some(action) = self.keymap.resolve(&key, modifiers) {
+                    return self.perform(action);
+                }
+            }
+            MainEvent::Action(action) => return self.perform(action),
+            MainEvent::WindowResized(width, height) => {
+                self.config.window.width = width;
+                self.config.window.height = height;
+                if let Some(ref mut grid) = self.grid {
+                    grid.resized(width as f32, height as f32);
+                }
+                self.filmstrip.resized(width as f32);
+                return Command::batch([self.request_thumbnails(), self.request_filmstrip()]);
+            }
+            MainEvent::WindowMoved(x, y) => {
+                self.config.window.x = Some(x);
+                self.config.window.y = Some(y);
+            }
+            MainEvent::CloseRequested(id) => return self.close(id),
+            MainEvent::RotateCW => return self.reorient(Orientation::rotate_cw),
+            MainEvent::RotateCCW => return self.reorient(Orientation::rotate_ccw),
+            MainEvent::FlipHorizontal => return self.reorient(Orientation::flip_horizontal),
+            MainEvent::FlipVertical => return self.reorient(Orientation::flip_vertical),
             _ => {}
         }
         Command::none()
     }
 
     fn view(&self) -> Element<Self::Message> {
-        if let Some(ref handle) = self.viewer {
-            let viewer = ViewerUI::default().set_handle(handle.clone()).set_scale(1.);
-            component(viewer)
-        } else {
-            container(
-                text("Loading...")
-                    .size(36)
-                    .shaping(Shaping::Advanced)
-                    .horizontal_alignment(alignment::Horizontal::Center)
-                    .vertical_alignment(alignment::Vertical::Center),
-            )
-            .into()
+        let content = self.content();
+        let Some(ref p) = self.syncing else {
+            return content;
+        };
+        let status = row![
+            progress_bar(0.0..=p.total.max(1) as f32, p.done as f32).height(Length::Fixed(8.0)),
+            text(format!("Writing edits {} / {}", p.done, p.total)).size(12),
+        ]
+        .spacing(8)
+        .padding(4)
+        .align_items(iced::Alignment::Center);
+        column![content, status].into()
+    }
+}
+
+/// Open the catalog, or with `memory` or when it cannot be opened, one
+/// kept in memory for this session.
+fn open_catalog(memory: bool) -> Arc<dyn Datastore> {
+    if !memory {
+        match SqliteStore::default_path().map(|x| SqliteStore::open(&x)) {
+            Some(Ok(store)) => return Arc::new(store),
+            Some(Err(e)) => log::warn!("catalog unavailable, keeping edits in memory: {:#}", e),
+            None => log::warn!("no data folder, keeping edits in memory"),
+        }
+    }
+    Arc::new(MemoryStore::new())
+}
+
+/// Queue a thumbnail of the file at `path`, ahead of those not in view.
+fn load_thumbnail(
+    thumbnailer: &Thumbnailer,
+    path: String,
+    size: ThumbnailSize,
+) -> Command<MainEvent> {
+    let thumbnail = thumbnailer.get(PathBuf::from(&path), size, Priority::Visible);
+    Command::perform(
+        async move { thumbnail.await.map(|x| x.handle()) },
+        move |x| MainEvent::Thumbnail(path, size, x),
+    )
+}
+
+/// Send the images of `source` to the grid opened as `run`, a page at a
+/// time. Folder images the catalog does not know are read from their
+/// files.
+async fn list_images(
+    store: Option<Arc<dyn Datastore>>,
+    source: &Source,
+    run: u64,
+    output: &mut mpsc::Sender<MainEvent>,
+) -> anyhow::Result<()> {
+    match source {
+        Source::Collection(name) => {
+            let store = store.ok_or_else(|| anyhow::anyhow!("no catalog"))?;
+            let query;
+            let mut pages = if store.get_collection(name).await?.is_some() {
+                store.get_images_in_collection_iter(name)
+            } else {
+                let smart = store.get_smart_collection(name).await?;
+                query = smart
+                    .ok_or_else(|| anyhow::anyhow!("no collection named {}", name))?
+                    .parse()?;
+                store.find_images_iter(&query)
+            };
+            while let Some(page) = pages.next().await {
+                output.send(MainEvent::Listed(run, page?)).await?;
+            }
+        }
+        Source::Folder(dir) => {
+            let dir = dir.clone();
+            let paths: Vec<PathBuf> = unblock(move || {
+                FolderIndex::scan(&dir, SortMode::Name, false)
+                    .map(|x| x.paths().map(Path::to_owned).collect())
+            })
+            .await??;
+            let keywords = match store {
+                Some(ref store) => Arc::new(store.get_keywords().await?),
+                None => Arc::new(vec![]),
+            };
+            // Reading files is slow; smaller pages show the first sooner.
+            for chunk in paths.chunks(PAGE_SIZE / 10) {
+                let mut records = vec![];
+                for path in chunk {
+                    let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+                    records.push(match store {
+                        Some(ref store) => store.get_image(&key.to_string_lossy()).await?,
+                        None => None,
+                    });
+                }
+                let (chunk, keywords) = (chunk.to_vec(), keywords.clone());
+                let page = unblock(move || {
+                    chunk
+                        .into_iter()
+                        .zip(records)
+                        .map(|(path, record)| {
+                            let image = record
+                                .map(Ok)
+                                .unwrap_or_else(|| read_image(&path, "", &keywords))
+                                .unwrap_or_else(|_| loose_image(&path));
+                            // Paths as the folder index has them, to match the viewer.
+                            datastore::Image {
+                                path: path.to_string_lossy().into_owned(),
+                                ..image
+                            }
+                        })
+                        .collect()
+                })
+                .await?;
+                output.send(MainEvent::Listed(run, page)).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run `f` on a thread of its own, so that reading files does not hold up
+/// the executor.
+async fn unblock<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> anyhow::Result<T> {
+    let (tx, rx) = oneshot::channel();
+    std::thread::Builder::new()
+        .name("unblock".into())
+        .spawn(move || {
+            let _ = tx.send(f());
+        })?;
+    rx.await
+        .map_err(|_| anyhow::anyhow!("worker thread failed"))
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs saves one at a time, in the order they were made, on a thread of
+/// its own: writing XMP is slow, and two writes to a file must not race.
+struct Saver(std::sync::mpsc::Sender<Job>);
+
+impl Default for Saver {
+    fn default() -> Saver {
+        let (tx, rx) = std::sync::mpsc::channel::<Job>();
+        let spawned = std::thread::Builder::new()
+            .name("save".into())
+            // Ends when the UI, and with it the sender, is dropped.
+            .spawn(move || rx.into_iter().for_each(|job| job()));
+        if let Err(e) = spawned {
+            log::warn!("cannot start the save thread: {}", e);
+        }
+        Saver(tx)
+    }
+}
+
+impl Saver {
+    /// Queue `f` now and wait for its result.
+    fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> impl std::future::Future<Output = anyhow::Result<T>> {
+        let (tx, rx) = oneshot::channel();
+        let sent = self.0.send(Box::new(move || {
+            let _ = tx.send(f());
+        }));
+        async move {
+            sent.map_err(|_| anyhow::anyhow!("save thread stopped"))?;
+            rx.await.map_err(|_| anyhow::anyhow!("save thread stopped"))
+        }
+    }
+}
+
+/// A change to the current image.
+#[derive(Debug, Clone)]
+enum Edit {
+    Marks(Marks),
+    Tags(Vec<String>),
+}
+
+/// Store `edit` of `path` in the catalog, if the image is there, noting it
+/// as pending for its file. With `mirror` set it is written right away: as
+/// the policy of its collection says, into the file itself if that is off,
+/// and into the file for images not in the catalog. `keywords` gives the
+/// synonyms written to XMP.
+async fn save(
+    store: Option<Arc<dyn Datastore>>,
+    path: &Path,
+    edit: Edit,
+    keywords: &[Keyword],
+    mirror: bool,
+) -> anyhow::Result<()> {
+    let key = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    let key = key.to_string_lossy();
+    if let Some(store) = store {
+        if store.get_image(&key).await?.is_some() {
+            let fields = match edit {
+                Edit::Marks(marks) => {
+                    store.set_marks(&key, marks).await?;
+                    Fields::MARKS
+                }
+                Edit::Tags(ref tags) => {
+                    store.set_tags(&key, tags).await?;
+                    Fields::TAGS
+                }
+            };
+            store.add_pending(&key, fields).await?;
+            if !mirror {
+                return Ok(());
+            }
+            let Some(image) = store.get_image(&key).await? else {
+                return Ok(());
+            };
+            let mut writer = Writer::new(&*store, keywords.to_vec(), false);
+            let outcome = writer
+                .write(&Pending { image, fields }, SyncPolicy::Embedded)
+                .await;
+            writer.finish().await?;
+            return match outcome? {
+                Outcome::Conflict(file) => Err(anyhow::anyhow!(
+                    "{} changed since last read; the edit stays pending",
+                    file.to_string_lossy()
+                )),
+                Outcome::Failed(e) => Err(anyhow::anyhow!("{}; the edit stays pending", e)),
+                _ => Ok(()),
+            };
+        }
+    }
+    if !mirror {
+        anyhow::bail!("not in the catalog; import its folder or enable catalog.mirror_xmp");
+    }
+    match edit {
+        Edit::Marks(marks) => {
+            write_rating(path, marks.xmp_rating(), marks.label.map(ColorLabel::name))
+        }
+        Edit::Tags(ref tags) => {
+            let (subjects, hierarchical) = keywords::to_xmp(tags, keywords);
+            write_keywords(path, &subjects, &hierarchical)
         }
     }
 }